@@ -0,0 +1,51 @@
+//! Times inference on a 2-64-64-1 MLP through the graph, through
+//! `MLP::predict` and through a `CompiledMlp`.
+//!
+//! Run with `cargo run --release --example inference_bench`.
+
+use smolgrad::engine::{Scalar, Value};
+use smolgrad::nn::{Activation, MLP};
+use std::time::Instant;
+
+const SAMPLES: usize = 20_000;
+
+fn main() {
+    let mlp = MLP::new_seeded(2, &[64, 64, 1], Activation::ReLU, 0);
+    let compiled = mlp.compile_inference();
+    let xs: Vec<Vec<Scalar>> = (0..SAMPLES)
+        .map(|i| {
+            let t = i as Scalar / SAMPLES as Scalar;
+            vec![t, 1.0 - 2.0 * t]
+        })
+        .collect();
+
+    // Building graphs is slow enough that a tenth of the samples will do
+    let start = Instant::now();
+    let mut graph_sum = 0.0;
+    for x in &xs[..SAMPLES / 10] {
+        let x: Vec<_> = x.iter().map(|&x| Value::new(x)).collect();
+        graph_sum += mlp.call(&x)[0].get_data();
+    }
+    let graph = start.elapsed() * 10;
+
+    let start = Instant::now();
+    let predict_sum: Scalar = xs.iter().map(|x| mlp.predict(x)[0]).sum();
+    let predict = start.elapsed();
+
+    let start = Instant::now();
+    let compiled_sum: Scalar = xs.iter().map(|x| compiled.predict(x)[0]).sum();
+    let compiled_time = start.elapsed();
+
+    println!("{} samples", SAMPLES);
+    println!("graph     {:>10.2?}  (sum of a tenth {})", graph, graph_sum);
+    println!("predict   {:>10.2?}  (sum {})", predict, predict_sum);
+    println!("compiled  {:>10.2?}  (sum {})", compiled_time, compiled_sum);
+    println!(
+        "predict is {:.1}x faster than the graph",
+        graph.as_secs_f64() / predict.as_secs_f64()
+    );
+    println!(
+        "compiled is {:.1}x faster than predict",
+        predict.as_secs_f64() / compiled_time.as_secs_f64()
+    );
+}
@@ -0,0 +1,71 @@
+//! Times training steps of a 4-layer MLP-shaped graph.
+//!
+//! Run with `cargo run --release --example mlp_bench`.
+
+use smolgrad::engine::{Scalar, Value};
+use std::time::Instant;
+
+const SIZES: [usize; 5] = [4, 32, 32, 32, 1];
+const STEPS: usize = 1000;
+
+fn main() {
+    let layers: Vec<Vec<(Vec<Value>, Value)>> = SIZES
+        .windows(2)
+        .enumerate()
+        .map(|(l, sz)| {
+            (0..sz[1])
+                .map(|j| {
+                    let w = (0..sz[0])
+                        .map(|i| {
+                            let x = ((l * 31 + j * 7 + i) % 13) as Scalar;
+                            Value::new(x / 13.0 - 0.5)
+                        })
+                        .collect();
+                    (w, Value::new(0.0))
+                })
+                .collect()
+        })
+        .collect();
+    let params: Vec<Value> = layers
+        .iter()
+        .flatten()
+        .flat_map(|(w, b)| w.iter().chain(Some(b)).cloned())
+        .collect();
+
+    let start = Instant::now();
+    for step in 0..STEPS {
+        let mut x: Vec<Value> = (0..SIZES[0])
+            .map(|i| Value::new(((step + i) % 5) as Scalar / 5.0))
+            .collect();
+        for (l, layer) in layers.iter().enumerate() {
+            x = layer
+                .iter()
+                .map(|(w, b)| {
+                    let act = w
+                        .iter()
+                        .zip(x.iter())
+                        .fold(b.clone(), |acc, (w, x)| acc + w * x);
+                    if l + 1 < layers.len() {
+                        act.relu()
+                    } else {
+                        act
+                    }
+                })
+                .collect();
+        }
+        let diff = &x[0] - &Value::new(1.0);
+        let loss = &diff * &diff;
+        for p in params.iter() {
+            p.set_grad(0.0);
+        }
+        loss.backward();
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{} steps over {} parameters: {:.3?} ({:.3?} per step)",
+        STEPS,
+        params.len(),
+        elapsed,
+        elapsed / STEPS as u32
+    );
+}
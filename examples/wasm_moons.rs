@@ -0,0 +1,100 @@
+//! Trains a moons classifier in the browser, one step per call, for a live
+//! demo of the decision boundary.
+//!
+//! Build it for the web with
+//!
+//! ```text
+//! cargo build --release --example wasm_moons --target wasm32-unknown-unknown
+//! wasm-bindgen --target web --out-dir pkg \
+//!     target/wasm32-unknown-unknown/release/examples/wasm_moons.wasm
+//! ```
+//!
+//! and drive it from JavaScript:
+//!
+//! ```text
+//! import init, { train_step, predict } from "./pkg/wasm_moons.js";
+//! await init();
+//! const loss = train_step();
+//! const score = predict(0.5, 0.25); // > 0 on the lower moon
+//! ```
+
+use std::cell::RefCell;
+
+use smolgrad::data::{make_moons, Dataset};
+use smolgrad::engine::Scalar;
+use smolgrad::losses::{Hinge, Loss};
+use smolgrad::nn::{Activation, Module, MLP};
+use smolgrad::optim::{Adam, Optimizer};
+use wasm_bindgen::prelude::*;
+
+const SAMPLES: usize = 100;
+const NOISE: Scalar = 0.1;
+
+struct Demo {
+    model: MLP,
+    optimizer: Adam,
+    xs: Vec<Vec<Scalar>>,
+    ys: Vec<Vec<Scalar>>,
+}
+
+impl Demo {
+    /// Everything is seeded, so no entropy source is needed to start.
+    fn new(seed: u64) -> Self {
+        let data = make_moons(SAMPLES, NOISE, seed);
+        let (xs, ys) = (0..data.len()).map(|i| data.get(i)).unzip();
+        let model = MLP::new_seeded(2, &[16, 16, 1], Activation::ReLU, seed);
+        let optimizer = Adam::new(model.parameters(), 0.05);
+        Self {
+            model,
+            optimizer,
+            xs,
+            ys,
+        }
+    }
+}
+
+thread_local! {
+    static DEMO: RefCell<Demo> = RefCell::new(Demo::new(0));
+}
+
+/// Starts over with new moons and a new model drawn from `seed`.
+#[wasm_bindgen]
+pub fn reset(seed: u32) {
+    DEMO.with(|demo| *demo.borrow_mut() = Demo::new(seed.into()))
+}
+
+/// Takes one full-batch step and returns the hinge loss before it.
+#[wasm_bindgen]
+pub fn train_step() -> Scalar {
+    DEMO.with(|demo| {
+        let demo = &mut *demo.borrow_mut();
+        let preds = demo.model.forward_batch_scalars(&demo.xs);
+        let loss = Hinge.batch_loss(&preds, &demo.ys);
+        demo.optimizer.zero_grad();
+        loss.backward();
+        demo.optimizer.step();
+        loss.get_data()
+    })
+}
+
+/// The model's score at `(x, y)`: positive for the lower moon, negative
+/// for the upper one.
+#[wasm_bindgen]
+pub fn predict(x: Scalar, y: Scalar) -> Scalar {
+    DEMO.with(|demo| demo.borrow().model.predict(&[x, y])[0])
+}
+
+/// The fraction of the training points on the right side of the boundary.
+#[wasm_bindgen]
+pub fn accuracy() -> Scalar {
+    DEMO.with(|demo| {
+        let demo = demo.borrow();
+        let scores = demo.model.predict_batch(&demo.xs);
+        let correct = scores
+            .iter()
+            .zip(&demo.ys)
+            .filter(|(score, y)| score[0] * y[0] > 0.0)
+            .count();
+        correct as Scalar / demo.xs.len() as Scalar
+    })
+}
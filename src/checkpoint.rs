@@ -0,0 +1,1445 @@
+//! Saving models to disk and loading them back.
+//!
+//! A checkpoint holds a model's [`Architecture`] and the data of every
+//! parameter, keyed by the names from [`Module::named_parameters`]. Loading
+//! rebuilds the model from the architecture and then fills in each
+//! parameter by name, so a file whose weights don't fit its architecture is
+//! rejected rather than loaded half way.
+//!
+//! JSON checkpoints need the `serde` feature, and can also carry an
+//! optimizer's [state](crate::optim::OptimState) to resume training where
+//! it left off. The binary format from [`save_bin`] holds only the weights,
+//! as `f32`, and loads into a model that has already been built.
+//! [`import_npy`] and [`export_npy`] move dense layer weights to and from
+//! NumPy's `.npy` files, for models trained in PyTorch or NumPy, and
+//! [`QuantizedMlp::serialize`] has a byte format of its own.
+//!
+//! Everything that touches the filesystem needs the default `fs` feature.
+//! Without it, as on `wasm32-unknown-unknown`, [`encode_bin`] and
+//! [`decode_bin`] still move the binary format through memory.
+
+use std::convert::TryInto;
+use std::fmt::Display;
+use std::io;
+
+use crate::engine::Scalar;
+use crate::nn::{
+    Activation, BuiltinActivation, Conv1d, Dropout, Embedding, Introspect,
+    Layer, Module, QuantizedLayer, QuantizedMlp, Residual, Sequential, MLP,
+};
+
+#[cfg(feature = "fs")]
+pub use fs::{export_npy, import_npy, load_bin, save_bin, WeightLayout};
+#[cfg(all(feature = "serde", feature = "fs"))]
+pub use json::{
+    load_json, load_json_with_optimizer, save_json, save_json_with_optimizer,
+};
+
+/// What it takes to rebuild a module with fresh weights; see
+/// [`architecture`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type")
+)]
+pub enum Architecture {
+    Layer {
+        nin: usize,
+        nout: usize,
+        activation: Activation,
+        bias: bool,
+    },
+    /// `sizes` are the input size followed by each layer's output size,
+    /// with one activation per layer.
+    Mlp {
+        sizes: Vec<usize>,
+        activations: Vec<Activation>,
+    },
+    Sequential {
+        modules: Vec<Architecture>,
+    },
+    Conv1d {
+        in_channels: usize,
+        out_channels: usize,
+        kernel_size: usize,
+        stride: usize,
+        padding: usize,
+    },
+    Residual {
+        inner: Box<Architecture>,
+    },
+    Dropout {
+        p: Scalar,
+    },
+    Embedding {
+        num_embeddings: usize,
+        dim: usize,
+    },
+}
+
+impl Architecture {
+    /// The module type this describes.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Architecture::Layer { .. } => "Layer",
+            Architecture::Mlp { .. } => "MLP",
+            Architecture::Sequential { .. } => "Sequential",
+            Architecture::Conv1d { .. } => "Conv1d",
+            Architecture::Residual { .. } => "Residual",
+            Architecture::Dropout { .. } => "Dropout",
+            Architecture::Embedding { .. } => "Embedding",
+        }
+    }
+
+    /// Builds the module with freshly drawn weights.
+    pub fn build(&self) -> Result<Box<dyn Module>, LoadError> {
+        Ok(match self {
+            &Architecture::Layer {
+                nin,
+                nout,
+                ref activation,
+                bias,
+            } => Box::new(
+                Layer::new(nin, nout, activation.clone()).with_bias(bias),
+            ),
+            Architecture::Mlp { .. } => Box::new(self.build_mlp()?),
+            Architecture::Sequential { .. } => {
+                Box::new(self.build_sequential()?)
+            }
+            &Architecture::Conv1d {
+                in_channels,
+                out_channels,
+                kernel_size,
+                stride,
+                padding,
+            } => {
+                if stride == 0 || kernel_size == 0 {
+                    return Err(LoadError::Architecture(format!(
+                        "Conv1d with kernel_size {} and stride {}",
+                        kernel_size, stride
+                    )));
+                }
+                Box::new(Conv1d::new(
+                    in_channels,
+                    out_channels,
+                    kernel_size,
+                    stride,
+                    padding,
+                ))
+            }
+            Architecture::Residual { inner } => {
+                Box::new(Residual::new(inner.build()?))
+            }
+            &Architecture::Dropout { p } => {
+                if !(0.0..=1.0).contains(&p) {
+                    return Err(LoadError::Architecture(format!(
+                        "dropout probability {}",
+                        p
+                    )));
+                }
+                Box::new(Dropout::new(p))
+            }
+            &Architecture::Embedding {
+                num_embeddings,
+                dim,
+            } => Box::new(Embedding::new(num_embeddings, dim)),
+        })
+    }
+
+    /// Like [`Architecture::build`], for a file that must hold an [`MLP`].
+    pub fn build_mlp(&self) -> Result<MLP, LoadError> {
+        let (sizes, activations) = match self {
+            Architecture::Mlp { sizes, activations } => (sizes, activations),
+            other => return Err(other.expected("MLP")),
+        };
+        if sizes.len() != activations.len() + 1 {
+            return Err(LoadError::Architecture(format!(
+                "MLP with {} sizes and {} activations",
+                sizes.len(),
+                activations.len()
+            )));
+        }
+        let layers = sizes
+            .windows(2)
+            .zip(activations)
+            .map(|(n, act)| Layer::new(n[0], n[1], act.clone()))
+            .collect();
+        Ok(MLP::from_layers(sizes[0], layers))
+    }
+
+    /// Like [`Architecture::build`], for a file that must hold a
+    /// [`Sequential`].
+    pub fn build_sequential(&self) -> Result<Sequential, LoadError> {
+        match self {
+            Architecture::Sequential { modules } => {
+                let modules = modules.iter().map(Architecture::build);
+                Ok(Sequential::new(modules.collect::<Result<_, _>>()?))
+            }
+            other => Err(other.expected("Sequential")),
+        }
+    }
+
+    fn expected(&self, name: &str) -> LoadError {
+        LoadError::Architecture(format!(
+            "expected {}, found {}",
+            name,
+            self.name()
+        ))
+    }
+}
+
+/// Describes `module` for a checkpoint, going by its concrete type from
+/// [`Introspect::as_any`] and into what a [`Residual`] wraps. `None` for
+/// modules that no [`Architecture`] describes, or that contain one.
+pub fn architecture(module: &dyn Module) -> Option<Architecture> {
+    if module.type_name() == "Residual" {
+        let inner = architecture(module.wrapped()?)?;
+        return Some(Architecture::Residual {
+            inner: Box::new(inner),
+        });
+    }
+    let any = module.as_any()?;
+    if let Some(layer) = any.downcast_ref::<Layer>() {
+        Some(Architecture::Layer {
+            nin: layer.nin(),
+            nout: layer.nout(),
+            activation: layer
+                .activation()
+                .cloned()
+                .unwrap_or(Activation::Linear),
+            bias: layer.has_bias(),
+        })
+    } else if let Some(mlp) = any.downcast_ref::<MLP>() {
+        let layers = mlp.children().into_iter().map(|(_, l)| l.activation());
+        Some(Architecture::Mlp {
+            sizes: mlp.sizes().to_vec(),
+            activations: layers
+                .map(|a| a.cloned().unwrap_or(Activation::Linear))
+                .collect(),
+        })
+    } else if any.is::<Sequential>() {
+        let modules = module.children().into_iter().map(|(_, m)| m);
+        Some(Architecture::Sequential {
+            modules: modules.map(architecture).collect::<Option<_>>()?,
+        })
+    } else if let Some(conv) = any.downcast_ref::<Conv1d>() {
+        Some(Architecture::Conv1d {
+            in_channels: conv.in_channels(),
+            out_channels: conv.out_channels(),
+            kernel_size: conv.kernel_size(),
+            stride: conv.stride(),
+            padding: conv.padding(),
+        })
+    } else if let Some(dropout) = any.downcast_ref::<Dropout>() {
+        Some(Architecture::Dropout { p: dropout.p() })
+    } else {
+        any.downcast_ref::<Embedding>()
+            .map(|emb| Architecture::Embedding {
+                num_embeddings: emb.num_embeddings(),
+                dim: emb.dim(),
+            })
+    }
+}
+
+/// Why a checkpoint couldn't be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file isn't a checkpoint this version can read.
+    Format(String),
+    /// The architecture is inconsistent, or not the kind of model asked
+    /// for.
+    Architecture(String),
+    /// The architecture has a parameter the file has no value for.
+    MissingParameter(String),
+    /// The file has a value for a parameter the architecture doesn't have.
+    UnexpectedParameter(String),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "couldn't read checkpoint: {}", e),
+            LoadError::Format(e) => write!(f, "not a checkpoint: {}", e),
+            LoadError::Architecture(e) => write!(f, "bad architecture: {}", e),
+            LoadError::MissingParameter(name) => {
+                write!(f, "checkpoint has no value for parameter `{}`", name)
+            }
+            LoadError::UnexpectedParameter(name) => write!(
+                f,
+                "checkpoint has a value for `{}`, which the architecture \
+                 doesn't have",
+                name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+#[cfg(all(feature = "serde", feature = "fs"))]
+mod json {
+    use std::collections::BTreeMap;
+    use std::io;
+    use std::path::Path;
+
+    use super::{architecture, Architecture, LoadError};
+    use crate::engine::Scalar;
+    use crate::nn::{Module, Sequential, MLP};
+    use crate::optim::{OptimState, Optimizer};
+
+    /// Sets every parameter of `module` from `params` by name. Every name must
+    /// be used exactly once.
+    fn restore(
+        module: &dyn Module,
+        mut params: BTreeMap<String, Scalar>,
+    ) -> Result<(), LoadError> {
+        for (name, p) in module.named_parameters() {
+            match params.remove(&name) {
+                Some(x) => p.set_data(x),
+                None => return Err(LoadError::MissingParameter(name)),
+            }
+        }
+        match params.into_keys().next() {
+            Some(name) => Err(LoadError::UnexpectedParameter(name)),
+            None => Ok(()),
+        }
+    }
+
+    fn architecture_to_save(module: &dyn Module) -> io::Result<Architecture> {
+        architecture(module).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} has no architecture to save", module.type_name()),
+            )
+        })
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct JsonCheckpoint {
+        architecture: Architecture,
+        parameters: BTreeMap<String, Scalar>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        optimizer: Option<OptimState>,
+    }
+
+    fn write_json(
+        module: &dyn Module,
+        optimizer: Option<OptimState>,
+        path: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        let checkpoint = JsonCheckpoint {
+            architecture: architecture_to_save(module)?,
+            parameters: module
+                .named_parameters()
+                .into_iter()
+                .map(|(name, p)| (name, p.get_data()))
+                .collect(),
+            optimizer,
+        };
+        let file = io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer_pretty(file, &checkpoint)?;
+        Ok(())
+    }
+
+    /// Writes `module`'s architecture and weights to `path` as JSON. Fails if
+    /// the module has no [`Architecture`] or uses a custom activation.
+    pub fn save_json(
+        module: &dyn Module,
+        path: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        write_json(module, None, path)
+    }
+
+    /// Like [`save_json`], but also saves the state of the `optimizer`
+    /// training `module`, so training can pick up where it left off.
+    pub fn save_json_with_optimizer(
+        module: &dyn Module,
+        optimizer: &dyn Optimizer,
+        path: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        write_json(module, Some(optimizer.state()), path)
+    }
+
+    fn read_json(path: impl AsRef<Path>) -> Result<JsonCheckpoint, LoadError> {
+        let text = std::fs::read_to_string(path)?;
+        serde_json::from_str(&text)
+            .map_err(|e| LoadError::Format(e.to_string()))
+    }
+
+    /// Rebuilds whatever module [`save_json`] wrote to `path`.
+    pub fn load_json(
+        path: impl AsRef<Path>,
+    ) -> Result<Box<dyn Module>, LoadError> {
+        let checkpoint = read_json(path)?;
+        let module = checkpoint.architecture.build()?;
+        restore(&module, checkpoint.parameters)?;
+        Ok(module)
+    }
+
+    /// Rebuilds the module saved by [`save_json_with_optimizer`], along with
+    /// the optimizer state, if the checkpoint has one. Load the state into an
+    /// optimizer built over the new module's parameters in the same order.
+    pub fn load_json_with_optimizer(
+        path: impl AsRef<Path>,
+    ) -> Result<(Box<dyn Module>, Option<OptimState>), LoadError> {
+        let checkpoint = read_json(path)?;
+        let module = checkpoint.architecture.build()?;
+        restore(&module, checkpoint.parameters)?;
+        Ok((module, checkpoint.optimizer))
+    }
+
+    impl MLP {
+        /// Saves the model to `path`; see [`save_json`].
+        pub fn save_json(&self, path: impl AsRef<Path>) -> io::Result<()> {
+            save_json(self, path)
+        }
+
+        /// Loads a model saved with [`MLP::save_json`].
+        pub fn load_json(path: impl AsRef<Path>) -> Result<MLP, LoadError> {
+            let checkpoint = read_json(path)?;
+            let mlp = checkpoint.architecture.build_mlp()?;
+            restore(&mlp, checkpoint.parameters)?;
+            Ok(mlp)
+        }
+    }
+
+    impl Sequential {
+        /// Saves the model to `path`; see [`save_json`].
+        pub fn save_json(&self, path: impl AsRef<Path>) -> io::Result<()> {
+            save_json(self, path)
+        }
+
+        /// Loads a model saved with [`Sequential::save_json`].
+        pub fn load_json(
+            path: impl AsRef<Path>,
+        ) -> Result<Sequential, LoadError> {
+            let checkpoint = read_json(path)?;
+            let seq = checkpoint.architecture.build_sequential()?;
+            restore(&seq, checkpoint.parameters)?;
+            Ok(seq)
+        }
+    }
+}
+
+const MAGIC: &[u8; 8] = b"SMOLGRAD";
+const VERSION: u8 = 1;
+/// Magic, version, parameter count and layout hash.
+const HEADER_LEN: usize = 8 + 1 + 4 + 8;
+
+/// FNV-1a, used both as the layout hash and the trailing checksum.
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Hashes the parameter names in order, so weights only load into a model
+/// laid out like the one that saved them.
+fn layout_hash(module: &dyn Module) -> u64 {
+    let names = module.named_parameters().into_iter();
+    fnv1a(names.flat_map(|(name, _)| name.into_bytes().into_iter().chain([0])))
+}
+
+/// The binary checkpoint of `module`, as [`save_bin`] writes it:
+/// `SMOLGRAD`, a version byte, the
+/// parameter count as a `u32`, the hash of the parameter names as a `u64`,
+/// each parameter as an `f32`, and a `u64` checksum of everything before
+/// it. Integers and floats are little-endian.
+#[allow(clippy::unnecessary_cast)] // Scalar may be f64
+pub fn encode_bin(module: &dyn Module) -> Vec<u8> {
+    let params = module.parameters();
+    let mut out = Vec::with_capacity(HEADER_LEN + 4 * params.len() + 8);
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&(params.len() as u32).to_le_bytes());
+    out.extend_from_slice(&layout_hash(module).to_le_bytes());
+    for p in &params {
+        out.extend_from_slice(&(p.get_data() as f32).to_le_bytes());
+    }
+    let checksum = fnv1a(out.iter().copied());
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+/// Checks `bytes` from [`encode_bin`] against `module` and only then
+/// overwrites its weights; see [`load_bin`].
+pub fn decode_bin(module: &dyn Module, bytes: &[u8]) -> Result<(), LoadError> {
+    let format = |e: &str| Err(LoadError::Format(e.to_string()));
+    if !bytes.starts_with(MAGIC) {
+        return format("missing the SMOLGRAD magic bytes");
+    }
+    if bytes.len() < HEADER_LEN + 8 {
+        return format("file is truncated");
+    }
+    if bytes[8] != VERSION {
+        return Err(LoadError::Format(format!(
+            "unsupported version {}, expected {}",
+            bytes[8], VERSION
+        )));
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - 8);
+    if fnv1a(body.iter().copied()).to_le_bytes() != checksum {
+        return format("checksum mismatch, the file is corrupted");
+    }
+    let count = u32::from_le_bytes(body[9..13].try_into().unwrap()) as usize;
+    let values = &body[HEADER_LEN..];
+    if values.len() != 4 * count {
+        return format("file length doesn't match its parameter count");
+    }
+    let params = module.parameters();
+    if count != params.len() {
+        return Err(LoadError::Architecture(format!(
+            "checkpoint has {} parameters, the model has {}",
+            count,
+            params.len()
+        )));
+    }
+    let hash = u64::from_le_bytes(body[13..HEADER_LEN].try_into().unwrap());
+    if hash != layout_hash(module) {
+        return Err(LoadError::Architecture(
+            "checkpoint parameter names don't match the model's".to_string(),
+        ));
+    }
+    for (p, x) in params.iter().zip(values.chunks_exact(4)) {
+        p.set_data(f32::from_le_bytes(x.try_into().unwrap()) as Scalar);
+    }
+    Ok(())
+}
+
+const QUANTIZED_MAGIC: &[u8; 8] = b"SMOLGRQ8";
+const QUANTIZED_VERSION: u8 = 1;
+
+impl QuantizedMlp {
+    /// The model as bytes: `SMOLGRQ8`, a version byte and the layer count
+    /// as a `u32`, then per layer its input and output sizes as `u32`s,
+    /// the activation as a tag byte and an `f32` slope, the weight scale
+    /// and biases as `f32`s and the weights as `i8`s, and finally a `u64`
+    /// checksum of everything before it. Integers and floats are
+    /// little-endian.
+    #[allow(clippy::unnecessary_cast)] // Scalar may be f64
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = QUANTIZED_MAGIC.to_vec();
+        out.push(QUANTIZED_VERSION);
+        out.extend_from_slice(&(self.layers.len() as u32).to_le_bytes());
+        for layer in &self.layers {
+            out.extend_from_slice(&(layer.nin as u32).to_le_bytes());
+            out.extend_from_slice(&(layer.bias.len() as u32).to_le_bytes());
+            let (tag, slope) = match layer.activation {
+                BuiltinActivation::Linear => (0, 0.0),
+                BuiltinActivation::ReLU => (1, 0.0),
+                BuiltinActivation::Tanh => (2, 0.0),
+                BuiltinActivation::Sigmoid => (3, 0.0),
+                BuiltinActivation::LeakyReLU(slope) => (4, slope),
+                BuiltinActivation::Gelu => (5, 0.0),
+            };
+            out.push(tag);
+            out.extend_from_slice(&(slope as f32).to_le_bytes());
+            out.extend_from_slice(&(layer.scale as f32).to_le_bytes());
+            for &b in &layer.bias {
+                out.extend_from_slice(&(b as f32).to_le_bytes());
+            }
+            out.extend(layer.weights.iter().map(|&w| w as u8));
+        }
+        let checksum = fnv1a(out.iter().copied());
+        out.extend_from_slice(&checksum.to_le_bytes());
+        out
+    }
+
+    /// Reads a model back from the bytes of [`QuantizedMlp::serialize`].
+    pub fn deserialize(bytes: &[u8]) -> Result<QuantizedMlp, LoadError> {
+        let format = |e: &str| LoadError::Format(e.to_string());
+        if !bytes.starts_with(QUANTIZED_MAGIC) {
+            return Err(format("missing the SMOLGRQ8 magic bytes"));
+        }
+        if bytes.len() < 8 + 1 + 4 + 8 {
+            return Err(format("file is truncated"));
+        }
+        if bytes[8] != QUANTIZED_VERSION {
+            return Err(LoadError::Format(format!(
+                "unsupported version {}, expected {}",
+                bytes[8], QUANTIZED_VERSION
+            )));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 8);
+        if fnv1a(body.iter().copied()).to_le_bytes() != checksum {
+            return Err(format("checksum mismatch, the file is corrupted"));
+        }
+        let mut rest = &body[9..];
+        let mut take = |n: usize| {
+            if rest.len() < n {
+                return Err(format("file is truncated"));
+            }
+            let (head, tail) = rest.split_at(n);
+            rest = tail;
+            Ok(head)
+        };
+        let u32_at = |b: &[u8]| u32::from_le_bytes(b.try_into().unwrap());
+        let f32_at = |b: &[u8]| f32::from_le_bytes(b.try_into().unwrap());
+        let count = u32_at(take(4)?);
+        let mut layers: Vec<QuantizedLayer> = Vec::new();
+        for _ in 0..count {
+            let nin = u32_at(take(4)?) as usize;
+            let nout = u32_at(take(4)?) as usize;
+            if nin == 0 {
+                return Err(format("layer has no inputs"));
+            }
+            if let Some(prev) = layers.last() {
+                if nin != prev.bias.len() {
+                    return Err(LoadError::Format(format!(
+                        "layer takes {} inputs, expected {}",
+                        nin,
+                        prev.bias.len()
+                    )));
+                }
+            }
+            let too_large = || format("layer is too large");
+            let tag = take(1)?[0];
+            let slope = f32_at(take(4)?) as Scalar;
+            let activation = match tag {
+                0 => BuiltinActivation::Linear,
+                1 => BuiltinActivation::ReLU,
+                2 => BuiltinActivation::Tanh,
+                3 => BuiltinActivation::Sigmoid,
+                4 => BuiltinActivation::LeakyReLU(slope),
+                5 => BuiltinActivation::Gelu,
+                _ => return Err(format("unknown activation tag")),
+            };
+            let scale = f32_at(take(4)?) as Scalar;
+            let bias = take(nout.checked_mul(4).ok_or_else(too_large)?)?;
+            let bias = bias.chunks_exact(4).map(|b| f32_at(b) as Scalar);
+            let bias = bias.collect();
+            let weights = nin.checked_mul(nout).ok_or_else(too_large)?;
+            let weights = take(weights)?.iter().map(|&w| w as i8);
+            layers.push(QuantizedLayer {
+                nin,
+                weights: weights.collect(),
+                scale,
+                bias,
+                activation,
+            });
+        }
+        if !rest.is_empty() {
+            return Err(format("file length doesn't match its layers"));
+        }
+        Ok(QuantizedMlp { layers })
+    }
+}
+
+#[cfg(feature = "fs")]
+mod fs {
+    use std::convert::TryInto;
+    use std::io;
+    use std::path::Path;
+
+    use super::{decode_bin, encode_bin, LoadError};
+    use crate::engine::Scalar;
+    use crate::nn::{Layer, Module, Sequential, MLP};
+
+    /// Writes `module`'s weights to `path` in the compact binary format. Under
+    /// the `f64` feature the weights are rounded to `f32`.
+    pub fn save_bin(
+        module: &dyn Module,
+        path: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        std::fs::write(path, encode_bin(module))
+    }
+
+    /// Overwrites `module`'s weights with those [`save_bin`] wrote to `path`.
+    /// The module must have the same parameters, by name and in order, as the
+    /// one that was saved; nothing is changed if it doesn't.
+    pub fn load_bin(
+        module: &dyn Module,
+        path: impl AsRef<Path>,
+    ) -> Result<(), LoadError> {
+        decode_bin(module, &std::fs::read(path)?)
+    }
+
+    impl MLP {
+        /// Saves the weights to `path`; see [`save_bin`].
+        pub fn save_bin(&self, path: impl AsRef<Path>) -> io::Result<()> {
+            save_bin(self, path)
+        }
+
+        /// Loads weights saved with [`MLP::save_bin`] into this model.
+        pub fn load_bin(
+            &self,
+            path: impl AsRef<Path>,
+        ) -> Result<(), LoadError> {
+            load_bin(self, path)
+        }
+    }
+
+    impl Sequential {
+        /// Saves the weights to `path`; see [`save_bin`].
+        pub fn save_bin(&self, path: impl AsRef<Path>) -> io::Result<()> {
+            save_bin(self, path)
+        }
+
+        /// Loads weights saved with [`Sequential::save_bin`] into this model.
+        pub fn load_bin(
+            &self,
+            path: impl AsRef<Path>,
+        ) -> Result<(), LoadError> {
+            load_bin(self, path)
+        }
+    }
+
+    /// How a dense layer's weight matrix is laid out in an imported array.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum WeightLayout {
+        /// Shape `(out, in)`, as PyTorch's `nn.Linear` stores `weight`: row `o`
+        /// holds neuron `o`'s weights.
+        OutIn,
+        /// Shape `(in, out)`, for `x @ w` in NumPy: neuron `o`'s weights are
+        /// column `o`, so the matrix is transposed on the way in and out.
+        InOut,
+    }
+
+    impl WeightLayout {
+        fn shape(self, nin: usize, nout: usize) -> Vec<usize> {
+            match self {
+                WeightLayout::OutIn => vec![nout, nin],
+                WeightLayout::InOut => vec![nin, nout],
+            }
+        }
+
+        /// Where the weight from input `i` to neuron `o` sits in the flat
+        /// row-major array.
+        fn index(self, o: usize, i: usize, nin: usize, nout: usize) -> usize {
+            match self {
+                WeightLayout::OutIn => o * nin + i,
+                WeightLayout::InOut => i * nout + o,
+            }
+        }
+    }
+
+    /// A [`Layer`] found inside a module, with the name its arrays use.
+    struct DenseLayer<'a> {
+        name: String,
+        module: &'a dyn Module,
+        nin: usize,
+        nout: usize,
+        bias: bool,
+    }
+
+    /// Every [`Layer`] in `module`, named by the path of child names leading
+    /// to it: `layer0` in an [`MLP`], or `0` and `2.layer1` in a
+    /// [`Sequential`], which is also how PyTorch names the layers of its
+    /// `nn.Sequential`. Anything else must have no parameters.
+    fn dense_layers(
+        module: &dyn Module,
+    ) -> Result<Vec<DenseLayer<'_>>, LoadError> {
+        fn walk<'a>(
+            name: String,
+            module: &'a dyn Module,
+            out: &mut Vec<DenseLayer<'a>>,
+        ) -> Result<(), LoadError> {
+            let any = module.as_any();
+            if let Some(layer) = any.and_then(|m| m.downcast_ref::<Layer>()) {
+                out.push(DenseLayer {
+                    name,
+                    module,
+                    nin: layer.nin(),
+                    nout: layer.nout(),
+                    bias: layer.has_bias(),
+                });
+                return Ok(());
+            }
+            let children = module.children();
+            if children.is_empty() && module.num_parameters() > 0 {
+                return Err(LoadError::Architecture(format!(
+                    "`{}` is a {}, only Layers have array weights",
+                    name,
+                    module.type_name()
+                )));
+            }
+            for (child, m) in children {
+                walk(array_name(&name, &child), m, out)?;
+            }
+            Ok(())
+        }
+
+        let mut out = vec![];
+        walk(String::new(), module, &mut out)?;
+        Ok(out)
+    }
+
+    fn array_name(prefix: &str, name: &str) -> String {
+        if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", prefix, name)
+        }
+    }
+
+    const NPY_MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+    /// A shape the way NumPy writes it, e.g. `(3,)` or `(3, 2)`.
+    fn npy_shape(shape: &[usize]) -> String {
+        let dims: Vec<_> = shape.iter().map(usize::to_string).collect();
+        match dims.len() {
+            1 => format!("({},)", dims[0]),
+            _ => format!("({})", dims.join(", ")),
+        }
+    }
+
+    /// Reads a C-ordered, little-endian `float32` or `float64` array from a
+    /// `.npy` file, returning its shape and its data in row-major order.
+    fn read_npy(path: &Path) -> Result<(Vec<usize>, Vec<Scalar>), LoadError> {
+        let bytes = std::fs::read(path)?;
+        let bad =
+            |e: &str| LoadError::Format(format!("{}: {}", path.display(), e));
+        if !bytes.starts_with(NPY_MAGIC) || bytes.len() < 12 {
+            return Err(bad("not a .npy file"));
+        }
+        let (len, start) = match bytes[6] {
+            1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+            2 | 3 => {
+                let len = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+                (len as usize, 12)
+            }
+            v => return Err(bad(&format!("unsupported .npy version {}", v))),
+        };
+        let header = bytes
+            .get(start..start + len)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .ok_or_else(|| bad("truncated header"))?;
+        let field = |key: &str| {
+            let value = header.split(&format!("'{}':", key)).nth(1);
+            value.map(str::trim_start)
+        };
+        if !field("fortran_order").is_some_and(|v| v.starts_with("False")) {
+            return Err(bad("only C-ordered arrays are supported"));
+        }
+        let shape: Vec<usize> = field("shape")
+            .and_then(|v| v.strip_prefix('('))
+            .and_then(|v| v.split(')').next())
+            .and_then(|v| {
+                let dims =
+                    v.split(',').map(str::trim).filter(|d| !d.is_empty());
+                dims.map(|d| d.parse().ok()).collect()
+            })
+            .ok_or_else(|| bad("can't read the shape"))?;
+        let size = match field("descr") {
+            Some(d) if d.starts_with("'<f4'") => 4,
+            Some(d) if d.starts_with("'<f8'") => 8,
+            _ => {
+                return Err(bad(
+                    "only little-endian float arrays are supported",
+                ))
+            }
+        };
+        let data = &bytes[start + len..];
+        let count: usize = shape.iter().product();
+        if data.len() != count * size {
+            return Err(bad(&format!(
+                "{} values of shape {} need {} bytes, found {}",
+                count,
+                npy_shape(&shape),
+                count * size,
+                data.len()
+            )));
+        }
+        let values = data.chunks_exact(size).map(|x| match size {
+            4 => f32::from_le_bytes(x.try_into().unwrap()) as Scalar,
+            _ => f64::from_le_bytes(x.try_into().unwrap()) as Scalar,
+        });
+        Ok((shape, values.collect()))
+    }
+
+    /// Writes `data` as a version 1.0 `.npy` file of `float32`.
+    #[allow(clippy::unnecessary_cast)] // Scalar may be f64
+    fn write_npy(
+        path: &Path,
+        shape: &[usize],
+        data: &[Scalar],
+    ) -> io::Result<()> {
+        let mut header = format!(
+            "{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}",
+            npy_shape(shape)
+        );
+        // NumPy pads the header so the data starts on a 64-byte boundary
+        let unpadded = NPY_MAGIC.len() + 4 + header.len() + 1;
+        header.extend(std::iter::repeat_n(' ', (64 - unpadded % 64) % 64));
+        header.push('\n');
+        let mut out = NPY_MAGIC.to_vec();
+        out.extend_from_slice(&[1, 0]);
+        out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        out.extend_from_slice(header.as_bytes());
+        for &x in data {
+            out.extend_from_slice(&(x as f32).to_le_bytes());
+        }
+        std::fs::write(path, out)
+    }
+
+    /// Reads `{name}.npy` from `dir` and checks it has `shape`.
+    fn read_array(
+        dir: &Path,
+        name: &str,
+        shape: &[usize],
+    ) -> Result<Vec<Scalar>, LoadError> {
+        let path = dir.join(format!("{}.npy", name));
+        if !path.exists() {
+            return Err(LoadError::MissingParameter(name.to_string()));
+        }
+        let (found, data) = read_npy(&path)?;
+        if found != shape {
+            return Err(LoadError::Architecture(format!(
+                "`{}` has shape {}, expected {}",
+                name,
+                npy_shape(&found),
+                npy_shape(shape)
+            )));
+        }
+        Ok(data)
+    }
+
+    /// Loads the weights of every [`Layer`] in `module` from `.npy` files in
+    /// `dir`: `{layer}.weight.npy`, laid out as `layout` says, and
+    /// `{layer}.bias.npy` of shape `(out,)` for layers with a bias. Layers are
+    /// named as in `named_parameters`, e.g. `layer0` in an [`MLP`].
+    ///
+    /// From PyTorch, save each entry of `model.state_dict()` with `np.save`
+    /// and use [`WeightLayout::OutIn`]; an `.npz` archive unzips into the
+    /// same files. Every array is checked before any weight is changed.
+    pub fn import_npy(
+        module: &dyn Module,
+        dir: impl AsRef<Path>,
+        layout: WeightLayout,
+    ) -> Result<(), LoadError> {
+        let dir = dir.as_ref();
+        let mut updates = vec![];
+        for layer in dense_layers(module)? {
+            let (nin, nout) = (layer.nin, layer.nout);
+            let weight_name = array_name(&layer.name, "weight");
+            let bias_name = array_name(&layer.name, "bias");
+            let weight =
+                read_array(dir, &weight_name, &layout.shape(nin, nout))?;
+            let bias = if layer.bias {
+                Some(read_array(dir, &bias_name, &[nout])?)
+            } else if dir.join(format!("{}.npy", bias_name)).exists() {
+                return Err(LoadError::UnexpectedParameter(bias_name));
+            } else {
+                None
+            };
+            // parameters() runs neuron by neuron, each weight then its bias
+            let mut data = vec![];
+            for o in 0..nout {
+                data.extend(
+                    (0..nin).map(|i| weight[layout.index(o, i, nin, nout)]),
+                );
+                data.extend(bias.as_ref().map(|b| b[o]));
+            }
+            updates.push((layer.module, data));
+        }
+        for (layer, data) in updates {
+            layer
+                .set_parameters_flat(&data)
+                .expect("array shapes were checked against the layer");
+        }
+        Ok(())
+    }
+
+    /// Writes every [`Layer`] in `module` to `dir` as `.npy` files that
+    /// [`import_npy`] reads back, creating `dir` if needed.
+    pub fn export_npy(
+        module: &dyn Module,
+        dir: impl AsRef<Path>,
+        layout: WeightLayout,
+    ) -> io::Result<()> {
+        let dir = dir.as_ref();
+        let layers = dense_layers(module).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidInput, e.to_string())
+        })?;
+        std::fs::create_dir_all(dir)?;
+        for layer in layers {
+            let (nin, nout) = (layer.nin, layer.nout);
+            let params = layer.module.get_parameters_flat();
+            let stride = nin + layer.bias as usize;
+            let mut weight = vec![0.0; nin * nout];
+            for o in 0..nout {
+                for i in 0..nin {
+                    weight[layout.index(o, i, nin, nout)] =
+                        params[o * stride + i];
+                }
+            }
+            let name = array_name(&layer.name, "weight.npy");
+            write_npy(&dir.join(name), &layout.shape(nin, nout), &weight)?;
+            if layer.bias {
+                let bias: Vec<_> =
+                    (0..nout).map(|o| params[o * stride + nin]).collect();
+                let name = array_name(&layer.name, "bias.npy");
+                write_npy(&dir.join(name), &[nout], &bias)?;
+            }
+        }
+        Ok(())
+    }
+
+    impl MLP {
+        /// Loads weights exported from NumPy or PyTorch; see [`import_npy`].
+        pub fn import_npy(
+            &self,
+            dir: impl AsRef<Path>,
+            layout: WeightLayout,
+        ) -> Result<(), LoadError> {
+            import_npy(self, dir, layout)
+        }
+
+        /// Writes the weights as `.npy` files; see [`export_npy`].
+        pub fn export_npy(
+            &self,
+            dir: impl AsRef<Path>,
+            layout: WeightLayout,
+        ) -> io::Result<()> {
+            export_npy(self, dir, layout)
+        }
+    }
+
+    impl Sequential {
+        /// Loads weights exported from NumPy or PyTorch; see [`import_npy`].
+        pub fn import_npy(
+            &self,
+            dir: impl AsRef<Path>,
+            layout: WeightLayout,
+        ) -> Result<(), LoadError> {
+            import_npy(self, dir, layout)
+        }
+
+        /// Writes the weights as `.npy` files; see [`export_npy`].
+        pub fn export_npy(
+            &self,
+            dir: impl AsRef<Path>,
+            layout: WeightLayout,
+        ) -> io::Result<()> {
+            export_npy(self, dir, layout)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "fs")]
+    use crate::engine::Value;
+    #[cfg(feature = "fs")]
+    use std::path::PathBuf;
+
+    #[cfg(feature = "fs")]
+    fn temp_path(name: &str) -> PathBuf {
+        let name = format!("smolgrad-{}-{}", std::process::id(), name);
+        std::env::temp_dir().join(name)
+    }
+
+    #[cfg(feature = "fs")]
+    fn predict(model: &dyn Module, x: &[Scalar]) -> Vec<Scalar> {
+        let x: Vec<_> = x.iter().map(|&x| Value::new(x)).collect();
+        model.forward(&x).iter().map(Value::get_data).collect()
+    }
+
+    #[test]
+    fn test_architecture() {
+        let mut model = Sequential::default();
+        model.push(Layer::new(3, 2, Activation::Tanh).with_bias(false));
+        model.push(Residual::new(Box::new(Dropout::new(0.25))));
+        let expected = Architecture::Sequential {
+            modules: vec![
+                Architecture::Layer {
+                    nin: 3,
+                    nout: 2,
+                    activation: Activation::Tanh,
+                    bias: false,
+                },
+                Architecture::Residual {
+                    inner: Box::new(Architecture::Dropout { p: 0.25 }),
+                },
+            ],
+        };
+        assert_eq!(architecture(&model), Some(expected.clone()));
+        let boxed: Box<dyn Module> = Box::new(model);
+        assert_eq!(architecture(&boxed), Some(expected));
+
+        // anything without an architecture spoils its containers
+        let prelu = Residual::new(crate::nn::PReLU::new(0.25));
+        assert_eq!(architecture(&prelu), None);
+        let mut model = Sequential::default();
+        model.push(prelu);
+        assert_eq!(architecture(&model), None);
+    }
+
+    #[test]
+    #[cfg(all(feature = "serde", feature = "fs"))]
+    fn test_mlp_json_round_trip() {
+        let model = MLP::new_seeded(2, &[4, 1], Activation::Tanh, 0);
+        let data: [([Scalar; 2], Scalar); 2] =
+            [([0.5, 1.0], 1.0), ([-1.0, 0.5], -1.0)];
+        for _ in 0..5 {
+            model.zero_grad();
+            let mut loss = Value::new(0.0);
+            for (x, y) in &data {
+                let x: Vec<_> = x.iter().map(|&x| Value::new(x)).collect();
+                let err = model.call(&x)[0].clone() + -y;
+                loss = loss + &err * &err;
+            }
+            loss.backward();
+            for p in model.parameters() {
+                p.add_data(-0.1 * p.get_grad());
+            }
+        }
+
+        let path = temp_path("mlp.json");
+        model.save_json(&path).unwrap();
+        let loaded = MLP::load_json(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.sizes(), model.sizes());
+        assert_eq!(architecture(&loaded), architecture(&model));
+        assert_eq!(loaded.get_parameters_flat(), model.get_parameters_flat());
+        let held_out = [0.3, -0.7];
+        assert_eq!(predict(&loaded, &held_out), predict(&model, &held_out));
+    }
+
+    #[test]
+    #[cfg(all(feature = "serde", feature = "fs"))]
+    fn test_sequential_json_round_trip() {
+        let mut model = Sequential::default();
+        model.push(
+            Layer::new(3, 4, Activation::LeakyReLU(0.1)).with_bias(false),
+        );
+        model.push(Dropout::new(0.5));
+        model.push(Residual::new(Layer::new(4, 4, Activation::Sigmoid)));
+        model.push(Conv1d::new(2, 1, 2, 1, 0));
+        model.eval();
+
+        let path = temp_path("sequential.json");
+        model.save_json(&path).unwrap();
+        let mut loaded = Sequential::load_json(&path).unwrap();
+        let boxed = load_json(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        loaded.eval();
+
+        assert_eq!(architecture(&loaded), architecture(&model));
+        assert_eq!(architecture(&*boxed), architecture(&model));
+        assert_eq!(loaded.num_parameters(), 3 * 4 + 4 * 5 + 2 * 2 + 1);
+        let x = [0.1, -0.2, 0.3];
+        assert_eq!(predict(&loaded, &x), predict(&model, &x));
+    }
+
+    #[test]
+    #[cfg(all(feature = "serde", feature = "fs"))]
+    fn test_json_with_optimizer() {
+        use crate::optim::{Adam, Optimizer};
+
+        fn train(model: &dyn Module, opt: &mut dyn Optimizer) {
+            for _ in 0..10 {
+                opt.zero_grad();
+                let x = [Value::new(0.5), Value::new(-1.0)];
+                (model.forward(&x)[0].clone() + -1.0).pow(2.0).backward();
+                opt.step();
+            }
+        }
+
+        let model = MLP::new_seeded(2, &[3, 1], Activation::Tanh, 1);
+        let mut opt = Adam::new(model.parameters(), 0.05);
+        train(&model, &mut opt);
+        let path = temp_path("with-optimizer.json");
+        save_json_with_optimizer(&model, &opt, &path).unwrap();
+        let (loaded, state) = load_json_with_optimizer(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let state = state.unwrap();
+        assert_eq!(state, opt.state());
+
+        let mut resumed = Adam::new(loaded.parameters(), 0.05);
+        resumed.load_state(state).unwrap();
+        train(&model, &mut opt);
+        train(&loaded, &mut resumed);
+        assert_eq!(loaded.get_parameters_flat(), model.get_parameters_flat());
+
+        let path = temp_path("without-optimizer.json");
+        model.save_json(&path).unwrap();
+        let (_, state) = load_json_with_optimizer(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(state, None);
+    }
+
+    #[test]
+    #[cfg(all(feature = "serde", feature = "fs"))]
+    fn test_load_json_errors() {
+        let path = temp_path("errors.json");
+        let model = MLP::new(2, &[2, 1], Activation::ReLU);
+        model.save_json(&path).unwrap();
+
+        let err = Sequential::load_json(&path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "bad architecture: expected Sequential, found MLP"
+        );
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let mut json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let params = json["parameters"].as_object_mut().unwrap();
+        params.remove("layer1.neuron0.b");
+        params.insert("layer2.neuron0.b".into(), 0.0.into());
+        std::fs::write(&path, json.to_string()).unwrap();
+        let err = MLP::load_json(&path).unwrap_err();
+        assert!(matches!(
+            err,
+            LoadError::MissingParameter(ref name) if name == "layer1.neuron0.b"
+        ));
+
+        let params = json["parameters"].as_object_mut().unwrap();
+        params.insert("layer1.neuron0.b".into(), 0.0.into());
+        std::fs::write(&path, json.to_string()).unwrap();
+        let err = MLP::load_json(&path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "checkpoint has a value for `layer2.neuron0.b`, which the \
+             architecture doesn't have"
+        );
+
+        json["architecture"]["sizes"] = serde_json::json!([2, 2, 1, 1]);
+        std::fs::write(&path, json.to_string()).unwrap();
+        let err = MLP::load_json(&path).unwrap_err();
+        assert!(matches!(err, LoadError::Architecture(_)));
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(matches!(
+            MLP::load_json(&path).unwrap_err(),
+            LoadError::Format(_)
+        ));
+
+        let custom = MLP::new(2, &[2, 1], Activation::custom(|x| x.tanh()));
+        assert!(custom.save_json(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            MLP::load_json(&path).unwrap_err(),
+            LoadError::Io(_)
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    #[allow(clippy::unnecessary_cast)]
+    fn test_bin_round_trip() {
+        let model = MLP::new(3, &[4, 2], Activation::ReLU);
+        let path = temp_path("round-trip.bin");
+        model.save_bin(&path).unwrap();
+        let loaded = MLP::new(3, &[4, 2], Activation::ReLU);
+        loaded.load_bin(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let expected = model.get_parameters_flat();
+        let expected = expected.iter().map(|&x| x as f32 as Scalar);
+        assert!(loaded.get_parameters_flat().into_iter().eq(expected));
+        let x = [0.2, -0.4, 0.6];
+        let (a, b) = (predict(&loaded, &x), predict(&model, &x));
+        for (a, b) in a.iter().zip(&b) {
+            assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "serde", feature = "fs"))]
+    fn test_bin_smaller_than_json() {
+        let model = MLP::new(100, &[90, 10], Activation::ReLU);
+        assert_eq!(model.num_parameters(), 10_000);
+        let (bin, json) = (temp_path("size.bin"), temp_path("size.json"));
+        model.save_bin(&bin).unwrap();
+        model.save_json(&json).unwrap();
+        let bin_len = std::fs::metadata(&bin).unwrap().len();
+        let json_len = std::fs::metadata(&json).unwrap().len();
+        std::fs::remove_file(&bin).unwrap();
+        std::fs::remove_file(&json).unwrap();
+        assert_eq!(bin_len, (HEADER_LEN + 4 * 10_000 + 8) as u64);
+        assert!(bin_len * 5 < json_len, "{} vs {} bytes", bin_len, json_len);
+    }
+
+    #[test]
+    fn test_bin_rejects_bad_files() {
+        let model = MLP::new(2, &[3, 1], Activation::Tanh);
+        let before = model.get_parameters_flat();
+        let bytes = encode_bin(&model);
+        let message =
+            |bytes: &[u8]| decode_bin(&model, bytes).unwrap_err().to_string();
+
+        let mut corrupted = bytes.clone();
+        corrupted[HEADER_LEN + 5] ^= 0x10;
+        assert_eq!(
+            message(&corrupted),
+            "not a checkpoint: checksum mismatch, the file is corrupted"
+        );
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert_eq!(
+            message(&wrong_magic),
+            "not a checkpoint: missing the SMOLGRAD magic bytes"
+        );
+        let mut wrong_version = bytes.clone();
+        wrong_version[8] = 7;
+        assert_eq!(
+            message(&wrong_version),
+            "not a checkpoint: unsupported version 7, expected 1"
+        );
+        assert_eq!(
+            message(&bytes[..12]),
+            "not a checkpoint: file is truncated"
+        );
+        assert_eq!(model.get_parameters_flat(), before);
+
+        let other = MLP::new(2, &[4, 1], Activation::Tanh);
+        assert!(matches!(
+            decode_bin(&other, &bytes),
+            Err(LoadError::Architecture(_))
+        ));
+        // same count, different names
+        let mut renamed = Sequential::default();
+        renamed.push(Layer::new(2, 3, Activation::Tanh));
+        renamed.push(Layer::new(3, 1, Activation::Linear));
+        assert_eq!(
+            decode_bin(&renamed, &bytes).unwrap_err().to_string(),
+            "bad architecture: checkpoint parameter names don't match the \
+             model's"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    #[allow(clippy::unnecessary_cast)]
+    fn test_npy_round_trip() {
+        for &layout in &[WeightLayout::OutIn, WeightLayout::InOut] {
+            let model = MLP::new(3, &[4, 2], Activation::Tanh);
+            let dir = temp_path(&format!("npy-{:?}", layout));
+            model.export_npy(&dir, layout).unwrap();
+            let header = std::fs::read(dir.join("layer0.weight.npy")).unwrap();
+            let len = u16::from_le_bytes([header[8], header[9]]) as usize;
+            assert_eq!((10 + len) % 64, 0);
+            assert_eq!(header[10 + len - 1], b'\n');
+
+            let loaded = MLP::new(3, &[4, 2], Activation::Tanh);
+            loaded.import_npy(&dir, layout).unwrap();
+            std::fs::remove_dir_all(&dir).unwrap();
+            let expected = model.get_parameters_flat();
+            let expected = expected.iter().map(|&x| x as f32 as Scalar);
+            assert!(loaded.get_parameters_flat().into_iter().eq(expected));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_npy_sequential_names() {
+        let mut model = Sequential::default();
+        model.push(Layer::new(2, 3, Activation::ReLU).with_bias(false));
+        model.push(Dropout::new(0.5));
+        model.push(MLP::new(3, &[1], Activation::ReLU));
+        let dir = temp_path("npy-sequential");
+        model.export_npy(&dir, WeightLayout::InOut).unwrap();
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|f| f.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            ["0.weight.npy", "2.layer0.bias.npy", "2.layer0.weight.npy"]
+        );
+        model.import_npy(&dir, WeightLayout::InOut).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let residual = Residual::new(Layer::new(2, 2, Activation::ReLU));
+        assert_eq!(
+            import_npy(&residual, "unused", WeightLayout::OutIn)
+                .unwrap_err()
+                .to_string(),
+            "bad architecture: `` is a Residual, only Layers have array \
+             weights"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_npy_errors() {
+        let dir = temp_path("npy-errors");
+        MLP::new(3, &[4, 2], Activation::ReLU)
+            .export_npy(&dir, WeightLayout::OutIn)
+            .unwrap();
+        let model = MLP::new(3, &[4, 2], Activation::ReLU);
+        let before = model.get_parameters_flat();
+
+        let err = model.import_npy(&dir, WeightLayout::InOut).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "bad architecture: `layer0.weight` has shape (4, 3), expected \
+             (3, 4)"
+        );
+        let wider = MLP::new(3, &[5, 2], Activation::ReLU);
+        let err = wider.import_npy(&dir, WeightLayout::OutIn).unwrap_err();
+        assert!(matches!(err, LoadError::Architecture(_)));
+
+        std::fs::remove_file(dir.join("layer1.bias.npy")).unwrap();
+        let err = model.import_npy(&dir, WeightLayout::OutIn).unwrap_err();
+        assert!(matches!(
+            err,
+            LoadError::MissingParameter(ref name) if name == "layer1.bias"
+        ));
+
+        std::fs::write(dir.join("layer1.bias.npy"), b"\x93NUMPY").unwrap();
+        let err = model.import_npy(&dir, WeightLayout::OutIn).unwrap_err();
+        assert!(matches!(err, LoadError::Format(_)));
+        assert_eq!(model.get_parameters_flat(), before);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_quantized_mlp_bad_bytes() {
+        let mlp = MLP::new_seeded(2, &[3, 1], Activation::ReLU, 0);
+        let bytes = mlp.compile_inference().quantize().serialize();
+        let error = |bytes: &[u8]| {
+            QuantizedMlp::deserialize(bytes).unwrap_err().to_string()
+        };
+        assert!(error(&bytes[1..]).contains("magic"));
+        let mut corrupted = bytes.clone();
+        corrupted[20] ^= 1;
+        assert!(error(&corrupted).contains("checksum"));
+        let mut truncated = bytes[..bytes.len() - 9].to_vec();
+        let checksum = fnv1a(truncated.iter().copied());
+        truncated.extend_from_slice(&checksum.to_le_bytes());
+        assert!(error(&truncated).contains("truncated"));
+    }
+
+    #[test]
+    fn test_quantized_mlp_bad_shapes() {
+        let layer = |nin: usize, nout: usize| QuantizedLayer {
+            nin,
+            weights: vec![1; nin * nout],
+            scale: 1.0,
+            bias: vec![0.0; nout],
+            activation: BuiltinActivation::Linear,
+        };
+        let error = |layers: Vec<QuantizedLayer>| {
+            let bytes = QuantizedMlp { layers }.serialize();
+            match QuantizedMlp::deserialize(&bytes) {
+                Err(LoadError::Format(e)) => e,
+                other => panic!("expected a format error, got {:?}", other),
+            }
+        };
+        let mismatched = error(vec![layer(2, 3), layer(5, 1)]);
+        assert!(mismatched.contains("takes 5 inputs"), "{}", mismatched);
+        assert!(error(vec![layer(0, 3)]).contains("no inputs"));
+        assert!(error(vec![layer(2, 3), layer(3, 0), layer(0, 1)])
+            .contains("no inputs"));
+    }
+}
@@ -0,0 +1,1972 @@
+//! Datasets of (features, targets) samples to train on.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Display};
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::engine::{Scalar, Value};
+use crate::random::standard_normal;
+
+/// A fixed-size collection of samples, each a pair of feature and target
+/// vectors.
+pub trait Dataset {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The features and targets of sample `i`.
+    ///
+    /// # Panics
+    ///
+    /// If `i` is out of range.
+    fn get(&self, i: usize) -> (Vec<Scalar>, Vec<Scalar>);
+
+    /// A view of the samples at `indices`, in that order.
+    ///
+    /// # Panics
+    ///
+    /// If any index is out of range.
+    fn subset(&self, indices: &[usize]) -> Subset<'_, Self>
+    where
+        Self: Sized,
+    {
+        Subset::new(self, indices.to_vec())
+    }
+}
+
+impl<D: Dataset + ?Sized> Dataset for &D {
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    fn get(&self, i: usize) -> (Vec<Scalar>, Vec<Scalar>) {
+        (**self).get(i)
+    }
+}
+
+impl<D: Dataset + ?Sized> Dataset for Box<D> {
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    fn get(&self, i: usize) -> (Vec<Scalar>, Vec<Scalar>) {
+        (**self).get(i)
+    }
+}
+
+fn check_index(i: usize, len: usize) {
+    assert!(i < len, "index {} out of range for {} samples", i, len);
+}
+
+/// Samples held in memory, all with the same number of features and of
+/// targets.
+#[derive(Clone, PartialEq)]
+pub struct InMemoryDataset {
+    xs: Vec<Vec<Scalar>>,
+    ys: Vec<Vec<Scalar>>,
+}
+
+impl InMemoryDataset {
+    pub fn new(
+        xs: Vec<Vec<Scalar>>,
+        ys: Vec<Vec<Scalar>>,
+    ) -> Result<Self, DatasetError> {
+        if xs.len() != ys.len() {
+            return Err(DatasetError::LengthMismatch {
+                features: xs.len(),
+                targets: ys.len(),
+            });
+        }
+        for (rows, what) in [(&xs, "features"), (&ys, "targets")] {
+            if let Some(first) = rows.first() {
+                let ragged = rows.iter().position(|r| r.len() != first.len());
+                if let Some(index) = ragged {
+                    return Err(DatasetError::Ragged {
+                        what,
+                        index,
+                        expected: first.len(),
+                        got: rows[index].len(),
+                    });
+                }
+            }
+        }
+        Ok(Self { xs, ys })
+    }
+
+    /// The number of features per sample; 0 for an empty dataset.
+    pub fn num_features(&self) -> usize {
+        self.xs.first().map_or(0, Vec::len)
+    }
+
+    /// The number of targets per sample; 0 for an empty dataset.
+    pub fn num_targets(&self) -> usize {
+        self.ys.first().map_or(0, Vec::len)
+    }
+}
+
+impl Dataset for InMemoryDataset {
+    fn len(&self) -> usize {
+        self.xs.len()
+    }
+
+    fn get(&self, i: usize) -> (Vec<Scalar>, Vec<Scalar>) {
+        check_index(i, self.len());
+        (self.xs[i].clone(), self.ys[i].clone())
+    }
+}
+
+impl Display for InMemoryDataset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "InMemoryDataset of {} samples ({} -> {})",
+            self.len(),
+            self.num_features(),
+            self.num_targets()
+        )
+    }
+}
+
+impl Debug for InMemoryDataset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Some of the samples of a parent dataset, which it borrows; see
+/// [`Dataset::subset`].
+pub struct Subset<'a, D: Dataset + ?Sized> {
+    parent: &'a D,
+    indices: Vec<usize>,
+}
+
+impl<'a, D: Dataset + ?Sized> Subset<'a, D> {
+    /// # Panics
+    ///
+    /// If any index is out of range for `parent`.
+    pub fn new(parent: &'a D, indices: Vec<usize>) -> Self {
+        for &i in &indices {
+            check_index(i, parent.len());
+        }
+        Self { parent, indices }
+    }
+
+    /// Which of the parent's samples the subset holds.
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+}
+
+impl<D: Dataset + ?Sized> Dataset for Subset<'_, D> {
+    fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn get(&self, i: usize) -> (Vec<Scalar>, Vec<Scalar>) {
+        check_index(i, self.len());
+        self.parent.get(self.indices[i])
+    }
+}
+
+impl<D: Dataset + ?Sized> Display for Subset<'_, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Subset of {} of {} samples",
+            self.len(),
+            self.parent.len()
+        )
+    }
+}
+
+impl<D: Dataset + ?Sized> Debug for Subset<'_, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Splits `indices` into a test set of `test_len` and the rest, each in
+/// ascending order.
+fn split_off(
+    mut indices: Vec<usize>,
+    test_len: usize,
+) -> (Vec<usize>, Vec<usize>) {
+    let mut train = indices.split_off(test_len);
+    indices.sort_unstable();
+    train.sort_unstable();
+    (train, indices)
+}
+
+fn check_test_fraction(test_fraction: Scalar) {
+    assert!(
+        test_fraction > 0.0 && test_fraction < 1.0,
+        "test fraction must be in (0, 1), got {}",
+        test_fraction
+    );
+}
+
+/// Randomly splits `dataset` into (train, test) views, with
+/// `test_fraction` of the samples, rounded, in the test set. The same
+/// seed gives the same split.
+///
+/// # Panics
+///
+/// Unless `test_fraction` is in (0, 1).
+pub fn train_test_split<D: Dataset + ?Sized>(
+    dataset: &D,
+    test_fraction: Scalar,
+    seed: u64,
+) -> (Subset<'_, D>, Subset<'_, D>) {
+    check_test_fraction(test_fraction);
+    let mut indices: Vec<_> = (0..dataset.len()).collect();
+    indices.shuffle(&mut StdRng::seed_from_u64(seed));
+    let test_len = (dataset.len() as Scalar * test_fraction).round() as usize;
+    let (train, test) = split_off(indices, test_len);
+    (Subset::new(dataset, train), Subset::new(dataset, test))
+}
+
+/// The class of a sample for stratifying: its single target, truncated,
+/// or the index of the largest of several one-hot targets.
+fn class_of(targets: &[Scalar]) -> i64 {
+    match targets {
+        [class] => *class as i64,
+        _ => from_one_hot(targets) as i64,
+    }
+}
+
+/// [`train_test_split`] that splits each class on its own, so both sides
+/// keep the class balance of `dataset` to within a sample per class. A
+/// single target is the class itself, truncated; several are read as
+/// one-hot, with the largest giving the class.
+///
+/// # Panics
+///
+/// Unless `test_fraction` is in (0, 1), or if a sample has no targets.
+pub fn stratified_train_test_split<D: Dataset + ?Sized>(
+    dataset: &D,
+    test_fraction: Scalar,
+    seed: u64,
+) -> (Subset<'_, D>, Subset<'_, D>) {
+    check_test_fraction(test_fraction);
+    let mut classes: BTreeMap<i64, Vec<usize>> = BTreeMap::new();
+    for i in 0..dataset.len() {
+        classes
+            .entry(class_of(&dataset.get(i).1))
+            .or_default()
+            .push(i);
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (mut train, mut test) = (Vec::new(), Vec::new());
+    for mut indices in classes.into_values() {
+        indices.shuffle(&mut rng);
+        let test_len = (indices.len() as Scalar * test_fraction).round();
+        let (class_train, class_test) = split_off(indices, test_len as usize);
+        train.extend(class_train);
+        test.extend(class_test);
+    }
+    train.sort_unstable();
+    test.sort_unstable();
+    (Subset::new(dataset, train), Subset::new(dataset, test))
+}
+
+/// Splits `dataset` into `k` random folds of near-equal size and returns
+/// a (train, validation) pair of views per fold, validating on that fold
+/// and training on the rest. Every sample is validated on exactly once.
+/// The same seed gives the same folds.
+///
+/// # Panics
+///
+/// Unless `k` is at least 2 and at most the number of samples.
+pub fn k_fold<D: Dataset + ?Sized>(
+    dataset: &D,
+    k: usize,
+    seed: u64,
+) -> Vec<(Subset<'_, D>, Subset<'_, D>)> {
+    let n = dataset.len();
+    assert!(
+        k >= 2 && k <= n,
+        "k-fold needs 2 <= k <= {} samples, got k = {}",
+        n,
+        k
+    );
+    let mut indices: Vec<_> = (0..n).collect();
+    indices.shuffle(&mut StdRng::seed_from_u64(seed));
+    let mut start = 0;
+    (0..k)
+        .map(|fold| {
+            // Bring this fold to the front to split it off
+            let len = class_size(n, k, fold);
+            let mut rotated = indices.clone();
+            rotated.rotate_left(start);
+            start += len;
+            let (train, val) = split_off(rotated, len);
+            (Subset::new(dataset, train), Subset::new(dataset, val))
+        })
+        .collect()
+}
+
+/// Samples from a dataset gathered into one mini-batch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch {
+    pub xs: Vec<Vec<Scalar>>,
+    pub ys: Vec<Vec<Scalar>>,
+}
+
+impl Batch {
+    pub fn len(&self) -> usize {
+        self.xs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.xs.is_empty()
+    }
+
+    /// The features as fresh leaves, ready to feed to a model.
+    pub fn inputs(&self) -> Vec<Vec<Value>> {
+        let leaves =
+            |x: &Vec<Scalar>| x.iter().map(|&x| Value::new(x)).collect();
+        self.xs.iter().map(leaves).collect()
+    }
+}
+
+/// Walks a dataset in mini-batches, one epoch per [`iter`](DataLoader::iter).
+///
+/// ```
+/// # use smolgrad::data::{DataLoader, InMemoryDataset};
+/// # use smolgrad::engine::Scalar;
+/// let xs = (0..10).map(|i| vec![i as Scalar]).collect();
+/// let ys = (0..10).map(|i| vec![i as Scalar * 2.0]).collect();
+/// let data = InMemoryDataset::new(xs, ys).unwrap();
+/// let mut loader = DataLoader::new(&data, 4).shuffle(true).seed(42);
+/// for _epoch in 0..3 {
+///     for batch in &mut loader {
+///         assert!(batch.len() <= 4);
+///     }
+/// }
+/// ```
+pub struct DataLoader<D: Dataset> {
+    dataset: D,
+    batch_size: usize,
+    shuffle: bool,
+    drop_last: bool,
+    rng: StdRng,
+}
+
+impl<D: Dataset> DataLoader<D> {
+    /// Batches in order, keeping the last partial batch, until told
+    /// otherwise.
+    ///
+    /// # Panics
+    ///
+    /// If `batch_size` is 0.
+    pub fn new(dataset: D, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch size must be positive");
+        Self {
+            dataset,
+            batch_size,
+            shuffle: false,
+            drop_last: false,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Visits the samples in a new random order every epoch.
+    pub fn shuffle(self, shuffle: bool) -> Self {
+        Self { shuffle, ..self }
+    }
+
+    /// Makes the shuffled orders reproducible: the same seed gives the same
+    /// sequence of epochs.
+    pub fn seed(self, seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            ..self
+        }
+    }
+
+    /// Skips the last batch of an epoch if it's smaller than the batch
+    /// size.
+    pub fn drop_last(self, drop_last: bool) -> Self {
+        Self { drop_last, ..self }
+    }
+
+    pub fn dataset(&self) -> &D {
+        &self.dataset
+    }
+
+    /// How many batches an epoch yields.
+    pub fn num_batches(&self) -> usize {
+        let n = self.dataset.len();
+        if self.drop_last {
+            n / self.batch_size
+        } else {
+            n.div_ceil(self.batch_size)
+        }
+    }
+
+    /// Starts a new epoch, drawing a new order if shuffling.
+    pub fn iter(&mut self) -> Batches<'_, D> {
+        let mut order: Vec<usize> = (0..self.dataset.len()).collect();
+        if self.shuffle {
+            order.shuffle(&mut self.rng);
+        }
+        order.truncate(self.num_batches() * self.batch_size);
+        Batches {
+            loader: self,
+            order,
+            next: 0,
+        }
+    }
+}
+
+impl<'a, D: Dataset> IntoIterator for &'a mut DataLoader<D> {
+    type Item = Batch;
+    type IntoIter = Batches<'a, D>;
+
+    fn into_iter(self) -> Batches<'a, D> {
+        self.iter()
+    }
+}
+
+/// One epoch of a [`DataLoader`].
+pub struct Batches<'a, D: Dataset> {
+    loader: &'a DataLoader<D>,
+    order: Vec<usize>,
+    next: usize,
+}
+
+impl<D: Dataset> Iterator for Batches<'_, D> {
+    type Item = Batch;
+
+    fn next(&mut self) -> Option<Batch> {
+        if self.next >= self.order.len() {
+            return None;
+        }
+        let end = (self.next + self.loader.batch_size).min(self.order.len());
+        let (xs, ys) = self.order[self.next..end]
+            .iter()
+            .map(|&i| self.loader.dataset.get(i))
+            .unzip();
+        self.next = end;
+        Some(Batch { xs, ys })
+    }
+}
+
+/// Rescales features with statistics fit on a dataset, so the same
+/// scaling can be applied to new samples at inference.
+pub trait Scaler {
+    /// Learns the per-feature statistics of `dataset`, replacing any from
+    /// before.
+    ///
+    /// # Panics
+    ///
+    /// If `dataset` is empty.
+    fn fit(&mut self, dataset: &dyn Dataset);
+
+    /// # Panics
+    ///
+    /// If `features` isn't as long as the samples the scaler was fit on.
+    fn transform(&self, features: &[Scalar]) -> Vec<Scalar>;
+
+    /// Undoes [`Scaler::transform`].
+    ///
+    /// # Panics
+    ///
+    /// If `features` isn't as long as the samples the scaler was fit on.
+    fn inverse_transform(&self, features: &[Scalar]) -> Vec<Scalar>;
+
+    /// A copy of `dataset` with its features transformed and its targets
+    /// left alone.
+    fn transform_dataset(&self, dataset: &dyn Dataset) -> InMemoryDataset {
+        let (xs, ys) = (0..dataset.len())
+            .map(|i| {
+                let (x, y) = dataset.get(i);
+                (self.transform(&x), y)
+            })
+            .unzip();
+        InMemoryDataset { xs, ys }
+    }
+
+    /// [`Scaler::fit`] then [`Scaler::transform_dataset`].
+    fn fit_transform(&mut self, dataset: &dyn Dataset) -> InMemoryDataset {
+        self.fit(dataset);
+        self.transform_dataset(dataset)
+    }
+}
+
+/// The features of every sample, as rows.
+fn feature_rows(dataset: &dyn Dataset) -> Vec<Vec<Scalar>> {
+    assert!(!dataset.is_empty(), "can't fit a scaler to no samples");
+    (0..dataset.len()).map(|i| dataset.get(i).0).collect()
+}
+
+fn check_fitted(features: usize, fitted: usize) {
+    assert_eq!(
+        features, fitted,
+        "{} features for a scaler fit on {}",
+        features, fitted
+    );
+}
+
+/// Scales each feature to zero mean and unit (population) standard
+/// deviation. A feature that never varies is only centered, to all
+/// zeros, rather than divided by zero.
+#[derive(Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StandardScaler {
+    mean: Vec<Scalar>,
+    std: Vec<Scalar>,
+}
+
+impl StandardScaler {
+    /// An unfitted scaler; see [`Scaler::fit`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mean(&self) -> &[Scalar] {
+        &self.mean
+    }
+
+    /// The standard deviation of each feature, or 1 for one that never
+    /// varies.
+    pub fn std(&self) -> &[Scalar] {
+        &self.std
+    }
+}
+
+impl Scaler for StandardScaler {
+    fn fit(&mut self, dataset: &dyn Dataset) {
+        let rows = feature_rows(dataset);
+        let n = rows.len() as Scalar;
+        let (mut mean, mut std) = (Vec::new(), Vec::new());
+        for j in 0..rows[0].len() {
+            let m = rows.iter().map(|r| r[j]).sum::<Scalar>() / n;
+            let var =
+                rows.iter().map(|r| (r[j] - m).powi(2)).sum::<Scalar>() / n;
+            mean.push(m);
+            std.push(if var > 0.0 { var.sqrt() } else { 1.0 });
+        }
+        *self = Self { mean, std };
+    }
+
+    fn transform(&self, features: &[Scalar]) -> Vec<Scalar> {
+        check_fitted(features.len(), self.mean.len());
+        let stats = self.mean.iter().zip(&self.std);
+        features
+            .iter()
+            .zip(stats)
+            .map(|(x, (m, s))| (x - m) / s)
+            .collect()
+    }
+
+    fn inverse_transform(&self, features: &[Scalar]) -> Vec<Scalar> {
+        check_fitted(features.len(), self.mean.len());
+        let stats = self.mean.iter().zip(&self.std);
+        features
+            .iter()
+            .zip(stats)
+            .map(|(x, (m, s))| x * s + m)
+            .collect()
+    }
+}
+
+impl Display for StandardScaler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "StandardScaler({} features)", self.mean.len())
+    }
+}
+
+impl Debug for StandardScaler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Scales each feature linearly so the range seen in fitting maps onto
+/// [0, 1]. A feature that never varies is only shifted, to all zeros,
+/// rather than divided by zero. New samples outside the range fall
+/// outside [0, 1].
+#[derive(Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MinMaxScaler {
+    min: Vec<Scalar>,
+    range: Vec<Scalar>,
+}
+
+impl MinMaxScaler {
+    /// An unfitted scaler; see [`Scaler::fit`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn min(&self) -> &[Scalar] {
+        &self.min
+    }
+
+    /// `max - min` of each feature, or 1 for one that never varies.
+    pub fn range(&self) -> &[Scalar] {
+        &self.range
+    }
+}
+
+impl Scaler for MinMaxScaler {
+    fn fit(&mut self, dataset: &dyn Dataset) {
+        let rows = feature_rows(dataset);
+        let (mut min, mut range) = (Vec::new(), Vec::new());
+        for j in 0..rows[0].len() {
+            let column = rows.iter().map(|r| r[j]);
+            let lo = column.clone().fold(Scalar::INFINITY, Scalar::min);
+            let hi = column.fold(Scalar::NEG_INFINITY, Scalar::max);
+            min.push(lo);
+            range.push(if hi > lo { hi - lo } else { 1.0 });
+        }
+        *self = Self { min, range };
+    }
+
+    fn transform(&self, features: &[Scalar]) -> Vec<Scalar> {
+        check_fitted(features.len(), self.min.len());
+        let stats = self.min.iter().zip(&self.range);
+        features
+            .iter()
+            .zip(stats)
+            .map(|(x, (m, r))| (x - m) / r)
+            .collect()
+    }
+
+    fn inverse_transform(&self, features: &[Scalar]) -> Vec<Scalar> {
+        check_fitted(features.len(), self.min.len());
+        let stats = self.min.iter().zip(&self.range);
+        features
+            .iter()
+            .zip(stats)
+            .map(|(x, (m, r))| x * r + m)
+            .collect()
+    }
+}
+
+impl Display for MinMaxScaler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MinMaxScaler({} features)", self.min.len())
+    }
+}
+
+impl Debug for MinMaxScaler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Expands features into every monomial of them up to a degree, so that a
+/// linear model on the expanded features can fit polynomial boundaries.
+///
+/// The output is ordered by degree, and within a degree by the indices of
+/// the features multiplied, lexicographically: degree 2 on `[a, b]` gives
+/// `[1, a, b, a², ab, b²]`, and on `[a, b, c]` the degree 2 part is
+/// `[a², ab, ac, b², bc, c²]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PolynomialFeatures {
+    degree: usize,
+    include_bias: bool,
+    interaction_only: bool,
+}
+
+impl PolynomialFeatures {
+    /// Monomials of total degree up to `degree`. With `include_bias` the
+    /// output starts with the constant 1, and with `interaction_only` only
+    /// products of distinct features are kept, dropping `a²` but not `ab`.
+    pub fn new(
+        degree: usize,
+        include_bias: bool,
+        interaction_only: bool,
+    ) -> Self {
+        Self {
+            degree,
+            include_bias,
+            interaction_only,
+        }
+    }
+
+    /// The features multiplied in each output, in output order, for
+    /// samples of `n_features` features.
+    fn terms(&self, n_features: usize) -> Vec<Vec<usize>> {
+        let mut terms = vec![];
+        if self.include_bias {
+            terms.push(vec![]);
+        }
+        // Each degree extends the terms of the one before by a feature no
+        // earlier than (or with interaction_only, after) their last one
+        let mut previous: Vec<Vec<usize>> = vec![vec![]];
+        for _ in 0..self.degree {
+            let mut current = vec![];
+            for term in &previous {
+                let first = match term.last() {
+                    Some(&last) if self.interaction_only => last + 1,
+                    Some(&last) => last,
+                    None => 0,
+                };
+                for j in first..n_features {
+                    let mut next = term.clone();
+                    next.push(j);
+                    current.push(next);
+                }
+            }
+            terms.extend(current.iter().cloned());
+            previous = current;
+        }
+        terms
+    }
+
+    /// How many features samples of `n_features` features expand to.
+    pub fn num_output_features(&self, n_features: usize) -> usize {
+        self.terms(n_features).len()
+    }
+
+    /// A name for each output when the inputs are called `x0`, `x1`, ...:
+    /// `1` for the bias, then e.g. `x0`, `x0^2`, `x0 x1`.
+    pub fn feature_names(&self, n_features: usize) -> Vec<String> {
+        let name = |term: &Vec<usize>| {
+            if term.is_empty() {
+                return "1".to_string();
+            }
+            let mut factors: Vec<String> = vec![];
+            let mut i = 0;
+            while i < term.len() {
+                let power =
+                    term[i..].iter().take_while(|&&j| j == term[i]).count();
+                factors.push(match power {
+                    1 => format!("x{}", term[i]),
+                    _ => format!("x{}^{}", term[i], power),
+                });
+                i += power;
+            }
+            factors.join(" ")
+        };
+        self.terms(n_features).iter().map(name).collect()
+    }
+
+    pub fn transform(&self, x: &[Scalar]) -> Vec<Scalar> {
+        monomials(&self.terms(x.len()), x)
+    }
+
+    /// A copy of `dataset` with its features expanded and its targets
+    /// left alone.
+    pub fn transform_dataset(&self, dataset: &dyn Dataset) -> InMemoryDataset {
+        let mut terms = None;
+        let (xs, ys) = (0..dataset.len())
+            .map(|i| {
+                let (x, y) = dataset.get(i);
+                let terms = terms.get_or_insert_with(|| self.terms(x.len()));
+                (monomials(terms, &x), y)
+            })
+            .unzip();
+        InMemoryDataset { xs, ys }
+    }
+}
+
+fn monomials(terms: &[Vec<usize>], x: &[Scalar]) -> Vec<Scalar> {
+    terms
+        .iter()
+        .map(|t| t.iter().map(|&j| x[j]).product())
+        .collect()
+}
+
+fn check_class(index: usize, num_classes: usize) {
+    assert!(
+        index < num_classes,
+        "class {} out of range for {} classes",
+        index,
+        num_classes
+    );
+}
+
+/// A vector of `num_classes` zeros with a one at `index`.
+///
+/// # Panics
+///
+/// If `index` isn't less than `num_classes`.
+pub fn one_hot(index: usize, num_classes: usize) -> Vec<Scalar> {
+    check_class(index, num_classes);
+    let mut encoded = vec![0.0; num_classes];
+    encoded[index] = 1.0;
+    encoded
+}
+
+/// [`one_hot`] for each of `indices`.
+///
+/// # Panics
+///
+/// If any index isn't less than `num_classes`.
+pub fn one_hot_batch(
+    indices: &[usize],
+    num_classes: usize,
+) -> Vec<Vec<Scalar>> {
+    indices.iter().map(|&i| one_hot(i, num_classes)).collect()
+}
+
+/// [`one_hot`] as [constants](Value::constant), for targets that take
+/// part in the graph. Backward leaves their grads at zero; build soft
+/// labels that should learn from [`Value::new`] instead.
+///
+/// # Panics
+///
+/// If `index` isn't less than `num_classes`.
+pub fn one_hot_values(index: usize, num_classes: usize) -> Vec<Value> {
+    one_hot(index, num_classes)
+        .into_iter()
+        .map(Value::constant)
+        .collect()
+}
+
+/// The class a one-hot (or soft) target stands for: the index of its
+/// largest entry, the first on a tie.
+///
+/// # Panics
+///
+/// If `encoded` is empty.
+pub fn from_one_hot(encoded: &[Scalar]) -> usize {
+    assert!(!encoded.is_empty(), "no classes to decode");
+    (1..encoded.len()).fold(0, |best, i| {
+        if encoded[i] > encoded[best] {
+            i
+        } else {
+            best
+        }
+    })
+}
+
+/// Moves `eps` of the mass of a one-hot target evenly over all the
+/// classes, so the true class gets `1 - eps + eps / k` and the others
+/// `eps / k`. The entries still sum to one.
+///
+/// # Panics
+///
+/// Unless `eps` is in `[0, 1]`.
+pub fn label_smooth(one_hot: &mut [Scalar], eps: Scalar) {
+    assert!(
+        (0.0..=1.0).contains(&eps),
+        "smoothing must be in [0, 1], got {}",
+        eps
+    );
+    let spread = eps / one_hot.len() as Scalar;
+    for y in one_hot {
+        *y = *y * (1.0 - eps) + spread;
+    }
+}
+
+/// How [`compute_class_weights`] weights the classes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClassWeightScheme {
+    /// Inverse frequency, `n_samples / (n_classes * count)`, so every class
+    /// carries the same total weight. Classes with no samples get 0.
+    Balanced,
+    /// The given weight per class.
+    Custom(Vec<Scalar>),
+}
+
+/// Per-class loss weights for the class labels `targets`, for
+/// [`CrossEntropy::with_class_weights`](crate::losses::CrossEntropy::with_class_weights)
+/// and [`Bce::with_class_weights`](crate::losses::Bce::with_class_weights).
+/// The classes are `0..n_classes`, whether or not each has samples.
+///
+/// # Panics
+///
+/// If a target is `n_classes` or more.
+pub fn compute_class_weights(
+    targets: &[usize],
+    n_classes: usize,
+    scheme: ClassWeightScheme,
+) -> Result<Vec<Scalar>, ClassWeightError> {
+    if let Some(&c) = targets.iter().find(|&&c| c >= n_classes) {
+        panic!("class {} out of range for {} classes", c, n_classes);
+    }
+    match scheme {
+        ClassWeightScheme::Balanced => {
+            let mut counts = vec![0usize; n_classes];
+            for &c in targets {
+                counts[c] += 1;
+            }
+            let n = targets.len() as Scalar;
+            Ok(counts
+                .iter()
+                .map(|&count| match count {
+                    0 => 0.0,
+                    _ => n / (n_classes * count) as Scalar,
+                })
+                .collect())
+        }
+        ClassWeightScheme::Custom(weights) if weights.len() != n_classes => {
+            Err(ClassWeightError {
+                expected: n_classes,
+                got: weights.len(),
+            })
+        }
+        ClassWeightScheme::Custom(weights) => Ok(weights),
+    }
+}
+
+/// Custom class weights that don't match the classes of the targets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassWeightError {
+    pub expected: usize,
+    pub got: usize,
+}
+
+impl Display for ClassWeightError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} class weights for {} classes",
+            self.got, self.expected
+        )
+    }
+}
+
+impl std::error::Error for ClassWeightError {}
+
+/// A column of a CSV file, by position from 0 or by its name in the
+/// header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+impl From<usize> for Column {
+    fn from(index: usize) -> Self {
+        Column::Index(index)
+    }
+}
+
+impl From<&str> for Column {
+    fn from(name: &str) -> Self {
+        Column::Name(name.to_string())
+    }
+}
+
+impl Display for Column {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Column::Index(i) => write!(f, "{}", i),
+            Column::Name(name) => write!(f, "`{}`", name),
+        }
+    }
+}
+
+/// How [`from_csv`] reads a file. The default reads comma-separated
+/// columns under a header, all of them features.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvOptions {
+    /// Whether the first line names the columns rather than holding a
+    /// sample.
+    pub has_header: bool,
+    /// The targets of each sample, in this order. Every other column that
+    /// isn't skipped is a feature, in the order of the file.
+    pub target_columns: Vec<Column>,
+    pub delimiter: char,
+    pub skip_columns: Vec<Column>,
+    /// A target column of class names rather than numbers. Each name
+    /// becomes its index in the sorted names, which
+    /// [`from_csv_with_labels`] returns. If the column isn't one of
+    /// `target_columns` it is the last target.
+    pub label_column: Option<Column>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            has_header: true,
+            target_columns: Vec::new(),
+            delimiter: ',',
+            skip_columns: Vec::new(),
+            label_column: None,
+        }
+    }
+}
+
+/// Splits a line into trimmed cells. A cell in double quotes may hold the
+/// delimiter, and `""` within it stands for a quote.
+fn split_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => {
+                cells.push(cell.trim().to_string());
+                cell.clear();
+            }
+            c => cell.push(c),
+        }
+    }
+    cells.push(cell.trim().to_string());
+    cells
+}
+
+/// Reads numeric samples from the CSV file at `path`; see [`parse_csv`].
+#[cfg(feature = "fs")]
+pub fn from_csv(
+    path: impl AsRef<Path>,
+    options: &CsvOptions,
+) -> Result<InMemoryDataset, CsvError> {
+    parse_csv(&std::fs::read_to_string(path)?, options)
+}
+
+/// [`from_csv`], also returning the class names of the
+/// [label column](CsvOptions::label_column); see [`parse_csv_with_labels`].
+#[cfg(feature = "fs")]
+pub fn from_csv_with_labels(
+    path: impl AsRef<Path>,
+    options: &CsvOptions,
+) -> Result<(InMemoryDataset, Vec<String>), CsvError> {
+    parse_csv_with_labels(&std::fs::read_to_string(path)?, options)
+}
+
+/// Reads numeric samples from CSV `text`; see [`CsvOptions`]. Blank lines
+/// are skipped.
+pub fn parse_csv(
+    text: &str,
+    options: &CsvOptions,
+) -> Result<InMemoryDataset, CsvError> {
+    parse_csv_with_labels(text, options).map(|(dataset, _)| dataset)
+}
+
+/// [`parse_csv`], also returning the class names of the
+/// [label column](CsvOptions::label_column) in the order of their indices,
+/// or nothing if there is none.
+pub fn parse_csv_with_labels(
+    text: &str,
+    options: &CsvOptions,
+) -> Result<(InMemoryDataset, Vec<String>), CsvError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| (i + 1, split_line(line, options.delimiter)));
+    let header = if options.has_header {
+        lines.next().map(|(_, cells)| cells)
+    } else {
+        None
+    };
+    let rows: Vec<_> = lines.collect();
+
+    let width = header
+        .as_ref()
+        .or_else(|| rows.first().map(|(_, cells)| cells))
+        .map_or(0, Vec::len);
+    if let Some((line, cells)) = rows.iter().find(|(_, c)| c.len() != width) {
+        return Err(CsvError::RowLength {
+            line: *line,
+            expected: width,
+            got: cells.len(),
+        });
+    }
+
+    let resolve = |column: &Column| {
+        let index = match column {
+            &Column::Index(i) => Some(i).filter(|&i| i < width),
+            Column::Name(name) => header
+                .as_ref()
+                .and_then(|h| h.iter().position(|h| h == name)),
+        };
+        index.ok_or_else(|| CsvError::UnknownColumn(column.clone()))
+    };
+    let resolve_all = |columns: &[Column]| {
+        columns.iter().map(resolve).collect::<Result<Vec<_>, _>>()
+    };
+    let mut targets = resolve_all(&options.target_columns)?;
+    let skipped = resolve_all(&options.skip_columns)?;
+    let label = options.label_column.as_ref().map(resolve).transpose()?;
+    if let Some(label) = label.filter(|l| !targets.contains(l)) {
+        targets.push(label);
+    }
+    let features: Vec<_> = (0..width)
+        .filter(|c| !targets.contains(c) && !skipped.contains(c))
+        .collect();
+
+    let labels: Vec<String> = match label {
+        Some(label) => rows
+            .iter()
+            .map(|(_, cells)| cells[label].clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect(),
+        None => Vec::new(),
+    };
+    let parse = |line: usize, cells: &[String], c: usize| {
+        if Some(c) == label {
+            let class = labels.binary_search(&cells[c]).unwrap();
+            return Ok(class as Scalar);
+        }
+        cells[c].parse().map_err(|_| CsvError::Parse {
+            line,
+            column: header
+                .as_ref()
+                .map_or(Column::Index(c), |h| Column::Name(h[c].clone())),
+            cell: cells[c].clone(),
+        })
+    };
+    let parse_all = |line, cells: &[String], columns: &[usize]| {
+        columns
+            .iter()
+            .map(|&c| parse(line, cells, c))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let mut xs = Vec::with_capacity(rows.len());
+    let mut ys = Vec::with_capacity(rows.len());
+    for (line, cells) in &rows {
+        xs.push(parse_all(*line, cells, &features)?);
+        ys.push(parse_all(*line, cells, &targets)?);
+    }
+    Ok((InMemoryDataset { xs, ys }, labels))
+}
+
+/// Why [`from_csv`] couldn't read a file. Lines count from 1, including
+/// the header and blank lines.
+#[derive(Debug)]
+pub enum CsvError {
+    Io(io::Error),
+    /// A line has a different number of cells than the header, or than
+    /// the first line without one.
+    RowLength {
+        line: usize,
+        expected: usize,
+        got: usize,
+    },
+    /// A cell of a numeric column isn't a number.
+    Parse {
+        line: usize,
+        column: Column,
+        cell: String,
+    },
+    /// The options name a column the file doesn't have.
+    UnknownColumn(Column),
+}
+
+impl Display for CsvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CsvError::Io(e) => write!(f, "couldn't read CSV: {}", e),
+            CsvError::RowLength {
+                line,
+                expected,
+                got,
+            } => write!(
+                f,
+                "line {} has {} cells, expected {}",
+                line, got, expected
+            ),
+            CsvError::Parse { line, column, cell } => write!(
+                f,
+                "line {}, column {}: {:?} is not a number",
+                line, column, cell
+            ),
+            CsvError::UnknownColumn(column) => {
+                write!(f, "no column {} in the file", column)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CsvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CsvError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CsvError {
+    fn from(e: io::Error) -> Self {
+        CsvError::Io(e)
+    }
+}
+
+/// How a generator such as [`make_moons`] labels the two classes of a
+/// binary problem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BinaryLabels {
+    /// -1 and 1, as [`Hinge`](crate::losses::Hinge) expects.
+    #[default]
+    Signed,
+    /// 0 and 1.
+    ZeroOne,
+}
+
+impl BinaryLabels {
+    fn label(self, positive: bool) -> Scalar {
+        match (self, positive) {
+            (_, true) => 1.0,
+            (BinaryLabels::Signed, false) => -1.0,
+            (BinaryLabels::ZeroOne, false) => 0.0,
+        }
+    }
+}
+
+/// Two interleaving half circles in the plane, as in scikit-learn: the
+/// upper one of radius 1 about the origin labelled -1, and the lower one
+/// about (1, 0.5) labelled 1, each point moved by Gaussian noise of std
+/// `noise`. The first `n_samples / 2` samples are on the upper moon. The
+/// same seed gives the same samples.
+pub fn make_moons(
+    n_samples: usize,
+    noise: Scalar,
+    seed: u64,
+) -> InMemoryDataset {
+    make_moons_with_labels(n_samples, noise, seed, BinaryLabels::Signed)
+}
+
+/// [`make_moons`] with the classes labelled by `labels`.
+pub fn make_moons_with_labels(
+    n_samples: usize,
+    noise: Scalar,
+    seed: u64,
+    labels: BinaryLabels,
+) -> InMemoryDataset {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let n_upper = n_samples / 2;
+    let n_lower = n_samples - n_upper;
+    let pi = std::f64::consts::PI as Scalar;
+    // Evenly spaced angles from 0 to pi inclusive
+    let angle = |i: usize, n: usize| {
+        if n > 1 {
+            pi * i as Scalar / (n - 1) as Scalar
+        } else {
+            0.0
+        }
+    };
+    let upper = (0..n_upper).map(|i| {
+        let t = angle(i, n_upper);
+        ([t.cos(), t.sin()], false)
+    });
+    let lower = (0..n_lower).map(|i| {
+        let t = angle(i, n_lower);
+        ([1.0 - t.cos(), 0.5 - t.sin()], true)
+    });
+    let (xs, ys) = upper
+        .chain(lower)
+        .map(|(point, positive)| {
+            let x = point
+                .iter()
+                .map(|&c| c + noise * standard_normal(&mut rng))
+                .collect();
+            (x, vec![labels.label(positive)])
+        })
+        .unzip();
+    InMemoryDataset { xs, ys }
+}
+
+/// How many of `n_samples` go to class `c` of `n_classes`, with the
+/// remainder spread over the first classes.
+fn class_size(n_samples: usize, n_classes: usize, c: usize) -> usize {
+    n_samples / n_classes + usize::from(c < n_samples % n_classes)
+}
+
+/// Gaussian blobs in the plane, one per center, with std `std` along each
+/// axis. A sample's target is the index of its center, and the samples
+/// are split between the centers as evenly as possible, in order. The
+/// same seed gives the same samples.
+///
+/// # Panics
+///
+/// If there are samples but no centers.
+pub fn make_blobs(
+    n_samples: usize,
+    centers: &[(Scalar, Scalar)],
+    std: Scalar,
+    seed: u64,
+) -> InMemoryDataset {
+    assert!(
+        n_samples == 0 || !centers.is_empty(),
+        "no centers for {} samples",
+        n_samples
+    );
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (mut xs, mut ys) = (Vec::new(), Vec::new());
+    for (c, &(cx, cy)) in centers.iter().enumerate() {
+        for _ in 0..class_size(n_samples, centers.len(), c) {
+            xs.push(vec![
+                cx + std * standard_normal(&mut rng),
+                cy + std * standard_normal(&mut rng),
+            ]);
+            ys.push(vec![c as Scalar]);
+        }
+    }
+    InMemoryDataset { xs, ys }
+}
+
+/// `n_classes` arms spiralling out from the origin, each through four
+/// radians, as in the CS231n notes. A sample's target is the index of its
+/// arm, and the samples are split between the arms as evenly as possible,
+/// in order. `noise` is the std of the Gaussian noise added to each
+/// point's angle. The same seed gives the same samples.
+///
+/// # Panics
+///
+/// If there are samples but no classes.
+pub fn make_spiral(
+    n_samples: usize,
+    n_classes: usize,
+    noise: Scalar,
+    seed: u64,
+) -> InMemoryDataset {
+    assert!(
+        n_samples == 0 || n_classes > 0,
+        "no classes for {} samples",
+        n_samples
+    );
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (mut xs, mut ys) = (Vec::new(), Vec::new());
+    for c in 0..n_classes {
+        let n = class_size(n_samples, n_classes, c);
+        for i in 0..n {
+            // Radius from 0 to 1 inclusive, and the angle along with it
+            let r = if n > 1 {
+                i as Scalar / (n - 1) as Scalar
+            } else {
+                0.0
+            };
+            let t = 4.0 * (c as Scalar + r) + noise * standard_normal(&mut rng);
+            xs.push(vec![r * t.sin(), r * t.cos()]);
+            ys.push(vec![c as Scalar]);
+        }
+    }
+    InMemoryDataset { xs, ys }
+}
+
+/// Features and targets that don't make a dataset.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatasetError {
+    LengthMismatch {
+        features: usize,
+        targets: usize,
+    },
+    /// The sample at `index` has a different number of `what` (features or
+    /// targets) than the first.
+    Ragged {
+        what: &'static str,
+        index: usize,
+        expected: usize,
+        got: usize,
+    },
+}
+
+impl Display for DatasetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DatasetError::LengthMismatch { features, targets } => write!(
+                f,
+                "{} feature rows but {} target rows",
+                features, targets
+            ),
+            DatasetError::Ragged {
+                what,
+                index,
+                expected,
+                got,
+            } => write!(
+                f,
+                "sample {} has {} {}, expected {}",
+                index, got, what, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DatasetError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn toy() -> InMemoryDataset {
+        let xs = (0..5).map(|i| vec![i as Scalar, -(i as Scalar)]).collect();
+        let ys = (0..5).map(|i| vec![(i * 10) as Scalar]).collect();
+        InMemoryDataset::new(xs, ys).unwrap()
+    }
+
+    #[test]
+    fn test_in_memory_dataset() {
+        let data = toy();
+        assert_eq!(data.len(), 5);
+        assert_eq!((data.num_features(), data.num_targets()), (2, 1));
+        assert_eq!(data.get(3), (vec![3.0, -3.0], vec![30.0]));
+        assert_eq!(data.to_string(), "InMemoryDataset of 5 samples (2 -> 1)");
+        let empty = InMemoryDataset::new(vec![], vec![]).unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_in_memory_dataset_errors() {
+        let err = InMemoryDataset::new(vec![vec![1.0]], vec![]).unwrap_err();
+        assert_eq!(
+            err,
+            DatasetError::LengthMismatch {
+                features: 1,
+                targets: 0
+            }
+        );
+        let xs = vec![vec![1.0, 2.0], vec![3.0, 4.0], vec![5.0]];
+        let ys = vec![vec![0.0]; 3];
+        let err = InMemoryDataset::new(xs, ys).unwrap_err();
+        assert_eq!(err.to_string(), "sample 2 has 1 features, expected 2");
+        let ys = vec![vec![0.0], vec![0.0, 1.0]];
+        let err = InMemoryDataset::new(vec![vec![]; 2], ys).unwrap_err();
+        assert_eq!(err.to_string(), "sample 1 has 2 targets, expected 1");
+    }
+
+    #[test]
+    #[should_panic(expected = "index 5 out of range for 5 samples")]
+    fn test_get_out_of_range() {
+        toy().get(5);
+    }
+
+    #[test]
+    fn test_subset() {
+        let data = toy();
+        let subset = data.subset(&[4, 0, 2]);
+        assert_eq!(subset.len(), 3);
+        assert_eq!(subset.indices(), [4, 0, 2]);
+        for (i, &parent) in subset.indices().iter().enumerate() {
+            assert_eq!(subset.get(i), data.get(parent));
+        }
+        let nested = subset.subset(&[2]);
+        assert_eq!(nested.get(0), data.get(2));
+
+        let boxed: Box<dyn Dataset> = Box::new(data.clone());
+        assert_eq!(Subset::new(&*boxed, vec![1]).get(0), data.get(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "index 7 out of range for 5 samples")]
+    fn test_subset_out_of_range() {
+        toy().subset(&[1, 7]);
+    }
+
+    /// A dataset whose sample `i` has target `i`.
+    fn numbered(n: usize) -> InMemoryDataset {
+        let xs = (0..n).map(|i| vec![i as Scalar; 2]).collect();
+        let ys = (0..n).map(|i| vec![i as Scalar]).collect();
+        InMemoryDataset::new(xs, ys).unwrap()
+    }
+
+    fn epoch<D: Dataset>(loader: &mut DataLoader<D>) -> Vec<Vec<usize>> {
+        let ids = |b: Batch| b.ys.iter().map(|y| y[0] as usize).collect();
+        loader.iter().map(ids).collect()
+    }
+
+    #[test]
+    fn test_data_loader_in_order() {
+        let data = numbered(10);
+        let mut loader = DataLoader::new(&data, 3);
+        assert_eq!(loader.num_batches(), 4);
+        let batches = epoch(&mut loader);
+        assert_eq!(
+            batches,
+            [vec![0, 1, 2], vec![3, 4, 5], vec![6, 7, 8], vec![9]]
+        );
+        assert_eq!(epoch(&mut loader), batches);
+
+        let mut loader = DataLoader::new(&data, 3).drop_last(true);
+        assert_eq!(loader.num_batches(), 3);
+        assert_eq!(epoch(&mut loader), batches[..3]);
+
+        let batch = DataLoader::new(&data, 4).iter().next().unwrap();
+        assert_eq!(batch.len(), 4);
+        assert_eq!(batch.xs[1], [1.0, 1.0]);
+        assert_eq!(batch.inputs()[1][0].get_data(), 1.0);
+    }
+
+    #[test]
+    fn test_data_loader_batch_larger_than_dataset() {
+        let data = numbered(5);
+        let mut loader = DataLoader::new(&data, 8);
+        assert_eq!(epoch(&mut loader), [vec![0, 1, 2, 3, 4]]);
+        let mut loader = DataLoader::new(&data, 8).drop_last(true);
+        assert!(epoch(&mut loader).is_empty());
+        let empty = numbered(0);
+        assert_eq!(DataLoader::new(&empty, 2).iter().count(), 0);
+    }
+
+    #[test]
+    fn test_data_loader_shuffle() {
+        let data = numbered(10);
+        let make = || DataLoader::new(&data, 3).shuffle(true).seed(42);
+        let (mut a, mut b) = (make(), make());
+        let epochs_a: Vec<_> = (0..3).map(|_| epoch(&mut a)).collect();
+        let epochs_b: Vec<_> = (0..3).map(|_| epoch(&mut b)).collect();
+        assert_eq!(epochs_a, epochs_b);
+        assert_ne!(epochs_a[0], epochs_a[1]);
+        for batches in &epochs_a {
+            assert_eq!(batches.len(), 4);
+            let mut seen: Vec<_> = batches.concat();
+            seen.sort_unstable();
+            assert_eq!(seen, (0..10).collect::<Vec<_>>());
+        }
+
+        let mut dropping = make().drop_last(true);
+        for _ in 0..3 {
+            let batches = epoch(&mut dropping);
+            assert_eq!(batches.len(), 3);
+            assert!(batches.iter().all(|b| b.len() == 3));
+            let mut seen = batches.concat();
+            seen.sort_unstable();
+            seen.dedup();
+            assert_eq!(seen.len(), 9);
+        }
+    }
+
+    #[test]
+    fn test_make_moons() {
+        let moons = make_moons(101, 0.0, 0);
+        assert_eq!(moons.len(), 101);
+        assert_eq!((moons.num_features(), moons.num_targets()), (2, 1));
+        let ys: Vec<_> = (0..101).map(|i| moons.get(i).1[0]).collect();
+        assert_eq!(ys.iter().filter(|&&y| y == -1.0).count(), 50);
+        assert_eq!(ys.iter().filter(|&&y| y == 1.0).count(), 51);
+        // Without noise every point is on its circle
+        for i in 0..101 {
+            let (x, y) = moons.get(i);
+            let (cx, cy) = if y[0] < 0.0 { (0.0, 0.0) } else { (1.0, 0.5) };
+            let r = ((x[0] - cx).powi(2) + (x[1] - cy).powi(2)).sqrt();
+            assert!((r - 1.0).abs() < 1e-5, "{:?}", x);
+            // The upper moon bulges up and the lower one down
+            assert!((x[1] - cy) * -y[0] >= -1e-5, "{:?}", x);
+        }
+        assert_eq!(moons.get(0).0, [1.0, 0.0]);
+
+        let noisy = make_moons(100, 0.1, 7);
+        assert_eq!(noisy, make_moons(100, 0.1, 7));
+        assert_ne!(noisy, make_moons(100, 0.1, 8));
+        assert_ne!(noisy, make_moons(100, 0.0, 7));
+
+        let binary = make_moons_with_labels(100, 0.1, 7, BinaryLabels::ZeroOne);
+        for i in 0..100 {
+            let (x, y) = noisy.get(i);
+            assert_eq!(binary.get(i), (x, vec![(y[0] + 1.0) / 2.0]));
+        }
+    }
+
+    fn class_counts(data: &InMemoryDataset, n_classes: usize) -> Vec<usize> {
+        let mut counts = vec![0; n_classes];
+        for i in 0..data.len() {
+            counts[data.get(i).1[0] as usize] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn test_make_blobs() {
+        let centers = [(0.0, 0.0), (5.0, -2.0), (-3.0, 4.0)];
+        let blobs = make_blobs(100, &centers, 0.5, 1);
+        assert_eq!((blobs.num_features(), blobs.num_targets()), (2, 1));
+        assert_eq!(class_counts(&blobs, 3), [34, 33, 33]);
+        for i in 0..blobs.len() {
+            let (x, y) = blobs.get(i);
+            let (cx, cy) = centers[y[0] as usize];
+            for d in [x[0] - cx, x[1] - cy] {
+                assert!(
+                    d.abs() < 4.0 * 0.5,
+                    "{:?} is far from blob {}",
+                    x,
+                    y[0]
+                );
+            }
+        }
+        assert_eq!(blobs, make_blobs(100, &centers, 0.5, 1));
+        assert_ne!(blobs, make_blobs(100, &centers, 0.5, 2));
+        assert!(make_blobs(0, &[], 1.0, 0).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "no centers for 3 samples")]
+    fn test_make_blobs_without_centers() {
+        make_blobs(3, &[], 1.0, 0);
+    }
+
+    #[test]
+    fn test_make_spiral() {
+        let spiral = make_spiral(200, 3, 0.2, 0);
+        assert_eq!((spiral.num_features(), spiral.num_targets()), (2, 1));
+        assert_eq!(class_counts(&spiral, 3), [67, 67, 66]);
+        for i in 0..spiral.len() {
+            let x = spiral.get(i).0;
+            assert!(x[0].hypot(x[1]) <= 1.0 + 1e-6);
+        }
+        assert_eq!(spiral, make_spiral(200, 3, 0.2, 0));
+        assert_ne!(spiral, make_spiral(200, 3, 0.2, 1));
+    }
+
+    #[test]
+    fn test_split_line() {
+        assert_eq!(split_line(" 1, 2 ,3", ','), ["1", "2", "3"]);
+        assert_eq!(split_line("a;;b", ';'), ["a", "", "b"]);
+        assert_eq!(
+            split_line(r#""x, y",2,"say ""hi""""#, ','),
+            ["x, y", "2", r#"say "hi""#]
+        );
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let options = CsvOptions {
+            label_column: Some("kind".into()),
+            ..CsvOptions::default()
+        };
+        let text = "x,kind\n1.5,b\n\n-2,a\n";
+        let (data, labels) = parse_csv_with_labels(text, &options).unwrap();
+        assert_eq!(labels, ["a", "b"]);
+        assert_eq!(data.get(0), (vec![1.5], vec![1.0]));
+        assert_eq!(data.get(1), (vec![-2.0], vec![0.0]));
+        let err = parse_csv("x\ny\n", &CsvOptions::default()).unwrap_err();
+        assert!(matches!(err, CsvError::Parse { line: 2, .. }));
+    }
+
+    #[test]
+    fn test_one_hot() {
+        assert_eq!(one_hot(2, 4), [0.0, 0.0, 1.0, 0.0]);
+        for i in 0..5 {
+            assert_eq!(from_one_hot(&one_hot(i, 5)), i);
+        }
+        let batch = one_hot_batch(&[1, 0, 1], 2);
+        assert_eq!(batch, [[0.0, 1.0], [1.0, 0.0], [0.0, 1.0]]);
+        let decoded: Vec<_> = batch.iter().map(|y| from_one_hot(y)).collect();
+        assert_eq!(decoded, [1, 0, 1]);
+        assert_eq!(from_one_hot(&[0.2, 0.5, 0.3]), 1);
+        assert_eq!(from_one_hot(&[0.4, 0.2, 0.4]), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "class 3 out of range for 3 classes")]
+    fn test_one_hot_out_of_range() {
+        one_hot_batch(&[0, 3], 3);
+    }
+
+    #[test]
+    fn test_label_smooth() {
+        let mut y = one_hot(1, 4);
+        label_smooth(&mut y, 0.2);
+        for (y, expected) in y.iter().zip([0.05, 0.85, 0.05, 0.05]) {
+            assert!((y - expected).abs() < 1e-6);
+        }
+        assert!((y.iter().sum::<Scalar>() - 1.0).abs() < 1e-6);
+        assert_eq!(from_one_hot(&y), 1);
+
+        let mut y = one_hot(0, 3);
+        label_smooth(&mut y, 0.0);
+        assert_eq!(y, one_hot(0, 3));
+    }
+
+    #[test]
+    fn test_compute_class_weights() {
+        let targets = [0, 0, 0, 1, 0, 0, 2, 0];
+        let weights =
+            compute_class_weights(&targets, 3, ClassWeightScheme::Balanced)
+                .unwrap();
+        let expected = [8.0 / 18.0, 8.0 / 3.0, 8.0 / 3.0];
+        for (w, e) in weights.iter().zip(expected) {
+            assert!((w - e).abs() < 1e-6);
+        }
+        let weights =
+            compute_class_weights(&[0, 2], 3, ClassWeightScheme::Balanced);
+        assert_eq!(weights, Ok(vec![1.0 / 1.5, 0.0, 1.0 / 1.5]));
+
+        let custom = ClassWeightScheme::Custom(vec![1.0, 5.0]);
+        let err = compute_class_weights(&targets, 3, custom).unwrap_err();
+        assert_eq!(err.to_string(), "2 class weights for 3 classes");
+        let custom = ClassWeightScheme::Custom(vec![1.0, 5.0, 2.0]);
+        assert_eq!(
+            compute_class_weights(&targets, 3, custom),
+            Ok(vec![1.0, 5.0, 2.0])
+        );
+        // the last class has no samples but still needs a weight
+        let custom = ClassWeightScheme::Custom(vec![1.0, 5.0, 2.0, 4.0]);
+        assert_eq!(
+            compute_class_weights(&targets, 4, custom),
+            Ok(vec![1.0, 5.0, 2.0, 4.0])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "class 2 out of range for 2 classes")]
+    fn test_compute_class_weights_range() {
+        let _ = compute_class_weights(&[0, 2], 2, ClassWeightScheme::Balanced);
+    }
+
+    #[test]
+    fn test_one_hot_values_get_no_grad() {
+        let targets = one_hot_values(1, 3);
+        let pred: Vec<_> =
+            [0.2, 0.5, 0.3].iter().map(|&p| Value::new(p)).collect();
+        let errs = pred.iter().zip(&targets).map(|(p, t)| (p - t).pow(2.0));
+        let loss = errs.fold(Value::constant(0.0), |acc, e| acc + e);
+        loss.backward();
+        assert_eq!(targets.iter().map(Value::get_data).sum::<Scalar>(), 1.0);
+        assert!(targets.iter().all(|t| t.get_grad() == 0.0));
+        assert!((pred[1].get_grad() - -1.0).abs() < 1e-6);
+    }
+
+    /// Samples whose single target is their class, `counts[c]` of class
+    /// `c`, interleaved.
+    fn classes(counts: &[usize]) -> InMemoryDataset {
+        let mut ys = Vec::new();
+        let mut left = counts.to_vec();
+        while left.iter().any(|&c| c > 0) {
+            for (class, left) in left.iter_mut().enumerate() {
+                if *left > 0 {
+                    *left -= 1;
+                    ys.push(vec![class as Scalar]);
+                }
+            }
+        }
+        let xs = (0..ys.len()).map(|i| vec![i as Scalar]).collect();
+        InMemoryDataset::new(xs, ys).unwrap()
+    }
+
+    #[test]
+    fn test_train_test_split() {
+        let data = numbered(10);
+        let (train, test) = train_test_split(&data, 0.3, 4);
+        assert_eq!((train.len(), test.len()), (7, 3));
+        let mut all = [train.indices(), test.indices()].concat();
+        all.sort_unstable();
+        assert_eq!(all, (0..10).collect::<Vec<_>>());
+
+        let (again, _) = train_test_split(&data, 0.3, 4);
+        assert_eq!(again.indices(), train.indices());
+        let other: Vec<_> = (0..10)
+            .map(|seed| train_test_split(&data, 0.3, seed).1.indices().to_vec())
+            .collect();
+        assert!(other.iter().any(|t| t != test.indices()));
+    }
+
+    #[test]
+    fn test_stratified_train_test_split() {
+        let counts = [30, 12, 5];
+        let data = classes(&counts);
+        for seed in 0..5 {
+            let (train, test) = stratified_train_test_split(&data, 0.25, seed);
+            assert_eq!(train.len() + test.len(), 47);
+            for (class, &count) in counts.iter().enumerate() {
+                let in_test = (0..test.len())
+                    .filter(|&i| test.get(i).1[0] as usize == class)
+                    .count();
+                let expected = count as Scalar * 0.25;
+                assert!((in_test as Scalar - expected).abs() <= 1.0);
+            }
+            let (again, _) = stratified_train_test_split(&data, 0.25, seed);
+            assert_eq!(again.indices(), train.indices());
+        }
+
+        // One-hot targets stratify by their class too
+        let xs = (0..8).map(|i| vec![i as Scalar]).collect();
+        let ys = one_hot_batch(&[0, 0, 0, 0, 0, 0, 1, 1], 2);
+        let data = InMemoryDataset::new(xs, ys).unwrap();
+        let (_, test) = stratified_train_test_split(&data, 0.5, 0);
+        let test_classes: Vec<_> = (0..test.len())
+            .map(|i| from_one_hot(&test.get(i).1))
+            .collect();
+        assert_eq!(test_classes.iter().filter(|&&c| c == 1).count(), 1);
+        assert_eq!(test.len(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "test fraction must be in (0, 1), got 1")]
+    fn test_train_test_split_fraction() {
+        train_test_split(&numbered(4), 1.0, 0);
+    }
+
+    #[test]
+    fn test_k_fold() {
+        let data = numbered(11);
+        let folds = k_fold(&data, 3, 9);
+        assert_eq!(folds.len(), 3);
+        let mut validated = Vec::new();
+        for (train, val) in &folds {
+            assert_eq!(train.len() + val.len(), 11);
+            assert!(val.indices().iter().all(|i| !train.indices().contains(i)));
+            validated.extend_from_slice(val.indices());
+        }
+        let sizes: Vec<_> = folds.iter().map(|(_, v)| v.len()).collect();
+        assert_eq!(sizes, [4, 4, 3]);
+        validated.sort_unstable();
+        assert_eq!(validated, (0..11).collect::<Vec<_>>());
+
+        let again = k_fold(&data, 3, 9);
+        for ((train, val), (train2, val2)) in folds.iter().zip(&again) {
+            assert_eq!(
+                (train.indices(), val.indices()),
+                (train2.indices(), val2.indices())
+            );
+        }
+        assert_eq!(k_fold(&data, 11, 0).len(), 11);
+    }
+
+    #[test]
+    #[should_panic(expected = "k-fold needs 2 <= k <= 3 samples, got k = 4")]
+    fn test_k_fold_too_many_folds() {
+        k_fold(&numbered(3), 4, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "got k = 1")]
+    fn test_k_fold_one_fold() {
+        k_fold(&numbered(3), 1, 0);
+    }
+
+    /// Three features: spread out, offset and constant.
+    fn unscaled() -> InMemoryDataset {
+        let xs = [[1.0, 100.0, 7.0], [4.0, 102.0, 7.0], [10.0, 106.0, 7.0]];
+        let xs = xs.iter().map(|x| x.to_vec()).collect();
+        let ys = (0..3).map(|i| vec![i as Scalar]).collect();
+        InMemoryDataset::new(xs, ys).unwrap()
+    }
+
+    fn columns(data: &InMemoryDataset) -> Vec<Vec<Scalar>> {
+        (0..data.num_features())
+            .map(|j| (0..data.len()).map(|i| data.get(i).0[j]).collect())
+            .collect()
+    }
+
+    fn assert_round_trips(scaler: &dyn Scaler, data: &InMemoryDataset) {
+        for i in 0..data.len() {
+            let x = data.get(i).0;
+            let back = scaler.inverse_transform(&scaler.transform(&x));
+            for (a, b) in x.iter().zip(&back) {
+                assert!(
+                    (a - b).abs() < 1e-4,
+                    "{:?} came back as {:?}",
+                    x,
+                    back
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_standard_scaler() {
+        let data = unscaled();
+        let mut scaler = StandardScaler::new();
+        let scaled = scaler.fit_transform(&data);
+        assert_eq!(scaled.get(1).1, [1.0]);
+        let cols = columns(&scaled);
+        for col in &cols[..2] {
+            let mean = col.iter().sum::<Scalar>() / 3.0;
+            let var =
+                col.iter().map(|x| (x - mean).powi(2)).sum::<Scalar>() / 3.0;
+            assert!(mean.abs() < 1e-5);
+            assert!((var.sqrt() - 1.0).abs() < 1e-5);
+        }
+        // The constant feature is centered, not divided by zero
+        assert_eq!(cols[2], [0.0; 3]);
+        assert_eq!(scaler.std()[2], 1.0);
+        let mean = scaler.mean();
+        assert_eq!((mean[0], mean[2]), (5.0, 7.0));
+        assert!((mean[1] - 308.0 / 3.0).abs() < 1e-4);
+        assert_round_trips(&scaler, &data);
+    }
+
+    #[test]
+    fn test_min_max_scaler() {
+        let data = unscaled();
+        let mut scaler = MinMaxScaler::new();
+        scaler.fit(&data);
+        let cols = columns(&scaler.transform_dataset(&data));
+        assert_eq!(cols[0], [0.0, 1.0 / 3.0, 1.0]);
+        assert_eq!(cols[1], [0.0, 1.0 / 3.0, 1.0]);
+        assert_eq!(cols[2], [0.0; 3]);
+        assert_eq!(
+            scaler.transform(&[13.0, 94.0, 8.0]),
+            [4.0 / 3.0, -1.0, 1.0]
+        );
+        assert_round_trips(&scaler, &data);
+    }
+
+    #[test]
+    fn test_polynomial_features() {
+        let (a, b) = (2.0, 3.0);
+        let poly = PolynomialFeatures::new(2, true, false);
+        assert_eq!(poly.transform(&[a, b]), [1.0, a, b, a * a, a * b, b * b]);
+        assert_eq!(
+            poly.feature_names(2),
+            ["1", "x0", "x1", "x0^2", "x0 x1", "x1^2"]
+        );
+        let cubic = PolynomialFeatures::new(3, false, false);
+        assert_eq!(
+            cubic.feature_names(2)[5..],
+            ["x0^3", "x0^2 x1", "x0 x1^2", "x1^3"]
+        );
+
+        let interactions = PolynomialFeatures::new(3, false, true);
+        let c = 5.0;
+        assert_eq!(
+            interactions.transform(&[a, b, c]),
+            [a, b, c, a * b, a * c, b * c, a * b * c]
+        );
+        assert_eq!(interactions.feature_names(3)[6], "x0 x1 x2");
+
+        let data = unscaled();
+        let expanded = poly.transform_dataset(&data);
+        assert_eq!(expanded.num_features(), 10);
+        assert_eq!(
+            expanded.get(2),
+            (poly.transform(&data.get(2).0), data.get(2).1)
+        );
+    }
+
+    #[test]
+    fn test_polynomial_feature_counts() {
+        fn choose(n: usize, k: usize) -> usize {
+            (0..k).fold(1, |acc, i| acc * (n - i) / (i + 1))
+        }
+        for (n, degree) in [(1, 3), (2, 2), (3, 3), (4, 2), (5, 4)] {
+            let all = PolynomialFeatures::new(degree, true, false);
+            assert_eq!(all.num_output_features(n), choose(n + degree, degree));
+            let no_bias = PolynomialFeatures::new(degree, false, false);
+            assert_eq!(
+                no_bias.num_output_features(n),
+                choose(n + degree, degree) - 1
+            );
+            let interactions = PolynomialFeatures::new(degree, true, true);
+            let expected: usize =
+                (0..=degree.min(n)).map(|k| choose(n, k)).sum();
+            assert_eq!(interactions.num_output_features(n), expected);
+            let x = vec![1.0; n];
+            assert_eq!(interactions.transform(&x).len(), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "2 features for a scaler fit on 3")]
+    fn test_scaler_feature_count() {
+        let mut scaler = MinMaxScaler::new();
+        scaler.fit(&unscaled());
+        scaler.transform(&[1.0, 2.0]);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_scaler_serde() {
+        let mut scaler = StandardScaler::new();
+        scaler.fit(&unscaled());
+        let json = serde_json::to_string(&scaler).unwrap();
+        let loaded: StandardScaler = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, scaler);
+    }
+}
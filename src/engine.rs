@@ -1,24 +1,190 @@
-use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display};
 use std::hash::Hash;
 use std::ops::{Add, Div, Mul, Neg, Sub};
-use std::rc::Rc;
 
+use crate::clock::Stopwatch;
+use crate::profiling;
+pub use crate::shared::MaybeSync;
+use crate::shared::{
+    BinaryBackward, BinaryForward, Lock, ScalarCell, Shared, UnaryBackward,
+    UnaryForward,
+};
+
+#[derive(Debug, Clone, PartialEq)]
 enum Ops {
     Add,
     Mul,
-    Pow,
+    Pow(Scalar),
     ReLU,
+    LeakyReLU(Scalar),
+    PReLU,
+    Tanh,
+    Sigmoid,
+    Gelu,
+    Exp,
+    Ln,
+    Sqrt,
+    Rsqrt,
+    Abs,
+    Erf,
+    Sin,
+    Cos,
+    Tan,
+    Atan,
+    Atan2,
+    Sinh,
+    Cosh,
+    Lerp,
+    Maximum,
+    Minimum,
+    Clamp(Scalar, Scalar),
+    LogSumExp,
     None,
+    Const,
+    Custom(Shared<CustomOp>),
+    Released,
+}
+
+impl Ops {
+    fn name(&self) -> &'static str {
+        match self {
+            Ops::Add => "Add",
+            Ops::Mul => "Mul",
+            Ops::Pow(_) => "Pow",
+            Ops::ReLU => "ReLU",
+            Ops::LeakyReLU(_) => "LeakyReLU",
+            Ops::PReLU => "PReLU",
+            Ops::Tanh => "Tanh",
+            Ops::Sigmoid => "Sigmoid",
+            Ops::Gelu => "Gelu",
+            Ops::Exp => "Exp",
+            Ops::Ln => "Ln",
+            Ops::Sqrt => "Sqrt",
+            Ops::Rsqrt => "Rsqrt",
+            Ops::Abs => "Abs",
+            Ops::Erf => "Erf",
+            Ops::Sin => "Sin",
+            Ops::Cos => "Cos",
+            Ops::Tan => "Tan",
+            Ops::Atan => "Atan",
+            Ops::Atan2 => "Atan2",
+            Ops::Sinh => "Sinh",
+            Ops::Cosh => "Cosh",
+            Ops::Lerp => "Lerp",
+            Ops::Maximum => "Maximum",
+            Ops::Minimum => "Minimum",
+            Ops::Clamp(..) => "Clamp",
+            Ops::LogSumExp => "LogSumExp",
+            Ops::None => "None",
+            Ops::Const => "Const",
+            Ops::Custom(_) => "Custom",
+            Ops::Released => "Released",
+        }
+    }
+
+    /// The op's parameters as raw bits, so two ops are interchangeable
+    /// exactly when their names and payloads agree.
+    #[allow(clippy::unnecessary_cast)] // Scalar may be f64
+    fn payload(&self) -> Vec<u64> {
+        match self {
+            Ops::Pow(rhs) | Ops::LeakyReLU(rhs) => vec![rhs.to_bits() as u64],
+            Ops::Clamp(lo, hi) => {
+                vec![lo.to_bits() as u64, hi.to_bits() as u64]
+            }
+            Ops::Custom(custom) => vec![Shared::as_ptr(custom) as usize as u64],
+            Ops::Add
+            | Ops::Mul
+            | Ops::ReLU
+            | Ops::PReLU
+            | Ops::Tanh
+            | Ops::Sigmoid
+            | Ops::Gelu
+            | Ops::Exp
+            | Ops::Ln
+            | Ops::Sqrt
+            | Ops::Rsqrt
+            | Ops::Abs
+            | Ops::Erf
+            | Ops::Sin
+            | Ops::Cos
+            | Ops::Tan
+            | Ops::Atan
+            | Ops::Atan2
+            | Ops::Sinh
+            | Ops::Cosh
+            | Ops::Lerp
+            | Ops::Maximum
+            | Ops::Minimum
+            | Ops::LogSumExp
+            | Ops::None
+            | Ops::Const
+            | Ops::Released => Vec::new(),
+        }
+    }
+}
+
+/// A user-defined op created by [`Value::custom_unary`] or
+/// [`Value::custom_binary`].
+struct CustomOp {
+    name: String,
+    kind: CustomKind,
+}
+
+enum CustomKind {
+    Unary {
+        forward: Box<UnaryForward>,
+        backward: Box<UnaryBackward>,
+    },
+    Binary {
+        forward: Box<BinaryForward>,
+        backward: Box<BinaryBackward>,
+    },
+}
+
+impl Debug for CustomOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.name, f)
+    }
+}
+
+/// Custom ops compare by name and arity, so that separately built graphs
+/// using the same op are structurally equal.
+impl PartialEq for CustomOp {
+    fn eq(&self, other: &Self) -> bool {
+        let arity = |op: &CustomOp| match op.kind {
+            CustomKind::Unary { .. } => 1,
+            CustomKind::Binary { .. } => 2,
+        };
+        self.name == other.name && arity(self) == arity(other)
+    }
 }
 
+/// The floating point type carried by every [`Value`].
+///
+/// This is `f32` unless the `f64` feature is enabled, which switches the
+/// whole crate over to double precision for gradient checking and long
+/// accumulation chains. Like any type-changing feature it applies to every
+/// user of the crate in a build.
+#[cfg(not(feature = "f64"))]
+pub type Scalar = f32;
+#[cfg(feature = "f64")]
+pub type Scalar = f64;
+
+/// A node of the computation graph.
+///
+/// Data and grad live inline in the node and the backward pass dispatches on
+/// `op`, so building a node costs a single allocation (plus its child list)
+/// instead of separately boxed cells and closures.
 struct Inner {
-    pub data: Rc<Cell<f32>>,
-    pub grad: Rc<Cell<f32>>,
-    backward: Box<dyn Fn() -> ()>,
-    pub prev: Vec<Value>,
+    data: ScalarCell,
+    grad: ScalarCell,
+    node: Lock<Node>,
+}
+
+struct Node {
     op: Ops,
+    prev: Vec<Value>,
 }
 
 impl Debug for Inner {
@@ -26,7 +192,7 @@ impl Debug for Inner {
         f.debug_struct("Inner")
             .field("data", &self.data)
             .field("grad", &self.grad)
-            .field("prev", &self.prev)
+            .field("prev", &self.node.read().prev)
             .finish()
     }
 }
@@ -41,11 +207,11 @@ impl Display for Inner {
 }
 
 #[derive(Debug, Clone)]
-pub struct Value(Rc<RefCell<Inner>>);
+pub struct Value(Shared<Inner>);
 
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
-        self.0.as_ptr() == other.0.as_ptr()
+        Shared::ptr_eq(&self.0, &other.0)
     }
 }
 
@@ -53,7 +219,7 @@ impl Eq for Value {}
 
 impl Hash for Value {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.0.as_ptr().hash(state)
+        Shared::as_ptr(&self.0).hash(state)
     }
 }
 
@@ -67,109 +233,798 @@ impl Display for Value {
 }
 
 impl Value {
-    pub fn new(data: f32) -> Self {
-        Self(Rc::new(RefCell::new(Inner {
-            data: Rc::new(Cell::new(data)),
-            grad: Rc::new(Cell::new(0.0)),
-            backward: Box::new(|| {}),
-            prev: vec![],
-            op: Ops::None,
-        })))
-    }
-
-    fn _new(data: f32, prev: Vec<Self>, op: Ops) -> Self {
-        Self(Rc::new(RefCell::new(Inner {
-            data: Rc::new(Cell::new(data)),
-            grad: Rc::new(Cell::new(0.0)),
-            backward: Box::new(|| {}),
-            prev,
-            op,
-        })))
-    }
-
-    fn backward(&self) {
+    pub fn new(data: Scalar) -> Self {
+        Self::_new(data, vec![], Ops::None)
+    }
+
+    /// Creates a constant leaf, as used for the scalar operands of mixed
+    /// `Value`/`Scalar` arithmetic. Constants are what
+    /// [`optimize`](Value::optimize) is allowed to fold, and
+    /// [`backward`](Value::backward) leaves their grad at zero.
+    pub fn constant(data: Scalar) -> Self {
+        Self::_new(data, vec![], Ops::Const)
+    }
+
+    fn _new(data: Scalar, prev: Vec<Self>, op: Ops) -> Self {
+        if profiling::is_enabled() {
+            profiling::record_node(op.name());
+        }
+        Self(Shared::new(Inner {
+            data: ScalarCell::new(data),
+            grad: ScalarCell::new(0.0),
+            node: Lock::new(Node { op, prev }),
+        }))
+    }
+
+    fn topo(&self) -> Vec<Value> {
         let mut topo = vec![];
         let mut visited = HashSet::new();
         fn build_topo(
             v: &Value,
-            visited: &mut HashSet<Value>,
+            visited: &mut HashSet<*const Inner>,
             topo: &mut Vec<Value>,
         ) {
-            if !visited.contains(&v) {
-                visited.insert(v.clone());
-                for child in v.0.borrow().prev.iter() {
+            if visited.insert(Shared::as_ptr(&v.0)) {
+                for child in v.0.node.read().prev.iter() {
                     build_topo(child, visited, topo)
                 }
                 topo.push(v.clone())
             }
         }
         build_topo(self, &mut visited, &mut topo);
-        self.0.borrow_mut().grad.set(1.0);
+        topo
+    }
+
+    /// Backpropagates from `self`, accumulating into the grads of every
+    /// node it depends on.
+    ///
+    /// [Constants](Value::constant) are the exception: their grads are
+    /// reset to zero once the pass is done, so a constant target or operand
+    /// never reports a gradient.
+    ///
+    /// Panics if any part of the graph has been [released](Value::release).
+    pub fn backward(&self) {
+        let topo = self.topo();
+        if topo.iter().any(|v| v.0.node.read().op == Ops::Released) {
+            panic!("backward through a graph that has been released");
+        }
+        self.0.grad.set(1.0);
         for v in topo.iter().rev() {
-            v.0.borrow_mut().backward.as_ref()();
-            // println!("{:?} {}", v.0.as_ptr(), v);
+            if profiling::is_enabled() {
+                let timer = Stopwatch::start();
+                v.backward_step();
+                let op = v.0.node.read().op.name();
+                let elapsed = timer.elapsed().unwrap_or_default();
+                profiling::record_backward(op, elapsed);
+                continue;
+            }
+            v.backward_step();
+        }
+        for v in topo.iter().filter(|v| v.is_const()) {
+            v.0.grad.set(0.0);
         }
     }
 
-    pub fn get_grad(&self) -> f32 {
-        self.0.borrow().grad.get()
+    /// Pushes this node's grad into its children.
+    fn backward_step(&self) {
+        let node = self.0.node.read();
+        let out_grad = self.get_grad();
+        match &node.op {
+            Ops::Add => {
+                for child in node.prev.iter() {
+                    child.add_grad(out_grad)
+                }
+            }
+            Ops::Mul => {
+                let (lhs, rhs) = (&node.prev[0], &node.prev[1]);
+                lhs.add_grad(rhs.get_data() * out_grad);
+                rhs.add_grad(lhs.get_data() * out_grad)
+            }
+            &Ops::Pow(rhs) => {
+                let base = &node.prev[0];
+                base.add_grad(rhs * base.get_data().powf(rhs - 1.0) * out_grad)
+            }
+            Ops::Exp => node.prev[0].add_grad(self.get_data() * out_grad),
+            Ops::Ln => {
+                let x = &node.prev[0];
+                x.add_grad(out_grad / x.get_data())
+            }
+            Ops::Sqrt => {
+                node.prev[0].add_grad(0.5 / self.get_data() * out_grad)
+            }
+            Ops::Rsqrt => {
+                let r = self.get_data();
+                node.prev[0].add_grad(-0.5 * r * r * r * out_grad)
+            }
+            Ops::Abs => {
+                let x = &node.prev[0];
+                x.add_grad(sign(x.get_data()) * out_grad)
+            }
+            Ops::Erf => {
+                let x = node.prev[0].get_data();
+                let slope = std::f64::consts::FRAC_2_SQRT_PI as Scalar;
+                node.prev[0].add_grad(slope * (-x * x).exp() * out_grad)
+            }
+            Ops::Sin => {
+                let x = &node.prev[0];
+                x.add_grad(x.get_data().cos() * out_grad)
+            }
+            Ops::Cos => {
+                let x = &node.prev[0];
+                x.add_grad(-x.get_data().sin() * out_grad)
+            }
+            Ops::Tan => {
+                let t = self.get_data();
+                node.prev[0].add_grad((1.0 + t * t) * out_grad)
+            }
+            Ops::Atan => {
+                let x = node.prev[0].get_data();
+                node.prev[0].add_grad(out_grad / (1.0 + x * x))
+            }
+            Ops::Atan2 => {
+                let (y, x) = (&node.prev[0], &node.prev[1]);
+                let r2 = y.get_data().powi(2) + x.get_data().powi(2);
+                if r2 > 0.0 {
+                    y.add_grad(x.get_data() / r2 * out_grad);
+                    x.add_grad(-y.get_data() / r2 * out_grad)
+                }
+            }
+            Ops::Sinh => {
+                let x = &node.prev[0];
+                x.add_grad(x.get_data().cosh() * out_grad)
+            }
+            Ops::Cosh => {
+                let x = &node.prev[0];
+                x.add_grad(x.get_data().sinh() * out_grad)
+            }
+            Ops::Lerp => {
+                let (a, b, t) = (&node.prev[0], &node.prev[1], &node.prev[2]);
+                let weight = t.get_data();
+                a.add_grad((1.0 - weight) * out_grad);
+                b.add_grad(weight * out_grad);
+                t.add_grad((b.get_data() - a.get_data()) * out_grad)
+            }
+            Ops::Maximum | Ops::Minimum => {
+                let (a, b) = (&node.prev[0], &node.prev[1]);
+                let max = node.op == Ops::Maximum;
+                let winner = if picks_first(a.get_data(), b.get_data(), max) {
+                    a
+                } else {
+                    b
+                };
+                winner.add_grad(out_grad)
+            }
+            &Ops::Clamp(lo, hi) => {
+                let x = &node.prev[0];
+                if (lo..=hi).contains(&x.get_data()) {
+                    x.add_grad(out_grad)
+                }
+            }
+            Ops::LogSumExp => {
+                let out = self.get_data();
+                for x in node.prev.iter() {
+                    x.add_grad((x.get_data() - out).exp() * out_grad)
+                }
+            }
+            Ops::ReLU => node.prev[0]
+                .add_grad(((self.get_data() > 0.0) as u8 as Scalar) * out_grad),
+            &Ops::LeakyReLU(slope) => {
+                let x = &node.prev[0];
+                let local = if x.get_data() > 0.0 { 1.0 } else { slope };
+                x.add_grad(local * out_grad)
+            }
+            Ops::PReLU => {
+                let (x, alpha) = (&node.prev[0], &node.prev[1]);
+                if x.get_data() > 0.0 {
+                    x.add_grad(out_grad)
+                } else {
+                    x.add_grad(alpha.get_data() * out_grad);
+                    alpha.add_grad(x.get_data() * out_grad)
+                }
+            }
+            Ops::Tanh => {
+                let t = self.get_data();
+                node.prev[0].add_grad((1.0 - t * t) * out_grad)
+            }
+            Ops::Sigmoid => {
+                let s = self.get_data();
+                node.prev[0].add_grad(s * (1.0 - s) * out_grad)
+            }
+            Ops::Gelu => {
+                let x = &node.prev[0];
+                x.add_grad(gelu_grad(x.get_data()) * out_grad)
+            }
+            Ops::Custom(custom) => match &custom.kind {
+                CustomKind::Unary { backward, .. } => {
+                    let x = &node.prev[0];
+                    x.add_grad(backward(
+                        x.get_data(),
+                        self.get_data(),
+                        out_grad,
+                    ))
+                }
+                CustomKind::Binary { backward, .. } => {
+                    let (lhs, rhs) = (&node.prev[0], &node.prev[1]);
+                    let (lhs_grad, rhs_grad) = backward(
+                        lhs.get_data(),
+                        rhs.get_data(),
+                        self.get_data(),
+                        out_grad,
+                    );
+                    lhs.add_grad(lhs_grad);
+                    rhs.add_grad(rhs_grad)
+                }
+            },
+            Ops::None | Ops::Const | Ops::Released => {}
+        }
+    }
+
+    /// Clones every node reachable from `self` into a fresh graph.
+    ///
+    /// Nodes shared in the original stay shared in the copy, data and ops are
+    /// carried over and grads start at zero, so the copy can be
+    /// backpropagated independently of the original.
+    pub fn deep_copy(&self) -> Self {
+        let mut copies: HashMap<Value, Value> = HashMap::new();
+        for v in self.topo() {
+            let node = v.0.node.read();
+            let prev: Vec<Value> =
+                node.prev.iter().map(|c| copies[c].clone()).collect();
+            let copy = match &node.op {
+                Ops::None => Value::new(0.0),
+                Ops::Const => Value::constant(0.0),
+                op => Value::apply(op, &prev),
+            };
+            copy.0.data.set(v.get_data());
+            drop(node);
+            copies.insert(v, copy);
+        }
+        copies[self].clone()
+    }
+
+    /// Builds a new node applying `op` to `prev`.
+    fn apply(op: &Ops, prev: &[Value]) -> Value {
+        match op {
+            Ops::Add => &prev[0] + &prev[1],
+            Ops::Mul => &prev[0] * &prev[1],
+            &Ops::Pow(rhs) => prev[0].pow(rhs),
+            Ops::ReLU => prev[0].relu(),
+            &Ops::LeakyReLU(slope) => prev[0].leaky_relu(slope),
+            Ops::PReLU => prev[0].prelu(&prev[1]),
+            Ops::Tanh => prev[0].tanh(),
+            Ops::Sigmoid => prev[0].sigmoid(),
+            Ops::Gelu => prev[0].gelu(),
+            Ops::Exp => prev[0].exp(),
+            Ops::Ln => prev[0].ln(),
+            Ops::Sqrt => prev[0].sqrt(),
+            Ops::Rsqrt => prev[0].rsqrt(),
+            Ops::Abs => prev[0].abs(),
+            Ops::Erf => prev[0].erf(),
+            Ops::Sin => prev[0].sin(),
+            Ops::Cos => prev[0].cos(),
+            Ops::Tan => prev[0].tan(),
+            Ops::Atan => prev[0].atan(),
+            Ops::Atan2 => prev[0].atan2(&prev[1]),
+            Ops::Sinh => prev[0].sinh(),
+            Ops::Cosh => prev[0].cosh(),
+            Ops::Lerp => prev[0].lerp(&prev[1], &prev[2]),
+            Ops::Maximum => prev[0].maximum(&prev[1]),
+            Ops::Minimum => prev[0].minimum(&prev[1]),
+            &Ops::Clamp(lo, hi) => prev[0].clamp(lo, hi),
+            Ops::LogSumExp => logsumexp(prev),
+            Ops::Custom(custom) => {
+                let data = match &custom.kind {
+                    CustomKind::Unary { forward, .. } => {
+                        forward(prev[0].get_data())
+                    }
+                    CustomKind::Binary { forward, .. } => {
+                        forward(prev[0].get_data(), prev[1].get_data())
+                    }
+                };
+                Value::_new(data, prev.to_vec(), op.clone())
+            }
+            Ops::None | Ops::Const => panic!("{} is not an op", op.name()),
+            Ops::Released => panic!("graph has been released"),
+        }
+    }
+
+    fn is_const(&self) -> bool {
+        self.0.node.read().op == Ops::Const
+    }
+
+    /// Rewrites the graph behind `self` into an equivalent, smaller one.
+    ///
+    /// Ops whose inputs are all [constants](Value::constant) are folded into
+    /// a single constant, identity ops (`* 1`, `+ 0`, `pow(1.0)`) are
+    /// dropped, and structurally identical subexpressions are merged. The
+    /// result shares the original's non-constant leaves, so its forward value
+    /// and the grads it backpropagates into them are unchanged.
+    pub fn optimize(&self) -> Value {
+        let mut rewritten: HashMap<Value, Value> = HashMap::new();
+        let mut consts: HashMap<_, Value> = HashMap::new();
+        let mut nodes: HashMap<(&'static str, Vec<u64>, Vec<usize>), Value> =
+            HashMap::new();
+        let bits = |x: Scalar| x.to_bits();
+        for v in self.topo() {
+            let node = v.0.node.read();
+            let prev: Vec<Value> =
+                node.prev.iter().map(|c| rewritten[c].clone()).collect();
+            let is_const_value = |i: usize, x: Scalar| {
+                prev[i].is_const() && prev[i].get_data() == x
+            };
+            let out = match &node.op {
+                Ops::None => v.clone(),
+                Ops::Const => consts
+                    .entry(bits(v.get_data()))
+                    .or_insert_with(|| v.clone())
+                    .clone(),
+                Ops::Add if is_const_value(0, 0.0) => prev[1].clone(),
+                Ops::Add if is_const_value(1, 0.0) => prev[0].clone(),
+                Ops::Mul if is_const_value(0, 1.0) => prev[1].clone(),
+                Ops::Mul if is_const_value(1, 1.0) => prev[0].clone(),
+                Ops::Pow(1.0) => prev[0].clone(),
+                op if prev.iter().all(Value::is_const) => {
+                    let data = Value::apply(op, &prev).get_data();
+                    consts
+                        .entry(bits(data))
+                        .or_insert_with(|| Value::constant(data))
+                        .clone()
+                }
+                op => {
+                    let mut children: Vec<usize> = prev
+                        .iter()
+                        .map(|c| Shared::as_ptr(&c.0) as usize)
+                        .collect();
+                    if let Ops::Add | Ops::Mul = op {
+                        children.sort_unstable();
+                    }
+                    nodes
+                        .entry((op.name(), op.payload(), children))
+                        .or_insert_with(|| Value::apply(op, &prev))
+                        .clone()
+                }
+            };
+            drop(node);
+            rewritten.insert(v, out);
+        }
+        rewritten[self].clone()
     }
 
-    pub fn get_data(&self) -> f32 {
-        self.0.borrow().data.get()
+    /// Tears down the graph behind `self` so its memory can be reclaimed
+    /// even while stray handles to it are still alive.
+    ///
+    /// Every reachable node with children drops them, while leaves such as
+    /// parameters keep their data and grad.
+    /// The released nodes keep their last data but calling
+    /// [`backward`](Value::backward) through any of them afterwards panics.
+    pub fn release(&self) {
+        for v in self.topo() {
+            let mut node = v.0.node.write();
+            if !node.prev.is_empty() {
+                node.prev = vec![];
+                node.op = Ops::Released;
+            }
+        }
     }
 
-    fn clone_grad(&self) -> Rc<Cell<f32>> {
-        self.0.borrow().grad.clone()
+    /// Compares two graphs by structure rather than by node identity.
+    ///
+    /// See [`Value::graph_diff`] for what is compared.
+    pub fn graph_eq(&self, other: &Value, data_tol: Scalar) -> bool {
+        self.graph_diff(other, data_tol).is_none()
+    }
+
+    /// Describes the first structural difference between two graphs, or
+    /// returns `None` if they match.
+    ///
+    /// Ops, data (within `data_tol`) and topology are compared, including
+    /// which nodes are shared, while grads are ignored.
+    pub fn graph_diff(
+        &self,
+        other: &Value,
+        data_tol: Scalar,
+    ) -> Option<String> {
+        let mut forward: HashMap<Value, Value> = HashMap::new();
+        let mut backward: HashMap<Value, Value> = HashMap::new();
+        let mut paths: HashMap<Value, String> = HashMap::new();
+        let mut stack = vec![(self.clone(), other.clone(), "root".to_string())];
+        while let Some((a, b, path)) = stack.pop() {
+            let a_seen = forward.get(&a).map(|mapped| *mapped == b);
+            let b_seen = backward.get(&b).map(|mapped| *mapped == a);
+            match (a_seen, b_seen) {
+                (Some(true), Some(true)) => continue,
+                (None, None) => {}
+                _ => {
+                    return Some(format!(
+                        "{}: node is shared differently",
+                        path
+                    ))
+                }
+            }
+            forward.insert(a.clone(), b.clone());
+            backward.insert(b.clone(), a.clone());
+            paths.insert(a.clone(), path.clone());
+            let (a, b) = (a.0.node.read(), b.0.node.read());
+            if a.op != b.op {
+                return Some(format!("{}: op {:?} vs {:?}", path, a.op, b.op));
+            }
+            if a.prev.len() != b.prev.len() {
+                return Some(format!(
+                    "{}: {} children vs {}",
+                    path,
+                    a.prev.len(),
+                    b.prev.len()
+                ));
+            }
+            for (i, (a_child, b_child)) in
+                a.prev.iter().zip(b.prev.iter()).enumerate().rev()
+            {
+                let child_path = format!("{}.prev[{}]", path, i);
+                stack.push((a_child.clone(), b_child.clone(), child_path));
+            }
+        }
+        // Data is compared children first so that the reported node is the
+        // source of a difference rather than something downstream of it.
+        for a in self.topo() {
+            let b = &forward[&a];
+            let (a_data, b_data) = (a.get_data(), b.get_data());
+            if !((a_data - b_data).abs() <= data_tol || a_data == b_data) {
+                return Some(format!(
+                    "{}: {:?} data {:?} vs {:?}",
+                    paths[&a],
+                    a.0.node.read().op,
+                    a_data,
+                    b_data
+                ));
+            }
+        }
+        None
     }
-    fn clone_data(&self) -> Rc<Cell<f32>> {
-        self.0.borrow().data.clone()
+
+    pub fn get_grad(&self) -> Scalar {
+        self.0.grad.get()
     }
 
-    fn set_backward(&self, func: Box<dyn Fn() -> ()>) {
-        self.0.borrow_mut().backward = func
+    pub fn get_data(&self) -> Scalar {
+        self.0.data.get()
     }
 
-    pub fn set_grad(&self, grad: f32) {
-        self.0.borrow().grad.set(grad)
+    pub fn set_grad(&self, grad: Scalar) {
+        self.0.grad.set(grad)
     }
 
-    fn pow(&self, rhs: f32) -> Self {
-        let out = Value::_new(
+    pub fn set_data(&self, data: Scalar) {
+        self.0.data.set(data)
+    }
+
+    /// Adds `delta` to the data, e.g. `w.add_data(-lr * w.get_grad())` for
+    /// a gradient descent step.
+    pub fn add_data(&self, delta: Scalar) {
+        self.0.data.add(delta)
+    }
+
+    /// Replaces the data with `f` applied to it.
+    pub fn update(&self, f: impl FnOnce(Scalar) -> Scalar) {
+        self.set_data(f(self.get_data()))
+    }
+
+    fn add_grad(&self, grad: Scalar) {
+        self.0.grad.add(grad)
+    }
+
+    pub fn pow(&self, rhs: Scalar) -> Self {
+        Value::_new(
             self.get_data().powf(rhs),
             vec![self.clone()],
-            Ops::Pow,
-        );
-        let self_grad = self.clone_grad();
-        let self_data = self.clone_data();
-        let out_grad = out.clone_grad();
-        let back = Box::new(move || {
-            self_grad.set(
-                self_grad.get()
-                    + (rhs * self_data.get().powf(rhs - 1.0)) * out_grad.get(),
-            )
-        }) as Box<dyn Fn() -> ()>;
-        out.set_backward(back);
-        out
+            Ops::Pow(rhs),
+        )
+    }
+
+    /// `e^x`, overflowing to inf once `x` is around 89 (710 under the `f64`
+    /// feature). The grad is the output itself.
+    pub fn exp(&self) -> Self {
+        Self::_new(self.get_data().exp(), vec![self.clone()], Ops::Exp)
+    }
+
+    pub fn ln(&self) -> Self {
+        Self::_new(self.get_data().ln(), vec![self.clone()], Ops::Ln)
+    }
+
+    pub fn sqrt(&self) -> Self {
+        Self::_new(self.get_data().sqrt(), vec![self.clone()], Ops::Sqrt)
+    }
+
+    /// `1 / sqrt(x)` as a single node, as in RMS norms; its grad is
+    /// `-out^3 / 2`.
+    pub fn rsqrt(&self) -> Self {
+        let data = 1.0 / self.get_data().sqrt();
+        Self::_new(data, vec![self.clone()], Ops::Rsqrt)
+    }
+
+    /// `|x|`, with a subgradient of 0 at 0 so that weights already at zero
+    /// stay put.
+    pub fn abs(&self) -> Self {
+        Self::_new(self.get_data().abs(), vec![self.clone()], Ops::Abs)
+    }
+
+    /// The error function `2/√π ∫₀ˣ e^(-t²) dt`. The forward pass uses
+    /// Abramowitz and Stegun's formula 7.1.26, within 1.5e-7 of the exact
+    /// value for every `x` (even under the `f64` feature); the backward
+    /// pass is exact.
+    pub fn erf(&self) -> Self {
+        Self::_new(erf(self.get_data()), vec![self.clone()], Ops::Erf)
+    }
+
+    pub fn sin(&self) -> Self {
+        Self::_new(self.get_data().sin(), vec![self.clone()], Ops::Sin)
+    }
+
+    pub fn cos(&self) -> Self {
+        Self::_new(self.get_data().cos(), vec![self.clone()], Ops::Cos)
+    }
+
+    pub fn tan(&self) -> Self {
+        Self::_new(self.get_data().tan(), vec![self.clone()], Ops::Tan)
+    }
+
+    pub fn atan(&self) -> Self {
+        Self::_new(self.get_data().atan(), vec![self.clone()], Ops::Atan)
+    }
+
+    /// The angle of the point `(x, self)` in `[-π, π]`, as
+    /// [`f32::atan2`]. The output jumps by 2π as the point crosses the
+    /// negative x axis, but the grads, `x / r²` for `self` and `-y / r²`
+    /// for `x`, don't. At the origin the angle is 0 and no grad flows.
+    pub fn atan2(&self, x: &Value) -> Self {
+        Self::_new(
+            self.get_data().atan2(x.get_data()),
+            vec![self.clone(), x.clone()],
+            Ops::Atan2,
+        )
+    }
+
+    /// `(e^x - e^-x) / 2`, overflowing to ±inf once `|x|` is around 89
+    /// (710 under the `f64` feature).
+    pub fn sinh(&self) -> Self {
+        Self::_new(self.get_data().sinh(), vec![self.clone()], Ops::Sinh)
+    }
+
+    /// `(e^x + e^-x) / 2`; overflows like [`sinh`](Value::sinh).
+    pub fn cosh(&self) -> Self {
+        Self::_new(self.get_data().cosh(), vec![self.clone()], Ops::Cosh)
+    }
+
+    /// `self + t (other - self)` as a single node: `self` at `t = 0` and
+    /// `other` at `t = 1`. Grads flow into all three operands.
+    pub fn lerp(&self, other: &Value, t: &Value) -> Self {
+        let (a, b) = (self.get_data(), other.get_data());
+        Self::_new(
+            a + t.get_data() * (b - a),
+            vec![self.clone(), other.clone(), t.clone()],
+            Ops::Lerp,
+        )
+    }
+
+    /// [`lerp`](Value::lerp) with a fixed weight `t`.
+    pub fn lerp_scalar(&self, other: &Value, t: Scalar) -> Self {
+        self.lerp(other, &Value::constant(t))
+    }
+
+    /// The larger of `self` and `other`, with the whole grad going to the
+    /// one picked: `self` on a tie, and whichever isn't NaN if one is.
+    pub fn maximum(&self, other: &Value) -> Self {
+        self.pick(other, Ops::Maximum)
+    }
+
+    /// The smaller of `self` and `other`; ties and NaNs as for
+    /// [`maximum`](Value::maximum).
+    pub fn minimum(&self, other: &Value) -> Self {
+        self.pick(other, Ops::Minimum)
+    }
+
+    /// `self` limited to `[lo, hi]`. The grad only flows back from inside
+    /// the interval, bounds included.
+    ///
+    /// # Panics
+    ///
+    /// If `lo > hi`, or either is NaN.
+    pub fn clamp(&self, lo: Scalar, hi: Scalar) -> Self {
+        assert!(lo <= hi, "clamp needs lo <= hi, got [{}, {}]", lo, hi);
+        let data = self.get_data().clamp(lo, hi);
+        Self::_new(data, vec![self.clone()], Ops::Clamp(lo, hi))
+    }
+
+    fn pick(&self, other: &Value, op: Ops) -> Self {
+        let (a, b) = (self.get_data(), other.get_data());
+        let data = if picks_first(a, b, op == Ops::Maximum) {
+            a
+        } else {
+            b
+        };
+        Self::_new(data, vec![self.clone(), other.clone()], op)
+    }
+
+    /// GELU with the tanh approximation of the Gaussian CDF, as in BERT
+    /// and GPT-2, as a single node; see [`gelu_exact`](Value::gelu_exact)
+    /// for the exact form, which is within 1e-3 of it.
+    pub fn gelu(&self) -> Self {
+        Self::_new(gelu(self.get_data()), vec![self.clone()], Ops::Gelu)
+    }
+
+    /// GELU with the exact Gaussian CDF: `x Φ(x) = x (1 + erf(x/√2)) / 2`.
+    pub fn gelu_exact(&self) -> Self {
+        let sqrt_half = std::f64::consts::FRAC_1_SQRT_2 as Scalar;
+        let cdf = ((self * sqrt_half).erf() + 1.0) * 0.5;
+        self * cdf
+    }
+
+    /// Applies a user-defined unary op.
+    ///
+    /// `backward` receives the input's data, the output's data and the
+    /// output's grad, and returns the grad to accumulate into the input.
+    /// `name` shows up when the graph is printed or compared.
+    pub fn custom_unary(
+        x: &Value,
+        forward: impl Fn(Scalar) -> Scalar + MaybeSync + 'static,
+        backward: impl Fn(Scalar, Scalar, Scalar) -> Scalar + MaybeSync + 'static,
+        name: &str,
+    ) -> Value {
+        let op = Ops::Custom(Shared::new(CustomOp {
+            name: name.to_string(),
+            kind: CustomKind::Unary {
+                forward: Box::new(forward),
+                backward: Box::new(backward),
+            },
+        }));
+        Value::apply(&op, std::slice::from_ref(x))
+    }
+
+    /// Applies a user-defined binary op.
+    ///
+    /// `backward` receives both inputs' data, the output's data and the
+    /// output's grad, and returns the grads to accumulate into `lhs` and
+    /// `rhs`.
+    pub fn custom_binary(
+        lhs: &Value,
+        rhs: &Value,
+        forward: impl Fn(Scalar, Scalar) -> Scalar + MaybeSync + 'static,
+        backward: impl Fn(Scalar, Scalar, Scalar, Scalar) -> (Scalar, Scalar)
+            + MaybeSync
+            + 'static,
+        name: &str,
+    ) -> Value {
+        let op = Ops::Custom(Shared::new(CustomOp {
+            name: name.to_string(),
+            kind: CustomKind::Binary {
+                forward: Box::new(forward),
+                backward: Box::new(backward),
+            },
+        }));
+        Value::apply(&op, &[lhs.clone(), rhs.clone()])
     }
 
     pub fn relu(&self) -> Self {
-        let out = if self.get_data() >= 0.0 {
-            Self::_new(self.get_data(), vec![self.clone()], Ops::ReLU)
+        let data = if self.get_data() >= 0.0 {
+            self.get_data()
         } else {
-            Self::_new(0.0, vec![self.clone()], Ops::ReLU)
+            0.0
         };
-        let self_grad = self.clone_grad();
-        let out_data = out.clone_data();
-        let out_grad = out.clone_grad();
-        let back = Box::new(move || {
-            self_grad.set(
-                self_grad.get()
-                    + ((out_data.get() > 0.0) as u8 as f32) * out_grad.get(),
-            )
-        });
-        out.set_backward(back);
-        out
+        Self::_new(data, vec![self.clone()], Ops::ReLU)
+    }
+
+    /// ReLU that lets negative inputs through scaled by `slope`.
+    pub fn leaky_relu(&self, slope: Scalar) -> Self {
+        let x = self.get_data();
+        let data = if x > 0.0 { x } else { slope * x };
+        Self::_new(data, vec![self.clone()], Ops::LeakyReLU(slope))
+    }
+
+    /// [`leaky_relu`](Value::leaky_relu) with a slope that is itself a
+    /// node, usually a parameter, so it gets a grad from negative inputs.
+    pub fn prelu(&self, alpha: &Value) -> Self {
+        let x = self.get_data();
+        let data = if x > 0.0 { x } else { alpha.get_data() * x };
+        Self::_new(data, vec![self.clone(), alpha.clone()], Ops::PReLU)
+    }
+
+    pub fn tanh(&self) -> Self {
+        Self::_new(self.get_data().tanh(), vec![self.clone()], Ops::Tanh)
+    }
+
+    /// The logistic function `1 / (1 + e^-x)`.
+    pub fn sigmoid(&self) -> Self {
+        let data = 1.0 / (1.0 + (-self.get_data()).exp());
+        Self::_new(data, vec![self.clone()], Ops::Sigmoid)
+    }
+}
+
+const SQRT_2_OVER_PI: Scalar = (std::f64::consts::FRAC_2_SQRT_PI
+    * std::f64::consts::FRAC_1_SQRT_2) as Scalar;
+const GELU_CUBIC: Scalar = 0.044715;
+
+/// `x Φ(x) ≈ x (1 + tanh(√(2/π) (x + 0.044715 x³))) / 2`, the forward pass
+/// of [`Value::gelu`].
+pub(crate) fn gelu(x: Scalar) -> Scalar {
+    let inner = SQRT_2_OVER_PI * (x + GELU_CUBIC * x * x * x);
+    0.5 * x * (1.0 + inner.tanh())
+}
+
+/// The derivative of [`gelu`].
+fn gelu_grad(x: Scalar) -> Scalar {
+    let inner = SQRT_2_OVER_PI * (x + GELU_CUBIC * x * x * x);
+    let t = inner.tanh();
+    let inner_grad = SQRT_2_OVER_PI * (1.0 + 3.0 * GELU_CUBIC * x * x);
+    0.5 * (1.0 + t) + 0.5 * x * (1.0 - t * t) * inner_grad
+}
+
+/// Whether [`Value::maximum`] (or with `max` false, [`Value::minimum`])
+/// picks `a` over `b`.
+fn picks_first(a: Scalar, b: Scalar, max: bool) -> bool {
+    b.is_nan() || if max { a >= b } else { a <= b }
+}
+
+/// `ln(sum(e^x))` over `xs` as a single node, shifted by the largest input
+/// so that it stays finite however large the inputs are. The grad of each
+/// input is its softmax weight.
+///
+/// # Panics
+///
+/// If `xs` is empty.
+pub fn logsumexp(xs: &[Value]) -> Value {
+    assert!(!xs.is_empty(), "logsumexp of no values");
+    let max = xs
+        .iter()
+        .map(Value::get_data)
+        .fold(Scalar::NEG_INFINITY, Scalar::max);
+    let sum: Scalar = xs.iter().map(|x| (x.get_data() - max).exp()).sum();
+    Value::_new(max + sum.ln(), xs.to_vec(), Ops::LogSumExp)
+}
+
+/// `temperature * logsumexp(xs / temperature)`, a differentiable stand-in
+/// for the maximum of `xs` that approaches it as `temperature` goes to 0
+/// and always lies above it.
+///
+/// # Panics
+///
+/// If `xs` is empty.
+pub fn smooth_max(xs: &[Value], temperature: Scalar) -> Value {
+    let scaled: Vec<_> = xs.iter().map(|x| x / temperature).collect();
+    logsumexp(&scaled) * temperature
+}
+
+/// Abramowitz and Stegun 7.1.26 for `|x|`, mirrored for negative `x`.
+/// Zeros and NaN come back as they are.
+#[allow(clippy::excessive_precision)] // Scalar may be f64
+fn erf(x: Scalar) -> Scalar {
+    const P: Scalar = 0.327_591_1;
+    const A: [Scalar; 5] = [
+        0.254_829_592,
+        -0.284_496_736,
+        1.421_413_741,
+        -1.453_152_027,
+        1.061_405_429,
+    ];
+    let z = x.abs();
+    let t = 1.0 / (1.0 + P * z);
+    let poly = A.iter().rev().fold(0.0, |acc, &a| acc * t + a) * t;
+    let y = 1.0 - poly * (-z * z).exp();
+    if x > 0.0 {
+        y
+    } else if x < 0.0 {
+        -y
+    } else {
+        x
+    }
+}
+
+/// -1, 0 or 1; unlike `signum`, 0 for both zeros.
+fn sign(x: Scalar) -> Scalar {
+    if x > 0.0 {
+        1.0
+    } else if x < 0.0 {
+        -1.0
+    } else {
+        0.0
     }
 }
 
@@ -177,20 +1032,11 @@ impl Add<Self> for &Value {
     type Output = Value;
 
     fn add(self, rhs: Self) -> Self::Output {
-        let out = Value::_new(
+        Value::_new(
             self.get_data() + rhs.get_data(),
             vec![self.clone(), rhs.clone()],
             Ops::Add,
-        );
-        let self_grad = self.clone_grad();
-        let rhs_grad = rhs.clone_grad();
-        let out_grad = out.clone_grad();
-        let back = Box::new(move || {
-            self_grad.set(self_grad.get() + out_grad.get());
-            rhs_grad.set(rhs_grad.get() + out_grad.get())
-        }) as Box<dyn Fn() -> ()>;
-        out.set_backward(back);
-        out
+        )
     }
 }
 
@@ -218,11 +1064,11 @@ impl Add<&Value> for Value {
     }
 }
 
-impl Add<f32> for Value {
+impl Add<Scalar> for Value {
     type Output = Value;
 
-    fn add(self, rhs: f32) -> Self::Output {
-        let v = Value::new(rhs);
+    fn add(self, rhs: Scalar) -> Self::Output {
+        let v = Value::constant(rhs);
         &self + v
     }
 }
@@ -255,22 +1101,11 @@ impl Mul<Self> for &Value {
     type Output = Value;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        let out = Value::_new(
+        Value::_new(
             self.get_data() * rhs.get_data(),
             vec![self.clone(), rhs.clone()],
             Ops::Mul,
-        );
-        let self_grad = self.clone_grad();
-        let self_data = self.clone_data();
-        let rhs_grad = rhs.clone_grad();
-        let rhs_data = rhs.clone_data();
-        let out_grad = out.clone_grad();
-        let back = Box::new(move || {
-            self_grad.set(self_grad.get() + rhs_data.get() * out_grad.get());
-            rhs_grad.set(rhs_grad.get() + self_data.get() * out_grad.get())
-        }) as Box<dyn Fn() -> ()>;
-        out.set_backward(back);
-        out
+        )
     }
 }
 
@@ -290,20 +1125,20 @@ impl Mul<Value> for Value {
     }
 }
 
-impl Mul<f32> for &Value {
+impl Mul<Scalar> for &Value {
     type Output = Value;
 
-    fn mul(self, rhs: f32) -> Self::Output {
-        let rhs = Value::new(rhs);
+    fn mul(self, rhs: Scalar) -> Self::Output {
+        let rhs = Value::constant(rhs);
         self * rhs
     }
 }
 
-impl Mul<f32> for Value {
+impl Mul<Scalar> for Value {
     type Output = Value;
 
-    fn mul(self, rhs: f32) -> Self::Output {
-        let rhs = Value::new(rhs);
+    fn mul(self, rhs: Scalar) -> Self::Output {
+        let rhs = Value::constant(rhs);
         &self * rhs
     }
 }
@@ -316,15 +1151,15 @@ impl Div<Self> for &Value {
     }
 }
 
-impl Div<f32> for &Value {
+impl Div<Scalar> for &Value {
     type Output = Value;
 
-    fn div(self, rhs: f32) -> Self::Output {
+    fn div(self, rhs: Scalar) -> Self::Output {
         self * (1.0 / rhs)
     }
 }
 
-impl Div<Value> for f32 {
+impl Div<Value> for Scalar {
     type Output = Value;
 
     fn div(self, rhs: Value) -> Self::Output {
@@ -332,95 +1167,996 @@ impl Div<Value> for f32 {
     }
 }
 
+/// A domain error caught by one of the `checked_*` operations on [`Value`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MathError {
+    /// `lhs / rhs` with `rhs == 0`.
+    DivByZero { lhs: Scalar },
+    /// `ln(x)` with `x <= 0`.
+    LnOfNonPositive { x: Scalar },
+    /// `base.pow(exp)` that has no real result: a negative base with a
+    /// fractional exponent, or zero raised to a negative power.
+    Pow { base: Scalar, exp: Scalar },
+    /// `sqrt(x)` with `x < 0`.
+    SqrtOfNegative { x: Scalar },
+}
+
+impl Display for MathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MathError::DivByZero { lhs } => write!(f, "div: {} / 0", lhs),
+            MathError::LnOfNonPositive { x } => {
+                write!(f, "ln: {} is not positive", x)
+            }
+            MathError::Pow { base, exp } => {
+                write!(f, "pow: {}^{} has no real value", base, exp)
+            }
+            MathError::SqrtOfNegative { x } => {
+                write!(f, "sqrt: {} is negative", x)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MathError {}
+
+/// Variants of the fallible ops that return a [`MathError`] instead of
+/// silently producing inf or NaN. On success the graph is the same as the
+/// one built by the unchecked op.
+impl Value {
+    pub fn checked_div(&self, rhs: &Value) -> Result<Value, MathError> {
+        if rhs.get_data() == 0.0 {
+            return Err(MathError::DivByZero {
+                lhs: self.get_data(),
+            });
+        }
+        Ok(self / rhs)
+    }
+
+    pub fn checked_ln(&self) -> Result<Value, MathError> {
+        if self.get_data() <= 0.0 {
+            return Err(MathError::LnOfNonPositive { x: self.get_data() });
+        }
+        Ok(self.ln())
+    }
+
+    pub fn checked_pow(&self, exp: Scalar) -> Result<Value, MathError> {
+        let base = self.get_data();
+        if (base < 0.0 && exp.fract() != 0.0) || (base == 0.0 && exp < 0.0) {
+            return Err(MathError::Pow { base, exp });
+        }
+        Ok(self.pow(exp))
+    }
+
+    pub fn checked_sqrt(&self) -> Result<Value, MathError> {
+        if self.get_data() < 0.0 {
+            return Err(MathError::SqrtOfNegative { x: self.get_data() });
+        }
+        Ok(self.sqrt())
+    }
+}
+
+#[cfg(test)]
 mod test {
 
     use super::*;
+    use crate::testing::{
+        assert_all_grads_close, assert_grad_close, assert_grads_match_numeric,
+        assert_value_close,
+    };
     #[test]
     fn test_add() {
-        let ref a = Value::new(1.0);
-        let ref b = Value::new(2.0);
-        let ref c = a + b;
+        let a = &Value::new(1.0);
+        let b = &Value::new(2.0);
+        let c = &(a + b);
         let d = c + b;
         d.backward();
-        assert_eq!(b.get_grad(), 2.0);
+        assert_grad_close(b, 2.0, 1e-6);
     }
 
     #[test]
     fn test_sub() {
-        let ref a = Value::new(1.0);
-        let ref b = Value::new(2.0);
-        let ref c = a - b;
+        let a = &Value::new(1.0);
+        let b = &Value::new(2.0);
+        let c = &(a - b);
         let d = c - b;
         d.backward();
-        assert_eq!(b.get_grad(), -2.0);
+        assert_grad_close(b, -2.0, 1e-6);
     }
 
     #[test]
     fn test_mul() {
-        let ref a = Value::new(1.0);
-        let ref b = Value::new(2.0);
-        let ref c = a + b;
+        let a = &Value::new(1.0);
+        let b = &Value::new(2.0);
+        let c = &(a + b);
         let d = c * b;
         d.backward();
-        assert_eq!(b.get_grad(), 5.0);
+        assert_grad_close(b, 5.0, 1e-6);
     }
 
     #[test]
     fn test_mul_neg() {
-        let ref a = Value::new(1.0);
-        let ref b = Value::new(2.0);
-        let ref c = a - b;
+        let a = &Value::new(1.0);
+        let b = &Value::new(2.0);
+        let c = &(a - b);
         let d = c * b;
         d.backward();
-        assert_eq!(b.get_grad(), -3.0);
+        assert_grad_close(b, -3.0, 1e-6);
     }
 
     #[test]
     fn test_pow() {
-        let ref a = Value::new(1.0);
-        let ref b = Value::new(2.0);
-        let ref c = a + b;
+        let a = &Value::new(1.0);
+        let b = &Value::new(2.0);
+        let c = &(a + b);
         let d = c.pow(2.0);
         d.backward();
-        assert_eq!(b.get_grad(), 6.0);
+        assert_grad_close(b, 6.0, 1e-6);
     }
 
     #[test]
+    #[allow(non_snake_case)]
     fn test_ReLU() {
         let a = Value::new(1.0);
-        let ref b = Value::new(2.0);
+        let b = &Value::new(2.0);
         let c = a + (b * 2.0);
         let d = c.relu();
         let e = d * 2.0;
         e.backward();
-        assert_eq!(b.get_grad(), 4.0);
+        assert_grad_close(b, 4.0, 1e-6);
     }
 
     #[test]
+    #[allow(non_snake_case)]
     fn test_ReLU_neg() {
         let a = Value::new(1.0);
-        let ref b = Value::new(2.0);
+        let b = &Value::new(2.0);
         let c = a - (b * 2.0);
         let d = c.relu();
         let e = d * 2.0;
         e.backward();
-        assert_eq!(b.get_grad(), 0.0);
+        assert_grad_close(b, 0.0, 1e-6);
     }
 
     #[test]
     fn test_div() {
-        let ref a = Value::new(1.0);
-        let ref b = Value::new(2.0);
-        let ref c = a + b;
+        let a = &Value::new(1.0);
+        let b = &Value::new(2.0);
+        let c = &(a + b);
         let d = c / b;
         d.backward();
-        assert_eq!(b.get_grad(), -0.25);
+        assert_grad_close(b, -0.25, 1e-6);
+    }
+
+    #[test]
+    fn test_contrived() {
+        let a = &Value::new(-4.0);
+        let b = &Value::new(2.0);
+        let mut c = a + b;
+        let mut d = a * b + b.pow(3.0);
+        c = c.clone() + c + 1.0;
+        c = c.clone() + 1.0 + c.clone() + (-a);
+        d = d.clone() + d.clone() * 2.0 + (b + a).relu();
+        d = d.clone() + d.clone() * 3.0 + (b - a).relu();
+        let e = c - d;
+        let f = e.pow(2.0);
+        let mut g = f.clone().div(2.0);
+        g = g + 10.0 / f;
+        assert_value_close(&g, 24.7041, 1e-4);
+        g.backward();
+        assert_all_grads_close(&[(a, 138.8338), (b, 645.5773)], 1e-4);
+    }
+
+    #[test]
+    fn test_deep_copy() {
+        let a = &Value::new(-4.0);
+        let b = &Value::new(2.0);
+        let c = &(a * b);
+        let d = (c + b).pow(2.0) + c.relu();
+        let copy = d.deep_copy();
+        assert_eq!(copy.get_data(), d.get_data());
+
+        d.backward();
+        copy.backward();
+        let copy_leaves = copy.topo();
+        let (copy_a, copy_b) = (&copy_leaves[0], &copy_leaves[1]);
+        assert_eq!(copy_a.get_data(), -4.0);
+        assert_eq!(copy_a.get_grad(), a.get_grad());
+        assert_eq!(copy_b.get_grad(), b.get_grad());
+
+        a.0.data.set(10.0);
+        assert_eq!(copy_a.get_data(), -4.0);
+        assert_eq!(copy.get_data(), 36.0);
+    }
+
+    #[test]
+    fn test_deep_copy_sharing() {
+        let a = &Value::new(3.0);
+        let b = &(a * a);
+        let c = b + b;
+        let copy = c.deep_copy();
+        assert_eq!(copy.topo().len(), c.topo().len());
+        let copy_prev = copy.0.node.read().prev.clone();
+        assert!(copy_prev[0] == copy_prev[1]);
+        assert!(copy_prev[0] != *b);
+        let copy_b_prev = copy_prev[0].0.node.read().prev.clone();
+        assert!(copy_b_prev[0] == copy_b_prev[1]);
+        assert!(copy_b_prev[0] != *a);
+    }
+
+    #[test]
+    fn test_graph_eq() {
+        let build = |k: Scalar| {
+            let a = &Value::new(-4.0);
+            let b = &Value::new(2.0);
+            let c = &(a * b);
+            (c + b).pow(2.0) + c.relu() + k
+        };
+        assert!(build(1.0).graph_eq(&build(1.0), 0.0));
+        assert!(build(1.0).graph_eq(&build(1.0).deep_copy(), 0.0));
+        assert!(build(1.0).graph_eq(&build(1.001), 0.01));
+
+        let diff = build(1.0).graph_diff(&build(2.0), 1e-6).unwrap();
+        assert!(!build(1.0).graph_eq(&build(2.0), 1e-6));
+        assert_eq!(diff, "root.prev[1]: Const data 1.0 vs 2.0");
+    }
+
+    #[test]
+    fn test_graph_eq_sharing() {
+        let a = &Value::new(3.0);
+        let shared = a * a;
+        let a = &Value::new(3.0);
+        let b = &Value::new(3.0);
+        let unshared = a * b;
+        let diff = shared.graph_diff(&unshared, 0.0).unwrap();
+        assert_eq!(diff, "root.prev[1]: node is shared differently");
+    }
+
+    #[test]
+    fn test_release() {
+        let a = &Value::new(-4.0);
+        let b = &Value::new(2.0);
+        let c = &(a * b);
+        let d = (c + b).pow(2.0) + c.relu();
+        assert!(Shared::strong_count(&a.0) > 1);
+        d.backward();
+        d.release();
+        assert_eq!(Shared::strong_count(&a.0), 1);
+        assert_eq!(Shared::strong_count(&b.0), 1);
+        assert_eq!(a.get_data(), -4.0);
+        assert_eq!(a.get_grad(), -24.0);
+        assert_eq!(d.get_data(), 36.0);
+        assert!(c.0.node.read().prev.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "released")]
+    fn test_backward_after_release() {
+        let a = &Value::new(1.0);
+        let b = a * 2.0;
+        let c = &b + a;
+        b.release();
+        c.backward();
+    }
+
+    #[test]
+    fn test_set_data() {
+        let a = Value::new(1.0);
+        a.set_data(3.0);
+        assert_eq!(a.get_data(), 3.0);
+        a.add_data(-0.5);
+        assert_eq!(a.get_data(), 2.5);
+        a.update(|x| x * 2.0);
+        assert_eq!(a.get_data(), 5.0);
+    }
+
+    #[test]
+    fn test_manual_sgd() {
+        let w = [Value::new(0.5), Value::new(-0.5)];
+        let b = Value::new(0.0);
+        let xs: [[Scalar; 2]; 3] = [[1.0, 2.0], [2.0, -1.0], [-1.0, 0.5]];
+        let ys: [Scalar; 3] = [3.0, 1.0, -0.5];
+        let loss = || {
+            xs.iter()
+                .zip(ys.iter())
+                .fold(Value::new(0.0), |acc, (x, y)| {
+                    let pred = &w[0] * x[0] + &w[1] * x[1] + &b;
+                    let diff = pred + (-y);
+                    acc + &diff * &diff
+                })
+        };
+        let mut losses = vec![];
+        for _ in 0..10 {
+            let loss = loss();
+            for p in w.iter().chain(Some(&b)) {
+                p.set_grad(0.0);
+            }
+            loss.backward();
+            for p in w.iter().chain(Some(&b)) {
+                p.add_data(-0.02 * p.get_grad());
+            }
+            losses.push(loss.get_data());
+        }
+        assert!(losses.windows(2).all(|l| l[1] < l[0]));
+        assert!(loss().get_data() < losses[0] / 2.0);
+    }
+
+    #[test]
+    fn test_constant_gets_no_grad() {
+        let (x, c) = (Value::new(3.0), Value::constant(2.0));
+        let y = &x * &c + 1.0;
+        y.backward();
+        assert_eq!(x.get_grad(), 2.0);
+        assert_eq!(c.get_grad(), 0.0);
+    }
+
+    #[test]
+    fn test_exp() {
+        let a = &Value::new(1.5);
+        let b = a.exp() * 2.0;
+        b.backward();
+        let e = (1.5 as Scalar).exp();
+        assert_eq!(b.get_data(), 2.0 * e);
+        assert_eq!(a.get_grad(), 2.0 * e);
+        // ln(e^x) e^x = x e^x, with grad (1 + x) e^x
+        let x = Value::new(-0.7);
+        let params = [x.clone()];
+        assert_grads_match_numeric(
+            &params,
+            |_| x.exp().ln() * x.exp(),
+            1e-3,
+            1e-3,
+        );
+        assert!(Value::new(1000.0).exp().get_data().is_infinite());
+    }
+
+    #[test]
+    fn test_ln() {
+        let a = &Value::new(2.0);
+        let b = a.ln() * 3.0;
+        b.backward();
+        assert_eq!(b.get_data(), 3.0 * (2.0 as Scalar).ln());
+        assert_eq!(a.get_grad(), 1.5);
+    }
+
+    #[test]
+    fn test_sqrt() {
+        let a = &Value::new(4.0);
+        let b = a.sqrt() * 3.0;
+        b.backward();
+        assert_eq!(b.get_data(), 6.0);
+        assert_eq!(a.get_grad(), 0.75);
+    }
+
+    #[test]
+    fn test_rsqrt() {
+        let a = &Value::new(4.0);
+        let b = a.rsqrt() * 3.0;
+        b.backward();
+        assert_eq!(b.get_data(), 1.5);
+        assert_eq!(a.get_grad(), -3.0 / 16.0);
+        let x = Value::new(0.3);
+        let params = [x.clone()];
+        assert_grads_match_numeric(&params, |_| x.rsqrt(), 1e-4, 1e-3);
+        assert!(
+            (x.rsqrt().get_data() - 1.0 / x.sqrt().get_data()).abs() < 1e-6
+        );
+    }
+
+    #[test]
+    fn test_abs() {
+        let xs = [Value::new(-1.5), Value::new(0.0), Value::new(2.0)];
+        for x in &xs {
+            x.abs().backward();
+        }
+        let data: Vec<_> = xs.iter().map(|x| x.abs().get_data()).collect();
+        let grads: Vec<_> = xs.iter().map(Value::get_grad).collect();
+        assert_eq!(data, [1.5, 0.0, 2.0]);
+        assert_eq!(grads, [-1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_erf() {
+        let zero = &Value::new(0.0);
+        let out = zero.erf();
+        out.backward();
+        assert_eq!(out.get_data(), 0.0);
+        let slope = std::f64::consts::FRAC_2_SQRT_PI as Scalar;
+        assert_grad_close(zero, slope, 1e-6);
+
+        // erf(0.5), erf(1) and erf(-2)
+        let exact =
+            [(0.5, 0.520_499_9), (1.0, 0.842_700_8), (-2.0, -0.995_322_3)];
+        for (x, y) in exact {
+            assert_value_close(&Value::new(x).erf(), y, 3e-7);
+        }
+        for x in [-30.0, 30.0, Scalar::INFINITY] {
+            let x = &Value::new(x);
+            let out = x.erf();
+            out.backward();
+            assert_eq!(out.get_data(), x.get_data().signum());
+            assert_eq!(x.get_grad(), 0.0);
+        }
+        assert!(Value::new(Scalar::NAN).erf().get_data().is_nan());
+
+        let x = Value::new(0.7);
+        let params = [x.clone()];
+        assert_grads_match_numeric(&params, |_| x.erf() * 2.0, 1e-3, 1e-3);
+    }
+
+    #[test]
+    fn test_sin_cos() {
+        let a = &Value::new(0.5);
+        let (s, c) = (a.sin(), a.cos());
+        (&s + &c).backward();
+        let (sin, cos) = ((0.5 as Scalar).sin(), (0.5 as Scalar).cos());
+        assert_value_close(&s, sin, 1e-6);
+        assert_value_close(&c, cos, 1e-6);
+        assert_grad_close(a, cos - sin, 1e-6);
+
+        // sin^2 + cos^2 = 1 has no slope anywhere
+        let x = Value::new(2.0);
+        let params = [x.clone()];
+        let f = |_: &[Value]| x.sin().pow(2.0) + x.cos().pow(2.0);
+        assert_grads_match_numeric(&params, f, 1e-3, 1e-3);
+        assert_grad_close(&x, 0.0, 1e-5);
+    }
+
+    #[test]
+    fn test_fit_sinusoid() {
+        // Recover the amplitude and phase of 1.5 sin(t + 0.7)
+        let (amp, phase) = (Value::new(1.0), Value::new(0.0));
+        let ts: Vec<Scalar> = (0..20).map(|i| i as Scalar * 0.3).collect();
+        for _ in 0..300 {
+            let loss = ts
+                .iter()
+                .map(|&t| {
+                    let target = 1.5 * (t + 0.7).sin();
+                    let err = &amp * (phase.clone() + t).sin() + -target;
+                    &err * &err
+                })
+                .fold(Value::new(0.0), |acc, e| acc + e);
+            amp.set_grad(0.0);
+            phase.set_grad(0.0);
+            loss.backward();
+            amp.add_data(-0.01 * amp.get_grad());
+            phase.add_data(-0.01 * phase.get_grad());
+        }
+        assert_value_close(&amp, 1.5, 1e-3);
+        assert_value_close(&phase, 0.7, 1e-3);
+    }
+
+    #[test]
+    fn test_tan_atan() {
+        let a = &Value::new(0.5);
+        let t = a.tan();
+        t.backward();
+        assert_value_close(&t, (0.5 as Scalar).tan(), 1e-6);
+        assert_grad_close(a, 1.0 / (0.5 as Scalar).cos().powi(2), 1e-5);
+
+        let b = &Value::new(-2.0);
+        let out = b.atan();
+        out.backward();
+        assert_value_close(&out, (-2.0 as Scalar).atan(), 1e-6);
+        assert_grad_close(b, 0.2, 1e-6);
+
+        // atan undoes tan, so the chain has slope 1
+        let c = Value::new(0.3);
+        let params = [c.clone()];
+        assert_grads_match_numeric(&params, |_| c.tan().atan(), 1e-3, 1e-3);
+        c.set_grad(0.0);
+        c.tan().atan().backward();
+        assert_grad_close(&c, 1.0, 1e-5);
+    }
+
+    #[test]
+    fn test_atan2_quadrants() {
+        let pi = std::f64::consts::PI as Scalar;
+        let cases = [
+            (1.0, 1.0, pi / 4.0),
+            (1.0, -1.0, 3.0 * pi / 4.0),
+            (-1.0, -1.0, -3.0 * pi / 4.0),
+            (-1.0, 1.0, -pi / 4.0),
+            (0.0, -1.0, pi),
+            (2.0, 0.0, pi / 2.0),
+        ];
+        for (y, x, angle) in cases {
+            let (y, x) = (&Value::new(y), &Value::new(x));
+            let out = y.atan2(x);
+            out.backward();
+            assert_value_close(&out, angle, 1e-6);
+            let r2 = y.get_data().powi(2) + x.get_data().powi(2);
+            let expected = [(y, x.get_data() / r2), (x, -y.get_data() / r2)];
+            assert_all_grads_close(&expected, 1e-6);
+        }
+        let (y, x) = (Value::new(0.4), Value::new(-1.3));
+        let params = [y.clone(), x.clone()];
+        assert_grads_match_numeric(&params, |_| y.atan2(&x), 1e-3, 1e-3);
+    }
+
+    #[test]
+    fn test_atan2_origin_and_branch_cut() {
+        let (y, x) = (&Value::new(0.0), &Value::new(0.0));
+        let out = y.atan2(x);
+        out.backward();
+        assert_eq!(out.get_data(), 0.0);
+        assert_all_grads_close(&[(y, 0.0), (x, 0.0)], 0.0);
+
+        // Just above and below the negative x axis the angle jumps from
+        // about π to about -π, but the grads agree
+        let pi = std::f64::consts::PI as Scalar;
+        let mut grads = vec![];
+        for side in [1.0, -1.0] {
+            let (y, x) = (&Value::new(side * 1e-4), &Value::new(-1.0));
+            let out = y.atan2(x);
+            out.backward();
+            assert_value_close(&out, side * pi, 1e-3);
+            grads.push((y.get_grad(), x.get_grad()));
+        }
+        for (dy, dx) in &grads {
+            assert!((dy + 1.0).abs() < 1e-6, "d/dy = {}", dy);
+            assert!(dx.abs() < 1e-3, "d/dx = {}", dx);
+        }
+    }
+
+    #[test]
+    fn test_sinh_cosh() {
+        for x in [-3.0, -0.5, 0.0, 1.2, 4.0] {
+            let a = &Value::new(x);
+            let (s, c) = (a.sinh(), a.cosh());
+            // cancellation loses precision in proportion to cosh^2
+            let identity = &c * &c - &s * &s;
+            let tol = 1e-6 * c.get_data().powi(2);
+            assert_value_close(&identity, 1.0, tol);
+            s.backward();
+            assert_grad_close(a, c.get_data(), 1e-5);
+            a.set_grad(0.0);
+            c.backward();
+            assert_grad_close(a, s.get_data(), 1e-5);
+        }
+        let x = Value::new(0.8);
+        let params = [x.clone()];
+        let catenary = |_: &[Value]| (&x * 0.5).cosh() * 2.0 + x.sinh();
+        assert_grads_match_numeric(&params, catenary, 1e-3, 1e-3);
+    }
+
+    #[test]
+    fn test_sinh_cosh_overflow() {
+        let x = &Value::new(1000.0);
+        let (s, c) = (x.sinh(), x.cosh());
+        assert_eq!(s.get_data(), Scalar::INFINITY);
+        assert_eq!(c.get_data(), Scalar::INFINITY);
+        (s + c).backward();
+        assert_eq!(x.get_grad(), Scalar::INFINITY);
+        assert_eq!(Value::new(-1000.0).sinh().get_data(), -Scalar::INFINITY);
+    }
+
+    #[test]
+    fn test_lerp() {
+        for w in [0.0, 1.0, 0.5] {
+            let (a, b, t) =
+                (&Value::new(2.0), &Value::new(-1.0), &Value::new(w));
+            let out = a.lerp(b, t);
+            out.backward();
+            assert_value_close(&out, 2.0 - 3.0 * w, 1e-6);
+            assert_all_grads_close(&[(a, 1.0 - w), (b, w), (t, -3.0)], 1e-6);
+        }
+        let (a, b, t) = (Value::new(0.3), Value::new(1.7), Value::new(0.25));
+        let params = [a.clone(), b.clone(), t.clone()];
+        let f = |_: &[Value]| &a * a.lerp(&b, &t);
+        assert_grads_match_numeric(&params, f, 1e-3, 1e-3);
+    }
+
+    #[test]
+    fn test_lerp_scalar_matches_value() {
+        let (a, b) = (&Value::new(0.4), &Value::new(3.0));
+        let fixed = a.lerp_scalar(b, 0.3);
+        fixed.backward();
+        let grads = (a.get_grad(), b.get_grad());
+        a.set_grad(0.0);
+        b.set_grad(0.0);
+        let t = &Value::new(0.3);
+        let out = a.lerp(b, t);
+        out.backward();
+        assert_eq!(fixed.get_data(), out.get_data());
+        assert_eq!(grads, (a.get_grad(), b.get_grad()));
+        assert_eq!(fixed.topo().len(), 4);
+    }
+
+    #[test]
+    fn test_maximum_minimum() {
+        let (a, b) = (Value::new(2.0), Value::new(-1.0));
+        let (max, min) = (a.maximum(&b), a.minimum(&b));
+        assert_eq!((max.get_data(), min.get_data()), (2.0, -1.0));
+        (&max * 3.0 + &min * 5.0).backward();
+        assert_eq!((a.get_grad(), b.get_grad()), (3.0, 5.0));
+
+        // Ties go to self
+        let (c, d) = (Value::new(1.0), Value::new(1.0));
+        (c.maximum(&d) + c.minimum(&d)).backward();
+        assert_eq!((c.get_grad(), d.get_grad()), (2.0, 0.0));
+
+        let (e, nan) = (Value::new(1.0), Value::new(Scalar::NAN));
+        assert_eq!(e.maximum(&nan).get_data(), 1.0);
+        assert_eq!(nan.minimum(&e).get_data(), 1.0);
+    }
+
+    #[test]
+    fn test_clamp() {
+        let xs: Vec<_> = [-2.0, -1.0, 0.5, 1.0, 3.0]
+            .iter()
+            .map(|&x| Value::new(x))
+            .collect();
+        for x in &xs {
+            (x.clamp(-1.0, 1.0) * 2.0).backward();
+        }
+        let data: Vec<_> =
+            xs.iter().map(|x| x.clamp(-1.0, 1.0).get_data()).collect();
+        let grads: Vec<_> = xs.iter().map(Value::get_grad).collect();
+        assert_eq!(data, [-1.0, -1.0, 0.5, 1.0, 1.0]);
+        assert_eq!(grads, [0.0, 2.0, 2.0, 2.0, 0.0]);
+        let x = &xs[2];
+        assert!(x
+            .clamp(0.0, 1.0)
+            .deep_copy()
+            .graph_eq(&x.clamp(0.0, 1.0), 0.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "clamp needs lo <= hi, got [1, -1]")]
+    fn test_clamp_empty_interval() {
+        Value::new(0.0).clamp(1.0, -1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "clamp needs lo <= hi, got [NaN, 1]")]
+    fn test_clamp_nan_bound() {
+        Value::new(0.0).clamp(Scalar::NAN, 1.0);
+    }
+
+    #[test]
+    fn test_hinge_with_maximum() {
+        // max(0, 1 - y p) for a correct and a wrong prediction
+        let zero = Value::constant(0.0);
+        let p = Value::new(-0.5);
+        let loss = zero.maximum(&(&p * -1.0 + 1.0));
+        loss.backward();
+        assert_eq!(loss.get_data(), 1.5);
+        assert_eq!(p.get_grad(), -1.0);
+        let q = Value::new(2.0);
+        zero.maximum(&(&q * -1.0 + 1.0)).backward();
+        assert_eq!(q.get_grad(), 0.0);
+        assert_eq!(zero.get_grad(), 0.0);
+    }
+
+    #[test]
+    fn test_logsumexp_grads_are_softmax() {
+        let xs: Vec<_> =
+            [0.5, -1.0, 2.0].iter().map(|&x| Value::new(x)).collect();
+        let out = logsumexp(&xs);
+        let sum: Scalar = xs.iter().map(|x| x.get_data().exp()).sum();
+        assert_value_close(&out, sum.ln(), 1e-6);
+        out.backward();
+        for x in &xs {
+            assert_grad_close(x, x.get_data().exp() / sum, 1e-6);
+        }
+        let params = xs.clone();
+        let f = |_: &[Value]| &xs[0] * logsumexp(&xs);
+        assert_grads_match_numeric(&params, f, 1e-3, 1e-3);
+    }
+
+    #[test]
+    fn test_logsumexp_large_inputs() {
+        for shift in [1000.0, -1000.0] {
+            let xs =
+                [Value::new(shift), Value::new(shift + (2.0 as Scalar).ln())];
+            let out = logsumexp(&xs);
+            out.backward();
+            assert_value_close(&out, shift + (3.0 as Scalar).ln(), 1e-3);
+            assert_all_grads_close(
+                &[(&xs[0], 1.0 / 3.0), (&xs[1], 2.0 / 3.0)],
+                1e-5,
+            );
+        }
+        let x = &Value::new(-4.0);
+        let out = logsumexp(std::slice::from_ref(x));
+        out.backward();
+        assert_eq!(out.get_data(), -4.0);
+        assert_eq!(x.get_grad(), 1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "logsumexp of no values")]
+    fn test_logsumexp_empty() {
+        logsumexp(&[]);
+    }
+
+    #[test]
+    fn test_smooth_max() {
+        let xs: Vec<_> =
+            [1.0, 3.0, 2.5].iter().map(|&x| Value::new(x)).collect();
+        let soft = smooth_max(&xs, 1.0).get_data();
+        let sharp = smooth_max(&xs, 0.01).get_data();
+        assert!((3.0..3.01).contains(&sharp), "{}", sharp);
+        assert!(soft > sharp);
+        smooth_max(&xs, 0.01).backward();
+        assert_all_grads_close(
+            &[(&xs[0], 0.0), (&xs[1], 1.0), (&xs[2], 0.0)],
+            1e-6,
+        );
+    }
+
+    #[test]
+    fn test_gelu() {
+        let xs: Vec<_> = (-30..=30).map(|i| i as Scalar / 10.0).collect();
+        for &x in &xs {
+            let approx = Value::new(x).gelu().get_data();
+            let exact = Value::new(x).gelu_exact().get_data();
+            assert!((approx - exact).abs() < 1e-3, "at {}", x);
+        }
+        assert_eq!(Value::new(0.0).gelu().get_data(), 0.0);
+        assert_value_close(&Value::new(10.0).gelu(), 10.0, 1e-6);
+        assert_value_close(&Value::new(-10.0).gelu(), 0.0, 1e-6);
+
+        let a = &Value::new(0.0);
+        a.gelu().backward();
+        assert_grad_close(a, 0.5, 1e-6);
+        for &x in &[-2.5, -0.4, 0.3, 1.7] {
+            let x = Value::new(x);
+            let params = [x.clone()];
+            assert_grads_match_numeric(&params, |_| x.gelu(), 1e-3, 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_gelu_exact() {
+        let sqrt_2_over_pi = (2.0 / std::f64::consts::PI).sqrt() as Scalar;
+        let tanh_gelu = |x: Scalar| {
+            let inner = sqrt_2_over_pi * (x + 0.044715 * x * x * x);
+            0.5 * x * (1.0 + inner.tanh())
+        };
+        for i in -30..=30 {
+            let x = i as Scalar / 10.0;
+            let exact = Value::new(x).gelu_exact().get_data();
+            assert!((exact - tanh_gelu(x)).abs() < 1e-3, "at {}", x);
+        }
+        let x = Value::new(-0.4);
+        let params = [x.clone()];
+        assert_grads_match_numeric(&params, |_| x.gelu_exact(), 1e-3, 1e-3);
+    }
+
+    #[test]
+    fn test_tanh_saturates() {
+        let xs = [Value::new(0.0), Value::new(50.0), Value::new(-500.0)];
+        for x in &xs {
+            x.tanh().backward();
+        }
+        let data: Vec<_> = xs.iter().map(|x| x.tanh().get_data()).collect();
+        let grads: Vec<_> = xs.iter().map(Value::get_grad).collect();
+        assert_eq!(data, [0.0, 1.0, -1.0]);
+        assert_eq!(grads, [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_prelu() {
+        let alpha = Value::new(0.25);
+        let xs = [Value::new(-2.0), Value::new(3.0), Value::new(-1.0)];
+        let outs: Vec<_> = xs.iter().map(|x| x.prelu(&alpha)).collect();
+        let data: Vec<_> = outs.iter().map(Value::get_data).collect();
+        assert_eq!(data, [-0.5, 3.0, -0.25]);
+        outs.iter()
+            .fold(Value::new(0.0), |acc, o| acc + o)
+            .backward();
+        let grads: Vec<_> = xs.iter().map(Value::get_grad).collect();
+        assert_eq!(grads, [0.25, 1.0, 0.25]);
+        assert_eq!(alpha.get_grad(), -3.0);
+
+        // A fixed alpha matches leaky_relu
+        let x = Value::new(-1.5);
+        let fixed = x.prelu(&Value::constant(0.1)).get_data();
+        assert_eq!(fixed, x.leaky_relu(0.1).get_data());
+        let params = [x.clone(), alpha.clone()];
+        assert_grads_match_numeric(&params, |_| x.prelu(&alpha), 1e-3, 1e-3);
+    }
+
+    #[test]
+    fn test_activations() {
+        let a = &Value::new(-2.0);
+        let (t, s, l) = (a.tanh(), a.sigmoid(), a.leaky_relu(0.1));
+        (&(&t + &s) + &l).backward();
+        let (tanh, sig): (Scalar, Scalar) =
+            ((-2.0f64).tanh() as Scalar, 0.1192029);
+        assert_value_close(&t, tanh, 1e-6);
+        assert_value_close(&s, sig, 1e-6);
+        assert_value_close(&l, -0.2, 1e-6);
+        assert_grad_close(
+            a,
+            (1.0 - tanh * tanh) + sig * (1.0 - sig) + 0.1,
+            1e-6,
+        );
+    }
+
+    #[test]
+    fn test_checked_ops_err() {
+        let (a, zero, neg) =
+            (Value::new(3.0), Value::new(0.0), Value::new(-8.0));
+        assert_eq!(
+            a.checked_div(&zero),
+            Err(MathError::DivByZero { lhs: 3.0 })
+        );
+        assert_eq!(
+            zero.checked_ln(),
+            Err(MathError::LnOfNonPositive { x: 0.0 })
+        );
+        assert_eq!(
+            neg.checked_ln(),
+            Err(MathError::LnOfNonPositive { x: -8.0 })
+        );
+        assert_eq!(
+            neg.checked_pow(0.5),
+            Err(MathError::Pow {
+                base: -8.0,
+                exp: 0.5
+            })
+        );
+        assert_eq!(
+            zero.checked_pow(-1.0),
+            Err(MathError::Pow {
+                base: 0.0,
+                exp: -1.0
+            })
+        );
+        assert_eq!(
+            neg.checked_sqrt(),
+            Err(MathError::SqrtOfNegative { x: -8.0 })
+        );
+        assert_eq!(
+            neg.checked_sqrt().unwrap_err().to_string(),
+            "sqrt: -8 is negative"
+        );
+    }
+
+    #[test]
+    fn test_checked_ops_ok() {
+        let (a, b, neg) = (Value::new(3.0), Value::new(2.0), Value::new(-8.0));
+        assert!(a.checked_div(&b).unwrap().graph_eq(&(&a / &b), 0.0));
+        assert!(a.checked_ln().unwrap().graph_eq(&a.ln(), 0.0));
+        assert!(a.checked_pow(0.5).unwrap().graph_eq(&a.pow(0.5), 0.0));
+        assert!(neg.checked_pow(3.0).unwrap().graph_eq(&neg.pow(3.0), 0.0));
+        assert!(a.checked_sqrt().unwrap().graph_eq(&a.sqrt(), 0.0));
+        assert_eq!(Value::new(0.0).checked_sqrt().unwrap().get_data(), 0.0);
+    }
+
+    #[test]
+    fn test_optimize() {
+        let x = &Value::new(1.5);
+        let y = &Value::new(-2.0);
+        let build = || {
+            let k = (Value::constant(2.0) * 3.0 + 1.0).pow(2.0);
+            let a = &k * x;
+            let b = (x * y) * (x * y);
+            let c = ((x * 1.0) + 0.0).pow(1.0).relu();
+            a + b + c + 1.0 + 1.0
+        };
+        let original = build();
+        let optimized = original.optimize();
+        assert_eq!(optimized.get_data(), original.get_data());
+        assert_eq!(original.topo().len(), 24);
+        assert_eq!(optimized.topo().len(), 12);
+
+        let grads = |out: &Value| {
+            x.set_grad(0.0);
+            y.set_grad(0.0);
+            out.backward();
+            (x.get_grad(), y.get_grad())
+        };
+        assert_eq!(grads(&original), (49.0 + 12.0 + 1.0, -9.0));
+        assert_eq!(grads(&optimized), (49.0 + 12.0 + 1.0, -9.0));
+    }
+
+    #[test]
+    fn test_optimize_merges_subexpressions() {
+        let x = &Value::new(3.0);
+        let y = &Value::new(4.0);
+        let out = (x * y).relu() + (y * x).relu();
+        let optimized = out.optimize();
+        let prev = optimized.0.node.read().prev.clone();
+        assert!(prev[0] == prev[1]);
+        assert_eq!(optimized.topo().len(), 5);
+        optimized.backward();
+        assert_eq!((x.get_grad(), y.get_grad()), (8.0, 6.0));
+    }
+
+    #[test]
+    fn test_optimize_keeps_clamp_bounds() {
+        let x = &Value::new(0.5);
+        let out = x.clamp(0.0, 0.2) + x.clamp(-1.0, 1.0);
+        let optimized = out.optimize();
+        assert_value_close(&optimized, 0.7, 1e-6);
+        let prev = optimized.0.node.read().prev.clone();
+        assert!(prev[0] != prev[1]);
+    }
+
+    #[test]
+    fn test_custom_unary() {
+        let exp = |x: &Value| {
+            Value::custom_unary(
+                x,
+                |x| x.exp(),
+                |_, out, grad| out * grad,
+                "exp",
+            )
+        };
+        let a = &Value::new(0.5);
+        let b = exp(a) * 2.0;
+        b.backward();
+        assert_value_close(&b, 2.0 * (0.5 as Scalar).exp(), 1e-6);
+        assert_grad_close(a, 2.0 * (0.5 as Scalar).exp(), 1e-6);
+        assert_eq!(format!("{:?}", exp(a).0.node.read().op), "Custom(\"exp\")");
+        assert!(exp(a).graph_eq(&exp(a), 0.0));
+        assert!(exp(a).deep_copy().graph_eq(&exp(a), 0.0));
+    }
+
+    #[test]
+    fn test_custom_binary() {
+        let atan2 = |y: &Value, x: &Value| {
+            Value::custom_binary(
+                y,
+                x,
+                |y, x| y.atan2(x),
+                |y, x, _, grad| {
+                    let r2 = x * x + y * y;
+                    (x / r2 * grad, -y / r2 * grad)
+                },
+                "atan2",
+            )
+        };
+        let y = &Value::new(1.0);
+        let x = &Value::new(-1.0);
+        let out = atan2(y, x);
+        out.backward();
+        assert_value_close(&out, 0.75 * std::f64::consts::PI as Scalar, 1e-6);
+        assert_all_grads_close(&[(y, -0.5), (x, -0.5)], 1e-6);
+
+        let c = Value::constant(2.0);
+        let folded = atan2(&c, &c).optimize();
+        assert!(folded.is_const());
+        assert_value_close(
+            &folded,
+            0.25 * std::f64::consts::PI as Scalar,
+            1e-6,
+        );
+    }
+}
+
+#[cfg(all(test, feature = "f64"))]
+mod test_f64 {
+    use super::*;
+
+    #[test]
+    fn test_scalar_is_f64() {
+        let a = Value::new(1.0);
+        let b = a + 1e-12;
+        assert!(b.get_data() > 1.0);
+        assert_eq!(std::mem::size_of::<Scalar>(), 8);
+    }
+
+    #[test]
+    fn test_accumulation() {
+        let a = &Value::new(0.1);
+        let mut sum = Value::new(0.0);
+        for _ in 0..1000 {
+            sum = sum + a;
+        }
+        sum.backward();
+        assert!((sum.get_data() - 100.0).abs() < 1e-10);
+        assert_eq!(a.get_grad(), 1000.0);
     }
 
     #[test]
     fn test_contrived() {
-        let ref a = Value::new(-4.0);
-        let ref b = Value::new(2.0);
+        let a = &Value::new(-4.0);
+        let b = &Value::new(2.0);
         let mut c = a + b;
         let mut d = a * b + b.pow(3.0);
         c = c.clone() + c + 1.0;
@@ -431,10 +2167,45 @@ mod test {
         let f = e.pow(2.0);
         let mut g = f.clone().div(2.0);
         g = g + 10.0 / f;
-        assert_eq!(format!("{:.4}", g.get_data()), "24.7041");
         g.backward();
-        assert_eq!(format!("{:.4}", a.get_grad()), "138.8338");
-        assert_eq!(format!("{:.4}", b.get_grad()), "645.5773");
-        // assert_eq!(b.get_grad(), -0.25);
+        assert!((g.get_data() - 24.70408163265306).abs() < 1e-12);
+        assert!((a.get_grad() - 138.83381924198252).abs() < 1e-10);
+        assert!((b.get_grad() - 645.5772594752186).abs() < 1e-10);
+    }
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod test_sync {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_value_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Value>();
+    }
+
+    #[test]
+    fn test_backward_from_threads() {
+        let w = &Value::new(2.0);
+        let b = &Value::new(1.0);
+        let handles: Vec<_> = (1..=8)
+            .map(|i| {
+                let (w, b) = (w.clone(), b.clone());
+                thread::spawn(move || {
+                    let x = Value::new(i as Scalar);
+                    let y = (&x * &w + &b).relu();
+                    y.backward();
+                    (y.get_data(), x.get_grad())
+                })
+            })
+            .collect();
+        for (i, handle) in (1..=8).zip(handles) {
+            let (y, x_grad) = handle.join().unwrap();
+            assert_eq!(y, 2.0 * i as Scalar + 1.0);
+            assert_eq!(x_grad, 2.0);
+        }
+        assert_eq!(w.get_grad(), 36.0);
+        assert_eq!(b.get_grad(), 8.0);
     }
 }
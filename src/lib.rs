@@ -1,2 +1,15 @@
-mod nn;
-mod engine;
\ No newline at end of file
+pub mod checkpoint;
+mod clock;
+pub mod data;
+pub mod engine;
+pub mod losses;
+pub mod metrics;
+pub mod nn;
+pub mod optim;
+pub mod profiling;
+mod random;
+pub mod rnn;
+mod shared;
+pub mod tensor;
+pub mod testing;
+pub mod train;
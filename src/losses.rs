@@ -0,0 +1,621 @@
+//! Loss functions and regularization terms to add to them.
+
+use crate::engine::{logsumexp, Scalar, Value};
+use crate::tensor::Vector;
+
+/// Scores a model's output for one sample against the sample's targets.
+pub trait Loss {
+    fn loss(&self, pred: &[Value], target: &[Scalar]) -> Value;
+
+    /// The mean loss over a batch, as from
+    /// [`Module::forward_batch`](crate::nn::Module::forward_batch).
+    ///
+    /// # Panics
+    ///
+    /// If `preds` and `targets` hold different numbers of samples or none,
+    /// or as [`Loss::loss`] does for any sample.
+    fn batch_loss(
+        &self,
+        preds: &[Vec<Value>],
+        targets: &[Vec<Scalar>],
+    ) -> Value {
+        assert_eq!(
+            preds.len(),
+            targets.len(),
+            "{} predicted samples for {} targets",
+            preds.len(),
+            targets.len()
+        );
+        let losses = preds.iter().zip(targets).map(|(p, t)| self.loss(p, t));
+        Vector::new(losses.collect()).mean()
+    }
+}
+
+fn check_lengths(pred: &[Value], target: &[Scalar]) {
+    assert_eq!(
+        pred.len(),
+        target.len(),
+        "{} outputs for {} targets",
+        pred.len(),
+        target.len()
+    );
+}
+
+/// Mean squared error over the outputs of a sample.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Mse;
+
+impl Loss for Mse {
+    /// # Panics
+    ///
+    /// If `pred` and `target` differ in length or are empty.
+    fn loss(&self, pred: &[Value], target: &[Scalar]) -> Value {
+        check_lengths(pred, target);
+        let errs = pred
+            .iter()
+            .zip(target)
+            .map(|(p, &t)| (p.clone() + -t).pow(2.0));
+        Vector::new(errs.collect()).mean()
+    }
+}
+
+/// The mean over the outputs of a sample of `max(0, 1 - t * p)`, for
+/// targets `t` of -1 or 1. Outputs on the right side of the margin cost
+/// nothing.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Hinge;
+
+impl Loss for Hinge {
+    /// # Panics
+    ///
+    /// If `pred` and `target` differ in length or are empty.
+    fn loss(&self, pred: &[Value], target: &[Scalar]) -> Value {
+        check_lengths(pred, target);
+        let margins =
+            pred.iter().zip(target).map(|(p, &t)| (p * -t + 1.0).relu());
+        Vector::new(margins.collect()).mean()
+    }
+}
+
+/// Softmax cross-entropy between a sample's logits and a target
+/// distribution over the classes, usually one-hot:
+///
+/// ```text
+/// loss = -sum(q * log_softmax(pred)) = logsumexp(pred) - sum(q * pred)
+/// ```
+///
+/// With label smoothing `eps` the target `t` becomes
+/// `q = (1 - eps) * t + eps * (1 - t) / (K - 1)` for `K` classes, so a
+/// one-hot target keeps `1 - eps` on its class and spreads the rest evenly
+/// over the others. (Unlike [`label_smooth`](crate::data::label_smooth),
+/// which spreads `eps` over all `K`.)
+///
+/// With class weights each sample's loss is scaled by the weight of its
+/// class, `sum(t * weights)` for a target `t`, to make up for imbalanced
+/// classes; see [`compute_class_weights`](crate::data::compute_class_weights).
+///
+/// ```
+/// # use smolgrad::losses::{CrossEntropy, Loss};
+/// # use smolgrad::engine::Value;
+/// let logits = [Value::new(2.0), Value::new(0.5)];
+/// let loss = CrossEntropy::new().label_smoothing(0.1);
+/// loss.loss(&logits, &[1.0, 0.0]).backward();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CrossEntropy {
+    label_smoothing: Scalar,
+    class_weights: Option<Vec<Scalar>>,
+}
+
+impl CrossEntropy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves `eps` of each target's mass off its class; 0 by default.
+    ///
+    /// # Panics
+    ///
+    /// Unless `eps` is in `[0, 1)`.
+    pub fn label_smoothing(self, eps: Scalar) -> Self {
+        assert!(
+            (0.0..1.0).contains(&eps),
+            "label smoothing must be in [0, 1), got {}",
+            eps
+        );
+        Self {
+            label_smoothing: eps,
+            ..self
+        }
+    }
+
+    /// Scales the loss of a sample of class `c` by `weights[c]`. There
+    /// must be a weight per class, which [`Loss::loss`] checks against the
+    /// number of outputs.
+    ///
+    /// # Panics
+    ///
+    /// If `weights` is empty or holds a negative or non-finite weight.
+    pub fn with_class_weights(self, weights: Vec<Scalar>) -> Self {
+        assert!(!weights.is_empty(), "no class weights");
+        check_class_weights(&weights);
+        Self {
+            class_weights: Some(weights),
+            ..self
+        }
+    }
+}
+
+impl Loss for CrossEntropy {
+    /// # Panics
+    ///
+    /// If `pred` and `target` differ in length or are empty, if there is a
+    /// single class to smooth over, or if there isn't a class weight per
+    /// class.
+    fn loss(&self, pred: &[Value], target: &[Scalar]) -> Value {
+        check_lengths(pred, target);
+        let eps = self.label_smoothing;
+        let k = pred.len();
+        assert!(
+            eps == 0.0 || k > 1,
+            "label smoothing needs at least 2 classes"
+        );
+        let weight = self.class_weights.as_ref().map(|weights| {
+            assert_eq!(
+                weights.len(),
+                k,
+                "{} class weights for {} classes",
+                weights.len(),
+                k
+            );
+            weights
+                .iter()
+                .zip(target)
+                .map(|(w, t)| w * t)
+                .sum::<Scalar>()
+        });
+        let spread = eps / (k as Scalar - 1.0);
+        let weighted = pred.iter().zip(target).filter_map(|(p, &t)| {
+            let q = if eps == 0.0 {
+                t
+            } else {
+                (1.0 - eps) * t + spread * (1.0 - t)
+            };
+            (q != 0.0).then(|| p * q)
+        });
+        let loss = logsumexp(pred) - Vector::new(weighted.collect()).sum();
+        match weight {
+            Some(weight) => loss * weight,
+            None => loss,
+        }
+    }
+}
+
+/// Binary cross-entropy on logits, averaged over the outputs of a sample,
+/// for targets of 0 or 1 (or probabilities in between):
+///
+/// ```text
+/// loss = -t * ln(sigmoid(p)) - (1 - t) * ln(1 - sigmoid(p))
+///      = (1 - t) * p - ln(sigmoid(p))
+///      = -t * p - ln(sigmoid(-p))
+/// ```
+///
+/// Whichever of the last two takes the sigmoid of a non-negative logit is
+/// what's computed, so it stays finite for any logit.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bce {
+    class_weights: Option<[Scalar; 2]>,
+}
+
+impl Bce {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scales the loss of each output by `weights[0]` where its target is 0
+    /// and `weights[1]` where it is 1, interpolating in between.
+    ///
+    /// # Panics
+    ///
+    /// If a weight is negative or not finite.
+    pub fn with_class_weights(self, weights: [Scalar; 2]) -> Self {
+        check_class_weights(&weights);
+        Self {
+            class_weights: Some(weights),
+        }
+    }
+}
+
+fn check_class_weights(weights: &[Scalar]) {
+    assert!(
+        weights.iter().all(|w| w.is_finite() && *w >= 0.0),
+        "class weights must be finite and non-negative, got {:?}",
+        weights
+    );
+}
+
+impl Loss for Bce {
+    /// # Panics
+    ///
+    /// If `pred` and `target` differ in length or are empty.
+    fn loss(&self, pred: &[Value], target: &[Scalar]) -> Value {
+        check_lengths(pred, target);
+        let losses = pred.iter().zip(target).map(|(p, &t)| {
+            let loss = if p.get_data() >= 0.0 {
+                p * (1.0 - t) - p.sigmoid().ln()
+            } else {
+                p * -t - (-p).sigmoid().ln()
+            };
+            match self.class_weights {
+                Some([w0, w1]) => loss * (w0 * (1.0 - t) + w1 * t),
+                None => loss,
+            }
+        });
+        Vector::new(losses.collect()).mean()
+    }
+}
+
+/// `alpha * sum(p^2)` over `params`, as part of the graph, so its gradient
+/// `2 * alpha * p` flows into each parameter on `backward`.
+///
+/// This builds a node per parameter on every call. For plain SGD, the
+/// optimizer's [`weight_decay`](crate::optim::Sgd::weight_decay) of
+/// `2 * alpha` has the same effect without touching the graph; the penalty
+/// is for when the regularized loss itself matters, e.g. to report it.
+pub fn l2_penalty(params: &[Value], alpha: Scalar) -> Value {
+    Vector::new(params.iter().map(|p| p * p).collect()).sum() * alpha
+}
+
+/// `alpha * sum(|p|)` over `params`, to add to a loss to push weights
+/// towards exactly zero. Weights already at zero get no gradient from it.
+pub fn l1_penalty(params: &[Value], alpha: Scalar) -> Value {
+    Vector::new(params.iter().map(Value::abs).collect()).sum() * alpha
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::{compute_class_weights, ClassWeightScheme};
+    use crate::optim::{Optimizer, Sgd};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_l2_penalty_grad() {
+        let params: Vec<_> =
+            [1.5, -2.0, 0.0].iter().map(|&x| Value::new(x)).collect();
+        let penalty = l2_penalty(&params, 0.1);
+        assert!((penalty.get_data() - 0.625).abs() < 1e-6);
+        penalty.backward();
+        for p in &params {
+            assert!((p.get_grad() - 2.0 * 0.1 * p.get_data()).abs() < 1e-6);
+        }
+        assert_eq!(l2_penalty(&[], 0.1).get_data(), 0.0);
+    }
+
+    #[test]
+    fn test_l2_penalty_matches_weight_decay() {
+        let xs: [[Scalar; 2]; 3] = [[1.0, 2.0], [-1.0, 0.5], [0.3, -0.7]];
+        let ys: [Scalar; 3] = [1.0, -0.5, 0.2];
+        let alpha = 0.05;
+        let loss = |w: &[Value]| {
+            let errs = xs.iter().zip(&ys).map(|(x, &y)| {
+                let err = &w[0] * x[0] + &w[1] * x[1] + -y;
+                &err * &err
+            });
+            Vector::new(errs.collect()).sum()
+        };
+        let w_penalty = vec![Value::new(0.5), Value::new(-0.5)];
+        let w_decay = vec![Value::new(0.5), Value::new(-0.5)];
+        let mut opt_penalty = Sgd::new(w_penalty.clone(), 0.05);
+        let mut opt_decay =
+            Sgd::new(w_decay.clone(), 0.05).weight_decay(2.0 * alpha);
+        for _ in 0..50 {
+            opt_penalty.zero_grad();
+            (loss(&w_penalty) + l2_penalty(&w_penalty, alpha)).backward();
+            opt_penalty.step();
+            opt_decay.zero_grad();
+            loss(&w_decay).backward();
+            opt_decay.step();
+            for (a, b) in w_penalty.iter().zip(&w_decay) {
+                assert!((a.get_data() - b.get_data()).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_l1_penalty_sparsifies() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let xs: Vec<Vec<Scalar>> = (0..40)
+            .map(|_| (0..10).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect();
+        let ys: Vec<Scalar> =
+            xs.iter().map(|x| 3.0 * x[0] - 2.0 * x[4]).collect();
+        let w: Vec<_> = (0..10)
+            .map(|_| Value::new(rng.gen_range(-0.5..0.5)))
+            .collect();
+        let mut opt = Sgd::new(w.clone(), 0.05);
+        for _ in 0..300 {
+            let errs = xs.iter().zip(&ys).map(|(x, &y)| {
+                let pred = Vector::new(w.clone()).dot(&Vector::from_data(x));
+                (pred + -y).pow(2.0)
+            });
+            let loss =
+                Vector::new(errs.collect()).mean() + l1_penalty(&w, 0.02);
+            opt.zero_grad();
+            loss.backward();
+            opt.step();
+        }
+        let smallest_relevant =
+            w[0].get_data().abs().min(w[4].get_data().abs());
+        for (i, p) in w.iter().enumerate() {
+            if i != 0 && i != 4 {
+                assert!(
+                    p.get_data().abs() * 10.0 < smallest_relevant,
+                    "w{} = {}",
+                    i,
+                    p.get_data()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_l1_penalty_grad() {
+        let params: Vec<_> =
+            [1.5, -2.0, 0.0].iter().map(|&x| Value::new(x)).collect();
+        let penalty = l1_penalty(&params, 0.5);
+        assert_eq!(penalty.get_data(), 1.75);
+        penalty.backward();
+        let grads: Vec<_> = params.iter().map(Value::get_grad).collect();
+        assert_eq!(grads, [0.5, -0.5, 0.0]);
+    }
+
+    #[test]
+    fn test_mse() {
+        let pred = [Value::new(1.0), Value::new(-1.0)];
+        let loss = Mse.loss(&pred, &[0.0, 1.0]);
+        assert_eq!(loss.get_data(), 2.5);
+        loss.backward();
+        assert_eq!(pred[0].get_grad(), 1.0);
+        assert_eq!(pred[1].get_grad(), -2.0);
+    }
+
+    #[test]
+    fn test_hinge() {
+        // Margins 0.5, 0 (past it) and 2 (wrong side)
+        let pred = [Value::new(0.5), Value::new(-3.0), Value::new(1.0)];
+        let loss = Hinge.loss(&pred, &[1.0, -1.0, -1.0]);
+        assert!((loss.get_data() - 2.5 / 3.0).abs() < 1e-6);
+        loss.backward();
+        assert_eq!(pred[0].get_grad(), -1.0 / 3.0);
+        assert_eq!(pred[1].get_grad(), 0.0);
+        assert_eq!(pred[2].get_grad(), 1.0 / 3.0);
+    }
+
+    #[test]
+    fn test_batch_loss() {
+        let preds = vec![vec![Value::new(1.0)], vec![Value::new(-1.0)]];
+        let loss = Mse.batch_loss(&preds, &[vec![0.0], vec![1.0]]);
+        assert_eq!(loss.get_data(), 2.5);
+        loss.backward();
+        assert_eq!(preds[0][0].get_grad(), 1.0);
+        assert_eq!(preds[1][0].get_grad(), -2.0);
+    }
+
+    #[test]
+    fn test_cross_entropy() {
+        let pred: Vec<_> =
+            [1.0, 2.0, 3.0].iter().map(|&x| Value::new(x)).collect();
+        let lse = (1.0 as Scalar).exp()
+            + (2.0 as Scalar).exp()
+            + (3.0 as Scalar).exp();
+        let lse = lse.ln();
+
+        let loss = CrossEntropy::new().loss(&pred, &[1.0, 0.0, 0.0]);
+        assert_eq!(loss.get_data(), logsumexp(&pred).get_data() - 1.0);
+        assert!((loss.get_data() - (lse - 1.0)).abs() < 1e-6);
+
+        // q = [0.7, 0.15, 0.15]
+        let loss = CrossEntropy::new()
+            .label_smoothing(0.3)
+            .loss(&pred, &[1.0, 0.0, 0.0]);
+        assert!((loss.get_data() - (lse - 1.45)).abs() < 1e-6);
+        loss.backward();
+        let e: Vec<Scalar> =
+            [1.0 as Scalar, 2.0, 3.0].iter().map(|x| x.exp()).collect();
+        let z: Scalar = e.iter().sum();
+        for ((p, q), e) in pred.iter().zip([0.7, 0.15, 0.15]).zip(&e) {
+            assert!((p.get_grad() - (e / z - q)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_cross_entropy_no_smoothing_is_unchanged() {
+        let pred = [Value::new(0.3), Value::new(-1.2), Value::new(2.5)];
+        let target = [0.0, 0.0, 1.0];
+        // the formula from before label smoothing
+        let expected = logsumexp(&pred) - pred[2].clone();
+        let bits = |loss: Value| loss.get_data().to_bits();
+        let plain = CrossEntropy::new().loss(&pred, &target);
+        assert_eq!(bits(plain), bits(expected.clone()));
+        let zero = CrossEntropy::new()
+            .label_smoothing(0.0)
+            .loss(&pred, &target);
+        assert_eq!(bits(zero), bits(expected));
+        assert_eq!(CrossEntropy::new(), CrossEntropy::default());
+    }
+
+    #[test]
+    fn test_cross_entropy_is_stable() {
+        let pred = [Value::new(1000.0), Value::new(-1000.0)];
+        let loss = CrossEntropy::new()
+            .label_smoothing(0.1)
+            .loss(&pred, &[1.0, 0.0]);
+        assert!((loss.get_data() - 0.1 * 2000.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_label_smoothing_lowers_confidence() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let centers = [[2.0, 0.0], [-1.0, 1.7], [-1.0, -1.7]];
+        let samples: Vec<([Scalar; 2], usize)> = (0..30)
+            .map(|i| {
+                let c = centers[i % 3];
+                let x = [
+                    c[0] + rng.gen_range(-0.5..0.5),
+                    c[1] + rng.gen_range(-0.5..0.5),
+                ];
+                (x, i % 3)
+            })
+            .collect();
+        let confidence = |loss: CrossEntropy| {
+            let w: Vec<_> = (0..9).map(|_| Value::new(0.0)).collect();
+            let logits = |x: &[Scalar; 2]| -> Vec<Value> {
+                w.chunks(3)
+                    .map(|r| &r[0] * x[0] + &r[1] * x[1] + &r[2])
+                    .collect()
+            };
+            let mut opt = Sgd::new(w.clone(), 0.5);
+            for _ in 0..200 {
+                let losses = samples.iter().map(|(x, y)| {
+                    let mut target = [0.0; 3];
+                    target[*y] = 1.0;
+                    loss.loss(&logits(x), &target)
+                });
+                opt.zero_grad();
+                Vector::new(losses.collect()).mean().backward();
+                opt.step();
+            }
+            let total: Scalar = samples
+                .iter()
+                .map(|(x, _)| {
+                    let p: Vec<_> =
+                        logits(x).iter().map(Value::get_data).collect();
+                    let lse = logsumexp(&logits(x)).get_data();
+                    p.iter().map(|p| (p - lse).exp()).fold(0.0, Scalar::max)
+                })
+                .sum();
+            total / samples.len() as Scalar
+        };
+        let hard = confidence(CrossEntropy::new());
+        let smooth = confidence(CrossEntropy::new().label_smoothing(0.2));
+        assert!(hard > 0.95, "{}", hard);
+        assert!(smooth < hard - 0.1, "{} vs {}", smooth, hard);
+    }
+
+    #[test]
+    fn test_bce() {
+        let pred = [Value::new(0.0), Value::new(2.0), Value::new(-800.0)];
+        let loss = Bce::new().loss(&pred, &[1.0, 0.0, 0.0]);
+        let ln2 = (2.0 as Scalar).ln();
+        let softplus2 = 2.0 + (1.0 + (-2.0 as Scalar).exp()).ln();
+        assert!((loss.get_data() - (ln2 + softplus2) / 3.0).abs() < 1e-6);
+        loss.backward();
+        let sigmoid2 = 1.0 / (1.0 + (-2.0 as Scalar).exp());
+        assert!((pred[0].get_grad() + 0.5 / 3.0).abs() < 1e-6);
+        assert!((pred[1].get_grad() - sigmoid2 / 3.0).abs() < 1e-6);
+        assert_eq!(pred[2].get_grad(), 0.0);
+    }
+
+    #[test]
+    fn test_uniform_class_weights_are_unweighted() {
+        let pred = [Value::new(0.3), Value::new(-1.2), Value::new(2.5)];
+        let target = [0.0, 1.0, 0.0];
+        let bits = |loss: Value| loss.get_data().to_bits();
+        let ce = CrossEntropy::new().label_smoothing(0.1);
+        let weighted = ce.clone().with_class_weights(vec![1.0; 3]);
+        assert_eq!(
+            bits(ce.loss(&pred, &target)),
+            bits(weighted.loss(&pred, &target))
+        );
+        let weighted = Bce::new().with_class_weights([1.0, 1.0]);
+        assert_eq!(
+            bits(Bce::new().loss(&pred, &target)),
+            bits(weighted.loss(&pred, &target))
+        );
+
+        // Weighting scales the loss by the weight of the true class
+        let weighted =
+            CrossEntropy::new().with_class_weights(vec![1.0, 4.0, 2.0]);
+        let plain = CrossEntropy::new().loss(&pred, &target).get_data();
+        assert_eq!(weighted.loss(&pred, &target).get_data(), 4.0 * plain);
+    }
+
+    #[test]
+    #[should_panic(expected = "2 class weights for 3 classes")]
+    fn test_class_weights_length() {
+        let pred = [Value::new(0.3), Value::new(-1.2), Value::new(2.5)];
+        CrossEntropy::new()
+            .with_class_weights(vec![1.0, 2.0])
+            .loss(&pred, &[0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "class weights must be finite and non-negative, got [1.0, -2.0]"
+    )]
+    fn test_bce_class_weights_values() {
+        Bce::new().with_class_weights([1.0, -2.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "class weights must be finite and non-negative")]
+    fn test_cross_entropy_class_weights_values() {
+        CrossEntropy::new().with_class_weights(vec![1.0, Scalar::NAN]);
+    }
+
+    #[test]
+    fn test_class_weights_improve_minority_recall() {
+        // 95/5 classes on a line, overlapping in [0.5, 1.5]
+        let mut rng = StdRng::seed_from_u64(5);
+        let labels: Vec<usize> =
+            (0..200).map(|i| usize::from(i % 20 == 0)).collect();
+        let xs: Vec<Scalar> = labels
+            .iter()
+            .map(|&y| match y {
+                0 => rng.gen_range(-1.0..1.5),
+                _ => rng.gen_range(0.5..3.0),
+            })
+            .collect();
+        let recall = |loss: Bce| {
+            let (w, b) = (Value::new(0.0), Value::new(0.0));
+            let mut opt = Sgd::new(vec![w.clone(), b.clone()], 0.5);
+            for _ in 0..300 {
+                let losses = xs
+                    .iter()
+                    .zip(&labels)
+                    .map(|(&x, &y)| loss.loss(&[&w * x + &b], &[y as Scalar]));
+                opt.zero_grad();
+                Vector::new(losses.collect()).mean().backward();
+                opt.step();
+            }
+            let (w, b) = (w.get_data(), b.get_data());
+            let minority: Vec<_> =
+                xs.iter().zip(&labels).filter(|(_, &y)| y == 1).collect();
+            let found =
+                minority.iter().filter(|(&x, _)| w * x + b > 0.0).count();
+            found as Scalar / minority.len() as Scalar
+        };
+        let weights =
+            compute_class_weights(&labels, 2, ClassWeightScheme::Balanced)
+                .unwrap();
+        let plain = recall(Bce::new());
+        let weights = [weights[0], weights[1]];
+        let weighted = recall(Bce::new().with_class_weights(weights));
+        assert!(weighted > plain + 0.3, "{} vs {}", weighted, plain);
+        assert!(weighted >= 0.7, "{}", weighted);
+    }
+
+    #[test]
+    #[should_panic(expected = "label smoothing must be in [0, 1), got 1")]
+    fn test_label_smoothing_range() {
+        CrossEntropy::new().label_smoothing(1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "1 predicted samples for 2 targets")]
+    fn test_batch_loss_lengths() {
+        Mse.batch_loss(&[vec![Value::new(1.0)]], &[vec![0.0], vec![1.0]]);
+    }
+}
@@ -0,0 +1,781 @@
+//! Classification and regression metrics over plain predictions, away
+//! from the graph.
+//!
+//! Wherever a metric would divide by zero, such as the precision of a class
+//! that's never predicted or the R² of a constant target, it is 0 instead
+//! of NaN.
+
+use std::fmt::{Debug, Display};
+
+use crate::engine::Scalar;
+
+/// `num / den`, or 0 if `den` is 0.
+fn ratio(num: usize, den: usize) -> Scalar {
+    if den == 0 {
+        0.0
+    } else {
+        num as Scalar / den as Scalar
+    }
+}
+
+fn check_lengths(preds: usize, targets: usize) {
+    assert_eq!(
+        preds, targets,
+        "{} predictions for {} targets",
+        preds, targets
+    );
+}
+
+/// The fraction of `preds` equal to their target; 0 if there are none.
+///
+/// # Panics
+///
+/// If `preds` and `targets` differ in length.
+pub fn accuracy(preds: &[usize], targets: &[usize]) -> Scalar {
+    check_lengths(preds.len(), targets.len());
+    let correct = preds.iter().zip(targets).filter(|(p, t)| p == t).count();
+    ratio(correct, preds.len())
+}
+
+/// [`accuracy`] counted over rayon's thread pool.
+///
+/// # Panics
+///
+/// If `preds` and `targets` differ in length.
+#[cfg(feature = "parallel")]
+pub fn accuracy_par(preds: &[usize], targets: &[usize]) -> Scalar {
+    use rayon::prelude::*;
+    check_lengths(preds.len(), targets.len());
+    let correct = preds
+        .par_iter()
+        .zip(targets)
+        .filter(|(p, t)| p == t)
+        .count();
+    ratio(correct, preds.len())
+}
+
+/// How well one class (or an average over classes) was picked out.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ClassMetrics {
+    pub precision: Scalar,
+    pub recall: Scalar,
+    pub f1: Scalar,
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+}
+
+impl ClassMetrics {
+    fn from_counts(tp: usize, fp: usize, fn_: usize) -> Self {
+        let (precision, recall) = (ratio(tp, tp + fp), ratio(tp, tp + fn_));
+        let f1 = if precision + recall == 0.0 {
+            0.0
+        } else {
+            2.0 * precision * recall / (precision + recall)
+        };
+        Self {
+            precision,
+            recall,
+            f1,
+            true_positives: tp,
+            false_positives: fp,
+            false_negatives: fn_,
+        }
+    }
+
+    /// How many samples belong to the class.
+    pub fn support(&self) -> usize {
+        self.true_positives + self.false_negatives
+    }
+}
+
+impl Display for ClassMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "precision {:.4}, recall {:.4}, f1 {:.4} ({} samples)",
+            self.precision,
+            self.recall,
+            self.f1,
+            self.support()
+        )
+    }
+}
+
+impl Debug for ClassMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Precision, recall and F1 of every class in `0..num_classes`.
+///
+/// # Panics
+///
+/// If `preds` and `targets` differ in length or hold a class out of range.
+pub fn precision_recall_f1(
+    preds: &[usize],
+    targets: &[usize],
+    num_classes: usize,
+) -> Vec<ClassMetrics> {
+    ConfusionMatrix::from_preds(preds, targets, num_classes).class_metrics()
+}
+
+/// Counts of each (true class, predicted class) pair.
+///
+/// ```
+/// # use smolgrad::metrics::ConfusionMatrix;
+/// let mut matrix = ConfusionMatrix::new(2).with_labels(&["no", "yes"]);
+/// for (preds, targets) in [([0, 1], [0, 0]), ([1, 1], [1, 1])] {
+///     matrix.update(&preds, &targets);
+/// }
+/// assert_eq!(matrix.count(0, 1), 1);
+/// println!("{}", matrix);
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct ConfusionMatrix {
+    counts: Vec<Vec<usize>>,
+    labels: Vec<String>,
+}
+
+impl ConfusionMatrix {
+    /// An empty matrix, with the classes labelled by number.
+    pub fn new(num_classes: usize) -> Self {
+        Self {
+            counts: vec![vec![0; num_classes]; num_classes],
+            labels: (0..num_classes).map(|c| c.to_string()).collect(),
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Like [`ConfusionMatrix::update`].
+    pub fn from_preds(
+        preds: &[usize],
+        targets: &[usize],
+        num_classes: usize,
+    ) -> Self {
+        let mut matrix = Self::new(num_classes);
+        matrix.update(preds, targets);
+        matrix
+    }
+
+    /// [`ConfusionMatrix::from_preds`] counted over rayon's thread pool,
+    /// each thread filling its own matrix before they are summed.
+    ///
+    /// # Panics
+    ///
+    /// Like [`ConfusionMatrix::update`].
+    #[cfg(feature = "parallel")]
+    pub fn from_preds_par(
+        preds: &[usize],
+        targets: &[usize],
+        num_classes: usize,
+    ) -> Self {
+        use rayon::prelude::*;
+        check_lengths(preds.len(), targets.len());
+        let chunk = 1024;
+        preds
+            .par_chunks(chunk)
+            .zip(targets.par_chunks(chunk))
+            .map(|(p, t)| Self::from_preds(p, t, num_classes))
+            .reduce(
+                || Self::new(num_classes),
+                |mut total, part| {
+                    let cells = total.counts.iter_mut().flatten();
+                    for (sum, count) in cells.zip(part.counts.iter().flatten())
+                    {
+                        *sum += count;
+                    }
+                    total
+                },
+            )
+    }
+
+    /// Names the classes in the printed table.
+    ///
+    /// # Panics
+    ///
+    /// Unless there is one label per class.
+    pub fn with_labels(self, labels: &[&str]) -> Self {
+        assert_eq!(
+            labels.len(),
+            self.num_classes(),
+            "{} labels for {} classes",
+            labels.len(),
+            self.num_classes()
+        );
+        let labels = labels.iter().map(|l| l.to_string()).collect();
+        Self { labels, ..self }
+    }
+
+    /// Adds more predictions, e.g. one batch's.
+    ///
+    /// # Panics
+    ///
+    /// If `preds` and `targets` differ in length or hold a class out of
+    /// range.
+    pub fn update(&mut self, preds: &[usize], targets: &[usize]) {
+        check_lengths(preds.len(), targets.len());
+        let n = self.num_classes();
+        for (&p, &t) in preds.iter().zip(targets) {
+            for class in [p, t] {
+                assert!(
+                    class < n,
+                    "class {} out of range for {} classes",
+                    class,
+                    n
+                );
+            }
+            self.counts[t][p] += 1;
+        }
+    }
+
+    pub fn num_classes(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// How many samples of `true_class` were predicted as `pred_class`.
+    pub fn count(&self, true_class: usize, pred_class: usize) -> usize {
+        self.counts[true_class][pred_class]
+    }
+
+    /// Each row divided by its total, so row `t` is the distribution of
+    /// predictions for class `t`. Rows of absent classes are all 0.
+    pub fn row_normalized(&self) -> Vec<Vec<Scalar>> {
+        let normalize = |row: &Vec<usize>| {
+            let total = row.iter().sum();
+            row.iter().map(|&c| ratio(c, total)).collect()
+        };
+        self.counts.iter().map(normalize).collect()
+    }
+
+    /// The fraction of predictions of `class` that were right.
+    pub fn precision(&self, class: usize) -> Scalar {
+        self.class_metrics()[class].precision
+    }
+
+    /// The fraction of samples of `class` that were predicted as such.
+    pub fn recall(&self, class: usize) -> Scalar {
+        self.class_metrics()[class].recall
+    }
+
+    /// Precision, recall and F1 of every class.
+    pub fn class_metrics(&self) -> Vec<ClassMetrics> {
+        let n = self.num_classes();
+        (0..n)
+            .map(|c| {
+                let tp = self.counts[c][c];
+                let predicted: usize = (0..n).map(|t| self.counts[t][c]).sum();
+                let actual: usize = self.counts[c].iter().sum();
+                ClassMetrics::from_counts(tp, predicted - tp, actual - tp)
+            })
+            .collect()
+    }
+}
+
+/// A table with a row per true class and a column per predicted class.
+impl Display for ConfusionMatrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const CORNER: &str = "true\\pred";
+        let label_width = self
+            .labels
+            .iter()
+            .map(String::len)
+            .chain(Some(CORNER.len()))
+            .max()
+            .unwrap_or(0);
+        let count_width = self
+            .counts
+            .iter()
+            .flatten()
+            .map(|c| c.to_string().len())
+            .chain(self.labels.iter().map(String::len))
+            .max()
+            .unwrap_or(0);
+        write!(f, "{:<w$}", CORNER, w = label_width)?;
+        for label in &self.labels {
+            write!(f, " {:>w$}", label, w = count_width)?;
+        }
+        for (label, row) in self.labels.iter().zip(&self.counts) {
+            write!(f, "\n{:<w$}", label, w = label_width)?;
+            for count in row {
+                write!(f, " {:>w$}", count, w = count_width)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Debug for ConfusionMatrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// The unweighted mean of each metric over `classes`, so every class
+/// counts the same however rare. Counts are summed.
+pub fn macro_average(classes: &[ClassMetrics]) -> ClassMetrics {
+    let n = classes.len().max(1) as Scalar;
+    let mean = |f: fn(&ClassMetrics) -> Scalar| {
+        classes.iter().map(f).sum::<Scalar>() / n
+    };
+    let sum = |f: fn(&ClassMetrics) -> usize| classes.iter().map(f).sum();
+    ClassMetrics {
+        precision: mean(|c| c.precision),
+        recall: mean(|c| c.recall),
+        f1: mean(|c| c.f1),
+        true_positives: sum(|c| c.true_positives),
+        false_positives: sum(|c| c.false_positives),
+        false_negatives: sum(|c| c.false_negatives),
+    }
+}
+
+/// The metrics of the counts summed over `classes`, so every sample counts
+/// the same. For single-label predictions all three equal the accuracy.
+pub fn micro_average(classes: &[ClassMetrics]) -> ClassMetrics {
+    let sum = |f: fn(&ClassMetrics) -> usize| classes.iter().map(f).sum();
+    ClassMetrics::from_counts(
+        sum(|c| c.true_positives),
+        sum(|c| c.false_positives),
+        sum(|c| c.false_negatives),
+    )
+}
+
+/// The area under the ROC curve of binary `scores` against `targets` (1
+/// for positive, 0 for negative): the chance that a random positive
+/// scores above a random negative, with ties counting half. Computed from
+/// the rank sum of the positives. 0 without both positives and negatives.
+///
+/// # Panics
+///
+/// If `scores` and `targets` differ in length.
+pub fn roc_auc(scores: &[Scalar], targets: &[u8]) -> Scalar {
+    check_lengths(scores.len(), targets.len());
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|&a, &b| scores[a].total_cmp(&scores[b]));
+
+    // Ranks from 1, with tied scores sharing the mean of their ranks
+    let mut positive_rank_sum = 0.0;
+    let mut start = 0;
+    while start < order.len() {
+        let score = scores[order[start]];
+        let end = start
+            + order[start..]
+                .iter()
+                .take_while(|&&i| scores[i] == score)
+                .count();
+        // A NaN score isn't equal even to itself
+        let end = end.max(start + 1);
+        let rank = (start + 1 + end) as f64 / 2.0;
+        let positives = order[start..end].iter().filter(|&&i| targets[i] != 0);
+        positive_rank_sum += rank * positives.count() as f64;
+        start = end;
+    }
+
+    let positives = targets.iter().filter(|&&t| t != 0).count();
+    let negatives = targets.len() - positives;
+    if positives == 0 || negatives == 0 {
+        return 0.0;
+    }
+    let (p, n) = (positives as f64, negatives as f64);
+    ((positive_rank_sum - p * (p + 1.0) / 2.0) / (p * n)) as Scalar
+}
+
+/// `num / den` for sums over samples, or 0 if `den` is 0.
+fn fraction(num: f64, den: f64) -> Scalar {
+    if den == 0.0 {
+        0.0
+    } else {
+        (num / den) as Scalar
+    }
+}
+
+/// The mean of `f(x)` over `xs`, in f64; 0 if there are none.
+fn mean(xs: &[Scalar], f: impl Fn(f64) -> f64) -> f64 {
+    #[allow(clippy::unnecessary_cast)] // Scalar may be f64
+    let sum: f64 = xs.iter().map(|&x| f(x as f64)).sum();
+    if xs.is_empty() {
+        0.0
+    } else {
+        sum / xs.len() as f64
+    }
+}
+
+/// The mean of `f(pred, target)` over the pairs, in f64; 0 if there are
+/// none.
+fn mean_of(
+    preds: &[Scalar],
+    targets: &[Scalar],
+    f: impl Fn(f64, f64) -> f64,
+) -> f64 {
+    check_lengths(preds.len(), targets.len());
+    #[allow(clippy::unnecessary_cast)] // Scalar may be f64
+    let sum: f64 = preds
+        .iter()
+        .zip(targets)
+        .map(|(&p, &t)| f(p as f64, t as f64))
+        .sum();
+    if preds.is_empty() {
+        0.0
+    } else {
+        sum / preds.len() as f64
+    }
+}
+
+/// The mean absolute error; 0 if there are no predictions.
+///
+/// # Panics
+///
+/// If `preds` and `targets` differ in length.
+pub fn mae(preds: &[Scalar], targets: &[Scalar]) -> Scalar {
+    mean_of(preds, targets, |p, t| (p - t).abs()) as Scalar
+}
+
+/// The root mean squared error; 0 if there are no predictions.
+///
+/// # Panics
+///
+/// If `preds` and `targets` differ in length.
+pub fn rmse(preds: &[Scalar], targets: &[Scalar]) -> Scalar {
+    mean_of(preds, targets, |p, t| (p - t).powi(2)).sqrt() as Scalar
+}
+
+/// The coefficient of determination, `1 - SS_res / SS_tot`: 1 for perfect
+/// predictions, 0 for always predicting the mean target, and negative for
+/// worse. 0 if the targets are constant, even for perfect predictions.
+///
+/// # Panics
+///
+/// If `preds` and `targets` differ in length.
+pub fn r2_score(preds: &[Scalar], targets: &[Scalar]) -> Scalar {
+    let target_mean = mean(targets, |t| t);
+    let ss_res = mean_of(preds, targets, |p, t| (t - p).powi(2));
+    let ss_tot = mean(targets, |t| (t - target_mean).powi(2));
+    fraction(ss_tot - ss_res, ss_tot)
+}
+
+/// `1 - Var(target - pred) / Var(target)`, which is the R² except that a
+/// constant bias in the predictions isn't held against them. 0 if the
+/// targets are constant.
+///
+/// # Panics
+///
+/// If `preds` and `targets` differ in length.
+pub fn explained_variance(preds: &[Scalar], targets: &[Scalar]) -> Scalar {
+    let target_mean = mean(targets, |t| t);
+    let mean_err = mean_of(preds, targets, |p, t| t - p);
+    let var_err = mean_of(preds, targets, |p, t| (t - p - mean_err).powi(2));
+    let var = mean(targets, |t| (t - target_mean).powi(2));
+    fraction(var - var_err, var)
+}
+
+/// Regression metrics accumulated one prediction at a time, e.g. over
+/// validation batches, without keeping the predictions. Running means and
+/// variances use Welford's method, so they match the functions on whole
+/// slices to within rounding.
+///
+/// ```
+/// # use smolgrad::metrics::RegressionMetrics;
+/// let mut metrics = RegressionMetrics::new();
+/// for (pred, target) in [(2.5, 3.0), (0.0, -0.5), (2.0, 2.0)] {
+///     metrics.update(pred, target);
+/// }
+/// let summary = metrics.finalize();
+/// assert!((summary.mae - 1.0 / 3.0).abs() < 1e-6);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RegressionMetrics {
+    count: usize,
+    sum_abs: f64,
+    sum_sq: f64,
+    target: Welford,
+    err: Welford,
+}
+
+/// A running mean and sum of squared deviations from it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Welford {
+    mean: f64,
+    m2: f64,
+}
+
+impl Welford {
+    fn push(&mut self, x: f64, count: usize) {
+        let delta = x - self.mean;
+        self.mean += delta / count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+}
+
+impl RegressionMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, pred: Scalar, target: Scalar) {
+        #[allow(clippy::unnecessary_cast)] // Scalar may be f64
+        let (pred, target) = (pred as f64, target as f64);
+        let err = target - pred;
+        self.count += 1;
+        self.sum_abs += err.abs();
+        self.sum_sq += err * err;
+        self.target.push(target, self.count);
+        self.err.push(err, self.count);
+    }
+
+    /// How many predictions have been added.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The metrics of everything added so far; all 0 if nothing was.
+    pub fn finalize(&self) -> RegressionSummary {
+        let n = self.count as f64;
+        RegressionSummary {
+            mae: fraction(self.sum_abs, n),
+            rmse: fraction(self.sum_sq, n).sqrt(),
+            r2: fraction(self.target.m2 - self.sum_sq, self.target.m2),
+            explained_variance: fraction(
+                self.target.m2 - self.err.m2,
+                self.target.m2,
+            ),
+        }
+    }
+}
+
+/// What [`RegressionMetrics::finalize`] found, as from [`mae`], [`rmse`],
+/// [`r2_score`] and [`explained_variance`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegressionSummary {
+    pub mae: Scalar,
+    pub rmse: Scalar,
+    pub r2: Scalar,
+    pub explained_variance: Scalar,
+}
+
+impl Display for RegressionSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "mae {:.4}, rmse {:.4}, r2 {:.4}, explained variance {:.4}",
+            self.mae, self.rmse, self.r2, self.explained_variance
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_accuracy() {
+        assert_eq!(accuracy(&[0, 1, 2, 1], &[0, 1, 1, 1]), 0.75);
+        assert_eq!(accuracy(&[], &[]), 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "2 predictions for 3 targets")]
+    fn test_accuracy_lengths() {
+        accuracy(&[0, 1], &[0, 1, 1]);
+    }
+
+    #[test]
+    fn test_precision_recall_f1() {
+        // Class 0: tp 2, fp 1, fn 1. Class 1: tp 1, fp 1, fn 1. Class 2
+        // is never predicted and never the target.
+        let preds = [0, 0, 1, 0, 1];
+        let targets = [0, 0, 1, 1, 0];
+        let classes = precision_recall_f1(&preds, &targets, 3);
+        let first = classes[0];
+        assert_eq!((first.precision, first.recall), (2.0 / 3.0, 2.0 / 3.0));
+        assert!((first.f1 - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!((classes[1].precision, classes[1].recall), (0.5, 0.5));
+        assert_eq!(classes[1].support(), 2);
+        let never = classes[2];
+        assert_eq!((never.precision, never.recall, never.f1), (0.0, 0.0, 0.0));
+        assert_eq!(never.support(), 0);
+    }
+
+    #[test]
+    fn test_macro_vs_micro() {
+        // 8 samples of class 0, all right; 2 of class 1, both called 0
+        let targets = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1];
+        let preds = [0; 10];
+        let classes = precision_recall_f1(&preds, &targets, 2);
+        let micro = micro_average(&classes);
+        let macro_ = macro_average(&classes);
+        assert_eq!(micro.precision, accuracy(&preds, &targets));
+        assert_eq!((micro.precision, micro.recall), (0.8, 0.8));
+        // Class 0 has precision 0.8 and recall 1; class 1 scores 0
+        assert_eq!((macro_.precision, macro_.recall), (0.4, 0.5));
+        assert!((macro_.f1 - 0.4444444).abs() < 1e-6);
+        assert!(macro_.f1 < micro.f1);
+        assert_eq!(macro_.support(), 10);
+    }
+
+    #[test]
+    fn test_roc_auc() {
+        let targets = [0, 0, 1, 1, 0, 1];
+        let perfect = [0.1, 0.2, 0.8, 0.9, 0.3, 0.7];
+        assert_eq!(roc_auc(&perfect, &targets), 1.0);
+        let inverted: Vec<_> = perfect.iter().map(|s| -s).collect();
+        assert_eq!(roc_auc(&inverted, &targets), 0.0);
+        // All tied: every pair counts half
+        assert_eq!(roc_auc(&[0.5; 6], &targets), 0.5);
+        // One of the nine pairs is out of order
+        let one_swap = [0.1, 0.2, 0.8, 0.9, 0.75, 0.7];
+        assert!((roc_auc(&one_swap, &targets) - 8.0 / 9.0).abs() < 1e-6);
+        assert_eq!(roc_auc(&[0.3, 0.4], &[1, 1]), 0.0);
+    }
+
+    #[test]
+    fn test_regression_metrics() {
+        let preds = [2.5, 0.0, 2.0, 8.0];
+        let targets = [3.0, -0.5, 2.0, 7.0];
+        // Errors 0.5, -0.5, 0, -1; targets have mean 2.875 and
+        // SS_tot 29.1875
+        assert_eq!(mae(&preds, &targets), 0.5);
+        assert!(
+            (rmse(&preds, &targets) - (1.5 as Scalar / 4.0).sqrt()).abs()
+                < 1e-6
+        );
+        assert!(
+            (r2_score(&preds, &targets) - (1.0 - 1.5 / 29.1875)).abs() < 1e-6
+        );
+        // Errors have mean -0.25 and squared deviations summing to 1.25
+        let ev = explained_variance(&preds, &targets);
+        assert!((ev - (1.0 - 1.25 / 29.1875)).abs() < 1e-6);
+
+        assert_eq!(r2_score(&targets, &targets), 1.0);
+        assert_eq!(explained_variance(&targets, &targets), 1.0);
+        assert_eq!(rmse(&targets, &targets), 0.0);
+        // A constant bias costs R² but not explained variance
+        let biased: Vec<_> = targets.iter().map(|t| t + 1.0).collect();
+        assert!(r2_score(&biased, &targets) < 1.0);
+        assert!((explained_variance(&biased, &targets) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_regression_metrics_degenerate() {
+        assert_eq!(r2_score(&[1.0, 2.0], &[2.0, 2.0]), 0.0);
+        assert_eq!(r2_score(&[2.0, 2.0], &[2.0, 2.0]), 0.0);
+        assert_eq!(explained_variance(&[1.0, 3.0], &[2.0, 2.0]), 0.0);
+        assert_eq!(mae(&[], &[]), 0.0);
+        assert_eq!(rmse(&[], &[]), 0.0);
+        let empty = RegressionMetrics::new().finalize();
+        assert_eq!((empty.mae, empty.rmse, empty.r2), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "3 predictions for 2 targets")]
+    fn test_regression_metrics_lengths() {
+        r2_score(&[1.0, 2.0, 3.0], &[1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_regression_metrics_accumulator() {
+        let preds: Vec<Scalar> = (0..100)
+            .map(|i| (i as Scalar * 0.37).sin() * 10.0)
+            .collect();
+        let targets: Vec<Scalar> = (0..100)
+            .map(|i| {
+                (i as Scalar * 0.37).sin() * 9.0
+                    + 100.0
+                    + (i % 7) as Scalar * 0.3
+            })
+            .collect();
+        let mut metrics = RegressionMetrics::new();
+        for batch in preds.chunks(16).zip(targets.chunks(16)) {
+            for (&p, &t) in batch.0.iter().zip(batch.1) {
+                metrics.update(p, t);
+            }
+        }
+        assert_eq!(metrics.count(), 100);
+        let summary = metrics.finalize();
+        let close =
+            |a: Scalar, b: Scalar| (a - b).abs() < 1e-4 * b.abs().max(1.0);
+        assert!(close(summary.mae, mae(&preds, &targets)));
+        assert!(close(summary.rmse, rmse(&preds, &targets)));
+        assert!(close(summary.r2, r2_score(&preds, &targets)));
+        assert!(close(
+            summary.explained_variance,
+            explained_variance(&preds, &targets)
+        ));
+        assert!(summary.r2 < 0.0);
+        assert!(summary.explained_variance > 0.9);
+        assert!(summary.to_string().starts_with("mae 100."));
+    }
+
+    fn three_classes() -> ConfusionMatrix {
+        let targets = [0, 0, 0, 1, 1, 1, 1, 2, 2, 0];
+        let preds = [0, 0, 1, 1, 1, 2, 1, 2, 0, 0];
+        ConfusionMatrix::from_preds(&preds, &targets, 3)
+    }
+
+    #[test]
+    fn test_confusion_matrix() {
+        let matrix = three_classes();
+        let counts: Vec<Vec<_>> = (0..3)
+            .map(|t| (0..3).map(|p| matrix.count(t, p)).collect())
+            .collect();
+        assert_eq!(counts, [[3, 1, 0], [0, 3, 1], [1, 0, 1]]);
+        assert_eq!(matrix.precision(0), 0.75);
+        assert_eq!(matrix.recall(1), 0.75);
+        assert_eq!(matrix.precision(2), 0.5);
+
+        let mut batched = ConfusionMatrix::new(3);
+        batched.update(&[0, 0, 1, 1, 1], &[0, 0, 0, 1, 1]);
+        batched.update(&[2, 1, 2, 0, 0], &[1, 1, 2, 2, 0]);
+        assert_eq!(batched, matrix);
+    }
+
+    #[test]
+    fn test_confusion_matrix_row_normalized() {
+        let matrix =
+            ConfusionMatrix::from_preds(&[0, 1, 1, 0], &[0, 0, 0, 1], 3);
+        let rows = matrix.row_normalized();
+        assert_eq!(rows[0], [1.0 / 3.0, 2.0 / 3.0, 0.0]);
+        assert_eq!(rows[1], [1.0, 0.0, 0.0]);
+        assert_eq!(rows[2], [0.0, 0.0, 0.0]);
+        for row in &rows[..2] {
+            assert!((row.iter().sum::<Scalar>() - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_confusion_matrix_display() {
+        let matrix = three_classes();
+        let expected = "\
+true\\pred 0 1 2
+0         3 1 0
+1         0 3 1
+2         1 0 1";
+        assert_eq!(matrix.to_string(), expected);
+
+        let mut matrix = matrix.with_labels(&["cat", "dog", "bird"]);
+        matrix.update(&[1; 12], &[1; 12]);
+        let expected = "\
+true\\pred  cat  dog bird
+cat          3    1    0
+dog          0   15    1
+bird         1    0    1";
+        assert_eq!(matrix.to_string(), expected);
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn test_parallel_metrics() {
+        let targets: Vec<_> = (0..5000).map(|i| i % 3).collect();
+        let preds: Vec<_> = (0..5000).map(|i| (i * 7 / 5) % 3).collect();
+        assert_eq!(accuracy_par(&preds, &targets), accuracy(&preds, &targets));
+        assert_eq!(
+            ConfusionMatrix::from_preds_par(&preds, &targets, 3),
+            ConfusionMatrix::from_preds(&preds, &targets, 3)
+        );
+        assert_eq!(
+            ConfusionMatrix::from_preds_par(&[], &[], 2),
+            ConfusionMatrix::new(2)
+        );
+    }
+}
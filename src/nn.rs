@@ -1,11 +1,46 @@
+use std::any::Any;
+use std::collections::HashSet;
 use std::fmt::{Debug, Display};
+use std::ops::Index;
+use std::str::FromStr;
 
-use crate::engine::Value;
-use rand::Rng;
+use crate::engine::{gelu, MaybeSync, Scalar, Value};
+use crate::random::standard_normal;
+use crate::shared::{Lock, Shared, ValueFn};
+use crate::tensor::Vector;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Anything with trainable parameters.
+pub trait Module: Debug + Introspect {
+    /// Runs the module on `x`.
+    fn forward(&self, x: &[Value]) -> Vec<Value>;
+
+    /// Runs the module on each sample of a batch. Modules that look across
+    /// the batch, like [`BatchNorm1d`], and containers of them override
+    /// this; everything else runs [`Module::forward`] per sample.
+    fn forward_batch(&self, xs: &[Vec<Value>]) -> Vec<Vec<Value>> {
+        xs.iter().map(|x| self.forward(x)).collect()
+    }
+
+    /// [`Module::forward_batch`] on plain numbers, fed in as
+    /// [constants](Value::constant) so they collect no grads.
+    fn forward_batch_scalars(&self, xs: &[Vec<Scalar>]) -> Vec<Vec<Value>> {
+        let constants =
+            |x: &Vec<Scalar>| x.iter().map(|&x| Value::constant(x)).collect();
+        self.forward_batch(&xs.iter().map(constants).collect::<Vec<_>>())
+    }
+
+    /// [`Module::forward`] on plain numbers. Modules with a graph-free
+    /// path, like [`MLP`], override this; the default runs `forward` on
+    /// [constants](Value::constant) and reads off the data.
+    fn predict_scalars(&self, x: &[Scalar]) -> Vec<Scalar> {
+        let x: Vec<_> = x.iter().map(|&x| Value::constant(x)).collect();
+        self.forward(&x).iter().map(Value::get_data).collect()
+    }
 
-trait Module {
     fn zero_grad(&self) {
-        for v in self.parameters().iter_mut() {
+        for v in self.parameters() {
             v.set_grad(0.0)
         }
     }
@@ -13,64 +48,753 @@ trait Module {
     fn parameters(&self) -> Vec<Value> {
         vec![]
     }
+
+    /// Every parameter with a name that is unique within the module and
+    /// stable across calls. Containers prefix their children's names, e.g.
+    /// `layer1.neuron0.w2`.
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        let params = self.parameters().into_iter().enumerate();
+        params.map(|(i, p)| (i.to_string(), p)).collect()
+    }
+
+    fn num_parameters(&self) -> usize {
+        self.parameters().len()
+    }
+
+    /// The parameters that multiply an input, as opposed to biases and
+    /// normalization parameters. These are what [`prune_by_magnitude`]
+    /// prunes; modules without weights leave the default of none.
+    fn weights(&self) -> Vec<Value> {
+        vec![]
+    }
+
+    /// Copies out the data of every parameter, in `parameters()` order.
+    fn get_parameters_flat(&self) -> Vec<Scalar> {
+        self.parameters().iter().map(Value::get_data).collect()
+    }
+
+    /// Overwrites every parameter's data from `data`, in `parameters()`
+    /// order. Existing handles to the parameters see the new values.
+    fn set_parameters_flat(
+        &self,
+        data: &[Scalar],
+    ) -> Result<(), ParameterCountError> {
+        let params = self.parameters();
+        if params.len() != data.len() {
+            return Err(ParameterCountError {
+                expected: params.len(),
+                got: data.len(),
+            });
+        }
+        for (p, &x) in params.iter().zip(data) {
+            p.set_data(x);
+        }
+        Ok(())
+    }
+
+    /// A copy whose parameters are new leaves holding the same data, with
+    /// zero grads, so training one leaves the other alone. `None` for
+    /// modules that can't be copied.
+    fn clone_detached_boxed(&self) -> Option<Box<dyn Module>> {
+        None
+    }
+
+    /// Switches between training and inference behaviour. Modules that
+    /// behave the same either way ignore it; containers pass it on.
+    fn set_training(&mut self, _training: bool) {}
+
+    /// Modules without a mode report that they are training.
+    fn is_training(&self) -> bool {
+        true
+    }
+
+    fn train(&mut self) {
+        self.set_training(true)
+    }
+
+    fn eval(&mut self) {
+        self.set_training(false)
+    }
+}
+
+/// What a module can tell about its own structure, for [`summary`]. Every
+/// method has a default, so modules with nothing to report implement it
+/// with an empty block.
+pub trait Introspect {
+    /// The layers a container is built from, each with the prefix its
+    /// parameters are named under. Leaf modules have none.
+    fn children(&self) -> Vec<(String, &dyn Module)> {
+        vec![]
+    }
+
+    /// A short name for the module's type, shown by [`summary`].
+    fn type_name(&self) -> String {
+        let name = std::any::type_name::<Self>();
+        let name = name.split('<').next().unwrap_or(name);
+        name.rsplit("::").next().unwrap_or(name).to_string()
+    }
+
+    fn activation(&self) -> Option<&Activation> {
+        None
+    }
+
+    /// How many values the module returns for `input_size` inputs, if it
+    /// knows. `None` as well if the module can't take that many.
+    fn output_size(&self, _input_size: usize) -> Option<usize> {
+        None
+    }
+
+    /// The module as [`Any`], for code that knows the concrete types it is
+    /// looking for, like [`checkpoint::architecture`]. `None` for modules
+    /// that don't offer it.
+    ///
+    /// [`checkpoint::architecture`]: crate::checkpoint::architecture
+    fn as_any(&self) -> Option<&dyn Any> {
+        None
+    }
+
+    /// The module a wrapper like [`Residual`] runs its input through.
+    /// Unlike [`Introspect::children`], the wrapped module is not a layer of
+    /// its own.
+    fn wrapped(&self) -> Option<&dyn Module> {
+        None
+    }
+}
+
+/// The data passed to [`Module::set_parameters_flat`] has the wrong length.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParameterCountError {
+    pub expected: usize,
+    pub got: usize,
+}
+
+impl Display for ParameterCountError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "expected {} parameter values, got {}",
+            self.expected, self.got
+        )
+    }
+}
+
+impl std::error::Error for ParameterCountError {}
+
+impl<M: Module + ?Sized> Module for Box<M> {
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        (**self).forward(x)
+    }
+
+    fn forward_batch(&self, xs: &[Vec<Value>]) -> Vec<Vec<Value>> {
+        (**self).forward_batch(xs)
+    }
+
+    fn predict_scalars(&self, x: &[Scalar]) -> Vec<Scalar> {
+        (**self).predict_scalars(x)
+    }
+
+    fn zero_grad(&self) {
+        (**self).zero_grad()
+    }
+
+    fn parameters(&self) -> Vec<Value> {
+        (**self).parameters()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        (**self).named_parameters()
+    }
+
+    fn weights(&self) -> Vec<Value> {
+        (**self).weights()
+    }
+
+    fn num_parameters(&self) -> usize {
+        (**self).num_parameters()
+    }
+
+    fn set_training(&mut self, training: bool) {
+        (**self).set_training(training)
+    }
+
+    fn is_training(&self) -> bool {
+        (**self).is_training()
+    }
+
+    fn clone_detached_boxed(&self) -> Option<Box<dyn Module>> {
+        (**self).clone_detached_boxed()
+    }
+}
+
+impl<M: Module + ?Sized> Introspect for Box<M> {
+    fn children(&self) -> Vec<(String, &dyn Module)> {
+        (**self).children()
+    }
+
+    fn type_name(&self) -> String {
+        (**self).type_name()
+    }
+
+    fn activation(&self) -> Option<&Activation> {
+        (**self).activation()
+    }
+
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        (**self).output_size(input_size)
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        (**self).as_any()
+    }
+
+    fn wrapped(&self) -> Option<&dyn Module> {
+        (**self).wrapped()
+    }
+}
+
+impl<M: Module + ?Sized> Module for &M {
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        (**self).forward(x)
+    }
+
+    fn forward_batch(&self, xs: &[Vec<Value>]) -> Vec<Vec<Value>> {
+        (**self).forward_batch(xs)
+    }
+
+    fn predict_scalars(&self, x: &[Scalar]) -> Vec<Scalar> {
+        (**self).predict_scalars(x)
+    }
+
+    fn zero_grad(&self) {
+        (**self).zero_grad()
+    }
+
+    fn parameters(&self) -> Vec<Value> {
+        (**self).parameters()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        (**self).named_parameters()
+    }
+
+    fn weights(&self) -> Vec<Value> {
+        (**self).weights()
+    }
+
+    fn num_parameters(&self) -> usize {
+        (**self).num_parameters()
+    }
+
+    fn clone_detached_boxed(&self) -> Option<Box<dyn Module>> {
+        (**self).clone_detached_boxed()
+    }
+
+    fn is_training(&self) -> bool {
+        (**self).is_training()
+    }
+}
+
+impl<M: Module + ?Sized> Introspect for &M {
+    fn children(&self) -> Vec<(String, &dyn Module)> {
+        (**self).children()
+    }
+
+    fn type_name(&self) -> String {
+        (**self).type_name()
+    }
+
+    fn activation(&self) -> Option<&Activation> {
+        (**self).activation()
+    }
+
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        (**self).output_size(input_size)
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        (**self).as_any()
+    }
+
+    fn wrapped(&self) -> Option<&dyn Module> {
+        (**self).wrapped()
+    }
+}
+
+/// A new leaf holding `v`'s data.
+pub(crate) fn detached(v: &Value) -> Value {
+    Value::new(v.get_data())
+}
+
+/// Names each child's parameters `{prefix}.{name}`.
+fn prefixed(children: Vec<(String, &dyn Module)>) -> Vec<(String, Value)> {
+    children
+        .into_iter()
+        .flat_map(|(prefix, child)| {
+            let named = child.named_parameters().into_iter();
+            named.map(move |(name, p)| (format!("{}.{}", prefix, name), p))
+        })
+        .collect()
+}
+
+/// Lays out a Keras-style table of `module`'s layers for `input_size`
+/// inputs: name, type, activation, output size and parameter count, with
+/// the layers of nested containers indented under them.
+pub fn summary(module: &dyn Module, input_size: usize) -> String {
+    fn rows(
+        module: &dyn Module,
+        input: Option<usize>,
+        depth: usize,
+        out: &mut Vec<[String; 5]>,
+    ) {
+        let mut size = input;
+        for (name, child) in module.children() {
+            let child_input = size;
+            size = size.and_then(|n| child.output_size(n));
+            out.push([
+                format!("{}{}", "  ".repeat(depth), name),
+                child.type_name(),
+                child.activation().map_or("-".into(), |a| a.to_string()),
+                size.map_or("?".into(), |n| n.to_string()),
+                child.num_parameters().to_string(),
+            ]);
+            rows(child, child_input, depth + 1, out);
+        }
+    }
+
+    let mut table = vec![[
+        "Layer".to_string(),
+        "Type".to_string(),
+        "Activation".to_string(),
+        "Output".to_string(),
+        "Params".to_string(),
+    ]];
+    rows(module, Some(input_size), 0, &mut table);
+    let mut widths = [0; 5];
+    for row in &table {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.len());
+        }
+    }
+    let rule = "=".repeat(widths.iter().sum::<usize>() + 2 * 4);
+    let mut lines = vec![];
+    for (i, row) in table.iter().enumerate() {
+        lines.push(format!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {:>w3$}  {:>w4$}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+            w4 = widths[4],
+        ));
+        if i == 0 {
+            lines.push(rule.clone());
+        }
+    }
+    lines.push(rule);
+    lines.push(format!("Total params: {}", module.num_parameters()));
+    lines.join("\n")
+}
+
+/// The nonlinearity a [`Neuron`] applies to `w . x + b`.
+#[derive(Clone)]
+pub enum Activation {
+    Linear,
+    ReLU,
+    Tanh,
+    Sigmoid,
+    /// ReLU with the given slope for negative inputs.
+    LeakyReLU(Scalar),
+    /// [`Value::gelu`], the tanh approximation.
+    Gelu,
+    /// Any composition of engine ops; see [`Activation::custom`].
+    Custom(Shared<ValueFn>),
+}
+
+impl Activation {
+    /// Wraps `f` as an activation.
+    pub fn custom(f: impl Fn(&Value) -> Value + MaybeSync + 'static) -> Self {
+        Activation::Custom(Shared::new(f))
+    }
+
+    pub fn apply(&self, x: &Value) -> Value {
+        match self {
+            Activation::Linear => x.clone(),
+            Activation::ReLU => x.relu(),
+            Activation::Tanh => x.tanh(),
+            Activation::Sigmoid => x.sigmoid(),
+            &Activation::LeakyReLU(slope) => x.leaky_relu(slope),
+            Activation::Gelu => x.gelu(),
+            Activation::Custom(f) => f(x),
+        }
+    }
+
+    /// [`Activation::apply`] on a plain number, computing exactly what the
+    /// engine op would. Custom activations still go through a throwaway
+    /// node.
+    pub fn apply_scalar(&self, x: Scalar) -> Scalar {
+        match self {
+            Activation::Linear => x,
+            Activation::ReLU => {
+                if x >= 0.0 {
+                    x
+                } else {
+                    0.0
+                }
+            }
+            Activation::Tanh => x.tanh(),
+            Activation::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+            &Activation::LeakyReLU(slope) => {
+                if x > 0.0 {
+                    x
+                } else {
+                    slope * x
+                }
+            }
+            Activation::Gelu => gelu(x),
+            Activation::Custom(f) => f(&Value::constant(x)).get_data(),
+        }
+    }
+}
+
+/// `true` is ReLU and `false` is linear, as `Neuron` used to take.
+impl From<bool> for Activation {
+    fn from(nonlin: bool) -> Self {
+        if nonlin {
+            Activation::ReLU
+        } else {
+            Activation::Linear
+        }
+    }
+}
+
+impl Display for Activation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Activation::Linear => f.write_str("Linear"),
+            Activation::ReLU => f.write_str("ReLU"),
+            Activation::Tanh => f.write_str("Tanh"),
+            Activation::Sigmoid => f.write_str("Sigmoid"),
+            Activation::LeakyReLU(slope) => write!(f, "LeakyReLU({})", slope),
+            Activation::Gelu => f.write_str("Gelu"),
+            Activation::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl Debug for Activation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+/// Parses the names [`Activation`]'s `Display` prints, except `Custom`.
+impl FromStr for Activation {
+    type Err = ParseActivationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let leaky = s
+            .strip_prefix("LeakyReLU(")
+            .and_then(|s| s.strip_suffix(')'));
+        match s {
+            "Linear" => Ok(Activation::Linear),
+            "ReLU" => Ok(Activation::ReLU),
+            "Tanh" => Ok(Activation::Tanh),
+            "Sigmoid" => Ok(Activation::Sigmoid),
+            "Gelu" => Ok(Activation::Gelu),
+            _ => leaky
+                .and_then(|slope| slope.parse().ok())
+                .map(Activation::LeakyReLU)
+                .ok_or_else(|| ParseActivationError(s.to_string())),
+        }
+    }
+}
+
+/// The string passed to `Activation::from_str` names no activation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseActivationError(pub String);
+
+impl Display for ParseActivationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown activation `{}`", self.0)
+    }
+}
+
+impl std::error::Error for ParseActivationError {}
+
+/// Stored by name; custom activations are closures and can't be saved.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Activation {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if let Activation::Custom(_) = self {
+            return Err(serde::ser::Error::custom(
+                "custom activations can't be saved",
+            ));
+        }
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Activation {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Custom activations are equal only if they share the same closure.
+impl PartialEq for Activation {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Activation::Custom(a), Activation::Custom(b)) => {
+                Shared::ptr_eq(a, b)
+            }
+            (Activation::LeakyReLU(a), Activation::LeakyReLU(b)) => a == b,
+            (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        }
+    }
+}
+
+/// How a layer's weights are drawn. Biases always start at zero.
+///
+/// `fan_in` and `fan_out` are the layer's input and output sizes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Init {
+    /// Uniform on `[-a, a]`; `UniformSymmetric(1.0)` is the default.
+    UniformSymmetric(Scalar),
+    Normal {
+        mean: Scalar,
+        std: Scalar,
+    },
+    /// Uniform with bound `sqrt(6 / (fan_in + fan_out))`.
+    XavierUniform,
+    /// Normal with std `sqrt(2 / (fan_in + fan_out))`.
+    XavierNormal,
+    /// Uniform with bound `sqrt(6 / fan_in)`, suited to ReLU.
+    KaimingUniform,
+    /// Normal with std `sqrt(2 / fan_in)`, suited to ReLU.
+    KaimingNormal,
+    Zeros,
+    Constant(Scalar),
+}
+
+impl Default for Init {
+    fn default() -> Self {
+        Init::UniformSymmetric(1.0)
+    }
+}
+
+impl Init {
+    /// Draws one weight for a layer with the given fan-in and fan-out.
+    pub fn sample<R: Rng + ?Sized>(
+        &self,
+        fan_in: usize,
+        fan_out: usize,
+        rng: &mut R,
+    ) -> Scalar {
+        let (fan_in, fan_out) = (fan_in as Scalar, fan_out as Scalar);
+        let uniform = |rng: &mut R, a: Scalar| rng.gen_range(-a..=a);
+        match *self {
+            Init::UniformSymmetric(a) => uniform(rng, a),
+            Init::Normal { mean, std } => mean + std * standard_normal(rng),
+            Init::XavierUniform => {
+                uniform(rng, (6.0 / (fan_in + fan_out)).sqrt())
+            }
+            Init::XavierNormal => {
+                (2.0 / (fan_in + fan_out)).sqrt() * standard_normal(rng)
+            }
+            Init::KaimingUniform => uniform(rng, (6.0 / fan_in).sqrt()),
+            Init::KaimingNormal => (2.0 / fan_in).sqrt() * standard_normal(rng),
+            Init::Zeros => 0.0,
+            Init::Constant(c) => c,
+        }
+    }
 }
 
-struct Neuron {
+/// A single unit computing `w . x + b` and applying its [`Activation`].
+pub struct Neuron {
     w: Vec<Value>,
-    b: Value,
-    nonlin: bool,
+    b: Option<Value>,
+    activation: Activation,
 }
 
-struct Layer {
+/// A fully connected layer of independent [`Neuron`]s.
+pub struct Layer {
     neurons: Vec<Neuron>,
 }
 
-struct MLP {
+/// A stack of [`Layer`]s applying one [`Activation`] on every layer but the
+/// last, which is linear.
+#[allow(clippy::upper_case_acronyms)]
+pub struct MLP {
     sz: Vec<usize>,
     layers: Vec<Layer>,
+    training: bool,
 }
 
 impl Neuron {
-    fn new(nin: usize, nonlin: bool) -> Self {
-        let mut rng = rand::thread_rng();
-        let mut w = (0..nin)
+    pub fn new(nin: usize, activation: impl Into<Activation>) -> Self {
+        Self::new_with_rng(nin, activation, &mut rand::thread_rng())
+    }
+
+    /// Like [`Neuron::new`], drawing the weights from `rng`.
+    pub fn new_with_rng(
+        nin: usize,
+        activation: impl Into<Activation>,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Self {
+        let w = (0..nin)
             .map(|_| Value::new(rng.gen_range(-1.0..=1.0)))
             .collect();
         Self {
             w,
-            b: Value::new(0.0),
-            nonlin,
+            b: Some(Value::new(0.0)),
+            activation: activation.into(),
+        }
+    }
+
+    /// Redraws the weights from `init`, treating the neuron as a layer of
+    /// one.
+    pub fn with_init(self, init: &Init) -> Self {
+        self.with_init_rng(init, &mut rand::thread_rng())
+    }
+
+    pub fn with_init_rng(
+        self,
+        init: &Init,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Self {
+        self.init(init, 1, rng)
+    }
+
+    fn init(
+        self,
+        init: &Init,
+        fan_out: usize,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Self {
+        for w in &self.w {
+            w.set_data(init.sample(self.w.len(), fan_out, rng));
+        }
+        self
+    }
+
+    /// Adds a bias (the default) or leaves it out entirely. A bias that is
+    /// already there is kept as it is.
+    pub fn with_bias(mut self, bias: bool) -> Self {
+        self.b = if bias {
+            self.b.or_else(|| Some(Value::new(0.0)))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// A copy with its own parameters, starting from the same data with
+    /// zero grads.
+    pub fn clone_detached(&self) -> Self {
+        Self {
+            w: self.w.iter().map(detached).collect(),
+            b: self.b.as_ref().map(detached),
+            activation: self.activation.clone(),
         }
     }
 
-    fn call(&self, x: &[Value]) -> Value {
-        let act = self.w.iter().zip(x.iter()).fold(
+    pub fn call(&self, x: &[Value]) -> Value {
+        let mut act = self.w.iter().zip(x.iter()).fold(
             Value::new(0.0),
             |mut acc, (a, b)| {
                 acc = acc + a * b;
                 acc
             },
-        ) + &self.b;
-        if self.nonlin {
-            act.relu()
-        } else {
-            act
+        );
+        if let Some(b) = &self.b {
+            act = act + b;
+        }
+        self.activation.apply(&act)
+    }
+
+    /// [`Neuron::call`] on plain numbers, without building a graph.
+    pub fn predict(&self, x: &[Scalar]) -> Scalar {
+        let mut act = self
+            .w
+            .iter()
+            .zip(x)
+            .fold(0.0, |acc, (w, x)| acc + w.get_data() * x);
+        if let Some(b) = &self.b {
+            act += b.get_data();
         }
+        self.activation.apply_scalar(act)
     }
 }
 
 impl Module for Neuron {
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        vec![self.call(x)]
+    }
+
+    fn predict_scalars(&self, x: &[Scalar]) -> Vec<Scalar> {
+        vec![self.predict(x)]
+    }
+
     fn parameters(&self) -> Vec<Value> {
         let mut out = self.w.clone();
-        out.push(self.b.clone());
+        out.extend(self.b.clone());
         out
     }
+
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        let w = self.w.iter().enumerate();
+        let mut out: Vec<_> =
+            w.map(|(i, w)| (format!("w{}", i), w.clone())).collect();
+        out.extend(self.b.iter().map(|b| ("b".to_string(), b.clone())));
+        out
+    }
+
+    fn weights(&self) -> Vec<Value> {
+        self.w.clone()
+    }
+
+    fn num_parameters(&self) -> usize {
+        self.w.len() + self.b.is_some() as usize
+    }
+
+    fn clone_detached_boxed(&self) -> Option<Box<dyn Module>> {
+        Some(Box::new(self.clone_detached()))
+    }
+}
+
+impl Introspect for Neuron {
+    fn activation(&self) -> Option<&Activation> {
+        Some(&self.activation)
+    }
+
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        Some(1).filter(|_| input_size == self.w.len())
+    }
 }
 
 impl Display for Neuron {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let ty = if self.nonlin { "ReLU" } else { "Linear" };
-        f.write_fmt(format_args!("{} Neuron{}", ty, self.w.len()))
+        f.write_fmt(format_args!(
+            "{} Neuron{}",
+            self.activation,
+            self.w.len()
+        ))?;
+        if self.b.is_none() {
+            f.write_str(" without bias")?;
+        }
+        Ok(())
     }
 }
 
@@ -81,66 +805,2020 @@ impl Debug for Neuron {
 }
 
 impl Layer {
-    fn new(nin: usize, nout: usize, nonlin: bool) -> Self {
-        let neurons = (0..nout).map(|_| Neuron::new(nin, nonlin)).collect();
-        Self { neurons }
+    pub fn new(
+        nin: usize,
+        nout: usize,
+        activation: impl Into<Activation>,
+    ) -> Self {
+        Self::new_with_rng(nin, nout, activation, &mut rand::thread_rng())
     }
 
-    fn call(&self, x: &[Value]) -> Vec<Value> {
-        self.neurons.iter().map(|n| n.call(x)).collect()
+    /// Like [`Layer::new`], drawing the weights from `rng`.
+    pub fn new_with_rng(
+        nin: usize,
+        nout: usize,
+        activation: impl Into<Activation>,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Self {
+        let activation = activation.into();
+        let neurons = (0..nout)
+            .map(|_| Neuron::new_with_rng(nin, activation.clone(), rng))
+            .collect();
+        Self { neurons }
     }
-}
 
-impl Module for Layer {
-    fn parameters(&self) -> Vec<Value> {
-        self.neurons.iter().flat_map(|n| n.parameters()).collect()
+    /// Redraws every weight from `init`.
+    pub fn with_init(self, init: &Init) -> Self {
+        self.with_init_rng(init, &mut rand::thread_rng())
     }
-}
 
-impl Debug for Layer {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("Layer of {:?}", self.neurons))
+    pub fn with_init_rng(
+        self,
+        init: &Init,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Self {
+        let fan_out = self.neurons.len();
+        let neurons = self
+            .neurons
+            .into_iter()
+            .map(|n| n.init(init, fan_out, rng))
+            .collect();
+        Self { neurons }
     }
-}
 
-impl MLP {
-    fn new(nin: usize, nouts: &[usize]) -> Self {
-        let mut sz = vec![nin];
-        sz.extend_from_slice(nouts);
-        let layers = (0..nouts.len())
-            .map(|i| Layer::new(sz[i], sz[i + 1], i != (nouts.len() - 1)))
+    /// Adds a bias to every neuron (the default) or leaves them out. Biases
+    /// that are already there are kept as they are.
+    pub fn with_bias(self, bias: bool) -> Self {
+        let neurons = self
+            .neurons
+            .into_iter()
+            .map(|n| n.with_bias(bias))
             .collect();
-        Self { sz, layers }
+        Self { neurons }
     }
 
-    fn call(&self, x: &[Value]) -> Vec<Value> {
-        self.layers.iter().fold(x.to_vec(), |mut acc, layer| {
+    pub fn nin(&self) -> usize {
+        self.neurons.first().map_or(0, |n| n.w.len())
+    }
+
+    pub fn nout(&self) -> usize {
+        self.neurons.len()
+    }
+
+    /// Whether the neurons have a bias, which an empty layer counts as.
+    pub fn has_bias(&self) -> bool {
+        self.neurons.first().is_none_or(|n| n.b.is_some())
+    }
+
+    /// A copy with its own parameters; see [`Neuron::clone_detached`].
+    pub fn clone_detached(&self) -> Self {
+        let neurons = self.neurons.iter().map(Neuron::clone_detached);
+        Self {
+            neurons: neurons.collect(),
+        }
+    }
+
+    pub fn call(&self, x: &[Value]) -> Vec<Value> {
+        self.neurons.iter().map(|n| n.call(x)).collect()
+    }
+
+    /// [`Layer::call`] on plain numbers, without building a graph.
+    pub fn predict(&self, x: &[Scalar]) -> Vec<Scalar> {
+        self.neurons.iter().map(|n| n.predict(x)).collect()
+    }
+
+    pub fn call_vector(&self, x: &Vector) -> Vector {
+        Vector::new(self.call(x.values()))
+    }
+}
+
+impl Module for Layer {
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        self.call(x)
+    }
+
+    fn predict_scalars(&self, x: &[Scalar]) -> Vec<Scalar> {
+        self.predict(x)
+    }
+
+    fn parameters(&self) -> Vec<Value> {
+        self.neurons.iter().flat_map(|n| n.parameters()).collect()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        let neurons = self.neurons.iter().enumerate();
+        prefixed(
+            neurons
+                .map(|(i, n)| (format!("neuron{}", i), n as &dyn Module))
+                .collect(),
+        )
+    }
+
+    fn num_parameters(&self) -> usize {
+        self.neurons.iter().map(Neuron::num_parameters).sum()
+    }
+
+    fn weights(&self) -> Vec<Value> {
+        self.neurons.iter().flat_map(|n| n.weights()).collect()
+    }
+
+    fn clone_detached_boxed(&self) -> Option<Box<dyn Module>> {
+        Some(Box::new(self.clone_detached()))
+    }
+}
+
+impl Introspect for Layer {
+    fn activation(&self) -> Option<&Activation> {
+        self.neurons.first().map(|n| &n.activation)
+    }
+
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        let fits = self.neurons.iter().all(|n| n.w.len() == input_size);
+        Some(self.neurons.len()).filter(|_| fits)
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+impl Debug for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("Layer of {:?}", self.neurons))
+    }
+}
+
+impl MLP {
+    /// Builds an MLP with `activation` on every layer but the last, which
+    /// is linear.
+    pub fn new(
+        nin: usize,
+        nouts: &[usize],
+        activation: impl Into<Activation>,
+    ) -> Self {
+        Self::new_with_rng(nin, nouts, activation, &mut rand::thread_rng())
+    }
+
+    /// Like [`MLP::new`], seeding a [`StdRng`] with `seed` so the same seed
+    /// always gives the same weights.
+    pub fn new_seeded(
+        nin: usize,
+        nouts: &[usize],
+        activation: impl Into<Activation>,
+        seed: u64,
+    ) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        Self::new_with_rng(nin, nouts, activation, &mut rng)
+    }
+
+    /// Like [`MLP::new`], drawing the weights from `rng`.
+    pub fn new_with_rng(
+        nin: usize,
+        nouts: &[usize],
+        activation: impl Into<Activation>,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Self {
+        let activation = activation.into();
+        let mut sz = vec![nin];
+        sz.extend_from_slice(nouts);
+        let layers = (0..nouts.len())
+            .map(|i| {
+                let act = if i == nouts.len() - 1 {
+                    Activation::Linear
+                } else {
+                    activation.clone()
+                };
+                Layer::new_with_rng(sz[i], sz[i + 1], act, rng)
+            })
+            .collect();
+        Self {
+            sz,
+            layers,
+            training: true,
+        }
+    }
+
+    /// Redraws every layer's weights from `init`.
+    pub fn with_init(self, init: &Init) -> Self {
+        self.with_init_rng(init, &mut rand::thread_rng())
+    }
+
+    pub fn with_init_rng(
+        self,
+        init: &Init,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Self {
+        let layers = self
+            .layers
+            .into_iter()
+            .map(|l| l.with_init_rng(init, rng))
+            .collect();
+        Self { layers, ..self }
+    }
+
+    /// A table of the layers and their sizes; see [`summary`].
+    pub fn summary(&self) -> String {
+        summary(self, self.sz[0])
+    }
+
+    /// Each layer's parameter count, named `layer0`, `layer1`, ...
+    pub fn parameter_counts(&self) -> Vec<(String, usize)> {
+        let counts = self.layers.iter().map(Layer::num_parameters);
+        counts
+            .enumerate()
+            .map(|(i, n)| (format!("layer{}", i), n))
+            .collect()
+    }
+
+    /// Wraps already built layers, which must chain up.
+    pub(crate) fn from_layers(nin: usize, layers: Vec<Layer>) -> Self {
+        let mut sz = vec![nin];
+        sz.extend(layers.iter().map(|l| l.neurons.len()));
+        Self {
+            sz,
+            layers,
+            training: true,
+        }
+    }
+
+    /// The input size followed by each layer's output size.
+    pub fn sizes(&self) -> &[usize] {
+        &self.sz
+    }
+
+    /// A copy with its own parameters; see [`Neuron::clone_detached`].
+    pub fn clone_detached(&self) -> Self {
+        Self {
+            sz: self.sz.clone(),
+            layers: self.layers.iter().map(Layer::clone_detached).collect(),
+            training: self.training,
+        }
+    }
+
+    pub fn call(&self, x: &[Value]) -> Vec<Value> {
+        self.layers.iter().fold(x.to_vec(), |mut acc, layer| {
             acc = layer.call(&acc);
             acc
         })
     }
+
+    pub fn call_vector(&self, x: &Vector) -> Vector {
+        Vector::new(self.call(x.values()))
+    }
+
+    /// [`MLP::call`] on plain numbers, reading the parameters' data
+    /// without building a graph, for fast inference. Gives the same
+    /// outputs as `call`.
+    pub fn predict(&self, x: &[Scalar]) -> Vec<Scalar> {
+        self.layers
+            .iter()
+            .fold(x.to_vec(), |acc, layer| layer.predict(&acc))
+    }
+
+    /// [`MLP::predict`] for each sample.
+    pub fn predict_batch(&self, xs: &[Vec<Scalar>]) -> Vec<Vec<Scalar>> {
+        xs.iter().map(|x| self.predict(x)).collect()
+    }
+
+    /// [`MLP::predict_batch`] spread over rayon's thread pool, with the
+    /// same results in the same order.
+    ///
+    /// Only this graph-free path is parallel: building graphs and
+    /// backpropagating through them stay on one thread. The `parallel`
+    /// feature turns on `sync`, so that the model can be shared at all.
+    #[cfg(feature = "parallel")]
+    pub fn predict_batch_par(&self, xs: &[Vec<Scalar>]) -> Vec<Vec<Scalar>> {
+        use rayon::prelude::*;
+        xs.par_iter().map(|x| self.predict(x)).collect()
+    }
 }
 
 impl Module for MLP {
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        self.call(x)
+    }
+
+    fn predict_scalars(&self, x: &[Scalar]) -> Vec<Scalar> {
+        self.predict(x)
+    }
+
     fn parameters(&self) -> Vec<Value> {
         self.layers.iter().flat_map(|l| l.parameters()).collect()
     }
+
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        prefixed(self.children())
+    }
+
+    fn num_parameters(&self) -> usize {
+        self.layers.iter().map(Layer::num_parameters).sum()
+    }
+
+    fn weights(&self) -> Vec<Value> {
+        self.layers.iter().flat_map(|l| l.weights()).collect()
+    }
+
+    fn clone_detached_boxed(&self) -> Option<Box<dyn Module>> {
+        Some(Box::new(self.clone_detached()))
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+        for m in self.layers.iter_mut() {
+            m.set_training(training)
+        }
+    }
+
+    fn is_training(&self) -> bool {
+        self.training
+    }
+}
+
+impl Introspect for MLP {
+    fn children(&self) -> Vec<(String, &dyn Module)> {
+        let layers = self.layers.iter().enumerate();
+        layers
+            .map(|(i, l)| (format!("layer{}", i), l as &dyn Module))
+            .collect()
+    }
+
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        if self.sz.first() != Some(&input_size) {
+            return None;
+        }
+        self.sz.last().copied()
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+impl Display for MLP {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("MLP of {:?}", self.layers))
+    }
+}
+
+impl Debug for MLP {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+impl MLP {
+    /// Copies the weights into a [`CompiledMlp`] for fast inference.
+    ///
+    /// # Panics
+    ///
+    /// If a layer has a custom activation, which the copy couldn't share
+    /// between threads.
+    pub fn compile_inference(&self) -> CompiledMlp {
+        let layers = self
+            .layers
+            .iter()
+            .zip(self.sz.windows(2))
+            .map(|(layer, sz)| {
+                let activation = layer
+                    .neurons
+                    .first()
+                    .map_or(Some(BuiltinActivation::Linear), |n| {
+                        BuiltinActivation::of(&n.activation)
+                    })
+                    .expect("can't compile a custom activation");
+                let mut compiled = CompiledLayer {
+                    nin: sz[0],
+                    weights: Vec::new(),
+                    bias: Vec::new(),
+                    activation,
+                };
+                compiled.copy_from(layer);
+                compiled
+            })
+            .collect();
+        CompiledMlp { layers }
+    }
+}
+
+/// An [`Activation`] other than `Custom`, which is plain data.
+#[derive(Clone, Copy)]
+pub(crate) enum BuiltinActivation {
+    Linear,
+    ReLU,
+    Tanh,
+    Sigmoid,
+    LeakyReLU(Scalar),
+    Gelu,
+}
+
+impl BuiltinActivation {
+    fn of(activation: &Activation) -> Option<Self> {
+        match *activation {
+            Activation::Linear => Some(Self::Linear),
+            Activation::ReLU => Some(Self::ReLU),
+            Activation::Tanh => Some(Self::Tanh),
+            Activation::Sigmoid => Some(Self::Sigmoid),
+            Activation::LeakyReLU(slope) => Some(Self::LeakyReLU(slope)),
+            Activation::Gelu => Some(Self::Gelu),
+            Activation::Custom(_) => None,
+        }
+    }
+
+    fn activation(self) -> Activation {
+        match self {
+            Self::Linear => Activation::Linear,
+            Self::ReLU => Activation::ReLU,
+            Self::Tanh => Activation::Tanh,
+            Self::Sigmoid => Activation::Sigmoid,
+            Self::LeakyReLU(slope) => Activation::LeakyReLU(slope),
+            Self::Gelu => Activation::Gelu,
+        }
+    }
+}
+
+/// One layer of a [`CompiledMlp`].
+#[derive(Clone)]
+struct CompiledLayer {
+    nin: usize,
+    /// Row-major `nout x nin`: row `j` is neuron `j`'s weights.
+    weights: Vec<Scalar>,
+    /// Zero for neurons without a bias.
+    bias: Vec<Scalar>,
+    activation: BuiltinActivation,
+}
+
+impl CompiledLayer {
+    fn copy_from(&mut self, layer: &Layer) {
+        self.weights.clear();
+        self.bias.clear();
+        for neuron in &layer.neurons {
+            self.weights.extend(neuron.w.iter().map(Value::get_data));
+            self.bias
+                .push(neuron.b.as_ref().map_or(0.0, Value::get_data));
+        }
+    }
+
+    fn forward(&self, x: &[Scalar], out: &mut Vec<Scalar>) {
+        assert_eq!(
+            x.len(),
+            self.nin,
+            "CompiledMlp layer expects {} inputs, got {}",
+            self.nin,
+            x.len()
+        );
+        let activation = self.activation.activation();
+        out.clear();
+        for (row, b) in self.weights.chunks_exact(self.nin).zip(&self.bias) {
+            out.push(activation.apply_scalar(dot(row, x) + b));
+        }
+    }
+}
+
+/// `a . b` summed in independent lanes, which the compiler may keep in
+/// SIMD registers since it can't reorder a single running sum itself.
+fn dot(a: &[Scalar], b: &[Scalar]) -> Scalar {
+    const LANES: usize = 8;
+    let mut sums = [0.0; LANES];
+    let (a_chunks, b_chunks) = (a.chunks_exact(LANES), b.chunks_exact(LANES));
+    let tail: Scalar = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(a, b)| a * b)
+        .sum();
+    for (a, b) in a_chunks.zip(b_chunks) {
+        for i in 0..LANES {
+            sums[i] += a[i] * b[i];
+        }
+    }
+    sums.iter().sum::<Scalar>() + tail
+}
+
+/// A snapshot of an [`MLP`]'s weights in contiguous, row-major
+/// `nout x nin` matrices, one per layer, with a bias vector each. Its
+/// forward pass is plain loops over slices that the compiler can
+/// vectorize, and it is `Send + Sync` whatever the features.
+///
+/// The snapshot doesn't follow training; call [`CompiledMlp::refresh`]
+/// after the weights change. Built by [`MLP::compile_inference`].
+#[derive(Clone)]
+pub struct CompiledMlp {
+    layers: Vec<CompiledLayer>,
+}
+
+impl CompiledMlp {
+    /// The outputs of the compiled network for `x`, which match
+    /// [`MLP::predict`] up to rounding.
+    ///
+    /// # Panics
+    ///
+    /// If `x` doesn't have one value per input of the first layer.
+    pub fn predict(&self, x: &[Scalar]) -> Vec<Scalar> {
+        let (mut input, mut output) = (x.to_vec(), Vec::new());
+        for layer in &self.layers {
+            layer.forward(&input, &mut output);
+            std::mem::swap(&mut input, &mut output);
+        }
+        input
+    }
+
+    /// [`CompiledMlp::predict`] for each sample.
+    pub fn predict_batch(&self, xs: &[Vec<Scalar>]) -> Vec<Vec<Scalar>> {
+        xs.iter().map(|x| self.predict(x)).collect()
+    }
+
+    /// Copies `mlp`'s current weights in, reusing the storage.
+    ///
+    /// # Panics
+    ///
+    /// If `mlp` has different layer sizes from the one compiled.
+    pub fn refresh(&mut self, mlp: &MLP) {
+        let sizes: Vec<_> =
+            self.layers.iter().map(|l| (l.nin, l.bias.len())).collect();
+        let theirs: Vec<_> =
+            mlp.sz.windows(2).map(|sz| (sz[0], sz[1])).collect();
+        assert_eq!(sizes, theirs, "refreshing from an MLP of another shape");
+        for (compiled, layer) in self.layers.iter_mut().zip(&mlp.layers) {
+            compiled.copy_from(layer);
+        }
+    }
+}
+
+impl Display for CompiledMlp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sizes = self.layers.first().map(|l| l.nin).into_iter();
+        let sizes = sizes.chain(self.layers.iter().map(|l| l.bias.len()));
+        let sizes: Vec<_> = sizes.map(|s| s.to_string()).collect();
+        write!(f, "CompiledMlp({})", sizes.join("-"))
+    }
+}
+
+impl Debug for CompiledMlp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl CompiledMlp {
+    /// Quantizes the weights to `i8` for a smaller, integer-only forward
+    /// pass; see [`QuantizedMlp`].
+    pub fn quantize(&self) -> QuantizedMlp {
+        let layers = self.layers.iter().map(QuantizedLayer::new).collect();
+        QuantizedMlp { layers }
+    }
+}
+
+/// One layer of a [`QuantizedMlp`].
+#[derive(Clone)]
+pub(crate) struct QuantizedLayer {
+    pub(crate) nin: usize,
+    /// Row-major `nout x nin`, each weight `w` stored as `w / scale`.
+    pub(crate) weights: Vec<i8>,
+    pub(crate) scale: Scalar,
+    pub(crate) bias: Vec<Scalar>,
+    pub(crate) activation: BuiltinActivation,
+}
+
+impl QuantizedLayer {
+    /// Rounds the scale and biases to `f32`, as [`QuantizedMlp::serialize`]
+    /// stores them, so a model predicts the same before and after a round
+    /// trip under the `f64` feature too.
+    #[allow(clippy::unnecessary_cast)] // Scalar may be f64
+    fn new(layer: &CompiledLayer) -> Self {
+        let (scale, weights) = quantize(&layer.weights);
+        Self {
+            nin: layer.nin,
+            weights,
+            scale: scale as f32 as Scalar,
+            bias: layer.bias.iter().map(|&b| b as f32 as Scalar).collect(),
+            activation: layer.activation,
+        }
+    }
+
+    fn forward(&self, x: &[Scalar], out: &mut Vec<Scalar>) {
+        assert_eq!(
+            x.len(),
+            self.nin,
+            "QuantizedMlp layer expects {} inputs, got {}",
+            self.nin,
+            x.len()
+        );
+        let activation = self.activation.activation();
+        let (x_scale, x) = quantize(x);
+        let scale = self.scale * x_scale;
+        out.clear();
+        for (row, b) in self.weights.chunks_exact(self.nin).zip(&self.bias) {
+            let acc: i32 =
+                row.iter().zip(&x).map(|(&w, &x)| w as i32 * x as i32).sum();
+            out.push(activation.apply_scalar(acc as Scalar * scale + b));
+        }
+    }
+}
+
+/// Scales `xs` so the largest magnitude maps to 127 and rounds them to
+/// `i8`, returning the scale to multiply back by. All zeros get scale 0.
+fn quantize(xs: &[Scalar]) -> (Scalar, Vec<i8>) {
+    let max = xs.iter().fold(0.0 as Scalar, |max, x| max.max(x.abs()));
+    if max == 0.0 {
+        return (0.0, vec![0; xs.len()]);
+    }
+    let scale = max / 127.0;
+    (
+        scale,
+        xs.iter().map(|x| (x / scale).round() as i8).collect(),
+    )
+}
+
+/// A [`CompiledMlp`] with each layer's weights stored as `i8` and one
+/// `f32` scale per layer, a quarter of the size. Each layer quantizes its
+/// input the same way, on the fly, so the products are accumulated in
+/// `i32` and only the sums are scaled back and have the (float) bias and
+/// activation applied.
+///
+/// Built by [`CompiledMlp::quantize`]; [`QuantizedMlp::serialize`] turns
+/// it into bytes that need nothing else to run.
+#[derive(Clone)]
+pub struct QuantizedMlp {
+    pub(crate) layers: Vec<QuantizedLayer>,
+}
+
+impl QuantizedMlp {
+    /// The outputs for `x`, close to those of the [`CompiledMlp`] it was
+    /// quantized from.
+    ///
+    /// # Panics
+    ///
+    /// If `x` doesn't have one value per input of the first layer.
+    pub fn predict(&self, x: &[Scalar]) -> Vec<Scalar> {
+        let (mut input, mut output) = (x.to_vec(), Vec::new());
+        for layer in &self.layers {
+            layer.forward(&input, &mut output);
+            std::mem::swap(&mut input, &mut output);
+        }
+        input
+    }
+
+    /// [`QuantizedMlp::predict`] for each sample.
+    pub fn predict_batch(&self, xs: &[Vec<Scalar>]) -> Vec<Vec<Scalar>> {
+        xs.iter().map(|x| self.predict(x)).collect()
+    }
+}
+
+impl Display for QuantizedMlp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sizes = self.layers.first().map(|l| l.nin).into_iter();
+        let sizes = sizes.chain(self.layers.iter().map(|l| l.bias.len()));
+        let sizes: Vec<_> = sizes.map(|s| s.to_string()).collect();
+        write!(f, "QuantizedMlp({})", sizes.join("-"))
+    }
+}
+
+impl Debug for QuantizedMlp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Runs its children one after another, feeding each the previous outputs.
+pub struct Sequential {
+    modules: Vec<Box<dyn Module>>,
+    training: bool,
+}
+
+impl Sequential {
+    pub fn new(modules: Vec<Box<dyn Module>>) -> Self {
+        Self {
+            modules,
+            training: true,
+        }
+    }
+
+    /// Appends `module` to the end of the chain, switching it to the
+    /// chain's training mode.
+    pub fn push(&mut self, mut module: impl Module + 'static) {
+        module.set_training(self.training);
+        self.modules.push(Box::new(module));
+    }
+
+    /// A table of the children and their sizes for `input_size` inputs;
+    /// see [`summary`].
+    pub fn summary(&self, input_size: usize) -> String {
+        summary(self, input_size)
+    }
+
+    /// Each child's parameter count, named by its index.
+    pub fn parameter_counts(&self) -> Vec<(String, usize)> {
+        let counts = self.modules.iter().map(|m| m.num_parameters());
+        counts
+            .enumerate()
+            .map(|(i, n)| (i.to_string(), n))
+            .collect()
+    }
+
+    /// A copy with its own parameters, made with each child's
+    /// [`Module::clone_detached_boxed`].
+    ///
+    /// # Panics
+    ///
+    /// If a child can't be cloned that way.
+    pub fn clone_detached(&self) -> Self {
+        let modules = self.modules.iter().map(|m| {
+            m.clone_detached_boxed().unwrap_or_else(|| {
+                panic!("{} can't be cloned detached", m.type_name())
+            })
+        });
+        Self {
+            modules: modules.collect(),
+            training: self.training,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+}
+
+impl Module for Sequential {
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        self.modules
+            .iter()
+            .fold(x.to_vec(), |acc, module| module.forward(&acc))
+    }
+
+    fn forward_batch(&self, xs: &[Vec<Value>]) -> Vec<Vec<Value>> {
+        self.modules
+            .iter()
+            .fold(xs.to_vec(), |acc, module| module.forward_batch(&acc))
+    }
+
+    fn predict_scalars(&self, x: &[Scalar]) -> Vec<Scalar> {
+        self.modules
+            .iter()
+            .fold(x.to_vec(), |acc, module| module.predict_scalars(&acc))
+    }
+
+    fn parameters(&self) -> Vec<Value> {
+        self.modules.iter().flat_map(|m| m.parameters()).collect()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        prefixed(self.children())
+    }
+
+    fn num_parameters(&self) -> usize {
+        self.modules.iter().map(|m| m.num_parameters()).sum()
+    }
+
+    fn weights(&self) -> Vec<Value> {
+        self.modules.iter().flat_map(|m| m.weights()).collect()
+    }
+
+    fn clone_detached_boxed(&self) -> Option<Box<dyn Module>> {
+        Some(Box::new(self.clone_detached()))
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+        for m in self.modules.iter_mut() {
+            m.set_training(training)
+        }
+    }
+
+    fn is_training(&self) -> bool {
+        self.training
+    }
+}
+
+impl Introspect for Sequential {
+    fn children(&self) -> Vec<(String, &dyn Module)> {
+        let modules = self.modules.iter().enumerate();
+        modules.map(|(i, m)| (i.to_string(), m.as_ref())).collect()
+    }
+
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        self.modules
+            .iter()
+            .try_fold(input_size, |n, m| m.output_size(n))
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+impl Default for Sequential {
+    fn default() -> Self {
+        Self::new(vec![])
+    }
+}
+
+impl Index<usize> for Sequential {
+    type Output = dyn Module;
+
+    fn index(&self, i: usize) -> &Self::Output {
+        self.modules[i].as_ref()
+    }
+}
+
+impl Display for Sequential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("Sequential of {:?}", self.modules))
+    }
+}
+
+impl Debug for Sequential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+/// A 1-D convolution over inputs shaped `[channels][length]`, with zero
+/// padding on both ends.
+pub struct Conv1d {
+    /// `[out_channels][in_channels][kernel_size]`
+    kernels: Vec<Vec<Vec<Value>>>,
+    b: Vec<Value>,
+    stride: usize,
+    padding: usize,
+}
+
+impl Conv1d {
+    pub fn new(
+        in_channels: usize,
+        out_channels: usize,
+        kernel_size: usize,
+        stride: usize,
+        padding: usize,
+    ) -> Self {
+        Self::new_with_rng(
+            in_channels,
+            out_channels,
+            kernel_size,
+            stride,
+            padding,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    /// Like [`Conv1d::new`], drawing the kernels from `rng`.
+    pub fn new_with_rng(
+        in_channels: usize,
+        out_channels: usize,
+        kernel_size: usize,
+        stride: usize,
+        padding: usize,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Self {
+        assert!(stride > 0, "Conv1d stride must be positive");
+        assert!(kernel_size > 0, "Conv1d kernel_size must be positive");
+        let (fan_in, fan_out) =
+            (in_channels * kernel_size, out_channels * kernel_size);
+        let init = Init::default();
+        let kernels = (0..out_channels)
+            .map(|_| {
+                (0..in_channels)
+                    .map(|_| {
+                        (0..kernel_size)
+                            .map(|_| {
+                                Value::new(init.sample(fan_in, fan_out, rng))
+                            })
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            kernels,
+            b: (0..out_channels).map(|_| Value::new(0.0)).collect(),
+            stride,
+            padding,
+        }
+    }
+
+    pub fn in_channels(&self) -> usize {
+        self.kernels.first().map_or(0, Vec::len)
+    }
+
+    pub fn out_channels(&self) -> usize {
+        self.kernels.len()
+    }
+
+    pub fn kernel_size(&self) -> usize {
+        self.kernels
+            .first()
+            .and_then(|k| k.first())
+            .map_or(0, Vec::len)
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn padding(&self) -> usize {
+        self.padding
+    }
+
+    /// A copy with its own parameters; see [`Neuron::clone_detached`].
+    pub fn clone_detached(&self) -> Self {
+        let kernel = |k: &Vec<Vec<Value>>| {
+            k.iter().map(|w| w.iter().map(detached).collect()).collect()
+        };
+        Self {
+            kernels: self.kernels.iter().map(kernel).collect(),
+            b: self.b.iter().map(detached).collect(),
+            stride: self.stride,
+            padding: self.padding,
+        }
+    }
+
+    /// `(length + 2 * padding - kernel_size) / stride + 1`
+    pub fn output_len(&self, length: usize) -> usize {
+        let padded = length + 2 * self.padding;
+        assert!(
+            padded >= self.kernel_size(),
+            "Conv1d kernel of {} is longer than the padded input of {}",
+            self.kernel_size(),
+            padded
+        );
+        (padded - self.kernel_size()) / self.stride + 1
+    }
+
+    /// Convolves `x`, shaped `[in_channels][length]`, into
+    /// `[out_channels][output_len(length)]`.
+    pub fn forward_channels(&self, x: &[Vec<Value>]) -> Vec<Vec<Value>> {
+        assert_eq!(
+            x.len(),
+            self.in_channels(),
+            "Conv1d expects {} input channels, got {}",
+            self.in_channels(),
+            x.len()
+        );
+        let length = x.first().map_or(0, Vec::len);
+        assert!(
+            x.iter().all(|c| c.len() == length),
+            "Conv1d input channels differ in length"
+        );
+        let out_len = self.output_len(length);
+        self.kernels
+            .iter()
+            .zip(&self.b)
+            .map(|(kernel, b)| {
+                (0..out_len)
+                    .map(|t| {
+                        let start = t * self.stride;
+                        let mut acc = b.clone();
+                        for (channel, weights) in x.iter().zip(kernel) {
+                            for (k, w) in weights.iter().enumerate() {
+                                // positions in the padding contribute zero
+                                let pos = (start + k).checked_sub(self.padding);
+                                if let Some(v) =
+                                    pos.and_then(|p| channel.get(p))
+                                {
+                                    acc = acc + w * v;
+                                }
+                            }
+                        }
+                        acc
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl Module for Conv1d {
+    /// Treats `x` as `in_channels` equal-length channels laid end to end and
+    /// returns the output channels the same way.
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        let channels = self.in_channels();
+        assert!(
+            channels > 0 && x.len().is_multiple_of(channels),
+            "Conv1d cannot split {} values into {} channels",
+            x.len(),
+            channels
+        );
+        let x: Vec<Vec<Value>> = x
+            .chunks(x.len() / channels)
+            .map(<[Value]>::to_vec)
+            .collect();
+        self.forward_channels(&x).concat()
+    }
+
+    fn parameters(&self) -> Vec<Value> {
+        let weights = self.kernels.iter().flatten().flatten();
+        weights.chain(&self.b).cloned().collect()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        let mut out = vec![];
+        for (o, kernel) in self.kernels.iter().enumerate() {
+            for (c, weights) in kernel.iter().enumerate() {
+                for (k, w) in weights.iter().enumerate() {
+                    out.push((format!("w{}.{}.{}", o, c, k), w.clone()));
+                }
+            }
+        }
+        let b = self.b.iter().enumerate();
+        out.extend(b.map(|(o, b)| (format!("b{}", o), b.clone())));
+        out
+    }
+
+    fn weights(&self) -> Vec<Value> {
+        self.kernels.iter().flatten().flatten().cloned().collect()
+    }
+
+    fn clone_detached_boxed(&self) -> Option<Box<dyn Module>> {
+        Some(Box::new(self.clone_detached()))
+    }
+}
+
+impl Introspect for Conv1d {
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        let channels = self.in_channels();
+        if channels == 0 || !input_size.is_multiple_of(channels) {
+            return None;
+        }
+        let padded = input_size / channels + 2 * self.padding;
+        let steps = padded.checked_sub(self.kernel_size())? / self.stride;
+        Some(self.out_channels() * (steps + 1))
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+impl Display for Conv1d {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "Conv1d({}, {}, kernel_size={}, stride={}, padding={})",
+            self.in_channels(),
+            self.out_channels(),
+            self.kernel_size(),
+            self.stride,
+            self.padding
+        ))
+    }
+}
+
+impl Debug for Conv1d {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+/// Adds the input back onto the output of `inner`, which must preserve the
+/// number of values.
+pub struct Residual<M: Module> {
+    inner: M,
+}
+
+impl<M: Module> Residual<M> {
+    pub fn new(inner: M) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+}
+
+/// `x + y`, where `y` is what a [`Residual`]'s inner module made of `x`.
+fn add_skip(x: &[Value], y: &[Value]) -> Vec<Value> {
+    assert_eq!(
+        x.len(),
+        y.len(),
+        "Residual inner module maps {} values to {}",
+        x.len(),
+        y.len()
+    );
+    x.iter().zip(y).map(|(a, b)| a + b).collect()
+}
+
+impl<M: Module> Module for Residual<M> {
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        add_skip(x, &self.inner.forward(x))
+    }
+
+    fn forward_batch(&self, xs: &[Vec<Value>]) -> Vec<Vec<Value>> {
+        let ys = self.inner.forward_batch(xs);
+        xs.iter().zip(&ys).map(|(x, y)| add_skip(x, y)).collect()
+    }
+
+    fn parameters(&self) -> Vec<Value> {
+        self.inner.parameters()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        self.inner.named_parameters()
+    }
+
+    fn num_parameters(&self) -> usize {
+        self.inner.num_parameters()
+    }
+
+    fn weights(&self) -> Vec<Value> {
+        self.inner.weights()
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.inner.set_training(training)
+    }
+
+    fn is_training(&self) -> bool {
+        self.inner.is_training()
+    }
+
+    fn clone_detached_boxed(&self) -> Option<Box<dyn Module>> {
+        let inner = self.inner.clone_detached_boxed()?;
+        Some(Box::new(Residual::new(inner)))
+    }
+}
+
+impl<M: Module> Introspect for Residual<M> {
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        Some(input_size)
+    }
+
+    fn wrapped(&self) -> Option<&dyn Module> {
+        Some(&self.inner)
+    }
+}
+
+impl<M: Module> Display for Residual<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("Residual of {:?}", self.inner))
+    }
+}
+
+impl<M: Module> Debug for Residual<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+/// Wraps a module whose outputs are class logits with softmax and argmax,
+/// computed on plain numbers from [`Module::predict_scalars`], which builds
+/// no graph for models with a graph-free path. Put the model in eval mode
+/// before predicting if it has dropout or batch norm.
+pub struct Classifier<M: Module> {
+    model: M,
+}
+
+impl<M: Module> Classifier<M> {
+    pub fn new(model: M) -> Self {
+        Self { model }
+    }
+
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    pub fn into_inner(self) -> M {
+        self.model
+    }
+
+    /// The probability of each class for the sample `x`.
+    pub fn predict_proba(&self, x: &[Scalar]) -> Vec<Scalar> {
+        softmax(&self.model.predict_scalars(x))
+    }
+
+    /// The most likely class for `x`, the first on a tie.
+    ///
+    /// # Panics
+    ///
+    /// If the model has no outputs.
+    pub fn predict(&self, x: &[Scalar]) -> usize {
+        argmax(&self.model.predict_scalars(x))
+    }
+
+    /// [`Classifier::predict_proba`] for each sample.
+    pub fn predict_proba_batch(&self, xs: &[Vec<Scalar>]) -> Vec<Vec<Scalar>> {
+        xs.iter().map(|x| self.predict_proba(x)).collect()
+    }
+
+    /// [`Classifier::predict`] for each sample.
+    pub fn predict_batch(&self, xs: &[Vec<Scalar>]) -> Vec<usize> {
+        xs.iter().map(|x| self.predict(x)).collect()
+    }
+}
+
+/// `exp(x - max) / sum`, which can't overflow.
+fn softmax(logits: &[Scalar]) -> Vec<Scalar> {
+    let max = logits
+        .iter()
+        .copied()
+        .fold(Scalar::NEG_INFINITY, Scalar::max);
+    let exps: Vec<_> = logits.iter().map(|&x| (x - max).exp()).collect();
+    let sum: Scalar = exps.iter().sum();
+    exps.iter().map(|e| e / sum).collect()
+}
+
+pub(crate) fn argmax(xs: &[Scalar]) -> usize {
+    assert!(!xs.is_empty(), "argmax of no values");
+    (1..xs.len()).fold(0, |best, i| if xs[i] > xs[best] { i } else { best })
+}
+
+impl<M: Module> Module for Classifier<M> {
+    /// The logits.
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        self.model.forward(x)
+    }
+
+    fn forward_batch(&self, xs: &[Vec<Value>]) -> Vec<Vec<Value>> {
+        self.model.forward_batch(xs)
+    }
+
+    fn predict_scalars(&self, x: &[Scalar]) -> Vec<Scalar> {
+        self.model.predict_scalars(x)
+    }
+
+    fn parameters(&self) -> Vec<Value> {
+        self.model.parameters()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        self.model.named_parameters()
+    }
+
+    fn num_parameters(&self) -> usize {
+        self.model.num_parameters()
+    }
+
+    fn weights(&self) -> Vec<Value> {
+        self.model.weights()
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.model.set_training(training)
+    }
+
+    fn is_training(&self) -> bool {
+        self.model.is_training()
+    }
+}
+
+impl<M: Module> Introspect for Classifier<M> {
+    fn children(&self) -> Vec<(String, &dyn Module)> {
+        self.model.children()
+    }
+
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        self.model.output_size(input_size)
+    }
+}
+
+impl<M: Module> Display for Classifier<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("Classifier of {:?}", self.model))
+    }
+}
+
+impl<M: Module> Debug for Classifier<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+/// A table of `dim`-sized vectors looked up by index, like PyTorch's
+/// `nn.Embedding`. Only the rows that are looked up get gradients.
+pub struct Embedding {
+    weight: Vec<Vec<Value>>,
+}
+
+impl Embedding {
+    /// Draws every entry from a standard normal.
+    pub fn new(num_embeddings: usize, dim: usize) -> Self {
+        Self::new_with_rng(num_embeddings, dim, &mut rand::thread_rng())
+    }
+
+    /// Like [`Embedding::new`], drawing the entries from `rng`.
+    pub fn new_with_rng(
+        num_embeddings: usize,
+        dim: usize,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Self {
+        let weight = (0..num_embeddings)
+            .map(|_| {
+                (0..dim).map(|_| Value::new(standard_normal(rng))).collect()
+            })
+            .collect();
+        Self { weight }
+    }
+
+    pub fn num_embeddings(&self) -> usize {
+        self.weight.len()
+    }
+
+    pub fn dim(&self) -> usize {
+        self.weight.first().map_or(0, Vec::len)
+    }
+
+    /// A copy with its own parameters; see [`Neuron::clone_detached`].
+    pub fn clone_detached(&self) -> Self {
+        let row = |r: &Vec<Value>| r.iter().map(detached).collect();
+        Self {
+            weight: self.weight.iter().map(row).collect(),
+        }
+    }
+
+    /// The vector for `index`.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of range.
+    pub fn lookup(&self, index: usize) -> &[Value] {
+        assert!(
+            index < self.weight.len(),
+            "Embedding index {} out of range for {} rows",
+            index,
+            self.weight.len()
+        );
+        &self.weight[index]
+    }
+
+    /// The vectors for `indices`, laid end to end.
+    pub fn forward_indices(&self, indices: &[usize]) -> Vec<Value> {
+        indices
+            .iter()
+            .flat_map(|&i| self.lookup(i).to_vec())
+            .collect()
+    }
+}
+
+impl Module for Embedding {
+    /// Reads each input's data as an index.
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        let indices: Vec<_> = x
+            .iter()
+            .map(|v| {
+                let i = v.get_data();
+                assert!(
+                    i >= 0.0 && i.fract() == 0.0,
+                    "Embedding index must be a whole number, got {}",
+                    i
+                );
+                i as usize
+            })
+            .collect();
+        self.forward_indices(&indices)
+    }
+
+    fn parameters(&self) -> Vec<Value> {
+        self.weight.iter().flatten().cloned().collect()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        let mut out = vec![];
+        for (r, row) in self.weight.iter().enumerate() {
+            for (c, w) in row.iter().enumerate() {
+                out.push((format!("w{}.{}", r, c), w.clone()));
+            }
+        }
+        out
+    }
+
+    fn weights(&self) -> Vec<Value> {
+        self.parameters()
+    }
+
+    fn clone_detached_boxed(&self) -> Option<Box<dyn Module>> {
+        Some(Box::new(self.clone_detached()))
+    }
+}
+
+impl Introspect for Embedding {
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        Some(input_size * self.dim())
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+impl Display for Embedding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "Embedding({}, {})",
+            self.num_embeddings(),
+            self.dim()
+        ))
+    }
+}
+
+impl Debug for Embedding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+/// Zeroes each input with probability `p` while training and scales the
+/// survivors by `1 / (1 - p)`, so eval mode can pass inputs through as is.
+pub struct Dropout {
+    p: Scalar,
+    training: bool,
+    rng: Lock<StdRng>,
+}
+
+impl Dropout {
+    pub fn new(p: Scalar) -> Self {
+        Self::with_rng(p, StdRng::from_entropy())
+    }
+
+    /// Like [`Dropout::new`], drawing masks from a [`StdRng`] seeded with
+    /// `seed`.
+    pub fn new_seeded(p: Scalar, seed: u64) -> Self {
+        Self::with_rng(p, StdRng::seed_from_u64(seed))
+    }
+
+    /// The probability of zeroing each input while training.
+    pub fn p(&self) -> Scalar {
+        self.p
+    }
+
+    fn with_rng(p: Scalar, rng: StdRng) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&p),
+            "dropout probability must be in [0, 1], got {}",
+            p
+        );
+        Self {
+            p,
+            training: true,
+            rng: Lock::new(rng),
+        }
+    }
+}
+
+impl Module for Dropout {
+    fn set_training(&mut self, training: bool) {
+        self.training = training
+    }
+
+    fn is_training(&self) -> bool {
+        self.training
+    }
+
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        if !self.training || self.p == 0.0 {
+            return x.to_vec();
+        }
+        let keep = 1.0 / (1.0 - self.p);
+        let mut rng = self.rng.write();
+        x.iter()
+            .map(|v| {
+                let dropped = rng.gen::<Scalar>() < self.p;
+                v * Value::constant(if dropped { 0.0 } else { keep })
+            })
+            .collect()
+    }
+
+    fn clone_detached_boxed(&self) -> Option<Box<dyn Module>> {
+        let mut copy = Dropout::with_rng(self.p, self.rng.read().clone());
+        copy.training = self.training;
+        Some(Box::new(copy))
+    }
+}
+
+impl Introspect for Dropout {
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        Some(input_size)
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+impl Display for Dropout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("Dropout({})", self.p))
+    }
+}
+
+impl Debug for Dropout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+/// Leaky ReLU with a learnable slope for negative inputs, shared by every
+/// input, as [`Value::prelu`].
+pub struct PReLU {
+    alpha: Value,
+}
+
+impl PReLU {
+    /// Starts the slope at `alpha`; PyTorch uses 0.25.
+    pub fn new(alpha: Scalar) -> Self {
+        Self {
+            alpha: Value::new(alpha),
+        }
+    }
+
+    pub fn alpha(&self) -> &Value {
+        &self.alpha
+    }
+}
+
+impl Module for PReLU {
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        x.iter().map(|v| v.prelu(&self.alpha)).collect()
+    }
+
+    fn parameters(&self) -> Vec<Value> {
+        vec![self.alpha.clone()]
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        vec![("alpha".to_string(), self.alpha.clone())]
+    }
+}
+
+impl Introspect for PReLU {
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        Some(input_size)
+    }
+}
+
+impl Display for PReLU {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("PReLU({})", self.alpha.get_data()))
+    }
+}
+
+impl Debug for PReLU {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+/// Normalizes each feature over a batch, then scales and shifts it by the
+/// learnable `gamma` and `beta`.
+///
+/// In training mode the batch statistics are part of the graph, so grads
+/// flow through them, and running estimates are updated as
+/// `running = (1 - momentum) * running + momentum * batch`. Eval mode
+/// normalizes with the running estimates instead.
+pub struct BatchNorm1d {
+    gamma: Vec<Value>,
+    beta: Vec<Value>,
+    momentum: Scalar,
+    eps: Scalar,
+    training: bool,
+    running: Lock<RunningStats>,
+}
+
+#[derive(Clone)]
+struct RunningStats {
+    mean: Vec<Scalar>,
+    var: Vec<Scalar>,
+}
+
+impl BatchNorm1d {
+    pub fn new(features: usize) -> Self {
+        Self {
+            gamma: (0..features).map(|_| Value::new(1.0)).collect(),
+            beta: (0..features).map(|_| Value::new(0.0)).collect(),
+            momentum: 0.1,
+            eps: 1e-5,
+            training: true,
+            running: Lock::new(RunningStats {
+                mean: vec![0.0; features],
+                var: vec![1.0; features],
+            }),
+        }
+    }
+
+    /// Sets how quickly the running statistics follow new batches; 0.1 by
+    /// default.
+    pub fn with_momentum(mut self, momentum: Scalar) -> Self {
+        self.momentum = momentum;
+        self
+    }
+
+    /// A copy with its own parameters and running statistics; see
+    /// [`Neuron::clone_detached`].
+    pub fn clone_detached(&self) -> Self {
+        Self {
+            gamma: self.gamma.iter().map(detached).collect(),
+            beta: self.beta.iter().map(detached).collect(),
+            running: Lock::new(self.running.read().clone()),
+            ..*self
+        }
+    }
+
+    pub fn running_mean(&self) -> Vec<Scalar> {
+        self.running.read().mean.clone()
+    }
+
+    pub fn running_var(&self) -> Vec<Scalar> {
+        self.running.read().var.clone()
+    }
+
+    fn update_running(&self, j: usize, mean: Scalar, var: Scalar, n: Scalar) {
+        let m = self.momentum;
+        let mut stats = self.running.write();
+        stats.mean[j] = (1.0 - m) * stats.mean[j] + m * mean;
+        // a single sample says nothing about the spread
+        if n > 1.0 {
+            let unbiased = var * n / (n - 1.0);
+            stats.var[j] = (1.0 - m) * stats.var[j] + m * unbiased;
+        }
+    }
+}
+
+impl Module for BatchNorm1d {
+    /// Normalizes a single sample as a batch of one.
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        self.forward_batch(&[x.to_vec()]).remove(0)
+    }
+
+    /// Normalizes a batch of samples, each with one value per feature.
+    fn forward_batch(&self, batch: &[Vec<Value>]) -> Vec<Vec<Value>> {
+        let features = self.gamma.len();
+        for sample in batch {
+            assert_eq!(
+                sample.len(),
+                features,
+                "BatchNorm1d over {} features got a sample of {}",
+                features,
+                sample.len()
+            );
+        }
+        if batch.is_empty() {
+            return vec![];
+        }
+        let n = batch.len() as Scalar;
+        let mut columns: Vec<Vec<Value>> = vec![vec![]; features];
+        for (j, column) in columns.iter_mut().enumerate() {
+            let x: Vec<Value> = batch.iter().map(|s| s[j].clone()).collect();
+            let (shift, scale) = if self.training {
+                let mean = Vector::new(x.clone()).mean();
+                let var = Vector::new(
+                    x.iter().map(|v| (v - &mean).pow(2.0)).collect(),
+                )
+                .mean();
+                self.update_running(j, mean.get_data(), var.get_data(), n);
+                (mean, (var + self.eps).sqrt())
+            } else {
+                let stats = self.running.read();
+                (
+                    Value::constant(stats.mean[j]),
+                    Value::constant((stats.var[j] + self.eps).sqrt()),
+                )
+            };
+            *column = x
+                .iter()
+                .map(|v| {
+                    &(&(v - &shift) / &scale) * &self.gamma[j] + &self.beta[j]
+                })
+                .collect();
+        }
+        (0..batch.len())
+            .map(|i| columns.iter().map(|c| c[i].clone()).collect())
+            .collect()
+    }
+
+    fn parameters(&self) -> Vec<Value> {
+        self.gamma.iter().chain(&self.beta).cloned().collect()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        let gamma = self.gamma.iter().enumerate();
+        let beta = self.beta.iter().enumerate();
+        gamma
+            .map(|(i, g)| (format!("gamma{}", i), g.clone()))
+            .chain(beta.map(|(i, b)| (format!("beta{}", i), b.clone())))
+            .collect()
+    }
+
+    fn clone_detached_boxed(&self) -> Option<Box<dyn Module>> {
+        Some(Box::new(self.clone_detached()))
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training
+    }
+
+    fn is_training(&self) -> bool {
+        self.training
+    }
+}
+
+impl Introspect for BatchNorm1d {
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        Some(input_size)
+    }
+}
+
+impl Display for BatchNorm1d {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("BatchNorm1d({})", self.gamma.len()))
+    }
+}
+
+impl Debug for BatchNorm1d {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+/// Describes a [`Sequential`] of dense layers and dropout one step at a
+/// time:
+///
+/// ```
+/// use smolgrad::nn::{Activation, Init, MlpBuilder, Module};
+///
+/// let net = MlpBuilder::new(2)
+///     .layer(16, Activation::Tanh)
+///     .dropout(0.1)
+///     .layer(1, Activation::Linear)
+///     .seed(42)
+///     .init(Init::XavierUniform)
+///     .build()
+///     .unwrap();
+/// assert_eq!(net.parameters().len(), 2 * 16 + 16 + 16 + 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct MlpBuilder {
+    nin: Option<usize>,
+    steps: Vec<BuildStep>,
+    bias: bool,
+    init: Option<Init>,
+    seed: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+enum BuildStep {
+    Layer {
+        nout: usize,
+        activation: Activation,
+        bias: bool,
+    },
+    Dropout(Scalar),
+}
+
+/// Why [`MlpBuilder::build`] refused a configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    /// A layer was added without ever setting the input size.
+    NoInputSize,
+    /// The builder has no dense layers.
+    NoLayers,
+    /// Dropout step `index` has a probability outside `[0, 1]`.
+    DropoutProbability { index: usize, p: Scalar },
+    /// Layer step `index` has no outputs.
+    EmptyLayer { index: usize },
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::NoInputSize => {
+                f.write_str("MlpBuilder: layers added before the input size")
+            }
+            BuildError::NoLayers => f.write_str("MlpBuilder: no layers"),
+            BuildError::DropoutProbability { index, p } => write!(
+                f,
+                "MlpBuilder: step {} has dropout probability {}, \
+                 outside [0, 1]",
+                index, p
+            ),
+            BuildError::EmptyLayer { index } => {
+                write!(f, "MlpBuilder: step {} is a layer of 0 neurons", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl Default for MlpBuilder {
+    fn default() -> Self {
+        Self {
+            nin: None,
+            steps: vec![],
+            bias: true,
+            init: None,
+            seed: None,
+        }
+    }
+}
+
+impl MlpBuilder {
+    /// Starts a network taking `nin` inputs.
+    pub fn new(nin: usize) -> Self {
+        Self::default().input(nin)
+    }
+
+    /// Sets the number of inputs.
+    pub fn input(mut self, nin: usize) -> Self {
+        self.nin = Some(nin);
+        self
+    }
+
+    /// Appends a dense layer of `nout` neurons.
+    pub fn layer(
+        mut self,
+        nout: usize,
+        activation: impl Into<Activation>,
+    ) -> Self {
+        self.steps.push(BuildStep::Layer {
+            nout,
+            activation: activation.into(),
+            bias: self.bias,
+        });
+        self
+    }
+
+    /// Appends a [`Dropout`] with drop probability `p`.
+    pub fn dropout(mut self, p: Scalar) -> Self {
+        self.steps.push(BuildStep::Dropout(p));
+        self
+    }
+
+    /// Whether the layers added after this call get a bias. On by default.
+    pub fn bias(mut self, bias: bool) -> Self {
+        self.bias = bias;
+        self
+    }
+
+    /// Draws every layer's weights from `init` instead of the default.
+    pub fn init(mut self, init: Init) -> Self {
+        self.init = Some(init);
+        self
+    }
+
+    /// Seeds the weights and dropout masks so builds are reproducible.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Checks the configuration and builds the network.
+    ///
+    /// Layers are built in order from one [`StdRng`], first with
+    /// [`Layer::new_with_rng`] and then, if an init was set,
+    /// [`Layer::with_init_rng`]; each dropout is seeded from the next
+    /// `u64` it draws.
+    pub fn build(&self) -> Result<Sequential, BuildError> {
+        let layers = self
+            .steps
+            .iter()
+            .filter(|s| matches!(s, BuildStep::Layer { .. }))
+            .count();
+        if layers == 0 {
+            return Err(BuildError::NoLayers);
+        }
+        let mut nin = self.nin.ok_or(BuildError::NoInputSize)?;
+        for (index, step) in self.steps.iter().enumerate() {
+            match *step {
+                BuildStep::Layer { nout: 0, .. } => {
+                    return Err(BuildError::EmptyLayer { index })
+                }
+                BuildStep::Dropout(p) if !(0.0..=1.0).contains(&p) => {
+                    return Err(BuildError::DropoutProbability { index, p })
+                }
+                _ => {}
+            }
+        }
+
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut net = Sequential::default();
+        for step in &self.steps {
+            match step {
+                BuildStep::Layer {
+                    nout,
+                    activation,
+                    bias,
+                } => {
+                    let mut layer = Layer::new_with_rng(
+                        nin,
+                        *nout,
+                        activation.clone(),
+                        &mut rng,
+                    )
+                    .with_bias(*bias);
+                    if let Some(init) = &self.init {
+                        layer = layer.with_init_rng(init, &mut rng);
+                    }
+                    net.push(layer);
+                    nin = *nout;
+                }
+                &BuildStep::Dropout(p) => {
+                    net.push(Dropout::new_seeded(p, rng.gen()))
+                }
+            }
+        }
+        Ok(net)
+    }
+}
+
+/// Which weights [`prune_by_magnitude`] zeroed, so they can be kept at zero
+/// while the rest of the model is fine-tuned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PruneMask {
+    /// One flag per parameter, in `parameters()` order.
+    pruned: Vec<bool>,
+    num_weights: usize,
+}
+
+impl PruneMask {
+    /// Zeroes the data of the pruned weights again, e.g. after an optimizer
+    /// step has moved them. `params` are the pruned module's
+    /// `parameters()`.
+    ///
+    /// # Panics
+    ///
+    /// If `params` isn't the length the mask was made for.
+    pub fn apply_after_step(&self, params: &[Value]) {
+        assert_eq!(
+            params.len(),
+            self.pruned.len(),
+            "mask for {} parameters applied to {}",
+            self.pruned.len(),
+            params.len()
+        );
+        for (p, _) in params.iter().zip(&self.pruned).filter(|(_, &m)| m) {
+            p.set_data(0.0)
+        }
+    }
+
+    /// Whether each parameter is pruned, in `parameters()` order.
+    pub fn pruned(&self) -> &[bool] {
+        &self.pruned
+    }
+
+    pub fn num_pruned(&self) -> usize {
+        self.pruned.iter().filter(|&&m| m).count()
+    }
+
+    /// The fraction of the weights that are pruned, or 0 for a module
+    /// without weights.
+    pub fn sparsity(&self) -> Scalar {
+        if self.num_weights == 0 {
+            return 0.0;
+        }
+        self.num_pruned() as Scalar / self.num_weights as Scalar
+    }
 }
 
-impl Display for MLP {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("MLP of {:?}", self.layers))
+/// Zeroes the `fraction` of `module`'s [weights](Module::weights) with the
+/// smallest magnitudes, leaving biases alone, and returns the mask of what
+/// it pruned. Ties go to the weight that comes first.
+///
+/// # Panics
+///
+/// If `fraction` isn't in `[0, 1]`.
+pub fn prune_by_magnitude(
+    module: &(impl Module + ?Sized),
+    fraction: Scalar,
+) -> PruneMask {
+    assert!(
+        (0.0..=1.0).contains(&fraction),
+        "pruning fraction must be in [0, 1], got {}",
+        fraction
+    );
+    let params = module.parameters();
+    let is_weight: HashSet<Value> = module.weights().into_iter().collect();
+    let mut weights: Vec<usize> = (0..params.len())
+        .filter(|&i| is_weight.contains(&params[i]))
+        .collect();
+    let num_weights = weights.len();
+    let magnitude = |i: usize| params[i].get_data().abs();
+    weights.sort_by(|&a, &b| magnitude(a).total_cmp(&magnitude(b)));
+    let count = (fraction * num_weights as Scalar).round() as usize;
+    let mut pruned = vec![false; params.len()];
+    for &i in &weights[..count] {
+        params[i].set_data(0.0);
+        pruned[i] = true;
+    }
+    PruneMask {
+        pruned,
+        num_weights,
     }
 }
 
+#[cfg(test)]
 mod test {
     use super::*;
+    use crate::tensor::Matrix;
+    use crate::testing::{assert_grad_close, assert_value_close};
     #[test]
     fn test_neuron() {
         let a = Neuron::new(10, true);
         // println!("{:?}", a.w);
         assert!(a.w.len() == 10);
-        assert!(a.b.get_data() == 0.0);
+        assert!(a.b.as_ref().unwrap().get_data() == 0.0);
         a.zero_grad();
         println!("{}", a);
         assert!(a.w[0].get_grad() == 0.0);
@@ -151,15 +2829,18 @@ mod test {
         let a = Layer::new(8, 2, false);
         // println!("{:?}", a.w);
         assert!(a.neurons.len() == 2);
-        assert!(a.neurons.first().unwrap().b.get_data() == 0.0);
+        assert!(
+            a.neurons.first().unwrap().b.as_ref().unwrap().get_data() == 0.0
+        );
         a.zero_grad();
         println!("{:?}", a);
         assert!(a.neurons.first().unwrap().w[0].get_grad() == 0.0);
     }
 
     #[test]
+    #[allow(non_snake_case)]
     fn test_MLP() {
-        let a = MLP::new(8, &[4, 2]);
+        let a = MLP::new(8, &[4, 2], Activation::ReLU);
         assert!(a.sz.len() == 3);
         assert!(a.layers.first().unwrap().neurons.len() == 4);
         a.zero_grad();
@@ -169,4 +2850,1165 @@ mod test {
                 == 0.0
         );
     }
+
+    #[test]
+    fn test_forward_batch() {
+        let mlp = MLP::new_seeded(3, &[4, 2], Activation::Tanh, 5);
+        let xs = vec![vec![0.5, -1.0, 2.0], vec![0.0, 0.3, -0.7]];
+        let ys = mlp.forward_batch_scalars(&xs);
+        assert_eq!(ys.len(), 2);
+        for (x, y) in xs.iter().zip(&ys) {
+            let x: Vec<_> = x.iter().map(|&x| Value::new(x)).collect();
+            let expected: Vec<_> =
+                mlp.forward(&x).iter().map(Value::get_data).collect();
+            let got: Vec<_> = y.iter().map(Value::get_data).collect();
+            assert_eq!(got, expected);
+        }
+
+        // A batch of one is the single-sample path, graph and all
+        let x: Vec<_> = xs[1].iter().map(|&x| Value::new(x)).collect();
+        let single = mlp.forward(&x);
+        let batched = mlp.forward_batch(&[x]);
+        for (a, b) in single.iter().zip(&batched[0]) {
+            assert!(a.graph_eq(b, 0.0));
+        }
+        assert!(mlp.forward_batch(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_forward_batch_scalars_are_constants() {
+        let layer = Layer::new(2, 1, Activation::Linear);
+        let ys = layer.forward_batch_scalars(&[vec![1.0, 2.0]]);
+        ys[0][0].backward();
+        let grads: Vec<_> =
+            layer.parameters().iter().map(Value::get_grad).collect();
+        assert_eq!(grads, [1.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn test_forward_batch_through_containers() {
+        // Sequential passes the whole batch to BatchNorm1d, so each output
+        // column is normalized across the batch
+        let mut seq = Sequential::new(vec![]);
+        seq.push(BatchNorm1d::new(1));
+        let model: Box<dyn Module> = Box::new(Residual::new(seq));
+        let xs = vec![vec![1.0], vec![3.0]];
+        let ys = model.forward_batch_scalars(&xs);
+        let normalized: Vec<_> = ys
+            .iter()
+            .zip(&xs)
+            .map(|(y, x)| y[0].get_data() - x[0])
+            .collect();
+        assert!((normalized[0] + 1.0).abs() < 1e-4);
+        assert!((normalized[1] - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_predict_matches_call() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let activations = [
+            Activation::ReLU,
+            Activation::Tanh,
+            Activation::Sigmoid,
+            Activation::LeakyReLU(0.1),
+            Activation::Gelu,
+            Activation::custom(|x| x.pow(2.0)),
+        ];
+        for activation in activations {
+            let mlp = MLP::new_with_rng(3, &[5, 4, 2], activation, &mut rng);
+            for _ in 0..20 {
+                let x: Vec<Scalar> =
+                    (0..3).map(|_| rng.gen_range(-2.0..2.0)).collect();
+                let values: Vec<_> = x.iter().map(|&x| Value::new(x)).collect();
+                let expected: Vec<_> =
+                    mlp.call(&values).iter().map(Value::get_data).collect();
+                assert_eq!(mlp.predict(&x), expected);
+            }
+        }
+
+        // The last layer is linear, so outputs can go negative
+        let mlp = MLP::new_seeded(1, &[1], Activation::ReLU, 0);
+        mlp.parameters()[0].set_data(-1.0);
+        assert_eq!(mlp.predict(&[2.0])[0], -2.0);
+        let batch = mlp.predict_batch(&[vec![1.0], vec![-3.0]]);
+        assert_eq!(batch, [vec![-1.0], vec![3.0]]);
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn test_predict_batch_par() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mlp = MLP::new_with_rng(4, &[16, 8, 3], Activation::Tanh, &mut rng);
+        let xs: Vec<Vec<Scalar>> = (0..10_000)
+            .map(|_| (0..4).map(|_| rng.gen_range(-3.0..3.0)).collect())
+            .collect();
+        assert_eq!(mlp.predict_batch_par(&xs), mlp.predict_batch(&xs));
+    }
+
+    #[test]
+    fn test_compiled_mlp() {
+        fn is_send_sync<T: Send + Sync>() {}
+        is_send_sync::<CompiledMlp>();
+
+        let mut rng = StdRng::seed_from_u64(21);
+        for activation in [Activation::ReLU, Activation::Tanh] {
+            let mlp = MLP::new_with_rng(4, &[8, 8, 3], activation, &mut rng);
+            let compiled = mlp.compile_inference();
+            assert_eq!(compiled.to_string(), "CompiledMlp(4-8-8-3)");
+            for _ in 0..20 {
+                let x: Vec<Scalar> =
+                    (0..4).map(|_| rng.gen_range(-2.0..2.0)).collect();
+                let values: Vec<_> = x.iter().map(|&x| Value::new(x)).collect();
+                let expected = mlp.call(&values);
+                for (got, want) in compiled.predict(&x).iter().zip(&expected) {
+                    assert!((got - want.get_data()).abs() < 1e-6);
+                }
+            }
+        }
+
+        let mlp = MLP::new_seeded(2, &[3, 1], Activation::ReLU, 0);
+        let mut compiled = mlp.compile_inference();
+        let before = compiled.predict(&[1.0, -1.0]);
+        for p in mlp.parameters() {
+            p.add_data(0.5);
+        }
+        assert_eq!(compiled.predict(&[1.0, -1.0]), before);
+        compiled.refresh(&mlp);
+        let after = compiled.predict_batch(&[vec![1.0, -1.0]]);
+        assert!((after[0][0] - mlp.predict(&[1.0, -1.0])[0]).abs() < 1e-6);
+        assert_ne!(after[0], before);
+    }
+
+    #[test]
+    #[should_panic(expected = "refreshing from an MLP of another shape")]
+    fn test_compiled_mlp_refresh_shape() {
+        let mut compiled =
+            MLP::new(2, &[3, 1], Activation::ReLU).compile_inference();
+        compiled.refresh(&MLP::new(2, &[4, 1], Activation::ReLU));
+    }
+
+    #[test]
+    #[should_panic(expected = "CompiledMlp layer expects 2 inputs, got 3")]
+    fn test_compiled_mlp_input_size() {
+        let compiled =
+            MLP::new(2, &[3, 1], Activation::ReLU).compile_inference();
+        compiled.predict(&[1.0, -1.0, 0.5]);
+    }
+
+    #[test]
+    #[should_panic(expected = "QuantizedMlp layer expects 2 inputs, got 1")]
+    fn test_quantized_mlp_input_size() {
+        let mlp = MLP::new(2, &[3, 1], Activation::ReLU);
+        mlp.compile_inference().quantize().predict(&[1.0]);
+    }
+
+    #[test]
+    fn test_quantized_mlp() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let activation = Activation::LeakyReLU(0.1);
+        let mlp = MLP::new_with_rng(3, &[8, 2], activation, &mut rng);
+        let compiled = mlp.compile_inference();
+        let quantized = compiled.quantize();
+        assert_eq!(quantized.to_string(), "QuantizedMlp(3-8-2)");
+        for _ in 0..20 {
+            let x: Vec<Scalar> =
+                (0..3).map(|_| rng.gen_range(-2.0..2.0)).collect();
+            let (got, want) = (quantized.predict(&x), compiled.predict(&x));
+            for (got, want) in got.iter().zip(&want) {
+                assert!((got - want).abs() < 0.05, "{} vs {}", got, want);
+            }
+        }
+        let zeros = quantized.predict(&[0.0; 3]);
+        for (got, want) in zeros.iter().zip(compiled.predict(&[0.0; 3])) {
+            assert!((got - want).abs() < 1e-6);
+        }
+
+        let bytes = quantized.serialize();
+        let restored = QuantizedMlp::deserialize(&bytes).unwrap();
+        assert_eq!(restored.serialize(), bytes);
+        let x = [0.3, -1.2, 0.7];
+        assert_eq!(restored.predict(&x), quantized.predict(&x));
+    }
+
+    #[test]
+    fn test_neuron_vector() {
+        let a = Neuron::new(4, false);
+        let x = Vector::from_data(&[1.0, -2.0, 0.5, 3.0]);
+        let out = a.call(x.values());
+        let w = Vector::new(a.w.clone());
+        let b = a.b.as_ref().unwrap();
+        let expected = w.dot(&x) + b;
+        assert!((out.get_data() - expected.get_data()).abs() < 1e-6);
+        expected.backward();
+        assert_eq!(w.iter().map(Value::get_grad).collect::<Vec<_>>(), x.data());
+        assert_eq!(b.get_grad(), 1.0);
+    }
+
+    #[test]
+    fn test_call_vector() {
+        let a = MLP::new(3, &[4, 2], Activation::ReLU);
+        let x = Vector::from_data(&[1.0, -2.0, 0.5]);
+        let out = a.call_vector(&x);
+        assert_eq!(out.len(), 2);
+        assert_eq!(out.data(), Vector::new(a.call(x.values())).data());
+        let out = a.layers[0].call_vector(&x);
+        assert_eq!(out.len(), 4);
+    }
+
+    #[test]
+    fn test_layer_matvec() {
+        let a = Layer::new(3, 2, false);
+        for (i, p) in a.parameters().iter().enumerate() {
+            p.set_data(i as Scalar * 0.5 - 1.0);
+        }
+        let w = Matrix::from_fn(2, 3, |r, c| a.neurons[r].w[c].clone());
+        let b =
+            Vector::new(a.neurons.iter().flat_map(|n| n.b.clone()).collect());
+        let x = Vector::from_data(&[1.0, -2.0, 0.5]);
+
+        let grads = |out: Vector| {
+            a.zero_grad();
+            out.sum().backward();
+            a.parameters()
+                .iter()
+                .map(Value::get_grad)
+                .collect::<Vec<_>>()
+        };
+        let layer_out = a.call_vector(&x);
+        let matvec_out = &w.matvec(&x) + &b;
+        assert_eq!(layer_out.data(), matvec_out.data());
+        assert_eq!(grads(layer_out), grads(matvec_out));
+    }
+
+    #[test]
+    fn test_dyn_module() {
+        let modules: Vec<Box<dyn Module>> = vec![
+            Box::new(Neuron::new(3, true)),
+            Box::new(Layer::new(3, 4, true)),
+            Box::new(MLP::new(3, &[4, 2], Activation::ReLU)),
+        ];
+        let x = [Value::new(1.0), Value::new(-2.0), Value::new(0.5)];
+        let sizes: Vec<usize> =
+            modules.iter().map(|m| m.forward(&x).len()).collect();
+        assert_eq!(sizes, vec![1, 4, 2]);
+        let counts: Vec<usize> =
+            modules.iter().map(|m| m.parameters().len()).collect();
+        assert_eq!(counts, vec![4, 16, 26]);
+
+        let layer = Layer::new(3, 2, false);
+        let by_ref: &dyn Module = &&layer;
+        assert_eq!(
+            by_ref.forward(&x)[1].get_data(),
+            layer.call(&x)[1].get_data()
+        );
+        by_ref.forward(&x)[0].backward();
+        by_ref.zero_grad();
+        assert!(layer.parameters().iter().all(|p| p.get_grad() == 0.0));
+    }
+
+    #[test]
+    fn test_sequential() {
+        let mlp = MLP::new(2, &[16, 1], Activation::ReLU);
+        let mut seq = Sequential::new(vec![Box::new(Layer::new(2, 16, true))]);
+        seq.push(Layer::new(16, 1, false));
+        assert_eq!(seq.len(), 2);
+        assert_eq!(seq[1].parameters().len(), 17);
+        for (p, q) in seq.parameters().iter().zip(mlp.parameters()) {
+            p.set_data(q.get_data());
+        }
+
+        let x = [Value::new(0.3), Value::new(-0.8)];
+        let expected = mlp.call(&x)[0].get_data();
+        assert_eq!(seq.forward(&x)[0].get_data(), expected);
+        let shown = format!("{}", seq);
+        assert!(shown.starts_with("Sequential of [Layer of [ReLU Neuron2, "));
+        assert!(shown.ends_with("], Layer of [Linear Neuron16]]"));
+    }
+
+    #[test]
+    fn test_activation() {
+        let tanh = MLP::new(3, &[8, 8, 1], Activation::Tanh);
+        let relu = MLP::new(3, &[8, 8, 1], Activation::ReLU);
+        for (p, q) in relu.parameters().iter().zip(tanh.parameters()) {
+            p.set_data(q.get_data());
+        }
+        assert_eq!(format!("{:?}", tanh.layers[0].neurons[0]), "Tanh Neuron3");
+        assert_eq!(
+            format!("{:?}", tanh.layers[2].neurons[0]),
+            "Linear Neuron8"
+        );
+
+        let x = [Value::new(2.0), Value::new(-3.0), Value::new(1.5)];
+        let hidden = tanh.layers[0].call(&x);
+        assert!(hidden.iter().all(|h| h.get_data().abs() < 1.0));
+        tanh.call(&x)[0].backward();
+        relu.call(&x)[0].backward();
+        let grads = |m: &MLP| -> Vec<Scalar> {
+            m.parameters().iter().map(Value::get_grad).collect()
+        };
+        assert_ne!(grads(&tanh), grads(&relu));
+
+        let leaky = Neuron::new(1, Activation::LeakyReLU(0.1));
+        leaky.w[0].set_data(1.0);
+        assert_eq!(leaky.call(&[Value::new(-2.0)]).get_data(), -0.2);
+
+        let gelu = Neuron::new(1, Activation::Gelu);
+        gelu.w[0].set_data(1.0);
+        let out = gelu.call(&[Value::new(1.0)]).get_data();
+        assert!((out - 0.841192).abs() < 1e-5);
+        assert_eq!(format!("{:?}", gelu), "Gelu Neuron1");
+        assert_eq!("Gelu".parse::<Activation>(), Ok(Activation::Gelu));
+    }
+
+    #[test]
+    fn test_custom_activation() {
+        let act = Activation::custom(|v| v.relu() * 0.5 + v * 0.1);
+        assert_eq!(format!("{:?}", act), "Custom");
+        assert_eq!(act, act.clone());
+        assert_ne!(act, Activation::custom(|v| v.clone()));
+
+        let a = Layer::new(1, 1, act);
+        assert_eq!(format!("{:?}", a), "Layer of [Custom Neuron1]");
+        let n = &a.neurons[0];
+        for &x in &[3.0, -3.0] {
+            n.w[0].set_data(2.0);
+            n.b.as_ref().unwrap().set_data(0.5);
+            a.zero_grad();
+            a.call(&[Value::new(x)])[0].backward();
+            // d/dw of 0.5 * relu(w x + b) + 0.1 * (w x + b)
+            let pre: Scalar = 2.0 * x + 0.5;
+            let slope = if pre > 0.0 { 0.5 } else { 0.0 } + 0.1;
+            assert_grad_close(&n.w[0], slope * x, 1e-6);
+            assert_grad_close(n.b.as_ref().unwrap(), slope, 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_without_bias() {
+        let a = Layer::new(4, 3, Activation::Linear).with_bias(false);
+        assert_eq!(a.parameters().len(), 12);
+        assert_eq!(
+            format!("{:?}", a.neurons[0]),
+            "Linear Neuron4 without bias"
+        );
+        let zeros: Vec<Value> = (0..4).map(|_| Value::new(0.0)).collect();
+        assert!(a.call(&zeros).iter().all(|y| y.get_data() == 0.0));
+        let a = a.with_bias(true);
+        assert_eq!(a.parameters().len(), 15);
+
+        // a trained bias survives asking for one again
+        let neuron = Neuron::new(2, Activation::Linear);
+        let b = neuron.b.clone().unwrap();
+        b.set_data(0.5);
+        let neuron = neuron.with_bias(true);
+        assert_eq!(neuron.b, Some(b));
+        assert_eq!(neuron.b.as_ref().unwrap().get_data(), 0.5);
+    }
+
+    #[test]
+    fn test_init() {
+        let bound = (6.0 as Scalar / 100.0).sqrt();
+        let a = Layer::new(100, 50, true).with_init(&Init::KaimingUniform);
+        let w: Vec<Scalar> = a
+            .neurons
+            .iter()
+            .flat_map(|n| n.w.iter())
+            .map(Value::get_data)
+            .collect();
+        assert!(w.iter().all(|x| x.abs() <= bound));
+        // 5000 draws should come close to both ends and average near zero
+        let max = w.iter().cloned().fold(0.0, Scalar::max);
+        let min = w.iter().cloned().fold(0.0, Scalar::min);
+        assert!(max > 0.95 * bound && min < -0.95 * bound);
+        let mean = w.iter().sum::<Scalar>() / w.len() as Scalar;
+        assert!(mean.abs() < 0.02);
+
+        let mlp = MLP::new(3, &[4, 2], true).with_init(&Init::Zeros);
+        assert!(mlp.parameters().iter().all(|p| p.get_data() == 0.0));
+
+        let n = Neuron::new(2000, true).with_init(&Init::Normal {
+            mean: 1.0,
+            std: 0.5,
+        });
+        let w: Vec<Scalar> = n.w.iter().map(Value::get_data).collect();
+        let mean = w.iter().sum::<Scalar>() / 2000.0;
+        let var = w.iter().map(|x| (x - mean).powi(2)).sum::<Scalar>() / 2000.0;
+        assert!((mean - 1.0).abs() < 0.05 && (var.sqrt() - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_seeded() {
+        let bits = |m: &MLP| -> Vec<_> {
+            m.parameters()
+                .iter()
+                .map(|p| p.get_data().to_bits())
+                .collect()
+        };
+        let a = MLP::new_seeded(3, &[8, 8, 1], true, 42);
+        let b = MLP::new_seeded(3, &[8, 8, 1], true, 42);
+        let c = MLP::new_seeded(3, &[8, 8, 1], true, 43);
+        assert_eq!(bits(&a), bits(&b));
+        assert_ne!(bits(&a), bits(&c));
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let d = Layer::new_with_rng(4, 2, true, &mut rng)
+            .with_init_rng(&Init::KaimingNormal, &mut rng);
+        let mut rng = StdRng::seed_from_u64(7);
+        let e = Layer::new_with_rng(4, 2, true, &mut rng)
+            .with_init_rng(&Init::KaimingNormal, &mut rng);
+        let data = |l: &Layer| -> Vec<Scalar> {
+            l.parameters().iter().map(Value::get_data).collect()
+        };
+        assert_eq!(data(&d), data(&e));
+    }
+
+    #[test]
+    fn test_prelu_learns_slope() {
+        let prelu = PReLU::new(0.25);
+        assert_eq!(prelu.to_string(), "PReLU(0.25)");
+        assert_eq!(prelu.named_parameters()[0].0, "alpha");
+        // Targets with a slope of 0.6 below zero
+        let xs: Vec<Scalar> = (-5..=5).map(|i| i as Scalar * 0.4).collect();
+        for _ in 0..100 {
+            let x: Vec<_> = xs.iter().map(|&x| Value::new(x)).collect();
+            let errs = prelu.forward(&x).into_iter().zip(&xs).map(|(y, &x)| {
+                let target = if x > 0.0 { x } else { 0.6 * x };
+                (y + -target).pow(2.0)
+            });
+            prelu.zero_grad();
+            Vector::new(errs.collect()).mean().backward();
+            prelu.alpha().add_data(-0.5 * prelu.alpha().get_grad());
+        }
+        assert_value_close(prelu.alpha(), 0.6, 1e-4);
+        assert_eq!(prelu.output_size(3), Some(3));
+    }
+
+    #[test]
+    fn test_dropout() {
+        let x: Vec<Value> = (1..=10).map(|i| Value::new(i as Scalar)).collect();
+        let all = Dropout::new(1.0);
+        assert!(all.forward(&x).iter().all(|y| y.get_data() == 0.0));
+
+        let mut d = Dropout::new_seeded(0.3, 0);
+        assert!(d.is_training());
+        let trials = 2000;
+        let mut dropped = 0;
+        for _ in 0..trials {
+            dropped +=
+                d.forward(&x).iter().filter(|y| y.get_data() == 0.0).count();
+        }
+        let rate = dropped as Scalar / (trials * x.len()) as Scalar;
+        assert!((rate - 0.3).abs() < 0.01, "drop rate {}", rate);
+
+        let y = d.forward(&x);
+        Vector::new(y.clone()).sum().backward();
+        for (xi, yi) in x.iter().zip(&y) {
+            let expected = if yi.get_data() == 0.0 { 0.0 } else { 1.0 / 0.7 };
+            assert_grad_close(xi, expected, 1e-5);
+            if expected != 0.0 {
+                assert_value_close(yi, xi.get_data() / 0.7, 1e-5);
+            }
+        }
+
+        // a copy carries on drawing the same masks
+        let copy = d.clone_detached_boxed().unwrap();
+        let data = |y: Vec<Value>| -> Vec<Scalar> {
+            y.iter().map(Value::get_data).collect()
+        };
+        for _ in 0..5 {
+            assert_eq!(data(copy.forward(&x)), data(d.forward(&x)));
+        }
+
+        d.set_training(false);
+        let y = d.forward(&x);
+        assert!(x.iter().zip(&y).all(|(a, b)| a == b));
+    }
+
+    #[test]
+    fn test_train_eval() {
+        let mut seq = Sequential::default();
+        seq.push(Layer::new(3, 3, false));
+        seq.push(Dropout::new_seeded(0.5, 1));
+        let x = [Value::new(1.0), Value::new(2.0), Value::new(3.0)];
+        let plain = seq[0].forward(&x);
+        let data = |y: &[Value]| -> Vec<Scalar> {
+            y.iter().map(Value::get_data).collect()
+        };
+
+        assert!(seq.is_training() && seq[1].is_training());
+        let masked = (0..10).any(|_| data(&seq.forward(&x)) != data(&plain));
+        assert!(masked);
+
+        seq.eval();
+        assert!(!seq.is_training() && !seq[1].is_training());
+        assert!((0..10).all(|_| data(&seq.forward(&x)) == data(&plain)));
+        // a Layer has no mode of its own
+        assert_eq!(data(&seq[0].forward(&x)), data(&plain));
+
+        seq.train();
+        assert!(seq[1].is_training());
+
+        // what's pushed later takes on the chain's mode
+        seq.eval();
+        seq.push(Dropout::new_seeded(0.5, 2));
+        assert!(!seq[2].is_training());
+        assert!((0..10).all(|_| data(&seq.forward(&x)) == data(&plain)));
+    }
+
+    #[test]
+    fn test_batch_norm() {
+        let data: [[Scalar; 2]; 4] =
+            [[1.0, 10.0], [2.0, 20.0], [3.0, 30.0], [6.0, 60.0]];
+        let batch = || -> Vec<Vec<Value>> {
+            data.iter()
+                .map(|r| r.iter().map(|&x| Value::new(x)).collect())
+                .collect()
+        };
+        let mut bn = BatchNorm1d::new(2);
+        assert_eq!(bn.parameters().len(), 4);
+
+        let x = batch();
+        let y = bn.forward_batch(&x);
+        for j in 0..2 {
+            let col = Vector::new(y.iter().map(|r| r[j].clone()).collect());
+            assert_value_close(&col.mean(), 0.0, 1e-5);
+            assert_value_close(&col.dot(&col), 4.0, 1e-3);
+        }
+        // the statistics are in the graph: the output mean is pinned to beta,
+        // so shifting every input leaves the sum unchanged
+        y.iter()
+            .map(|r| r[0].clone())
+            .fold(Value::new(0.0), |a, b| a + b)
+            .backward();
+        assert!(x.iter().all(|r| r[0].get_grad().abs() < 1e-5));
+        assert_grad_close(&bn.beta[0], 4.0, 1e-6);
+
+        // mean 3 and 30, unbiased variance 14/3 and 1400/3
+        for _ in 0..200 {
+            bn.forward_batch(&batch());
+        }
+        let (mean, var) = (bn.running_mean(), bn.running_var());
+        assert!((mean[0] - 3.0).abs() < 1e-3 && (mean[1] - 30.0).abs() < 1e-2);
+        assert!((var[0] - 14.0 / 3.0).abs() < 1e-3);
+        assert!((var[1] - 1400.0 / 3.0).abs() < 0.1);
+
+        bn.eval();
+        let y = bn.forward(&[Value::new(3.0), Value::new(60.0)]);
+        let expected = 30.0 / (var[1] + 1e-5).sqrt();
+        assert_value_close(&y[0], 0.0, 1e-3);
+        assert_value_close(&y[1], expected, 1e-3);
+
+        // a batch of one normalizes to beta rather than dividing by zero
+        bn.train();
+        let y = bn.forward(&[Value::new(5.0), Value::new(-5.0)]);
+        assert!(y.iter().all(|v| v.get_data() == 0.0));
+        assert_eq!(bn.running_var(), var);
+    }
+
+    #[test]
+    fn test_residual() {
+        let x = [Value::new(1.0), Value::new(-2.0)];
+        let block =
+            Residual::new(Layer::new(2, 2, false).with_init(&Init::Zeros));
+        assert_eq!(block.parameters().len(), 6);
+        let y = block.forward(&x);
+        assert_eq!(y[0].get_data(), 1.0);
+        assert_eq!(y[1].get_data(), -2.0);
+        (&y[0] + &y[1]).backward();
+        assert!(x.iter().all(|v| v.get_grad() == 1.0));
+
+        let x = [Value::new(1.0), Value::new(-2.0)];
+        let w: [[Scalar; 2]; 2] = [[0.5, -1.0], [2.0, 0.25]];
+        for (n, row) in block.inner().neurons.iter().zip(&w) {
+            n.w[0].set_data(row[0]);
+            n.w[1].set_data(row[1]);
+        }
+        let y = block.forward(&x);
+        assert_value_close(&y[0], 1.0 + 0.5 + 2.0, 1e-6);
+        (&y[0] + &y[1]).backward();
+        // skip path plus the column sums of the weights
+        assert_grad_close(&x[0], 1.0 + 0.5 + 2.0, 1e-6);
+        assert_grad_close(&x[1], 1.0 - 1.0 + 0.25, 1e-6);
+    }
+
+    #[test]
+    #[should_panic(expected = "Residual inner module maps 3 values to 2")]
+    fn test_residual_mismatch() {
+        let block = Residual::new(Layer::new(3, 2, false));
+        block.forward(&[Value::new(0.0), Value::new(0.0), Value::new(0.0)]);
+    }
+
+    #[test]
+    fn test_conv1d() {
+        let conv = Conv1d::new(1, 1, 3, 1, 1);
+        for (w, &k) in conv.kernels[0][0].iter().zip(&[1.0, 2.0, -1.0]) {
+            w.set_data(k);
+        }
+        conv.b[0].set_data(0.5);
+        let x = vec![[1.0, 2.0, 3.0, 4.0]
+            .iter()
+            .map(|&v| Value::new(v))
+            .collect::<Vec<_>>()];
+        let y = conv.forward_channels(&x);
+        // padded input is [0, 1, 2, 3, 4, 0]
+        let expected = [0.5, 2.5, 4.5, 11.5];
+        assert_eq!(
+            y[0].iter().map(Value::get_data).collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(conv.forward(&x[0]).len(), 4);
+        assert_eq!(
+            format!("{:?}", conv),
+            "Conv1d(1, 1, kernel_size=3, stride=1, padding=1)"
+        );
+
+        let y = Vector::new(y.concat()).sum();
+        y.backward();
+        // the middle tap sees every input once
+        assert_grad_close(&conv.kernels[0][0][1], 10.0, 1e-6);
+        assert_grad_close(&conv.kernels[0][0][0], 6.0, 1e-6);
+    }
+
+    #[test]
+    fn test_conv1d_output_len() {
+        // (length, kernel, stride, padding, expected)
+        let cases = [
+            (10, 3, 1, 0, 8),
+            (10, 3, 2, 0, 4),
+            (10, 3, 2, 1, 5),
+            (7, 5, 3, 2, 3),
+            (4, 4, 1, 0, 1),
+        ];
+        for &(length, kernel, stride, padding, expected) in &cases {
+            let conv = Conv1d::new(2, 3, kernel, stride, padding);
+            assert_eq!(conv.output_len(length), expected);
+            let x: Vec<Vec<Value>> = (0..2)
+                .map(|_| (0..length).map(|_| Value::new(1.0)).collect())
+                .collect();
+            let y = conv.forward_channels(&x);
+            assert_eq!(y.len(), 3);
+            assert!(y.iter().all(|c| c.len() == expected));
+        }
+        assert_eq!(
+            Conv1d::new(2, 3, 3, 1, 0).parameters().len(),
+            3 * 2 * 3 + 3
+        );
+    }
+
+    #[test]
+    fn test_conv1d_numeric_grad() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let conv = Conv1d::new_with_rng(2, 2, 3, 2, 1, &mut rng);
+        let x: Vec<Vec<Value>> = (0..2)
+            .map(|c| {
+                (0..5)
+                    .map(|t| Value::new((c * 5 + t) as Scalar * 0.3 - 1.0))
+                    .collect()
+            })
+            .collect();
+        let loss = |_: &[Value]| {
+            let y = Vector::new(conv.forward_channels(&x).concat());
+            y.map(Value::tanh).sum()
+        };
+        let w = conv.kernels[1][0][2].clone();
+        crate::testing::assert_grads_match_numeric(&[w], loss, 1e-2, 1e-2);
+    }
+
+    fn outputs(net: &dyn Module, x: &[Value]) -> Vec<Scalar> {
+        net.forward(x).iter().map(Value::get_data).collect()
+    }
+
+    #[test]
+    fn test_builder_layer() {
+        let net = MlpBuilder::new(3)
+            .layer(4, Activation::Tanh)
+            .layer(2, false)
+            .build()
+            .unwrap();
+        assert_eq!(net.len(), 2);
+        assert_eq!(net.parameters().len(), 4 * 4 + 2 * 5);
+        assert_eq!(
+            format!("{:?}", &net[1]),
+            "Layer of [Linear Neuron4, Linear Neuron4]"
+        );
+        // small enough inputs that tanh can't round to exactly 1
+        let hidden = net[0].forward(&[
+            Value::new(0.5),
+            Value::new(-0.5),
+            Value::new(1.0),
+        ]);
+        assert!(hidden.iter().all(|h| h.get_data().abs() < 1.0));
+    }
+
+    #[test]
+    fn test_builder_dropout() {
+        let mut net = MlpBuilder::new(2)
+            .layer(8, true)
+            .dropout(1.0)
+            .layer(1, false)
+            .build()
+            .unwrap();
+        assert_eq!(format!("{:?}", &net[1]), "Dropout(1)");
+        let x = [Value::new(1.0), Value::new(2.0)];
+        // everything is dropped, leaving only the output bias
+        assert_eq!(outputs(&net, &x), vec![0.0]);
+        net.eval();
+        assert!(!net[1].is_training());
+    }
+
+    #[test]
+    fn test_builder_bias() {
+        let net = MlpBuilder::new(2)
+            .layer(3, true)
+            .bias(false)
+            .layer(1, false)
+            .build()
+            .unwrap();
+        assert_eq!(net[0].parameters().len(), 9);
+        assert_eq!(net[1].parameters().len(), 3);
+    }
+
+    #[test]
+    fn test_builder_init() {
+        let net = MlpBuilder::new(2)
+            .layer(3, true)
+            .layer(1, false)
+            .init(Init::Constant(0.5))
+            .build()
+            .unwrap();
+        let data: Vec<Scalar> =
+            net.parameters().iter().map(Value::get_data).collect();
+        assert_eq!(
+            data,
+            vec![
+                0.5, 0.5, 0.0, 0.5, 0.5, 0.0, 0.5, 0.5, 0.0, 0.5, 0.5, 0.5, 0.0
+            ]
+        );
+    }
+
+    #[test]
+    fn test_builder_seed() {
+        let builder = MlpBuilder::new(2)
+            .layer(4, true)
+            .dropout(0.5)
+            .layer(1, false);
+        let x = [Value::new(1.0), Value::new(-1.0)];
+        let a = builder.clone().seed(7).build().unwrap();
+        let b = builder.seed(7).build().unwrap();
+        assert_eq!(outputs(&a, &x), outputs(&b, &x));
+    }
+
+    #[test]
+    fn test_builder_matches_sequential() {
+        let built = MlpBuilder::new(2)
+            .layer(16, Activation::Tanh)
+            .dropout(0.1)
+            .layer(16, Activation::Tanh)
+            .layer(1, Activation::Linear)
+            .seed(42)
+            .init(Init::XavierUniform)
+            .build()
+            .unwrap();
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let init = Init::XavierUniform;
+        let layer = |nin, nout, act, rng: &mut StdRng| {
+            Layer::new_with_rng(nin, nout, act, rng).with_init_rng(&init, rng)
+        };
+        let first = layer(2, 16, Activation::Tanh, &mut rng);
+        let mut manual = Sequential::new(vec![Box::new(first)]);
+        manual.push(Dropout::new_seeded(0.1, rng.gen()));
+        manual.push(layer(16, 16, Activation::Tanh, &mut rng));
+        manual.push(layer(16, 1, Activation::Linear, &mut rng));
+
+        let x = [Value::new(0.3), Value::new(-0.7)];
+        assert_eq!(outputs(&built, &x), outputs(&manual, &x));
+        assert_eq!(format!("{}", built), format!("{}", manual));
+    }
+
+    #[test]
+    fn test_builder_errors() {
+        let err = MlpBuilder::default().layer(2, true).build().unwrap_err();
+        assert_eq!(err, BuildError::NoInputSize);
+        assert_eq!(
+            err.to_string(),
+            "MlpBuilder: layers added before the input size"
+        );
+        let err = MlpBuilder::new(2)
+            .layer(2, true)
+            .dropout(1.5)
+            .layer(1, false)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, BuildError::DropoutProbability { index: 1, p: 1.5 });
+        assert_eq!(
+            err.to_string(),
+            "MlpBuilder: step 1 has dropout probability 1.5, outside [0, 1]"
+        );
+        assert_eq!(
+            MlpBuilder::new(2).build().unwrap_err(),
+            BuildError::NoLayers
+        );
+        assert_eq!(
+            MlpBuilder::new(2).layer(0, true).build().unwrap_err(),
+            BuildError::EmptyLayer { index: 0 }
+        );
+    }
+
+    #[test]
+    fn test_num_parameters() {
+        let mlp = MLP::new(3, &[4, 4, 1], true);
+        assert_eq!(mlp.num_parameters(), 3 * 4 + 4 + 4 * 4 + 4 + 4 + 1);
+        assert_eq!(mlp.num_parameters(), mlp.parameters().len());
+        let counts = mlp.parameter_counts();
+        assert_eq!(counts[1], ("layer1".to_string(), 20));
+        assert_eq!(counts.iter().map(|c| c.1).sum::<usize>(), 41);
+
+        let mut seq = Sequential::new(vec![Box::new(mlp)]);
+        seq.push(Dropout::new(0.5));
+        seq.push(Layer::new(1, 2, true).with_bias(false));
+        seq.push(BatchNorm1d::new(2));
+        let counts: Vec<usize> =
+            seq.parameter_counts().iter().map(|c| c.1).collect();
+        assert_eq!(counts, vec![41, 0, 2, 4]);
+        assert_eq!(seq.num_parameters(), 47);
+        assert_eq!(seq.num_parameters(), seq.parameters().len());
+    }
+
+    #[test]
+    fn test_named_parameters() {
+        let mlp = MLP::new(2, &[3, 3, 1], true);
+        let named = mlp.named_parameters();
+        assert_eq!(named.len(), mlp.num_parameters());
+        let names: Vec<&str> = named.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(
+            &names[..4],
+            [
+                "layer0.neuron0.w0",
+                "layer0.neuron0.w1",
+                "layer0.neuron0.b",
+                "layer0.neuron1.w0"
+            ]
+        );
+        assert_eq!(names.last(), Some(&"layer2.neuron0.b"));
+        let unique: std::collections::HashSet<&str> =
+            names.iter().cloned().collect();
+        assert_eq!(unique.len(), names.len());
+        // stable across calls, in the same order as parameters()
+        assert_eq!(
+            mlp.named_parameters()
+                .iter()
+                .map(|(n, _)| n.clone())
+                .collect::<Vec<_>>(),
+            names
+        );
+        assert!(named
+            .iter()
+            .zip(mlp.parameters())
+            .all(|((_, a), b)| *a == b));
+
+        let mut seq = Sequential::default();
+        seq.push(Dropout::new(0.5));
+        seq.push(Residual::new(Layer::new(1, 1, false).with_bias(false)));
+        seq.push(BatchNorm1d::new(1));
+        let names: Vec<String> =
+            seq.named_parameters().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, ["1.neuron0.w0", "2.gamma0", "2.beta0"]);
+    }
+
+    #[test]
+    fn test_summary() {
+        let mlp = MLP::new(2, &[16, 16, 1], true);
+        let expected = "\
+Layer   Type   Activation  Output  Params
+=========================================
+layer0  Layer  ReLU            16      48
+layer1  Layer  ReLU            16     272
+layer2  Layer  Linear           1      17
+=========================================
+Total params: 337";
+        assert_eq!(mlp.summary(), expected);
+    }
+
+    #[test]
+    fn test_summary_nested() {
+        let mut inner = Sequential::default();
+        inner.push(Layer::new(4, 3, Activation::Tanh));
+        inner.push(Dropout::new(0.5));
+        let mut seq = Sequential::default();
+        seq.push(Conv1d::new(2, 1, 3, 1, 0));
+        seq.push(inner);
+        seq.push(BatchNorm1d::new(3));
+        seq.push(Residual::new(Layer::new(3, 3, false)));
+        let expected = "\
+Layer  Type         Activation  Output  Params
+==============================================
+0      Conv1d       -                4       7
+1      Sequential   -                3      15
+  0    Layer        Tanh             3      15
+  1    Dropout      -                3       0
+2      BatchNorm1d  -                3       6
+3      Residual     -                3      12
+==============================================
+Total params: 40";
+        assert_eq!(seq.summary(12), expected);
+        // shapes that don't fit the first layer can't be followed
+        assert!(seq.summary(5).lines().nth(2).unwrap().contains(" ? "));
+    }
+
+    #[test]
+    fn test_output_size_checks_input() {
+        let neuron = Neuron::new(3, Activation::Tanh);
+        assert_eq!(neuron.output_size(3), Some(1));
+        assert_eq!(neuron.output_size(2), None);
+        let layer = Layer::new(3, 2, Activation::Tanh);
+        assert_eq!(layer.output_size(3), Some(2));
+        assert_eq!(layer.output_size(4), None);
+        let mlp = MLP::new(3, &[4, 1], Activation::Tanh);
+        assert_eq!(mlp.output_size(3), Some(1));
+        assert_eq!(mlp.output_size(2), None);
+        let seq = Sequential::new(vec![Box::new(layer), Box::new(mlp)]);
+        assert_eq!(seq.output_size(3), None);
+    }
+
+    #[test]
+    fn test_parameters_flat() {
+        let a = MLP::new(3, &[4, 1], Activation::Tanh);
+        let b = MLP::new(3, &[4, 1], Activation::Tanh);
+        let x = [Value::new(0.5), Value::new(-1.0), Value::new(2.0)];
+        let flat = a.get_parameters_flat();
+        assert_eq!(flat.len(), 21);
+
+        let before = a.call(&x)[0].get_data();
+        let handle = a.parameters()[0].clone();
+        a.set_parameters_flat(&[0.0; 21]).unwrap();
+        assert_eq!(handle.get_data(), 0.0);
+        a.set_parameters_flat(&flat).unwrap();
+        assert_eq!(a.get_parameters_flat(), flat);
+        assert_eq!(a.call(&x)[0].get_data(), before);
+
+        b.set_parameters_flat(&flat).unwrap();
+        assert_eq!(b.call(&x)[0].get_data(), before);
+
+        let err = b.set_parameters_flat(&flat[1..]).unwrap_err();
+        assert_eq!(
+            err,
+            ParameterCountError {
+                expected: 21,
+                got: 20
+            }
+        );
+        assert_eq!(err.to_string(), "expected 21 parameter values, got 20");
+        assert_eq!(b.get_parameters_flat(), flat);
+    }
+
+    #[test]
+    fn test_clone_detached() {
+        let model = MLP::new_seeded(2, &[4, 1], Activation::Tanh, 1);
+        let x = [Value::new(0.5), Value::new(-0.5)];
+        let step = |model: &MLP| {
+            model.zero_grad();
+            model.call(&x)[0].pow(2.0).backward();
+            for p in model.parameters() {
+                p.add_data(-0.1 * p.get_grad());
+            }
+        };
+        step(&model);
+        model.call(&x)[0].backward();
+
+        let copy = model.clone_detached();
+        let (data, out) = (copy.get_parameters_flat(), copy.call(&x));
+        assert_eq!(data, model.get_parameters_flat());
+        assert!(copy.parameters().iter().all(|p| p.get_grad() == 0.0));
+        for (a, b) in copy.parameters().iter().zip(model.parameters()) {
+            assert!(*a != b);
+        }
+        for _ in 0..3 {
+            step(&model);
+        }
+        assert_ne!(model.get_parameters_flat(), data);
+        assert_eq!(copy.get_parameters_flat(), data);
+        assert_eq!(copy.call(&x)[0].get_data(), out[0].get_data());
+    }
+
+    #[test]
+    fn test_clone_detached_sequential() {
+        let mut net = Sequential::default();
+        net.push(Layer::new(3, 3, Activation::ReLU));
+        net.push(BatchNorm1d::new(3));
+        net.push(Residual::new(Conv1d::new(1, 1, 3, 1, 1)));
+        net.push(Dropout::new(0.5));
+        net.push(Embedding::new(4, 2));
+        net.eval();
+        let copy = net.clone_detached();
+        assert!(!copy[3].is_training());
+        assert!(!copy.is_training());
+        assert!(!copy[1].is_training());
+        assert_eq!(copy.get_parameters_flat(), net.get_parameters_flat());
+        let (a, b) = (copy.parameters(), net.parameters());
+        assert!(a.iter().zip(&b).all(|(a, b)| a != b));
+        net.parameters()[0].add_data(1.0);
+        assert_ne!(copy.get_parameters_flat(), net.get_parameters_flat());
+    }
+
+    #[test]
+    fn test_predict_scalars_matches_forward() {
+        let mlp = MLP::new_seeded(3, &[4, 2], Activation::Tanh, 5);
+        let modules: Vec<Box<dyn Module>> = vec![
+            Box::new(Neuron::new(3, Activation::Sigmoid)),
+            Box::new(Layer::new(3, 2, Activation::ReLU)),
+            Box::new(mlp.clone_detached()),
+            Box::new(Classifier::new(mlp)),
+            Box::new(PReLU::new(0.25)),
+        ];
+        let x = [0.5, -1.0, 2.0];
+        let values: Vec<_> = x.iter().map(|&x| Value::new(x)).collect();
+        for module in &modules {
+            let want = module.forward(&values);
+            let got = module.predict_scalars(&x);
+            assert_eq!(got.len(), want.len());
+            for (got, want) in got.iter().zip(&want) {
+                assert!((got - want.get_data()).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sequential_predict_scalars() {
+        let mut net = MlpBuilder::new(3)
+            .layer(4, Activation::Tanh)
+            .dropout(0.5)
+            .layer(2, Activation::Linear)
+            .seed(3)
+            .build()
+            .unwrap();
+        net.set_training(false);
+        let x = [0.5, -1.0, 2.0];
+        let values: Vec<_> = x.iter().map(|&x| Value::new(x)).collect();
+        let want = net.forward(&values);
+        let got = net.predict_scalars(&x);
+        assert_eq!(got.len(), 2);
+        for (got, want) in got.iter().zip(&want) {
+            assert!((got - want.get_data()).abs() < 1e-6);
+        }
+        let clf = Classifier::new(net);
+        assert_eq!(clf.predict(&x), argmax(&got));
+    }
+
+    #[test]
+    fn test_classifier() {
+        let model = MLP::new_seeded(3, &[5, 4], Activation::ReLU, 2);
+        let clf = Classifier::new(model);
+        let xs = vec![vec![0.5, -1.0, 2.0], vec![-0.3, 0.1, 0.0]];
+        for (x, proba) in xs.iter().zip(clf.predict_proba_batch(&xs)) {
+            assert_eq!(proba.len(), 4);
+            assert!((proba.iter().sum::<Scalar>() - 1.0).abs() < 1e-6);
+            assert!(proba.iter().all(|&p| (0.0..=1.0).contains(&p)));
+            assert_eq!(clf.predict(x), argmax(&proba));
+        }
+        let batch = clf.predict_batch(&xs);
+        assert_eq!(batch, [clf.predict(&xs[0]), clf.predict(&xs[1])]);
+
+        // class 0 scores x0 - x1, class 1 scores x1 - x0
+        let layer = Layer::new(2, 2, Activation::Linear);
+        layer
+            .set_parameters_flat(&[1.0, -1.0, 0.0, -1.0, 1.0, 0.0])
+            .unwrap();
+        let clf = Classifier::new(layer);
+        assert_eq!(clf.predict(&[2.0, 1.0]), 0);
+        assert_eq!(clf.predict(&[-1.0, 1.0]), 1);
+        let proba = clf.predict_proba(&[2.0, 1.0]);
+        let expected = 1.0 / (1.0 + (-2.0 as Scalar).exp());
+        assert!((proba[0] - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_softmax_is_stable() {
+        let proba = softmax(&[1000.0, 1000.0, -1000.0]);
+        assert_eq!(proba, [0.5, 0.5, 0.0]);
+        assert_eq!(argmax(&[1.0, 3.0, 3.0]), 1);
+    }
+
+    #[test]
+    fn test_embedding() {
+        let emb = Embedding::new(4, 3);
+        assert_eq!(emb.num_parameters(), 12);
+        let x = [Value::new(2.0), Value::new(0.0), Value::new(2.0)];
+        let out = emb.forward(&x);
+        assert_eq!(out.len(), 9);
+        assert_eq!(out[..3], *emb.lookup(2));
+        assert_eq!(emb.output_size(3), Some(9));
+        assert_eq!(emb.named_parameters()[4].0, "w1.1");
+
+        out.iter()
+            .fold(Value::new(0.0), |acc, v| acc + v)
+            .backward();
+        let grads: Vec<_> =
+            emb.parameters().iter().map(Value::get_grad).collect();
+        assert_eq!(grads[..6], [1.0, 1.0, 1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(grads[6..9], [2.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn test_prune_by_magnitude() {
+        let layer = Layer::new(4, 2, Activation::Linear);
+        let weights = [0.5, -0.1, 2.0, 0.3, -1.0, 0.05, 0.4, -3.0];
+        // w0..w3 and b for each neuron; the biases are smaller than any
+        // weight but must be left alone
+        let mut data = weights[..4].to_vec();
+        data.push(0.01);
+        data.extend(&weights[4..]);
+        data.push(-0.01);
+        layer.set_parameters_flat(&data).unwrap();
+
+        let mask = prune_by_magnitude(&layer, 0.5);
+        assert_eq!(mask.num_pruned(), 4);
+        assert_eq!(mask.sparsity(), 0.5);
+        let after = layer.get_parameters_flat();
+        assert_eq!(
+            after,
+            [0.5, 0.0, 2.0, 0.0, 0.01, -1.0, 0.0, 0.0, -3.0, -0.01]
+        );
+        let expected_mask = [
+            false, true, false, true, false, false, true, true, false, false,
+        ];
+        assert_eq!(mask.pruned(), expected_mask);
+
+        // An optimizer step moves every weight, and the mask undoes it for
+        // the pruned ones
+        let params = layer.parameters();
+        for p in &params {
+            p.add_data(0.25);
+        }
+        mask.apply_after_step(&params);
+        for (p, &pruned) in params.iter().zip(mask.pruned()) {
+            assert_eq!(p.get_data() == 0.0, pruned, "{}", p);
+        }
+    }
+
+    #[test]
+    fn test_prune_weights() {
+        let layer = Layer::new(3, 2, false);
+        let biases: Vec<_> = layer.neurons.iter().flat_map(|n| &n.b).collect();
+        assert_eq!(layer.weights().len(), 3 * 2);
+        assert!(biases.iter().all(|&b| !layer.weights().contains(b)));
+        let conv = Conv1d::new(2, 3, 2, 1, 0);
+        assert_eq!(conv.weights().len(), 3 * 2 * 2);
+        assert!(PReLU::new(0.25).weights().is_empty());
+
+        let mlp = MLP::new_seeded(3, &[4, 1], Activation::ReLU, 0);
+        let none = prune_by_magnitude(&mlp, 0.0);
+        assert_eq!(none.sparsity(), 0.0);
+        let all = prune_by_magnitude(&mlp, 1.0);
+        assert_eq!(all.num_pruned(), 3 * 4 + 4);
+        assert_eq!(all.sparsity(), 1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "mask for 5 parameters applied to 4")]
+    fn test_prune_mask_length() {
+        let neuron = Neuron::new(4, true);
+        let mask = prune_by_magnitude(&neuron, 0.5);
+        mask.apply_after_step(&neuron.parameters()[..4]);
+    }
 }
@@ -0,0 +1,2426 @@
+//! Optimizers that update parameters from their gradients.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Display};
+
+use crate::engine::{Scalar, Value};
+use crate::random::NormalRng;
+use sealed::Progress;
+
+/// Updates a fixed set of parameters from their gradients.
+pub trait Optimizer {
+    /// Moves every parameter using its current gradient.
+    fn step(&mut self);
+
+    /// Resets the gradients of the parameters. Intermediate nodes of a
+    /// graph keep theirs, but a fresh forward pass builds new ones anyway.
+    fn zero_grad(&mut self) {
+        for group in self.param_groups() {
+            for p in &group.params {
+                p.set_grad(0.0);
+            }
+        }
+    }
+
+    /// The default lr, used by every group that doesn't set its own.
+    fn lr(&self) -> Scalar;
+
+    fn set_lr(&mut self, lr: Scalar);
+
+    /// The parameters, in groups that may override the optimizer's
+    /// hyperparameters.
+    fn param_groups(&self) -> &[ParamGroup];
+
+    fn param_groups_mut(&mut self) -> &mut [ParamGroup];
+
+    /// A snapshot of everything the optimizer has accumulated, to resume
+    /// training from later.
+    fn state(&self) -> OptimState;
+
+    /// Restores a [state](Optimizer::state) taken from the same kind of
+    /// optimizer over the same number of parameters, grouped the same way.
+    fn load_state(&mut self, state: OptimState) -> Result<(), StateError>;
+
+    /// Sets the lr of one group, or the default lr with `None`.
+    ///
+    /// # Panics
+    ///
+    /// If there is no such group.
+    fn set_group_lr(&mut self, group: Option<usize>, lr: Scalar) {
+        match group {
+            Some(i) => self.param_groups_mut()[i].lr = Some(lr),
+            None => self.set_lr(lr),
+        }
+    }
+}
+
+impl<O: Optimizer + ?Sized> Optimizer for Box<O> {
+    fn step(&mut self) {
+        (**self).step()
+    }
+
+    fn zero_grad(&mut self) {
+        (**self).zero_grad()
+    }
+
+    fn lr(&self) -> Scalar {
+        (**self).lr()
+    }
+
+    fn set_lr(&mut self, lr: Scalar) {
+        (**self).set_lr(lr)
+    }
+
+    fn param_groups(&self) -> &[ParamGroup] {
+        (**self).param_groups()
+    }
+
+    fn param_groups_mut(&mut self) -> &mut [ParamGroup] {
+        (**self).param_groups_mut()
+    }
+
+    fn state(&self) -> OptimState {
+        (**self).state()
+    }
+
+    fn load_state(&mut self, state: OptimState) -> Result<(), StateError> {
+        (**self).load_state(state)
+    }
+
+    fn set_group_lr(&mut self, group: Option<usize>, lr: Scalar) {
+        (**self).set_group_lr(group, lr)
+    }
+}
+
+/// Some of an optimizer's parameters, with hyperparameters that override
+/// the optimizer's own, as in PyTorch's `param_groups`.
+///
+/// ```
+/// # use smolgrad::nn::{Activation, Module, MLP};
+/// # use smolgrad::optim::{split_params, Optimizer, ParamGroup, Sgd};
+/// let mlp = MLP::new(2, &[4, 1], Activation::Tanh);
+/// let named = mlp.named_parameters();
+/// let (biases, weights) = split_params(&named, |name| name.ends_with(".b"));
+/// let groups = vec![
+///     ParamGroup::new(weights),
+///     ParamGroup::new(biases).weight_decay(0.0),
+/// ];
+/// let mut opt = Sgd::with_groups(groups, 0.1).weight_decay(1e-4);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ParamGroup {
+    pub params: Vec<Value>,
+    pub lr: Option<Scalar>,
+    pub weight_decay: Option<Scalar>,
+}
+
+impl ParamGroup {
+    /// A group that uses the optimizer's settings until told otherwise.
+    pub fn new(params: Vec<Value>) -> Self {
+        Self {
+            params,
+            lr: None,
+            weight_decay: None,
+        }
+    }
+
+    pub fn lr(self, lr: Scalar) -> Self {
+        Self {
+            lr: Some(lr),
+            ..self
+        }
+    }
+
+    pub fn weight_decay(self, weight_decay: Scalar) -> Self {
+        Self {
+            weight_decay: Some(weight_decay),
+            ..self
+        }
+    }
+}
+
+/// Splits `named` parameters into those whose name matches `pred` and the
+/// rest, keeping their order.
+pub fn split_params(
+    named: &[(String, Value)],
+    pred: impl Fn(&str) -> bool,
+) -> (Vec<Value>, Vec<Value>) {
+    let (matching, rest): (Vec<_>, Vec<_>) =
+        named.iter().partition(|(name, _)| pred(name));
+    let values = |named: Vec<&(String, Value)>| {
+        named.into_iter().map(|(_, p)| p.clone()).collect()
+    };
+    (values(matching), values(rest))
+}
+
+/// An optimizer's progress, from [`Optimizer::state`]: its lrs, how many
+/// parameters it covers, its step count, how many noise values it has drawn
+/// and its per-parameter buffers, such as Adam's moments. Each buffer lists
+/// one value per parameter, in group order.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OptimState {
+    pub lr: Scalar,
+    pub group_lrs: Vec<Option<Scalar>>,
+    pub num_params: usize,
+    pub steps: u64,
+    /// How far optimizers that add noise, like [`Sgld`], are through their
+    /// seeded sequence, so a resumed run draws the same noise as one that
+    /// never stopped. 0 for the others.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub noise_draws: u64,
+    pub buffers: BTreeMap<String, Vec<Scalar>>,
+}
+
+impl OptimState {
+    fn new(lr: Scalar, groups: &[ParamGroup], steps: u64) -> Self {
+        Self {
+            lr,
+            group_lrs: groups.iter().map(|g| g.lr).collect(),
+            num_params: all_params(groups).count(),
+            steps,
+            noise_draws: 0,
+            buffers: BTreeMap::new(),
+        }
+    }
+
+    /// Adds `values` as the buffer `name`, unless nothing has been stored
+    /// in it yet.
+    fn buffer(
+        mut self,
+        name: &str,
+        values: &HashMap<Value, Scalar>,
+        groups: &[ParamGroup],
+    ) -> Self {
+        if !values.is_empty() {
+            let buffer = all_params(groups)
+                .map(|p| values.get(p).copied().unwrap_or(0.0))
+                .collect();
+            self.buffers.insert(name.to_string(), buffer);
+        }
+        self
+    }
+
+    /// Checks that the state fits `groups` and has no buffers other than
+    /// `names`, then sets the group lrs.
+    fn restore_groups(
+        &self,
+        groups: &mut [ParamGroup],
+        names: &[&str],
+    ) -> Result<(), StateError> {
+        if self.group_lrs.len() != groups.len() {
+            return Err(StateError::GroupCount {
+                expected: groups.len(),
+                got: self.group_lrs.len(),
+            });
+        }
+        let count = all_params(groups).count();
+        if self.num_params != count {
+            return Err(StateError::ParameterCount {
+                expected: count,
+                got: self.num_params,
+            });
+        }
+        for (name, buffer) in &self.buffers {
+            if !names.contains(&name.as_str()) {
+                return Err(StateError::UnknownBuffer(name.clone()));
+            }
+            if buffer.len() != count {
+                return Err(StateError::ParameterCount {
+                    expected: count,
+                    got: buffer.len(),
+                });
+            }
+        }
+        for (group, &lr) in groups.iter_mut().zip(&self.group_lrs) {
+            group.lr = lr;
+        }
+        Ok(())
+    }
+
+    /// The buffer `name` keyed by parameter; empty if the state has none.
+    fn take_buffer(
+        &mut self,
+        name: &str,
+        groups: &[ParamGroup],
+    ) -> HashMap<Value, Scalar> {
+        match self.buffers.remove(name) {
+            Some(buffer) => all_params(groups).cloned().zip(buffer).collect(),
+            None => HashMap::new(),
+        }
+    }
+}
+
+fn all_params(groups: &[ParamGroup]) -> impl Iterator<Item = &Value> {
+    groups.iter().flat_map(|g| &g.params)
+}
+
+/// An [`OptimState`] that doesn't fit the optimizer it's loaded into.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    GroupCount { expected: usize, got: usize },
+    ParameterCount { expected: usize, got: usize },
+    UnknownBuffer(String),
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::GroupCount { expected, got } => write!(
+                f,
+                "expected state for {} parameter groups, got {}",
+                expected, got
+            ),
+            StateError::ParameterCount { expected, got } => write!(
+                f,
+                "expected state for {} parameters, got {}",
+                expected, got
+            ),
+            StateError::UnknownBuffer(name) => write!(
+                f,
+                "state has a `{}` buffer, which this optimizer doesn't use",
+                name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Gradient noise for [`Sgd::gradient_noise`]: at step `t` (from 0) each
+/// gradient gets `N(0, 1)` noise scaled by `eta / (1 + t)^gamma`, drawn
+/// from an rng seeded with `seed` so runs are reproducible. Neelakantan et
+/// al. suggest `gamma = 0.55`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoiseSchedule {
+    pub eta: Scalar,
+    pub gamma: Scalar,
+    pub seed: u64,
+}
+
+impl NoiseSchedule {
+    pub fn new(eta: Scalar, gamma: Scalar, seed: u64) -> Self {
+        Self { eta, gamma, seed }
+    }
+
+    /// The standard deviation of the noise at step `t`.
+    pub fn std_at(&self, t: u64) -> Scalar {
+        self.eta / (1.0 + t as Scalar).powf(self.gamma)
+    }
+}
+
+/// Stochastic gradient descent, optionally with (Nesterov) momentum:
+///
+/// ```text
+/// v = momentum * v + (1 - dampening) * grad    (v = grad on the first step)
+/// p -= lr * v                                  (plain momentum)
+/// p -= lr * (grad + momentum * v)              (Nesterov)
+/// ```
+///
+/// ```
+/// # use smolgrad::engine::Value;
+/// # use smolgrad::optim::{Optimizer, Sgd};
+/// let params = vec![Value::new(1.0)];
+/// let mut opt = Sgd::new(params, 0.1).momentum(0.9).nesterov(true);
+/// ```
+pub struct Sgd {
+    groups: Vec<ParamGroup>,
+    lr: Scalar,
+    momentum: Scalar,
+    dampening: Scalar,
+    nesterov: bool,
+    weight_decay: Scalar,
+    velocity: HashMap<Value, Scalar>,
+    gradient_noise: Option<(NoiseSchedule, NormalRng)>,
+    t: u64,
+}
+
+impl Sgd {
+    pub fn new(params: Vec<Value>, lr: Scalar) -> Self {
+        Self::with_groups(vec![ParamGroup::new(params)], lr)
+    }
+
+    /// Like `new`, with groups that can override the defaults.
+    pub fn with_groups(groups: Vec<ParamGroup>, lr: Scalar) -> Self {
+        Self {
+            groups,
+            lr,
+            momentum: 0.0,
+            dampening: 0.0,
+            nesterov: false,
+            weight_decay: 0.0,
+            velocity: HashMap::new(),
+            gradient_noise: None,
+            t: 0,
+        }
+    }
+
+    /// How much of the previous update carries into the next; 0 (plain SGD)
+    /// by default.
+    pub fn momentum(self, momentum: Scalar) -> Self {
+        Self { momentum, ..self }
+    }
+
+    /// Scales down each new gradient's share of the velocity.
+    pub fn dampening(self, dampening: Scalar) -> Self {
+        Self { dampening, ..self }
+    }
+
+    /// Looks ahead along the velocity. Needs momentum and no dampening.
+    pub fn nesterov(self, nesterov: bool) -> Self {
+        Self { nesterov, ..self }
+    }
+
+    /// Adds `weight_decay * p` to each gradient at step time; 0 by default.
+    /// Unlike [`l2_penalty`](crate::losses::l2_penalty) this adds nothing
+    /// to the graph, and matches it for `weight_decay = 2 * alpha`.
+    pub fn weight_decay(self, weight_decay: Scalar) -> Self {
+        Self {
+            weight_decay,
+            ..self
+        }
+    }
+
+    /// Adds Gaussian noise drawn from `schedule` to each gradient, before
+    /// momentum. Groups with an lr of 0 are frozen and get none.
+    pub fn gradient_noise(self, schedule: NoiseSchedule) -> Self {
+        let rng = NormalRng::new(schedule.seed);
+        Self {
+            gradient_noise: Some((schedule, rng)),
+            ..self
+        }
+    }
+
+    /// The momentum buffer of `param`, once a step has created it.
+    pub fn velocity(&self, param: &Value) -> Option<Scalar> {
+        self.velocity.get(param).copied()
+    }
+}
+
+impl Optimizer for Sgd {
+    /// Moves every parameter against its gradient.
+    ///
+    /// # Panics
+    ///
+    /// If Nesterov momentum is on without momentum or with dampening.
+    fn step(&mut self) {
+        assert!(
+            !self.nesterov || (self.momentum > 0.0 && self.dampening == 0.0),
+            "Nesterov momentum requires a momentum and zero dampening"
+        );
+        let t = self.t;
+        self.t += 1;
+        for group in &self.groups {
+            let lr = group.lr.unwrap_or(self.lr);
+            let wd = group.weight_decay.unwrap_or(self.weight_decay);
+            for p in &group.params {
+                let mut grad = p.get_grad() + wd * p.get_data();
+                if let Some((schedule, rng)) = &mut self.gradient_noise {
+                    if lr != 0.0 {
+                        grad += schedule.std_at(t) * rng.sample();
+                    }
+                }
+                if self.momentum != 0.0 {
+                    let v = match self.velocity.get(p) {
+                        Some(&v) => {
+                            self.momentum * v + (1.0 - self.dampening) * grad
+                        }
+                        None => grad,
+                    };
+                    self.velocity.insert(p.clone(), v);
+                    grad = if self.nesterov {
+                        grad + self.momentum * v
+                    } else {
+                        v
+                    };
+                }
+                p.add_data(-lr * grad);
+            }
+        }
+    }
+
+    fn lr(&self) -> Scalar {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: Scalar) {
+        self.lr = lr
+    }
+
+    fn param_groups(&self) -> &[ParamGroup] {
+        &self.groups
+    }
+
+    fn param_groups_mut(&mut self) -> &mut [ParamGroup] {
+        &mut self.groups
+    }
+
+    fn state(&self) -> OptimState {
+        let mut state = OptimState::new(self.lr, &self.groups, self.t);
+        if let Some((_, rng)) = &self.gradient_noise {
+            state.noise_draws = rng.draws();
+        }
+        state.buffer("momentum_buffer", &self.velocity, &self.groups)
+    }
+
+    fn load_state(&mut self, mut state: OptimState) -> Result<(), StateError> {
+        state.restore_groups(&mut self.groups, &["momentum_buffer"])?;
+        self.lr = state.lr;
+        self.t = state.steps;
+        if let Some((_, rng)) = &mut self.gradient_noise {
+            rng.seek(state.noise_draws);
+        }
+        self.velocity = state.take_buffer("momentum_buffer", &self.groups);
+        Ok(())
+    }
+}
+
+/// Adam's running averages of each parameter's gradient and squared
+/// gradient, shared by [`Adam`] and [`AdamW`].
+struct Moments {
+    beta1: Scalar,
+    beta2: Scalar,
+    eps: Scalar,
+    t: i32,
+    m: HashMap<Value, Scalar>,
+    v: HashMap<Value, Scalar>,
+}
+
+impl Moments {
+    fn new() -> Self {
+        Self {
+            beta1: 0.9,
+            beta2: 0.999,
+            eps: 1e-8,
+            t: 0,
+            m: HashMap::new(),
+            v: HashMap::new(),
+        }
+    }
+
+    /// Folds `grad` into `p`'s averages and returns the bias-corrected
+    /// `m / (sqrt(v) + eps)`. Call [`Moments::tick`] once per step first.
+    fn update(&mut self, p: &Value, grad: Scalar) -> Scalar {
+        let m = self.m.entry(p.clone()).or_insert(0.0);
+        *m = self.beta1 * *m + (1.0 - self.beta1) * grad;
+        let v = self.v.entry(p.clone()).or_insert(0.0);
+        *v = self.beta2 * *v + (1.0 - self.beta2) * grad * grad;
+        let m_hat = *m / (1.0 - self.beta1.powi(self.t));
+        let v_hat = *v / (1.0 - self.beta2.powi(self.t));
+        m_hat / (v_hat.sqrt() + self.eps)
+    }
+
+    fn tick(&mut self) {
+        self.t += 1;
+    }
+
+    fn state(&self, lr: Scalar, groups: &[ParamGroup]) -> OptimState {
+        OptimState::new(lr, groups, self.t as u64)
+            .buffer("exp_avg", &self.m, groups)
+            .buffer("exp_avg_sq", &self.v, groups)
+    }
+
+    fn load_state(
+        &mut self,
+        mut state: OptimState,
+        groups: &mut [ParamGroup],
+    ) -> Result<(), StateError> {
+        state.restore_groups(groups, &["exp_avg", "exp_avg_sq"])?;
+        self.t = state.steps as i32;
+        self.m = state.take_buffer("exp_avg", groups);
+        self.v = state.take_buffer("exp_avg_sq", groups);
+        Ok(())
+    }
+}
+
+/// Adam, with optional L2 weight decay added to the gradient as in PyTorch.
+/// See [`AdamW`] for decay that bypasses the adaptive scaling.
+pub struct Adam {
+    groups: Vec<ParamGroup>,
+    lr: Scalar,
+    weight_decay: Scalar,
+    moments: Moments,
+}
+
+impl Adam {
+    /// Betas default to `(0.9, 0.999)` and eps to `1e-8`.
+    pub fn new(params: Vec<Value>, lr: Scalar) -> Self {
+        Self::with_groups(vec![ParamGroup::new(params)], lr)
+    }
+
+    /// Like `new`, with groups that can override the defaults.
+    pub fn with_groups(groups: Vec<ParamGroup>, lr: Scalar) -> Self {
+        Self {
+            groups,
+            lr,
+            weight_decay: 0.0,
+            moments: Moments::new(),
+        }
+    }
+
+    pub fn betas(mut self, beta1: Scalar, beta2: Scalar) -> Self {
+        self.moments.beta1 = beta1;
+        self.moments.beta2 = beta2;
+        self
+    }
+
+    pub fn eps(mut self, eps: Scalar) -> Self {
+        self.moments.eps = eps;
+        self
+    }
+
+    /// Adds `weight_decay * p` to each gradient; 0 by default.
+    pub fn weight_decay(self, weight_decay: Scalar) -> Self {
+        Self {
+            weight_decay,
+            ..self
+        }
+    }
+}
+
+impl Optimizer for Adam {
+    fn step(&mut self) {
+        self.moments.tick();
+        for group in &self.groups {
+            let lr = group.lr.unwrap_or(self.lr);
+            let wd = group.weight_decay.unwrap_or(self.weight_decay);
+            for p in &group.params {
+                let grad = p.get_grad() + wd * p.get_data();
+                let update = self.moments.update(p, grad);
+                p.add_data(-lr * update);
+            }
+        }
+    }
+
+    fn lr(&self) -> Scalar {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: Scalar) {
+        self.lr = lr
+    }
+
+    fn param_groups(&self) -> &[ParamGroup] {
+        &self.groups
+    }
+
+    fn param_groups_mut(&mut self) -> &mut [ParamGroup] {
+        &mut self.groups
+    }
+
+    fn state(&self) -> OptimState {
+        self.moments.state(self.lr, &self.groups)
+    }
+
+    fn load_state(&mut self, state: OptimState) -> Result<(), StateError> {
+        let lr = state.lr;
+        self.moments.load_state(state, &mut self.groups)?;
+        self.lr = lr;
+        Ok(())
+    }
+}
+
+/// Adam with decoupled weight decay: each step first shrinks every
+/// parameter by `lr * weight_decay * p`, then takes the Adam step on the
+/// raw gradient.
+pub struct AdamW {
+    groups: Vec<ParamGroup>,
+    lr: Scalar,
+    weight_decay: Scalar,
+    moments: Moments,
+}
+
+impl AdamW {
+    /// Weight decay defaults to 0.01, betas to `(0.9, 0.999)` and eps to
+    /// `1e-8`.
+    pub fn new(params: Vec<Value>, lr: Scalar) -> Self {
+        Self::with_groups(vec![ParamGroup::new(params)], lr)
+    }
+
+    /// Like `new`, with groups that can override the defaults.
+    pub fn with_groups(groups: Vec<ParamGroup>, lr: Scalar) -> Self {
+        Self {
+            groups,
+            lr,
+            weight_decay: 0.01,
+            moments: Moments::new(),
+        }
+    }
+
+    pub fn betas(mut self, beta1: Scalar, beta2: Scalar) -> Self {
+        self.moments.beta1 = beta1;
+        self.moments.beta2 = beta2;
+        self
+    }
+
+    pub fn eps(mut self, eps: Scalar) -> Self {
+        self.moments.eps = eps;
+        self
+    }
+
+    pub fn weight_decay(self, weight_decay: Scalar) -> Self {
+        Self {
+            weight_decay,
+            ..self
+        }
+    }
+}
+
+impl Optimizer for AdamW {
+    fn step(&mut self) {
+        self.moments.tick();
+        for group in &self.groups {
+            let lr = group.lr.unwrap_or(self.lr);
+            let wd = group.weight_decay.unwrap_or(self.weight_decay);
+            for p in &group.params {
+                p.update(|w| w * (1.0 - lr * wd));
+                let update = self.moments.update(p, p.get_grad());
+                p.add_data(-lr * update);
+            }
+        }
+    }
+
+    fn lr(&self) -> Scalar {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: Scalar) {
+        self.lr = lr
+    }
+
+    fn param_groups(&self) -> &[ParamGroup] {
+        &self.groups
+    }
+
+    fn param_groups_mut(&mut self) -> &mut [ParamGroup] {
+        &mut self.groups
+    }
+
+    fn state(&self) -> OptimState {
+        self.moments.state(self.lr, &self.groups)
+    }
+
+    fn load_state(&mut self, state: OptimState) -> Result<(), StateError> {
+        let lr = state.lr;
+        self.moments.load_state(state, &mut self.groups)?;
+        self.lr = lr;
+        Ok(())
+    }
+}
+
+/// Adagrad: scales each step by the inverse square root of the sum of all
+/// the parameter's squared gradients so far,
+///
+/// ```text
+/// sum += grad^2
+/// p -= lr / (1 + (t - 1) * lr_decay) * grad / (sqrt(sum) + eps)
+/// ```
+///
+/// A parameter whose gradient is exactly zero is skipped entirely, so
+/// embedding rows that weren't looked up keep their accumulator at zero.
+pub struct Adagrad {
+    groups: Vec<ParamGroup>,
+    lr: Scalar,
+    lr_decay: Scalar,
+    eps: Scalar,
+    weight_decay: Scalar,
+    t: i32,
+    sum: HashMap<Value, Scalar>,
+}
+
+impl Adagrad {
+    /// eps defaults to `1e-10` and there's no lr or weight decay.
+    pub fn new(params: Vec<Value>, lr: Scalar) -> Self {
+        Self::with_groups(vec![ParamGroup::new(params)], lr)
+    }
+
+    /// Like `new`, with groups that can override the defaults.
+    pub fn with_groups(groups: Vec<ParamGroup>, lr: Scalar) -> Self {
+        Self {
+            groups,
+            lr,
+            lr_decay: 0.0,
+            eps: 1e-10,
+            weight_decay: 0.0,
+            t: 0,
+            sum: HashMap::new(),
+        }
+    }
+
+    pub fn lr_decay(self, lr_decay: Scalar) -> Self {
+        Self { lr_decay, ..self }
+    }
+
+    pub fn eps(self, eps: Scalar) -> Self {
+        Self { eps, ..self }
+    }
+
+    /// Adds `weight_decay * p` to each gradient that isn't zero.
+    pub fn weight_decay(self, weight_decay: Scalar) -> Self {
+        Self {
+            weight_decay,
+            ..self
+        }
+    }
+
+    /// The sum of `param`'s squared gradients so far.
+    pub fn accumulator(&self, param: &Value) -> Scalar {
+        self.sum.get(param).copied().unwrap_or(0.0)
+    }
+}
+
+impl Optimizer for Adagrad {
+    fn step(&mut self) {
+        self.t += 1;
+        let decay = 1.0 + (self.t - 1) as Scalar * self.lr_decay;
+        for group in &self.groups {
+            let lr = group.lr.unwrap_or(self.lr) / decay;
+            let wd = group.weight_decay.unwrap_or(self.weight_decay);
+            for p in &group.params {
+                if p.get_grad() == 0.0 {
+                    continue;
+                }
+                let grad = p.get_grad() + wd * p.get_data();
+                let sum = self.sum.entry(p.clone()).or_insert(0.0);
+                *sum += grad * grad;
+                p.add_data(-lr * grad / (sum.sqrt() + self.eps));
+            }
+        }
+    }
+
+    fn lr(&self) -> Scalar {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: Scalar) {
+        self.lr = lr
+    }
+
+    fn param_groups(&self) -> &[ParamGroup] {
+        &self.groups
+    }
+
+    fn param_groups_mut(&mut self) -> &mut [ParamGroup] {
+        &mut self.groups
+    }
+
+    fn state(&self) -> OptimState {
+        OptimState::new(self.lr, &self.groups, self.t as u64).buffer(
+            "sum",
+            &self.sum,
+            &self.groups,
+        )
+    }
+
+    fn load_state(&mut self, mut state: OptimState) -> Result<(), StateError> {
+        state.restore_groups(&mut self.groups, &["sum"])?;
+        self.lr = state.lr;
+        self.t = state.steps as i32;
+        self.sum = state.take_buffer("sum", &self.groups);
+        Ok(())
+    }
+}
+
+/// RMSprop: divides each step by a running root mean square of the
+/// parameter's gradients,
+///
+/// ```text
+/// sq = alpha * sq + (1 - alpha) * grad^2
+/// p -= lr * grad / (sqrt(sq) + eps)
+/// ```
+pub struct RmsProp {
+    groups: Vec<ParamGroup>,
+    lr: Scalar,
+    alpha: Scalar,
+    eps: Scalar,
+    weight_decay: Scalar,
+    sq: HashMap<Value, Scalar>,
+}
+
+impl RmsProp {
+    /// alpha defaults to 0.99, eps to `1e-8` and weight decay to 0.
+    pub fn new(params: Vec<Value>, lr: Scalar) -> Self {
+        Self::with_groups(vec![ParamGroup::new(params)], lr)
+    }
+
+    /// Like `new`, with groups that can override the defaults.
+    pub fn with_groups(groups: Vec<ParamGroup>, lr: Scalar) -> Self {
+        Self {
+            groups,
+            lr,
+            alpha: 0.99,
+            eps: 1e-8,
+            weight_decay: 0.0,
+            sq: HashMap::new(),
+        }
+    }
+
+    /// How slowly the mean square forgets old gradients.
+    pub fn alpha(self, alpha: Scalar) -> Self {
+        Self { alpha, ..self }
+    }
+
+    pub fn eps(self, eps: Scalar) -> Self {
+        Self { eps, ..self }
+    }
+
+    /// Adds `weight_decay * p` to each gradient.
+    pub fn weight_decay(self, weight_decay: Scalar) -> Self {
+        Self {
+            weight_decay,
+            ..self
+        }
+    }
+}
+
+impl Optimizer for RmsProp {
+    fn step(&mut self) {
+        for group in &self.groups {
+            let lr = group.lr.unwrap_or(self.lr);
+            let wd = group.weight_decay.unwrap_or(self.weight_decay);
+            for p in &group.params {
+                let grad = p.get_grad() + wd * p.get_data();
+                let sq = self.sq.entry(p.clone()).or_insert(0.0);
+                *sq = self.alpha * *sq + (1.0 - self.alpha) * grad * grad;
+                p.add_data(-lr * grad / (sq.sqrt() + self.eps));
+            }
+        }
+    }
+
+    fn lr(&self) -> Scalar {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: Scalar) {
+        self.lr = lr
+    }
+
+    fn param_groups(&self) -> &[ParamGroup] {
+        &self.groups
+    }
+
+    fn param_groups_mut(&mut self) -> &mut [ParamGroup] {
+        &mut self.groups
+    }
+
+    fn state(&self) -> OptimState {
+        OptimState::new(self.lr, &self.groups, 0).buffer(
+            "square_avg",
+            &self.sq,
+            &self.groups,
+        )
+    }
+
+    fn load_state(&mut self, mut state: OptimState) -> Result<(), StateError> {
+        state.restore_groups(&mut self.groups, &["square_avg"])?;
+        self.lr = state.lr;
+        self.sq = state.take_buffer("square_avg", &self.groups);
+        Ok(())
+    }
+}
+
+/// Stochastic gradient Langevin dynamics: SGD plus Gaussian noise scaled
+/// to the lr, so the parameters sample from `exp(-loss / temperature)`
+/// instead of settling in a minimum,
+///
+/// ```text
+/// p -= lr * grad + sqrt(2 * lr * temperature) * N(0, 1)
+/// ```
+///
+/// The noise comes from an rng seeded with `seed`. Groups with an lr of 0
+/// are frozen and get none.
+pub struct Sgld {
+    groups: Vec<ParamGroup>,
+    lr: Scalar,
+    temperature: Scalar,
+    weight_decay: Scalar,
+    rng: NormalRng,
+    t: u64,
+}
+
+impl Sgld {
+    pub fn new(
+        params: Vec<Value>,
+        lr: Scalar,
+        temperature: Scalar,
+        seed: u64,
+    ) -> Self {
+        Self::with_groups(vec![ParamGroup::new(params)], lr, temperature, seed)
+    }
+
+    /// Like `new`, with groups that can override the defaults.
+    pub fn with_groups(
+        groups: Vec<ParamGroup>,
+        lr: Scalar,
+        temperature: Scalar,
+        seed: u64,
+    ) -> Self {
+        Self {
+            groups,
+            lr,
+            temperature,
+            weight_decay: 0.0,
+            rng: NormalRng::new(seed),
+            t: 0,
+        }
+    }
+
+    /// Adds `weight_decay * p` to each gradient; 0 by default.
+    pub fn weight_decay(self, weight_decay: Scalar) -> Self {
+        Self {
+            weight_decay,
+            ..self
+        }
+    }
+
+    pub fn temperature(&self) -> Scalar {
+        self.temperature
+    }
+}
+
+impl Optimizer for Sgld {
+    fn step(&mut self) {
+        self.t += 1;
+        for group in &self.groups {
+            let lr = group.lr.unwrap_or(self.lr);
+            if lr == 0.0 {
+                continue;
+            }
+            let wd = group.weight_decay.unwrap_or(self.weight_decay);
+            let std = (2.0 * lr * self.temperature).sqrt();
+            for p in &group.params {
+                let grad = p.get_grad() + wd * p.get_data();
+                let noise = std * self.rng.sample();
+                p.add_data(-lr * grad - noise);
+            }
+        }
+    }
+
+    fn lr(&self) -> Scalar {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: Scalar) {
+        self.lr = lr
+    }
+
+    fn param_groups(&self) -> &[ParamGroup] {
+        &self.groups
+    }
+
+    fn param_groups_mut(&mut self) -> &mut [ParamGroup] {
+        &mut self.groups
+    }
+
+    fn state(&self) -> OptimState {
+        let mut state = OptimState::new(self.lr, &self.groups, self.t);
+        state.noise_draws = self.rng.draws();
+        state
+    }
+
+    fn load_state(&mut self, state: OptimState) -> Result<(), StateError> {
+        state.restore_groups(&mut self.groups, &[])?;
+        self.lr = state.lr;
+        self.t = state.steps;
+        self.rng.seek(state.noise_draws);
+        Ok(())
+    }
+}
+
+/// Adjusts an optimizer's lr as training goes on.
+///
+/// A schedule is a pure function of the base lr and the number of steps
+/// taken, [`Scheduler::lr_at`]; the provided methods keep count and apply
+/// it. Whether a step is an epoch or a batch is up to the caller: call
+/// [`Scheduler::step`] as often as the schedule should advance.
+///
+/// Only the optimizer's default lr is scheduled; [groups](ParamGroup) with
+/// an lr of their own keep it. The trait is sealed, since the count lives
+/// in bookkeeping only this module's schedulers have.
+pub trait Scheduler: sealed::Tracked {
+    /// The lr after `t` steps, starting from `base_lr`.
+    fn lr_at(&self, base_lr: Scalar, t: usize) -> Scalar;
+
+    /// Takes `opt`'s lr as the base and sets the lr for step 0. [`step`]
+    /// does this itself the first time, which only matters for schedules
+    /// that don't start at the base lr.
+    ///
+    /// [`step`]: Scheduler::step
+    fn init(&mut self, opt: &mut dyn Optimizer) {
+        let base_lr = self.progress_mut().base_lr(opt);
+        let lr = self.lr_at(base_lr, 0);
+        self.progress_mut().last_lr = Some(lr);
+        opt.set_lr(lr);
+    }
+
+    /// Counts one more step and sets `opt`'s lr for it.
+    fn step(&mut self, opt: &mut dyn Optimizer) {
+        let base_lr = self.progress_mut().base_lr(opt);
+        let progress = self.progress_mut();
+        progress.t += 1;
+        let t = progress.t;
+        let lr = self.lr_at(base_lr, t);
+        self.progress_mut().last_lr = Some(lr);
+        opt.set_lr(lr);
+    }
+
+    /// The lr set by the last [`Scheduler::init`] or [`Scheduler::step`].
+    fn get_last_lr(&self) -> Option<Scalar> {
+        self.progress().last_lr
+    }
+
+    /// Steps taken so far.
+    fn steps(&self) -> usize {
+        self.progress().t
+    }
+}
+
+mod sealed {
+    use super::{Optimizer, Scalar};
+
+    /// How far a scheduler has got: the lr it started from and the steps
+    /// it has taken.
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct Progress {
+        base_lr: Option<Scalar>,
+        pub t: usize,
+        pub last_lr: Option<Scalar>,
+    }
+
+    impl Progress {
+        /// The base lr, taken from `opt` the first time.
+        pub fn base_lr(&mut self, opt: &dyn Optimizer) -> Scalar {
+            *self.base_lr.get_or_insert_with(|| opt.lr())
+        }
+    }
+
+    /// Where a [`Scheduler`](super::Scheduler) keeps its [`Progress`].
+    pub trait Tracked {
+        fn progress(&self) -> &Progress;
+
+        fn progress_mut(&mut self) -> &mut Progress;
+    }
+}
+
+/// Multiplies the lr by `gamma` every `step_size` steps.
+pub struct StepLr {
+    step_size: usize,
+    gamma: Scalar,
+    progress: Progress,
+}
+
+impl StepLr {
+    pub fn new(step_size: usize, gamma: Scalar) -> Self {
+        assert!(step_size > 0, "StepLr step_size must be positive");
+        Self {
+            step_size,
+            gamma,
+            progress: Progress::default(),
+        }
+    }
+}
+
+impl Scheduler for StepLr {
+    fn lr_at(&self, base_lr: Scalar, t: usize) -> Scalar {
+        base_lr * self.gamma.powi((t / self.step_size) as i32)
+    }
+}
+
+impl sealed::Tracked for StepLr {
+    fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    fn progress_mut(&mut self) -> &mut Progress {
+        &mut self.progress
+    }
+}
+
+/// Multiplies the lr by `gamma` every step.
+pub struct ExponentialLr {
+    gamma: Scalar,
+    progress: Progress,
+}
+
+impl ExponentialLr {
+    pub fn new(gamma: Scalar) -> Self {
+        Self {
+            gamma,
+            progress: Progress::default(),
+        }
+    }
+}
+
+impl Scheduler for ExponentialLr {
+    fn lr_at(&self, base_lr: Scalar, t: usize) -> Scalar {
+        base_lr * self.gamma.powi(t as i32)
+    }
+}
+
+impl sealed::Tracked for ExponentialLr {
+    fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    fn progress_mut(&mut self) -> &mut Progress {
+        &mut self.progress
+    }
+}
+
+/// Follows half a cosine from the base lr at step 0 down to `eta_min` at
+/// step `t_max`, then back up, as PyTorch's closed form does.
+pub struct CosineAnnealingLr {
+    t_max: usize,
+    eta_min: Scalar,
+    progress: Progress,
+}
+
+impl CosineAnnealingLr {
+    pub fn new(t_max: usize, eta_min: Scalar) -> Self {
+        assert!(t_max > 0, "CosineAnnealingLr t_max must be positive");
+        Self {
+            t_max,
+            eta_min,
+            progress: Progress::default(),
+        }
+    }
+}
+
+impl Scheduler for CosineAnnealingLr {
+    fn lr_at(&self, base_lr: Scalar, t: usize) -> Scalar {
+        let pi = std::f64::consts::PI as Scalar;
+        let cos = (pi * t as Scalar / self.t_max as Scalar).cos();
+        self.eta_min + (base_lr - self.eta_min) * (1.0 + cos) / 2.0
+    }
+}
+
+impl sealed::Tracked for CosineAnnealingLr {
+    fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    fn progress_mut(&mut self) -> &mut Progress {
+        &mut self.progress
+    }
+}
+
+/// Ramps the lr linearly from 0 at step 0 to the base lr at step
+/// `warmup_steps`, then hands over to `inner`, which counts its own steps
+/// from there. Warmup is usually counted in batches; step it accordingly.
+pub struct LinearWarmup {
+    warmup_steps: usize,
+    inner: Box<dyn Scheduler>,
+    progress: Progress,
+}
+
+impl LinearWarmup {
+    pub fn new(warmup_steps: usize, inner: Box<dyn Scheduler>) -> Self {
+        Self {
+            warmup_steps,
+            inner,
+            progress: Progress::default(),
+        }
+    }
+}
+
+impl Scheduler for LinearWarmup {
+    fn lr_at(&self, base_lr: Scalar, t: usize) -> Scalar {
+        if t < self.warmup_steps {
+            base_lr * t as Scalar / self.warmup_steps as Scalar
+        } else {
+            self.inner.lr_at(base_lr, t - self.warmup_steps)
+        }
+    }
+}
+
+impl sealed::Tracked for LinearWarmup {
+    fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    fn progress_mut(&mut self) -> &mut Progress {
+        &mut self.progress
+    }
+}
+
+/// Runs `schedulers` one after another, switching to the next at each
+/// milestone. Each starts counting from 0 at its milestone, from the same
+/// base lr.
+pub struct SequentialScheduler {
+    schedulers: Vec<Box<dyn Scheduler>>,
+    milestones: Vec<usize>,
+    progress: Progress,
+}
+
+impl SequentialScheduler {
+    /// # Panics
+    ///
+    /// Unless there is one milestone fewer than schedulers and they
+    /// increase.
+    pub fn new(
+        schedulers: Vec<Box<dyn Scheduler>>,
+        milestones: Vec<usize>,
+    ) -> Self {
+        assert_eq!(
+            milestones.len() + 1,
+            schedulers.len(),
+            "{} schedulers need {} milestones, got {}",
+            schedulers.len(),
+            schedulers.len().saturating_sub(1),
+            milestones.len()
+        );
+        assert!(
+            milestones.windows(2).all(|m| m[0] < m[1]),
+            "milestones must increase, got {:?}",
+            milestones
+        );
+        Self {
+            schedulers,
+            milestones,
+            progress: Progress::default(),
+        }
+    }
+}
+
+impl Scheduler for SequentialScheduler {
+    fn lr_at(&self, base_lr: Scalar, t: usize) -> Scalar {
+        let i = self.milestones.iter().take_while(|&&m| m <= t).count();
+        let start = if i == 0 { 0 } else { self.milestones[i - 1] };
+        self.schedulers[i].lr_at(base_lr, t - start)
+    }
+}
+
+impl sealed::Tracked for SequentialScheduler {
+    fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    fn progress_mut(&mut self) -> &mut Progress {
+        &mut self.progress
+    }
+}
+
+/// Rescales the gradients of `params` so their global L2 norm is at most
+/// `max_norm`, and returns the norm from before clipping.
+///
+/// Non-finite gradients propagate: if any gradient is NaN or infinite, the
+/// returned norm is too and no gradient is touched, so callers can check
+/// the result and skip the step.
+pub fn clip_grad_norm(params: &[Value], max_norm: Scalar) -> Scalar {
+    const EPS: Scalar = 1e-6;
+    let norm = grad_norm(params);
+    if norm.is_finite() && norm > max_norm {
+        let scale = max_norm / (norm + EPS);
+        for p in params {
+            p.set_grad(p.get_grad() * scale);
+        }
+    }
+    norm
+}
+
+/// Clamps the gradient of each of `params` into `[-clip, clip]`. Call it
+/// between `backward` and `step`. NaN gradients stay NaN, so they still
+/// show up downstream.
+///
+/// # Panics
+///
+/// Unless `clip` is positive.
+pub fn clip_grad_value(params: &[Value], clip: Scalar) {
+    assert!(clip > 0.0, "clip must be positive, got {}", clip);
+    for p in params {
+        p.set_grad(p.get_grad().clamp(-clip, clip));
+    }
+}
+
+/// A way of clipping gradients, to pick declaratively.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClipMode {
+    /// See [`clip_grad_norm`].
+    Norm(Scalar),
+    /// See [`clip_grad_value`].
+    Value(Scalar),
+}
+
+impl ClipMode {
+    pub fn apply(&self, params: &[Value]) {
+        match *self {
+            ClipMode::Norm(max_norm) => {
+                clip_grad_norm(params, max_norm);
+            }
+            ClipMode::Value(clip) => clip_grad_value(params, clip),
+        }
+    }
+}
+
+/// The global L2 norm of the gradients of `params`, without building any
+/// graph.
+pub fn grad_norm(params: &[Value]) -> Scalar {
+    params
+        .iter()
+        .map(|p| p.get_grad().powi(2))
+        .sum::<Scalar>()
+        .sqrt()
+}
+
+/// Summary of the gradients in one group of parameters, from
+/// [`grad_stats`].
+#[derive(Clone, PartialEq)]
+pub struct GradStat {
+    pub name: String,
+    pub count: usize,
+    pub min: Scalar,
+    pub max: Scalar,
+    pub mean_abs: Scalar,
+}
+
+impl GradStat {
+    /// Column titles lined up with the rows printed by `Display`.
+    pub fn header() -> String {
+        format!(
+            "{:<16} {:>6} {:>11} {:>11} {:>11}",
+            "group", "params", "min", "max", "mean |g|"
+        )
+    }
+}
+
+impl Display for GradStat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<16} {:>6} {:>11.4e} {:>11.4e} {:>11.4e}",
+            self.name, self.count, self.min, self.max, self.mean_abs
+        )
+    }
+}
+
+impl Debug for GradStat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Gradient statistics per group of `named` parameters, as returned by
+/// [`Module::named_parameters`](crate::nn::Module::named_parameters).
+/// Parameters are grouped by the part of their name before the first `.`,
+/// which is the layer for an MLP or a Sequential, and groups keep the order
+/// they first appear in.
+///
+/// ```
+/// # use smolgrad::nn::{Activation, Module, MLP};
+/// # use smolgrad::optim::{grad_stats, GradStat};
+/// let mlp = MLP::new(2, &[3, 1], Activation::Tanh);
+/// println!("{}", GradStat::header());
+/// for stat in grad_stats(&mlp.named_parameters()) {
+///     println!("{}", stat);
+/// }
+/// ```
+pub fn grad_stats(named: &[(String, Value)]) -> Vec<GradStat> {
+    let mut stats: Vec<GradStat> = vec![];
+    for (name, p) in named {
+        let group = name.split('.').next().unwrap_or_default();
+        let grad = p.get_grad();
+        match stats.iter_mut().find(|s| s.name == group) {
+            Some(stat) => {
+                stat.count += 1;
+                stat.min = stat.min.min(grad);
+                stat.max = stat.max.max(grad);
+                stat.mean_abs += grad.abs();
+            }
+            None => stats.push(GradStat {
+                name: group.to_string(),
+                count: 1,
+                min: grad,
+                max: grad,
+                mean_abs: grad.abs(),
+            }),
+        }
+    }
+    for stat in &mut stats {
+        stat.mean_abs /= stat.count as Scalar;
+    }
+    stats
+}
+
+/// An exponential moving average of parameter values, for evaluating with
+/// smoothed weights:
+///
+/// ```text
+/// shadow = decay * shadow + (1 - decay) * p
+/// ```
+///
+/// ```
+/// # use smolgrad::engine::Value;
+/// # use smolgrad::optim::Ema;
+/// let params = vec![Value::new(1.0)];
+/// let mut ema = Ema::new(&params, 0.99);
+/// // ... after each optimizer step:
+/// ema.update();
+/// {
+///     let _guard = ema.apply();
+///     // evaluate with the averaged weights
+/// }
+/// // the trained weights are back
+/// ```
+pub struct Ema {
+    params: Vec<Value>,
+    shadow: Vec<Scalar>,
+    decay: Scalar,
+}
+
+impl Ema {
+    /// Starts the average at the current values of `params`.
+    pub fn new(params: &[Value], decay: Scalar) -> Self {
+        Self {
+            params: params.to_vec(),
+            shadow: params.iter().map(Value::get_data).collect(),
+            decay,
+        }
+    }
+
+    /// Folds the current parameter values into the average.
+    pub fn update(&mut self) {
+        for (s, p) in self.shadow.iter_mut().zip(&self.params) {
+            *s = self.decay * *s + (1.0 - self.decay) * p.get_data();
+        }
+    }
+
+    /// Writes the averages into the parameters until the returned guard is
+    /// dropped or [restored](EmaGuard::restore), which puts the original
+    /// values back exactly.
+    pub fn apply(&self) -> EmaGuard<'_> {
+        let saved = self.params.iter().map(Value::get_data).collect();
+        for (p, &s) in self.params.iter().zip(&self.shadow) {
+            p.set_data(s);
+        }
+        EmaGuard { ema: self, saved }
+    }
+
+    /// The averages, in the order the parameters were given.
+    pub fn to_vec(&self) -> Vec<Scalar> {
+        self.shadow.clone()
+    }
+}
+
+/// Keeps an [`Ema`]'s averages in the parameters; see [`Ema::apply`].
+pub struct EmaGuard<'a> {
+    ema: &'a Ema,
+    saved: Vec<Scalar>,
+}
+
+impl EmaGuard<'_> {
+    /// Puts the original values back, same as dropping the guard.
+    pub fn restore(self) {}
+}
+
+impl Drop for EmaGuard<'_> {
+    fn drop(&mut self) {
+        for (p, &x) in self.ema.params.iter().zip(&self.saved) {
+            p.set_data(x);
+        }
+    }
+}
+
+/// Accumulates gradients over several micro-batches before each optimizer
+/// step, for an effective batch too big to build a graph for at once.
+///
+/// Each micro-batch's loss is backpropagated weighted by its number of
+/// samples, and [`finish`](GradAccumulator::finish) divides by the total,
+/// so a step sees the gradient of the mean loss over all the window's
+/// samples, as from one big batch. This holds for a short last window
+/// too.
+///
+/// ```
+/// # use smolgrad::engine::{Scalar, Value};
+/// # use smolgrad::optim::{GradAccumulator, Optimizer, Sgd};
+/// let w = Value::new(1.0);
+/// let mut opt = Sgd::new(vec![w.clone()], 0.1);
+/// let mut acc = GradAccumulator::new(4);
+/// for x in [1.0, 2.0, 3.0, 4.0, 5.0] {
+///     let loss = &w * (x as Scalar);
+///     if acc.accumulate(&mut opt, &loss, 1) {
+///         acc.step(&mut opt);
+///     }
+/// }
+/// // The last window holds one micro-batch
+/// assert_eq!(acc.pending(), 1);
+/// acc.step(&mut opt);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GradAccumulator {
+    steps: usize,
+    pending: usize,
+    samples: usize,
+}
+
+impl GradAccumulator {
+    /// Steps every `steps` micro-batches; 1 steps on every one.
+    ///
+    /// # Panics
+    ///
+    /// If `steps` is 0.
+    pub fn new(steps: usize) -> Self {
+        assert!(steps > 0, "accumulation needs at least one step");
+        Self {
+            steps,
+            pending: 0,
+            samples: 0,
+        }
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// How many micro-batches have been accumulated since the last step.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Backpropagates `loss`, the mean over `samples` samples, on top of
+    /// the grads from the window so far. The first micro-batch of a window
+    /// zeroes the optimizer's grads first. Returns whether the window is
+    /// full, so it's time to step.
+    pub fn accumulate<O: Optimizer + ?Sized>(
+        &mut self,
+        optimizer: &mut O,
+        loss: &Value,
+        samples: usize,
+    ) -> bool {
+        if self.pending == 0 {
+            optimizer.zero_grad();
+        }
+        (loss * samples as Scalar).backward();
+        self.pending += 1;
+        self.samples += samples;
+        self.pending >= self.steps
+    }
+
+    /// Turns the accumulated grads into those of the mean loss over the
+    /// window's samples and starts a new window. Clip after this, then
+    /// step. Returns whether there was anything to finish.
+    pub fn finish<O: Optimizer + ?Sized>(&mut self, optimizer: &O) -> bool {
+        if self.pending == 0 {
+            return false;
+        }
+        let scale = 1.0 / self.samples.max(1) as Scalar;
+        for p in all_params(optimizer.param_groups()) {
+            p.set_grad(p.get_grad() * scale);
+        }
+        self.pending = 0;
+        self.samples = 0;
+        true
+    }
+
+    /// [`finish`](GradAccumulator::finish), then steps the optimizer if
+    /// there was anything pending. Returns whether it stepped.
+    pub fn step<O: Optimizer + ?Sized>(&mut self, optimizer: &mut O) -> bool {
+        let stepped = self.finish(optimizer);
+        if stepped {
+            optimizer.step();
+        }
+        stepped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nn::{Activation, Embedding, Module, MLP};
+
+    /// Loss on `0.5 * (x^2 + 50 y^2)`, an ill-conditioned bowl, after 100
+    /// steps from `(1, 1)`.
+    fn bowl(configure: impl Fn(Sgd) -> Sgd) -> Scalar {
+        let (x, y) = (Value::new(1.0), Value::new(1.0));
+        let mut opt = configure(Sgd::new(vec![x.clone(), y.clone()], 0.01));
+        for _ in 0..100 {
+            opt.zero_grad();
+            let loss = (x.pow(2.0) + y.pow(2.0) * 50.0) * 0.5;
+            loss.backward();
+            opt.step();
+        }
+        0.5 * (x.get_data().powi(2) + 50.0 * y.get_data().powi(2))
+    }
+
+    #[test]
+    fn test_sgd_step() {
+        let p = Value::new(1.0);
+        let mut opt = Sgd::new(vec![p.clone()], 0.1);
+        p.set_grad(2.0);
+        opt.step();
+        assert_eq!(p.get_data(), 0.8);
+        assert_eq!(opt.velocity(&p), None);
+        opt.zero_grad();
+        assert_eq!(p.get_grad(), 0.0);
+    }
+
+    #[test]
+    fn test_momentum_is_faster() {
+        let plain = bowl(|opt| opt);
+        let momentum = bowl(|opt| opt.momentum(0.9));
+        let nesterov = bowl(|opt| opt.momentum(0.9).nesterov(true));
+        assert!(momentum < plain / 10.0, "{} vs {}", momentum, plain);
+        assert!(nesterov < plain / 10.0, "{} vs {}", nesterov, plain);
+    }
+
+    #[test]
+    fn test_velocity_persists() {
+        let p = Value::new(0.0);
+        let mut opt =
+            Sgd::new(vec![p.clone()], 1.0).momentum(0.5).dampening(0.5);
+        p.set_grad(1.0);
+        opt.step();
+        assert_eq!(opt.velocity(&p), Some(1.0));
+        opt.step();
+        // 0.5 * 1.0 + (1 - 0.5) * 1.0
+        assert_eq!(opt.velocity(&p), Some(1.0));
+        p.set_grad(0.0);
+        opt.step();
+        assert_eq!(opt.velocity(&p), Some(0.5));
+        assert_eq!(p.get_data(), -2.5);
+
+        let q = Value::new(0.0);
+        let mut opt =
+            Sgd::new(vec![q.clone()], 1.0).momentum(0.5).nesterov(true);
+        q.set_grad(1.0);
+        opt.step();
+        opt.step();
+        // v = 1 then 1.5; steps of 1 + 0.5 and 1 + 0.75
+        assert_eq!(opt.velocity(&q), Some(1.5));
+        assert_eq!(q.get_data(), -3.25);
+    }
+
+    #[test]
+    #[should_panic(expected = "requires a momentum and zero dampening")]
+    fn test_nesterov_rejects_dampening() {
+        let p = Value::new(0.0);
+        let mut opt = Sgd::new(vec![p], 0.1)
+            .momentum(0.9)
+            .dampening(0.1)
+            .nesterov(true);
+        opt.step();
+    }
+
+    #[test]
+    fn test_adam_step() {
+        // the first bias-corrected step is lr * sign(grad)
+        let p = Value::new(1.0);
+        let mut opt = Adam::new(vec![p.clone()], 0.1);
+        p.set_grad(-3.0);
+        opt.step();
+        assert!((p.get_data() - 1.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_adamw_decay_without_grads() {
+        let p = Value::new(2.0);
+        let mut opt = AdamW::new(vec![p.clone()], 0.1).weight_decay(0.5);
+        for t in 1..=10 {
+            opt.zero_grad();
+            opt.step();
+            let expected = 2.0 * (0.95 as Scalar).powi(t);
+            assert!((p.get_data() - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_adamw_without_decay_is_adam() {
+        let (a, b) = (Value::new(0.5), Value::new(0.5));
+        let mut adam = Adam::new(vec![a.clone()], 0.05).betas(0.8, 0.99);
+        let mut adamw = AdamW::new(vec![b.clone()], 0.05)
+            .betas(0.8, 0.99)
+            .weight_decay(0.0);
+        for &grad in &[1.0, -0.5, 0.25, 2.0, 0.0, -1.5] {
+            a.set_grad(grad);
+            b.set_grad(grad);
+            adam.step();
+            adamw.step();
+            assert_eq!(a.get_data(), b.get_data());
+        }
+    }
+
+    /// The standard deviation of `p`'s per-step moves under `opt` with a zero
+    /// gradient, each divided by `scale(t)`.
+    fn noise_std(
+        mut opt: impl Optimizer,
+        p: &Value,
+        scale: impl Fn(u64) -> Scalar,
+    ) -> Scalar {
+        let n = 20_000;
+        let mut sq = 0.0;
+        for t in 0..n {
+            let before = p.get_data();
+            opt.step();
+            sq += ((p.get_data() - before) / scale(t)).powi(2);
+        }
+        (sq / n as Scalar).sqrt()
+    }
+
+    #[test]
+    fn test_sgd_gradient_noise() {
+        let schedule = NoiseSchedule::new(0.3, 0.55, 7);
+        assert_eq!(schedule.std_at(0), 0.3);
+        let p = Value::new(0.0);
+        let opt = Sgd::new(vec![p.clone()], 0.5).gradient_noise(schedule);
+        let std = noise_std(opt, &p, |t| 0.5 * schedule.std_at(t));
+        assert!((0.97..1.03).contains(&std), "{}", std);
+
+        let run = |seed| {
+            let q = Value::new(1.0);
+            let schedule = NoiseSchedule::new(0.1, 0.55, seed);
+            let mut opt =
+                Sgd::new(vec![q.clone()], 0.1).gradient_noise(schedule);
+            for _ in 0..5 {
+                q.set_grad(1.0);
+                opt.step();
+            }
+            q.get_data()
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+
+    #[test]
+    fn test_sgld_zero_temperature_is_sgd() {
+        let (a, b) = (Value::new(0.5), Value::new(0.5));
+        let mut sgd = Sgd::new(vec![a.clone()], 0.05);
+        let mut sgld = Sgld::new(vec![b.clone()], 0.05, 0.0, 3);
+        for &grad in &[1.0, -0.5, 0.25, 2.0, 0.0, -1.5] {
+            a.set_grad(grad);
+            b.set_grad(grad);
+            sgd.step();
+            sgld.step();
+            assert_eq!(a.get_data(), b.get_data());
+        }
+    }
+
+    #[test]
+    fn test_sgld_noise_std() {
+        let p = Value::new(0.0);
+        let opt = Sgld::new(vec![p.clone()], 0.01, 0.5, 11);
+        let std = noise_std(opt, &p, |_| 1.0);
+        let expected = (2.0 * 0.01 * 0.5 as Scalar).sqrt();
+        assert!((std / expected - 1.0).abs() < 0.03, "{}", std);
+    }
+
+    #[test]
+    fn test_noise_skips_frozen_params() {
+        let moving = Value::new(1.0);
+        let frozen = Value::new(2.0);
+        let groups = || {
+            vec![
+                ParamGroup::new(vec![moving.clone()]),
+                ParamGroup::new(vec![frozen.clone()]).lr(0.0),
+            ]
+        };
+        let noise = NoiseSchedule::new(1.0, 0.55, 0);
+        let mut sgd = Sgd::with_groups(groups(), 0.1).gradient_noise(noise);
+        let mut sgld = Sgld::with_groups(groups(), 0.1, 1.0, 0);
+        for _ in 0..10 {
+            sgd.step();
+            sgld.step();
+        }
+        assert_ne!(moving.get_data(), 1.0);
+        assert_eq!(frozen.get_data(), 2.0);
+    }
+
+    #[test]
+    fn test_adagrad_converges() {
+        let x = Value::new(0.0);
+        let mut opt = Adagrad::new(vec![x.clone()], 0.5);
+        for _ in 0..200 {
+            opt.zero_grad();
+            (x.clone() + -3.0).pow(2.0).backward();
+            opt.step();
+        }
+        assert!((x.get_data() - 3.0).abs() < 1e-3, "{}", x.get_data());
+    }
+
+    #[test]
+    fn test_adagrad_lr_decay() {
+        let p = Value::new(0.0);
+        let mut opt = Adagrad::new(vec![p.clone()], 1.0).lr_decay(1.0);
+        p.set_grad(1.0);
+        opt.step();
+        opt.step();
+        // lr 1 then 1/2; sum 1 then 2
+        let expected = -1.0 - 0.5 / (2.0 as Scalar).sqrt();
+        assert!((p.get_data() - expected).abs() < 1e-6);
+        assert_eq!(opt.accumulator(&p), 2.0);
+    }
+
+    #[test]
+    fn test_adagrad_skips_unused_embeddings() {
+        let emb = Embedding::new(5, 2);
+        let before = emb.get_parameters_flat();
+        let mut opt = Adagrad::new(emb.parameters(), 0.1);
+        for _ in 0..3 {
+            opt.zero_grad();
+            let out = emb.forward_indices(&[1, 3]);
+            out.iter()
+                .fold(Value::new(0.0), |acc, v| acc + v.pow(2.0))
+                .backward();
+            opt.step();
+        }
+        let after = emb.get_parameters_flat();
+        for (i, p) in emb.parameters().iter().enumerate() {
+            let row = i / 2;
+            if row == 1 || row == 3 {
+                assert!(opt.accumulator(p) > 0.0);
+                assert_ne!(after[i], before[i]);
+            } else {
+                assert_eq!(opt.accumulator(p), 0.0);
+                assert_eq!(after[i], before[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rmsprop_step() {
+        let p = Value::new(1.0);
+        let mut opt = RmsProp::new(vec![p.clone()], 0.01).alpha(0.75);
+        p.set_grad(2.0);
+        opt.step();
+        // sq = 0.25 * 4, so the step is lr * 2 / 1
+        assert!((p.get_data() - 0.98).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_dyn_optimizer() {
+        let make = |name: &str, params: Vec<Value>| -> Box<dyn Optimizer> {
+            match name {
+                "sgd" => Box::new(Sgd::new(params, 0.05).momentum(0.9)),
+                "adam" => Box::new(Adam::new(params, 0.01)),
+                "rmsprop" => Box::new(RmsProp::new(params, 0.01)),
+                _ => Box::new(Adagrad::new(params, 0.1)),
+            }
+        };
+        let data: [([Scalar; 2], Scalar); 4] = [
+            ([0.0, 1.0], 1.0),
+            ([1.0, 0.0], -1.0),
+            ([0.5, 0.5], 0.0),
+            ([1.0, 1.0], 0.0),
+        ];
+        for name in &["sgd", "adam", "rmsprop", "adagrad"] {
+            let model = MLP::new_seeded(2, &[8, 1], Activation::Tanh, 3);
+            let mut opt = make(name, model.parameters());
+            let mut losses = vec![];
+            for _ in 0..50 {
+                opt.zero_grad();
+                let mut loss = Value::new(0.0);
+                for (x, y) in &data {
+                    let x: Vec<_> = x.iter().map(|&x| Value::new(x)).collect();
+                    loss = loss + (model.call(&x)[0].clone() + -y).pow(2.0);
+                }
+                loss.backward();
+                opt.step();
+                losses.push(loss.get_data());
+            }
+            let (first, last) = (losses[0], losses[losses.len() - 1]);
+            assert!(last < first / 4.0, "{}: {} -> {}", name, first, last);
+
+            opt.set_lr(0.5);
+            assert_eq!(opt.lr(), 0.5);
+        }
+    }
+
+    fn record(scheduler: &mut dyn Scheduler, epochs: usize) -> Vec<Scalar> {
+        record_from(scheduler, &mut Sgd::new(vec![], 0.1), epochs)
+    }
+
+    /// The lr after each of `steps` steps.
+    fn record_from(
+        scheduler: &mut dyn Scheduler,
+        opt: &mut dyn Optimizer,
+        steps: usize,
+    ) -> Vec<Scalar> {
+        (0..steps)
+            .map(|_| {
+                scheduler.step(opt);
+                assert_eq!(scheduler.get_last_lr(), Some(opt.lr()));
+                opt.lr()
+            })
+            .collect()
+    }
+
+    fn assert_lrs(actual: &[Scalar], expected: &[Scalar]) {
+        assert_eq!(actual.len(), expected.len());
+        for (t, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!((a - e).abs() < 1e-6, "step {}: {} != {}", t + 1, a, e);
+        }
+    }
+
+    #[test]
+    fn test_step_lr() {
+        let expected = [
+            0.1, 0.1, 0.1, 0.1, 0.05, 0.05, 0.05, 0.05, 0.05, 0.025, 0.025,
+            0.025, 0.025, 0.025, 0.0125, 0.0125, 0.0125, 0.0125, 0.0125,
+            0.00625,
+        ];
+        assert_lrs(&record(&mut StepLr::new(5, 0.5), 20), &expected);
+    }
+
+    #[test]
+    fn test_exponential_lr() {
+        let expected = [
+            0.09, 0.081, 0.0729, 0.06561, 0.059049, 0.0531441, 0.04782969,
+            0.04304672, 0.03874205, 0.03486784, 0.03138106, 0.02824295,
+            0.02541866, 0.02287679, 0.02058911, 0.0185302, 0.01667718,
+            0.01500946, 0.01350852, 0.01215767,
+        ];
+        assert_lrs(&record(&mut ExponentialLr::new(0.9), 20), &expected);
+    }
+
+    #[test]
+    fn test_cosine_annealing_lr() {
+        let expected = [
+            0.0975773,
+            0.09054634,
+            0.07959537,
+            0.06579634,
+            0.0505,
+            0.03520366,
+            0.02140463,
+            0.01045366,
+            0.003422702,
+            0.001,
+            0.003422702,
+            0.01045366,
+            0.02140463,
+            0.03520366,
+            0.0505,
+            0.06579634,
+            0.07959537,
+            0.09054634,
+            0.0975773,
+            0.1,
+        ];
+        let mut cosine = CosineAnnealingLr::new(10, 0.001);
+        assert_lrs(&record(&mut cosine, 20), &expected);
+        // the ends of the half cosine are exact
+        assert_eq!(cosine.lr_at(0.1, 0), 0.1);
+        assert_eq!(cosine.lr_at(0.1, 10), 0.001);
+        assert_eq!(cosine.steps(), 20);
+    }
+
+    #[test]
+    fn test_scheduler_init() {
+        let mut opt = Sgd::new(vec![], 0.1);
+        let mut cosine = CosineAnnealingLr::new(4, 0.0);
+        assert_eq!(cosine.get_last_lr(), None);
+        cosine.init(&mut opt);
+        assert_eq!(cosine.get_last_lr(), Some(0.1));
+        cosine.step(&mut opt);
+        // the base is still the lr at init, not the one step 1 set
+        cosine.step(&mut opt);
+        assert!((opt.lr() - 0.05).abs() < 1e-7);
+    }
+
+    #[test]
+    fn test_linear_warmup() {
+        let cosine = CosineAnnealingLr::new(10, 0.0);
+        let expected: Vec<_> = (0..=10).map(|t| cosine.lr_at(1.0, t)).collect();
+        let mut warmup = LinearWarmup::new(4, Box::new(cosine));
+        let mut opt = Sgd::new(vec![], 1.0);
+        warmup.init(&mut opt);
+        let mut lrs = vec![opt.lr()];
+        lrs.extend(record_from(&mut warmup, &mut opt, 14));
+        assert_eq!(lrs[..5], [0.0, 0.25, 0.5, 0.75, 1.0]);
+        assert_eq!(lrs[4..], expected[..]);
+    }
+
+    #[test]
+    fn test_sequential_scheduler() {
+        let parts = || -> Vec<Box<dyn Scheduler>> {
+            vec![
+                Box::new(ExponentialLr::new(0.5)),
+                Box::new(StepLr::new(2, 0.1)),
+                Box::new(CosineAnnealingLr::new(4, 0.0)),
+            ]
+        };
+        let mut chain = SequentialScheduler::new(parts(), vec![3, 7]);
+        let mut opt = Sgd::new(vec![], 1.0);
+        let lrs = record_from(&mut chain, &mut opt, 10);
+        let [exp, step, cosine] = [0, 1, 2].map(|i| parts().remove(i));
+        let expected = [
+            exp.lr_at(1.0, 1),
+            exp.lr_at(1.0, 2),
+            step.lr_at(1.0, 0),
+            step.lr_at(1.0, 1),
+            step.lr_at(1.0, 2),
+            step.lr_at(1.0, 3),
+            cosine.lr_at(1.0, 0),
+            cosine.lr_at(1.0, 1),
+            cosine.lr_at(1.0, 2),
+            cosine.lr_at(1.0, 3),
+        ];
+        assert_eq!(lrs, expected);
+        assert_eq!(lrs[..4], [0.5, 0.25, 1.0, 1.0]);
+        assert_eq!(lrs[5], 0.1 as Scalar);
+    }
+
+    #[test]
+    #[should_panic(expected = "3 schedulers need 2 milestones, got 1")]
+    fn test_sequential_scheduler_milestones() {
+        let parts: Vec<Box<dyn Scheduler>> = vec![
+            Box::new(ExponentialLr::new(0.5)),
+            Box::new(ExponentialLr::new(0.5)),
+            Box::new(ExponentialLr::new(0.5)),
+        ];
+        SequentialScheduler::new(parts, vec![3]);
+    }
+
+    #[test]
+    fn test_clip_grad_norm() {
+        let params: Vec<_> = (0..2).map(|_| Value::new(0.0)).collect();
+        params[0].set_grad(3.0);
+        params[1].set_grad(-4.0);
+        assert_eq!(clip_grad_norm(&params, 1.0), 5.0);
+        assert!((params[0].get_grad() - 0.6).abs() < 1e-6);
+        assert!((params[1].get_grad() + 0.8).abs() < 1e-6);
+        let clipped: Scalar = params.iter().map(|p| p.get_grad().powi(2)).sum();
+        assert!((clipped.sqrt() - 1.0).abs() < 1e-6);
+
+        // Under the limit, nothing changes
+        let grads: [Scalar; 2] = [0.1, -0.3];
+        for (p, &g) in params.iter().zip(&grads) {
+            p.set_grad(g);
+        }
+        clip_grad_norm(&params, 1.0);
+        for (p, &g) in params.iter().zip(&grads) {
+            assert_eq!(p.get_grad().to_bits(), g.to_bits());
+        }
+    }
+
+    #[test]
+    fn test_clip_grad_norm_degenerate() {
+        let params: Vec<_> = (0..2).map(|_| Value::new(0.0)).collect();
+        assert_eq!(clip_grad_norm(&params, 0.0), 0.0);
+        assert_eq!(params[0].get_grad(), 0.0);
+
+        params[0].set_grad(Scalar::NAN);
+        params[1].set_grad(2.0);
+        assert!(clip_grad_norm(&params, 1.0).is_nan());
+        assert_eq!(params[1].get_grad(), 2.0);
+        params[0].set_grad(Scalar::INFINITY);
+        assert_eq!(clip_grad_norm(&params, 1.0), Scalar::INFINITY);
+        assert_eq!(params[1].get_grad(), 2.0);
+    }
+
+    #[test]
+    fn test_clip_grad_value() {
+        let grads: [Scalar; 5] = [5.0, -2.5, 0.5, -1.0, Scalar::NAN];
+        let params: Vec<_> = grads
+            .iter()
+            .map(|&g| {
+                let p = Value::new(0.0);
+                p.set_grad(g);
+                p
+            })
+            .collect();
+        clip_grad_value(&params, 1.0);
+        let clipped: Vec<_> = params.iter().map(Value::get_grad).collect();
+        assert_eq!(clipped[..4], [1.0, -1.0, 0.5, -1.0]);
+        assert!(clipped[4].is_nan());
+        clip_grad_value(&[], 1.0);
+
+        params[0].set_grad(3.0);
+        params[1].set_grad(4.0);
+        ClipMode::Norm(1.0).apply(&params[..2]);
+        assert!((params[1].get_grad() - 0.8).abs() < 1e-6);
+        ClipMode::Value(0.5).apply(&params[..2]);
+        assert_eq!(params[1].get_grad(), 0.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "clip must be positive")]
+    fn test_clip_grad_value_positive() {
+        clip_grad_value(&[], 0.0);
+    }
+
+    #[test]
+    fn test_grad_norm() {
+        let params: Vec<_> = (0..3).map(|_| Value::new(1.0)).collect();
+        assert_eq!(grad_norm(&params), 0.0);
+        for (p, &g) in params.iter().zip(&[2.0, -1.0, 2.0]) {
+            p.set_grad(g);
+        }
+        assert_eq!(grad_norm(&params), 3.0);
+        assert_eq!(grad_norm(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_grad_stats() {
+        let mlp = MLP::new(2, &[2, 1], Activation::Tanh);
+        let named = mlp.named_parameters();
+        for (i, (_, p)) in named.iter().enumerate().take(6) {
+            p.set_grad(if i % 2 == 0 { 0.5 } else { -1.0 });
+        }
+        let stats = grad_stats(&named);
+        let names: Vec<_> = stats.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["layer0", "layer1"]);
+        let first = &stats[0];
+        assert_eq!(first.count, 6);
+        assert_eq!((first.min, first.max, first.mean_abs), (-1.0, 0.5, 0.75));
+        // layer1 took no part in a backward pass, like a frozen layer
+        let frozen = &stats[1];
+        assert_eq!(frozen.count, 3);
+        assert_eq!((frozen.min, frozen.max, frozen.mean_abs), (0.0, 0.0, 0.0));
+
+        let table = format!("{}\n{}", GradStat::header(), stats[0]);
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines[0].len(), lines[1].len());
+        assert!(lines[1].starts_with("layer0"));
+    }
+
+    #[test]
+    fn test_ema_decay() {
+        let params = vec![Value::new(1.0), Value::new(-2.0)];
+        let mut latest = Ema::new(&params, 0.0);
+        let mut frozen = Ema::new(&params, 1.0);
+        let mut half = Ema::new(&params, 0.5);
+        for (p, &x) in params.iter().zip(&[3.0, 0.5]) {
+            p.set_data(x);
+        }
+        for ema in [&mut latest, &mut frozen, &mut half] {
+            ema.update();
+        }
+        assert_eq!(latest.to_vec(), [3.0, 0.5]);
+        assert_eq!(frozen.to_vec(), [1.0, -2.0]);
+        assert_eq!(half.to_vec(), [2.0, -0.75]);
+    }
+
+    #[test]
+    fn test_ema_apply_restore() {
+        let params = vec![Value::new(0.1), Value::new(-1.0 / 3.0)];
+        let mut ema = Ema::new(&params, 0.9);
+        let originals: Vec<_> = params.iter().map(Value::get_data).collect();
+        params[0].set_data(0.7);
+        ema.update();
+        let trained: Vec<_> = params.iter().map(Value::get_data).collect();
+        {
+            let _guard = ema.apply();
+            let applied: Vec<_> = params.iter().map(Value::get_data).collect();
+            assert_eq!(applied, ema.to_vec());
+            assert_eq!(applied[1], originals[1]);
+        }
+        let bits = |v: &[Scalar]| -> Vec<_> {
+            v.iter().map(|x| x.to_bits()).collect()
+        };
+        let restored: Vec<_> = params.iter().map(Value::get_data).collect();
+        assert_eq!(bits(&restored), bits(&trained));
+
+        ema.apply().restore();
+        let restored: Vec<_> = params.iter().map(Value::get_data).collect();
+        assert_eq!(bits(&restored), bits(&trained));
+    }
+
+    #[test]
+    fn test_param_groups_lr() {
+        let moving = vec![Value::new(1.0), Value::new(2.0)];
+        let frozen = vec![Value::new(3.0)];
+        let groups = vec![
+            ParamGroup::new(moving.clone()).lr(0.1),
+            ParamGroup::new(frozen.clone()).lr(0.0),
+        ];
+        let mut opt = Sgd::with_groups(groups, 0.5);
+        for p in moving.iter().chain(&frozen) {
+            p.set_grad(1.0);
+        }
+        opt.step();
+        assert_eq!(moving[0].get_data(), 0.9);
+        assert_eq!(frozen[0].get_data(), 3.0);
+
+        opt.set_group_lr(Some(1), 1.0);
+        opt.set_group_lr(None, 0.2);
+        opt.step();
+        assert_eq!(frozen[0].get_data(), 2.0);
+        assert_eq!(opt.lr(), 0.2);
+        opt.zero_grad();
+        assert!(moving.iter().chain(&frozen).all(|p| p.get_grad() == 0.0));
+    }
+
+    #[test]
+    fn test_param_groups_weight_decay() {
+        let mlp = MLP::new(2, &[2, 1], Activation::Tanh);
+        let named = mlp.named_parameters();
+        let (biases, weights) =
+            split_params(&named, |name| name.ends_with(".b"));
+        assert_eq!(biases.len(), 3);
+        assert_eq!(weights.len(), 6);
+        for b in &biases {
+            b.set_data(1.0);
+        }
+        let before: Vec<_> = weights.iter().map(Value::get_data).collect();
+        let groups = vec![
+            ParamGroup::new(weights.clone()),
+            ParamGroup::new(biases.clone()).weight_decay(0.0),
+        ];
+        let optimizers: Vec<Box<dyn Optimizer>> = vec![
+            Box::new(Sgd::with_groups(groups.clone(), 0.1).weight_decay(0.5)),
+            Box::new(AdamW::with_groups(groups, 0.1).weight_decay(0.5)),
+        ];
+        for mut opt in optimizers {
+            opt.zero_grad();
+            opt.step();
+        }
+        assert!(biases.iter().all(|b| b.get_data() == 1.0));
+        for (w, before) in weights.iter().zip(before) {
+            assert!(w.get_data().abs() < before.abs() || before == 0.0);
+        }
+    }
+
+    /// Trains `model` for `steps` full-batch steps on a fixed problem.
+    fn train(model: &MLP, opt: &mut dyn Optimizer, steps: usize) {
+        let data: [([Scalar; 2], Scalar); 3] =
+            [([0.0, 1.0], 1.0), ([1.0, 0.0], -1.0), ([0.5, 0.5], 0.0)];
+        for _ in 0..steps {
+            opt.zero_grad();
+            let mut loss = Value::new(0.0);
+            for (x, y) in &data {
+                let x: Vec<_> = x.iter().map(|&x| Value::new(x)).collect();
+                loss = loss + (model.call(&x)[0].clone() + -y).pow(2.0);
+            }
+            loss.backward();
+            opt.step();
+        }
+    }
+
+    #[test]
+    fn test_optimizer_state_resume() {
+        let make = |name: &str, params: Vec<Value>| -> Box<dyn Optimizer> {
+            match name {
+                "sgd" => Box::new(
+                    Sgd::new(params, 0.05).momentum(0.9).dampening(0.1),
+                ),
+                "adam" => Box::new(Adam::new(params, 0.01)),
+                "adamw" => Box::new(AdamW::new(params, 0.01)),
+                "rmsprop" => Box::new(RmsProp::new(params, 0.01)),
+                "sgd_noise" => Box::new(
+                    Sgd::new(params, 0.05)
+                        .gradient_noise(NoiseSchedule::new(0.1, 0.55, 3)),
+                ),
+                "sgld" => Box::new(Sgld::new(params, 0.01, 1e-4, 3)),
+                _ => Box::new(Adagrad::new(params, 0.1).lr_decay(0.01)),
+            }
+        };
+        let names = [
+            "sgd",
+            "adam",
+            "adamw",
+            "rmsprop",
+            "sgd_noise",
+            "sgld",
+            "adagrad",
+        ];
+        for name in &names {
+            let straight = MLP::new_seeded(2, &[4, 1], Activation::Tanh, 5);
+            let mut opt = make(name, straight.parameters());
+            train(&straight, opt.as_mut(), 100);
+
+            let first = MLP::new_seeded(2, &[4, 1], Activation::Tanh, 5);
+            let mut opt = make(name, first.parameters());
+            train(&first, opt.as_mut(), 50);
+            let (weights, state) = (first.get_parameters_flat(), opt.state());
+
+            let resumed = MLP::new_seeded(2, &[4, 1], Activation::Tanh, 6);
+            resumed.set_parameters_flat(&weights).unwrap();
+            let mut opt = make(name, resumed.parameters());
+            opt.load_state(state).unwrap();
+            train(&resumed, opt.as_mut(), 50);
+
+            let pairs = straight
+                .get_parameters_flat()
+                .into_iter()
+                .zip(resumed.get_parameters_flat());
+            for (a, b) in pairs {
+                assert!((a - b).abs() < 1e-6, "{}: {} vs {}", name, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_optimizer_state_mismatch() {
+        let model = MLP::new(2, &[2, 1], Activation::Tanh);
+        let mut adam = Adam::new(model.parameters(), 0.01);
+        train(&model, &mut adam, 1);
+        let state = adam.state();
+        assert_eq!(state.steps, 1);
+        assert_eq!(state.buffers["exp_avg"].len(), 9);
+
+        let params = model.parameters()[..5].to_vec();
+        let mut smaller = Adam::new(params.clone(), 0.01);
+        assert_eq!(
+            smaller.load_state(state.clone()),
+            Err(StateError::ParameterCount {
+                expected: 5,
+                got: 9
+            })
+        );
+        let groups = vec![ParamGroup::new(params.clone()); 2];
+        let mut grouped = Adam::with_groups(groups, 0.01);
+        assert_eq!(
+            grouped.load_state(state.clone()),
+            Err(StateError::GroupCount {
+                expected: 2,
+                got: 1
+            })
+        );
+        let mut sgd = Sgd::new(model.parameters(), 0.1);
+        let err = sgd.load_state(state).unwrap_err();
+        assert_eq!(err, StateError::UnknownBuffer("exp_avg".to_string()));
+        assert_eq!(
+            err.to_string(),
+            "state has a `exp_avg` buffer, which this optimizer doesn't use"
+        );
+    }
+
+    #[test]
+    fn test_optimizer_state_without_buffers_mismatch() {
+        let params: Vec<_> = (0..3).map(|_| Value::new(1.0)).collect();
+        let fewer = params[..2].to_vec();
+        let mismatch = Err(StateError::ParameterCount {
+            expected: 2,
+            got: 3,
+        });
+
+        // A plain Sgd keeps no buffers, before or after stepping
+        let mut sgd = Sgd::new(params.clone(), 0.1);
+        let fresh = sgd.state();
+        sgd.step();
+        let stepped = sgd.state();
+        assert!(stepped.buffers.is_empty());
+        for state in [fresh, stepped] {
+            assert_eq!(
+                Sgd::new(fewer.clone(), 0.1).load_state(state),
+                mismatch
+            );
+        }
+        let mut sgld = Sgld::new(params, 0.1, 1.0, 0);
+        sgld.step();
+        let state = sgld.state();
+        assert_eq!(state.num_params, 3);
+        assert_eq!((state.steps, state.noise_draws), (1, 3));
+        assert_eq!(Sgld::new(fewer, 0.1, 1.0, 0).load_state(state), mismatch);
+    }
+
+    #[test]
+    fn test_grad_accumulator_averages_by_samples() {
+        let w = Value::new(1.0);
+        let mut opt = Sgd::new(vec![w.clone()], 1.0);
+        let mut acc = GradAccumulator::new(2);
+        // mean-loss gradients 2 over 3 samples and 4 over 1 sample
+        assert!(!acc.accumulate(&mut opt, &(&w * 2.0), 3));
+        assert_eq!(acc.pending(), 1);
+        assert!(acc.accumulate(&mut opt, &(&w * 4.0), 1));
+        assert!(acc.step(&mut opt));
+        assert_eq!(acc.pending(), 0);
+        assert!((w.get_data() - (1.0 - 2.5)).abs() < 1e-6);
+        assert!(!acc.step(&mut opt));
+    }
+
+    #[test]
+    #[should_panic(expected = "accumulation needs at least one step")]
+    fn test_grad_accumulator_needs_steps() {
+        GradAccumulator::new(0);
+    }
+}
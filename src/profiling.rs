@@ -0,0 +1,145 @@
+//! Opt-in counters for graph construction and backward time per op.
+//!
+//! Nothing is recorded until [`enable`] is called; while disabled the only
+//! cost is a relaxed load of a global flag per node. The counters themselves
+//! are thread-local, so each thread reports only the graphs it built.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static STATS: RefCell<BTreeMap<&'static str, OpProfile>> =
+        const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Starts recording on every thread.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed)
+}
+
+/// Stops recording; counters collected so far are kept.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed)
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Clears the current thread's counters.
+pub fn reset() {
+    STATS.with(|stats| stats.borrow_mut().clear())
+}
+
+/// Snapshots the current thread's counters.
+pub fn report() -> ProfileReport {
+    STATS.with(|stats| ProfileReport {
+        ops: stats.borrow().values().cloned().collect(),
+    })
+}
+
+fn with_op(op: &'static str, f: impl FnOnce(&mut OpProfile)) {
+    STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        f(stats.entry(op).or_insert(OpProfile {
+            op,
+            nodes: 0,
+            backward_time: Duration::ZERO,
+        }))
+    })
+}
+
+pub(crate) fn record_node(op: &'static str) {
+    with_op(op, |profile| profile.nodes += 1)
+}
+
+pub(crate) fn record_backward(op: &'static str, elapsed: Duration) {
+    with_op(op, |profile| profile.backward_time += elapsed)
+}
+
+/// Counters for one kind of op.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpProfile {
+    pub op: &'static str,
+    /// Nodes created with this op.
+    pub nodes: u64,
+    /// Time spent propagating grads out of nodes with this op. Always zero
+    /// on `wasm32-unknown-unknown`, which has no clock.
+    pub backward_time: Duration,
+}
+
+/// Per-op counters, sorted by op name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileReport {
+    pub ops: Vec<OpProfile>,
+}
+
+impl ProfileReport {
+    /// Number of nodes created with `op`, e.g. `"Mul"`.
+    pub fn nodes(&self, op: &str) -> u64 {
+        self.ops
+            .iter()
+            .find(|profile| profile.op == op)
+            .map_or(0, |profile| profile.nodes)
+    }
+}
+
+impl Display for ProfileReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:<10} {:>10} {:>14}", "op", "nodes", "backward")?;
+        for profile in self.ops.iter() {
+            writeln!(
+                f,
+                "{:<10} {:>10} {:>14}",
+                profile.op,
+                profile.nodes,
+                format!("{:.3?}", profile.backward_time)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::Value;
+    use std::ops::Div;
+
+    #[test]
+    fn test_contrived_counts() {
+        reset();
+        enable();
+        let a = &Value::new(-4.0);
+        let b = &Value::new(2.0);
+        let mut c = a + b;
+        let mut d = a * b + b.pow(3.0);
+        c = c.clone() + c + 1.0;
+        c = c.clone() + 1.0 + c.clone() + (-a);
+        d = d.clone() + d.clone() * 2.0 + (b + a).relu();
+        d = d.clone() + d.clone() * 3.0 + (b - a).relu();
+        let e = c - d;
+        let f = e.pow(2.0);
+        let mut g = f.clone().div(2.0);
+        g = g + 10.0 / f;
+        g.backward();
+        disable();
+
+        let stats = report();
+        assert_eq!(stats.nodes("Mul"), 8);
+        assert_eq!(stats.nodes("Add"), 15);
+        assert_eq!(stats.nodes("Pow"), 3);
+        assert_eq!(stats.nodes("ReLU"), 2);
+        assert_eq!(stats.nodes("Sqrt"), 0);
+        assert!(stats.to_string().starts_with("op "));
+        assert_eq!(stats.to_string().lines().count(), stats.ops.len() + 1);
+
+        reset();
+        assert!(report().ops.is_empty());
+    }
+}
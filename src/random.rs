@@ -0,0 +1,52 @@
+//! Random numbers shared by initialization, datasets and the optimizers
+//! that add noise.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::engine::Scalar;
+
+/// Box-Muller transform, so we don't need `rand_distr` for one function.
+pub(crate) fn standard_normal<R: Rng + ?Sized>(rng: &mut R) -> Scalar {
+    let u1: Scalar = 1.0 - rng.gen::<Scalar>();
+    let u2: Scalar = rng.gen();
+    let tau = 2.0 * std::f64::consts::PI as Scalar;
+    (-2.0 * u1.ln()).sqrt() * (tau * u2).cos()
+}
+
+/// Standard normals from a seeded [`StdRng`], counted so that an
+/// optimizer's state can record how far along the sequence it is.
+pub(crate) struct NormalRng {
+    seed: u64,
+    draws: u64,
+    rng: StdRng,
+}
+
+impl NormalRng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            draws: 0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub(crate) fn sample(&mut self) -> Scalar {
+        self.draws += 1;
+        standard_normal(&mut self.rng)
+    }
+
+    /// How many values have been drawn since seeding.
+    pub(crate) fn draws(&self) -> u64 {
+        self.draws
+    }
+
+    /// Reseeds and draws `draws` values, so the next one is the one that
+    /// followed them. Takes time in proportion to `draws`.
+    pub(crate) fn seek(&mut self, draws: u64) {
+        *self = Self::new(self.seed);
+        for _ in 0..draws {
+            self.sample();
+        }
+    }
+}
@@ -0,0 +1,500 @@
+//! Recurrent cells that carry a hidden state across the steps of a
+//! sequence.
+
+use std::fmt::{Debug, Display};
+
+use crate::engine::{Scalar, Value};
+use crate::nn::{detached, Init, Introspect, Module};
+use crate::tensor::{Matrix, Vector};
+use rand::Rng;
+
+/// A cell that is stepped through a sequence one input at a time.
+pub trait Recurrent: Module {
+    /// Whatever the cell carries between steps.
+    type State: Clone;
+
+    /// The state at the start of a sequence, all zeros.
+    fn zero_state(&self) -> Self::State;
+
+    /// Consumes one input and returns the next state.
+    fn step(&self, x: &[Value], state: &Self::State) -> Self::State;
+
+    /// The part of `state` exposed as the cell's output.
+    fn output(&self, state: &Self::State) -> Vec<Value>;
+
+    /// Runs the cell over `xs` from the zero state and returns the output
+    /// after every step.
+    fn forward_sequence(&self, xs: &[Vec<Value>]) -> Vec<Vec<Value>> {
+        let mut state = self.zero_state();
+        xs.iter()
+            .map(|x| {
+                state = self.step(x, &state);
+                self.output(&state)
+            })
+            .collect()
+    }
+}
+
+fn zeros(n: usize) -> Vec<Value> {
+    (0..n).map(|_| Value::constant(0.0)).collect()
+}
+
+/// `wx . x + wh . h + b`, the pre-activation shared by every gate.
+struct Gate {
+    wx: Matrix,
+    wh: Matrix,
+    b: Vector,
+}
+
+impl Gate {
+    fn new(
+        nin: usize,
+        hidden: usize,
+        init: &Init,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Self {
+        let mut weights = |cols: usize| {
+            Matrix::from_fn(hidden, cols, |_, _| {
+                Value::new(init.sample(cols, hidden, rng))
+            })
+        };
+        Self {
+            wx: weights(nin),
+            wh: weights(hidden),
+            b: Vector::new((0..hidden).map(|_| Value::new(0.0)).collect()),
+        }
+    }
+
+    fn clone_detached(&self) -> Self {
+        Self {
+            wx: self.wx.map(detached),
+            wh: self.wh.map(detached),
+            b: self.b.map(detached),
+        }
+    }
+
+    fn input(&self, x: &Vector) -> Vector {
+        &self.wx.matvec(x) + &self.b
+    }
+
+    fn hidden(&self, h: &Vector) -> Vector {
+        self.wh.matvec(h)
+    }
+
+    fn named_parameters(&self, gate: &str) -> Vec<(String, Value)> {
+        let mut out = vec![];
+        for (name, m) in [("wx", &self.wx), ("wh", &self.wh)].iter() {
+            for r in 0..m.rows() {
+                for c in 0..m.cols() {
+                    let name = format!("{}.{}{}.{}", gate, name, r, c);
+                    out.push((name, m[(r, c)].clone()));
+                }
+            }
+        }
+        let b = self.b.iter().enumerate();
+        out.extend(b.map(|(i, b)| (format!("{}.b{}", gate, i), b.clone())));
+        out
+    }
+
+    fn weights(&self) -> impl Iterator<Item = &Value> {
+        self.wx.values().iter().chain(self.wh.values())
+    }
+
+    fn parameters(&self) -> impl Iterator<Item = Value> + '_ {
+        self.weights().chain(self.b.iter()).cloned()
+    }
+}
+
+/// A gated recurrent unit:
+///
+/// ```text
+/// r  = sigmoid(W_ir x + W_hr h + b_r)
+/// z  = sigmoid(W_iz x + W_hz h + b_z)
+/// n  = tanh(W_in x + b_n + r * (W_hn h))
+/// h' = (1 - z) * n + z * h
+/// ```
+pub struct GruCell {
+    reset: Gate,
+    update: Gate,
+    candidate: Gate,
+}
+
+impl GruCell {
+    pub fn new(nin: usize, hidden: usize) -> Self {
+        Self::new_with_rng(
+            nin,
+            hidden,
+            &Init::default(),
+            &mut rand::thread_rng(),
+        )
+    }
+
+    /// Like [`GruCell::new`], drawing every gate weight from `init` with
+    /// `rng`. Biases start at zero.
+    pub fn new_with_rng(
+        nin: usize,
+        hidden: usize,
+        init: &Init,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Self {
+        Self {
+            reset: Gate::new(nin, hidden, init, rng),
+            update: Gate::new(nin, hidden, init, rng),
+            candidate: Gate::new(nin, hidden, init, rng),
+        }
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.reset.wx.cols()
+    }
+
+    pub fn hidden_size(&self) -> usize {
+        self.reset.b.len()
+    }
+
+    /// A copy with its own parameters, starting from the same data with
+    /// zero grads.
+    pub fn clone_detached(&self) -> Self {
+        Self {
+            reset: self.reset.clone_detached(),
+            update: self.update.clone_detached(),
+            candidate: self.candidate.clone_detached(),
+        }
+    }
+}
+
+impl Recurrent for GruCell {
+    type State = Vec<Value>;
+
+    fn zero_state(&self) -> Vec<Value> {
+        zeros(self.hidden_size())
+    }
+
+    fn step(&self, x: &[Value], h: &Vec<Value>) -> Vec<Value> {
+        let (x, h) = (Vector::new(x.to_vec()), Vector::new(h.clone()));
+        let r = (&self.reset.input(&x) + &self.reset.hidden(&h))
+            .map(Value::sigmoid);
+        let z = (&self.update.input(&x) + &self.update.hidden(&h))
+            .map(Value::sigmoid);
+        let n = (&self.candidate.input(&x)
+            + &(&r * &self.candidate.hidden(&h)))
+            .map(Value::tanh);
+        (&n + &(&z * &(&h - &n))).into_values()
+    }
+
+    fn output(&self, h: &Vec<Value>) -> Vec<Value> {
+        h.clone()
+    }
+}
+
+impl Module for GruCell {
+    /// One step from the zero state.
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        self.step(x, &self.zero_state())
+    }
+
+    fn parameters(&self) -> Vec<Value> {
+        let gates = [&self.reset, &self.update, &self.candidate];
+        gates.iter().flat_map(|g| g.parameters()).collect()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        let mut out = self.reset.named_parameters("reset");
+        out.extend(self.update.named_parameters("update"));
+        out.extend(self.candidate.named_parameters("candidate"));
+        out
+    }
+
+    fn weights(&self) -> Vec<Value> {
+        let gates = [&self.reset, &self.update, &self.candidate];
+        gates.iter().flat_map(|g| g.weights()).cloned().collect()
+    }
+
+    fn clone_detached_boxed(&self) -> Option<Box<dyn Module>> {
+        Some(Box::new(self.clone_detached()))
+    }
+}
+
+impl Introspect for GruCell {
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        Some(self.hidden_size()).filter(|_| input_size == self.input_size())
+    }
+}
+
+impl Display for GruCell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "GruCell({}, {})",
+            self.reset.wx.cols(),
+            self.hidden_size()
+        ))
+    }
+}
+
+impl Debug for GruCell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+/// A long short-term memory cell carrying `(h, c)`:
+///
+/// ```text
+/// i  = sigmoid(W_ii x + W_hi h + b_i)
+/// f  = sigmoid(W_if x + W_hf h + b_f)
+/// g  = tanh(W_ig x + W_hg h + b_g)
+/// o  = sigmoid(W_io x + W_ho h + b_o)
+/// c' = f * c + i * g
+/// h' = o * tanh(c')
+/// ```
+pub struct LstmCell {
+    input: Gate,
+    forget: Gate,
+    cell: Gate,
+    output: Gate,
+}
+
+impl LstmCell {
+    /// `forget_bias` is the initial `b_f`; 1.0 is the usual choice, so the
+    /// cell starts out remembering.
+    pub fn new(nin: usize, hidden: usize, forget_bias: Scalar) -> Self {
+        Self::new_with_rng(
+            nin,
+            hidden,
+            &Init::default(),
+            forget_bias,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    /// Like [`LstmCell::new`], drawing every gate weight from `init` with
+    /// `rng`.
+    pub fn new_with_rng(
+        nin: usize,
+        hidden: usize,
+        init: &Init,
+        forget_bias: Scalar,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Self {
+        let forget = Gate::new(nin, hidden, init, rng);
+        for b in forget.b.iter() {
+            b.set_data(forget_bias);
+        }
+        Self {
+            input: Gate::new(nin, hidden, init, rng),
+            forget,
+            cell: Gate::new(nin, hidden, init, rng),
+            output: Gate::new(nin, hidden, init, rng),
+        }
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.input.wx.cols()
+    }
+
+    pub fn hidden_size(&self) -> usize {
+        self.input.b.len()
+    }
+
+    /// A copy with its own parameters, starting from the same data with
+    /// zero grads.
+    pub fn clone_detached(&self) -> Self {
+        Self {
+            input: self.input.clone_detached(),
+            forget: self.forget.clone_detached(),
+            cell: self.cell.clone_detached(),
+            output: self.output.clone_detached(),
+        }
+    }
+}
+
+impl Recurrent for LstmCell {
+    type State = (Vec<Value>, Vec<Value>);
+
+    fn zero_state(&self) -> Self::State {
+        (zeros(self.hidden_size()), zeros(self.hidden_size()))
+    }
+
+    fn step(&self, x: &[Value], (h, c): &Self::State) -> Self::State {
+        let (x, h) = (Vector::new(x.to_vec()), Vector::new(h.clone()));
+        let c = Vector::new(c.clone());
+        let gate = |g: &Gate| &g.input(&x) + &g.hidden(&h);
+        let i = gate(&self.input).map(Value::sigmoid);
+        let f = gate(&self.forget).map(Value::sigmoid);
+        let g = gate(&self.cell).map(Value::tanh);
+        let o = gate(&self.output).map(Value::sigmoid);
+        let c = &(&f * &c) + &(&i * &g);
+        let h = &o * &c.map(Value::tanh);
+        (h.into_values(), c.into_values())
+    }
+
+    fn output(&self, (h, _): &Self::State) -> Vec<Value> {
+        h.clone()
+    }
+}
+
+impl Module for LstmCell {
+    /// One step from the zero state, returning `h`.
+    fn forward(&self, x: &[Value]) -> Vec<Value> {
+        self.step(x, &self.zero_state()).0
+    }
+
+    fn parameters(&self) -> Vec<Value> {
+        let gates = [&self.input, &self.forget, &self.cell, &self.output];
+        gates.iter().flat_map(|g| g.parameters()).collect()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Value)> {
+        let mut out = self.input.named_parameters("input");
+        out.extend(self.forget.named_parameters("forget"));
+        out.extend(self.cell.named_parameters("cell"));
+        out.extend(self.output.named_parameters("output"));
+        out
+    }
+
+    fn weights(&self) -> Vec<Value> {
+        let gates = [&self.input, &self.forget, &self.cell, &self.output];
+        gates.iter().flat_map(|g| g.weights()).cloned().collect()
+    }
+
+    fn clone_detached_boxed(&self) -> Option<Box<dyn Module>> {
+        Some(Box::new(self.clone_detached()))
+    }
+}
+
+impl Introspect for LstmCell {
+    fn output_size(&self, input_size: usize) -> Option<usize> {
+        Some(self.hidden_size()).filter(|_| input_size == self.input_size())
+    }
+}
+
+impl Display for LstmCell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "LstmCell({}, {})",
+            self.input.wx.cols(),
+            self.hidden_size()
+        ))
+    }
+}
+
+impl Debug for LstmCell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::assert_grads_match_numeric;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn sequence(data: &[[Scalar; 2]]) -> Vec<Vec<Value>> {
+        data.iter()
+            .map(|x| x.iter().map(|&v| Value::new(v)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_gru_shapes() {
+        let cell = GruCell::new(3, 4);
+        assert_eq!(format!("{:?}", cell), "GruCell(3, 4)");
+        assert_eq!(cell.output_size(3), Some(4));
+        assert_eq!(cell.output_size(4), None);
+        assert_eq!(cell.parameters().len(), 3 * (4 * 3 + 4 * 4 + 4));
+        assert_eq!(cell.weights().len(), 3 * (4 * 3 + 4 * 4));
+        let xs: Vec<Vec<Value>> = (0..5)
+            .map(|_| (0..3).map(|_| Value::new(0.5)).collect())
+            .collect();
+        let hs = cell.forward_sequence(&xs);
+        assert_eq!(hs.len(), 5);
+        assert!(hs.iter().all(|h| h.len() == 4));
+        // each state is a convex mix of the previous one and a tanh
+        assert!(hs.iter().flatten().all(|h| h.get_data().abs() < 1.0));
+    }
+
+    #[test]
+    fn test_gru_numeric_grads() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let cell = GruCell::new_with_rng(2, 2, &Init::XavierUniform, &mut rng);
+        for (i, p) in cell.parameters().iter().enumerate() {
+            p.update(|w| w + 0.01 * i as Scalar);
+        }
+        let xs = sequence(&[[0.5, -1.0], [1.5, 0.25], [-0.75, 0.5]]);
+        let loss = |_: &[Value]| {
+            let hs = cell.forward_sequence(&xs);
+            Vector::new(hs.concat()).dot(&Vector::new(hs.concat()))
+        };
+        assert_grads_match_numeric(&cell.parameters(), loss, 1e-2, 1e-2);
+    }
+
+    #[test]
+    fn test_gru_update_gate() {
+        let cell = GruCell::new(2, 2);
+        for b in cell.update.b.iter() {
+            b.set_data(20.0);
+        }
+        let h = vec![Value::new(0.5), Value::new(-0.5)];
+        let next = cell.step(&[Value::new(1.0), Value::new(-1.0)], &h);
+        for (a, b) in h.iter().zip(&next) {
+            assert!((a.get_data() - b.get_data()).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_lstm() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let cell =
+            LstmCell::new_with_rng(2, 3, &Init::XavierUniform, 1.0, &mut rng);
+        assert_eq!(cell.parameters().len(), 4 * (2 + 3 + 1) * 3);
+        let named = cell.named_parameters();
+        assert!(named
+            .iter()
+            .zip(cell.parameters())
+            .all(|((_, a), b)| *a == b));
+        assert_eq!(named[0].0, "input.wx0.0");
+        assert_eq!(named[3 * 2 + 1].0, "input.wh0.1");
+        assert_eq!(named.last().unwrap().0, "output.b2");
+        let weights = cell.weights();
+        assert_eq!(weights.len(), 4 * (2 + 3) * 3);
+        assert!(named
+            .iter()
+            .all(|(name, p)| name.contains(".b") != weights.contains(p)));
+        assert_eq!(format!("{:?}", cell), "LstmCell(2, 3)");
+        assert_eq!(cell.output_size(2), Some(3));
+        assert_eq!(cell.output_size(3), None);
+
+        let xs = sequence(&[[0.5, -1.0], [1.5, 0.25], [-0.75, 0.5]]);
+        let hs = cell.forward_sequence(&xs);
+        assert_eq!(hs.len(), 3);
+        assert!(hs.iter().all(|h| h.len() == 3));
+        let loss = |_: &[Value]| {
+            let hs = cell.forward_sequence(&xs);
+            Vector::new(hs.concat()).dot(&Vector::new(hs.concat()))
+        };
+        assert_grads_match_numeric(&cell.parameters(), loss, 1e-2, 1e-2);
+    }
+
+    #[test]
+    fn test_lstm_forget_bias() {
+        let pre = |forget_bias: Scalar| -> Vec<Scalar> {
+            let mut rng = StdRng::seed_from_u64(9);
+            let cell = LstmCell::new_with_rng(
+                2,
+                2,
+                &Init::default(),
+                forget_bias,
+                &mut rng,
+            );
+            let x = Vector::from_data(&[0.5, -0.5]);
+            let h = Vector::from_data(&[0.25, 0.75]);
+            (&cell.forget.input(&x) + &cell.forget.hidden(&h)).data()
+        };
+        let (zero, one) = (pre(0.0), pre(1.0));
+        for (a, b) in zero.iter().zip(&one) {
+            assert!((b - a - 1.0).abs() < 1e-6);
+        }
+    }
+}
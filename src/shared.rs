@@ -0,0 +1,155 @@
+//! Pointer, cell and lock types that graph nodes are built from.
+//!
+//! By default these are the single-threaded `Rc`, `Cell` and `RefCell`. The
+//! `sync` feature swaps them for `Arc`, atomics and `RwLock` so that
+//! [`Value`](crate::engine::Value) becomes `Send + Sync`, at the cost of
+//! atomic reference counting on every node.
+
+use crate::engine::{Scalar, Value};
+use std::fmt::Debug;
+use std::ops::{Deref, DerefMut};
+
+#[cfg(not(feature = "sync"))]
+pub(crate) use std::rc::Rc as Shared;
+#[cfg(feature = "sync")]
+pub(crate) use std::sync::Arc as Shared;
+
+/// Bound on closures stored in the graph, such as those passed to
+/// [`Value::custom_unary`](crate::engine::Value::custom_unary).
+///
+/// With the `sync` feature this requires `Send + Sync`; otherwise every type
+/// implements it.
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSync {}
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> MaybeSync for T {}
+#[cfg(feature = "sync")]
+pub trait MaybeSync: Send + Sync {}
+#[cfg(feature = "sync")]
+impl<T: ?Sized + Send + Sync> MaybeSync for T {}
+
+#[cfg(not(feature = "sync"))]
+pub(crate) type UnaryForward = dyn Fn(Scalar) -> Scalar;
+#[cfg(not(feature = "sync"))]
+pub(crate) type UnaryBackward = dyn Fn(Scalar, Scalar, Scalar) -> Scalar;
+#[cfg(not(feature = "sync"))]
+pub(crate) type BinaryForward = dyn Fn(Scalar, Scalar) -> Scalar;
+#[cfg(not(feature = "sync"))]
+pub(crate) type BinaryBackward =
+    dyn Fn(Scalar, Scalar, Scalar, Scalar) -> (Scalar, Scalar);
+
+#[cfg(feature = "sync")]
+pub(crate) type UnaryForward = dyn Fn(Scalar) -> Scalar + Send + Sync;
+#[cfg(feature = "sync")]
+pub(crate) type UnaryBackward =
+    dyn Fn(Scalar, Scalar, Scalar) -> Scalar + Send + Sync;
+#[cfg(feature = "sync")]
+pub(crate) type BinaryForward = dyn Fn(Scalar, Scalar) -> Scalar + Send + Sync;
+#[cfg(feature = "sync")]
+pub(crate) type BinaryBackward =
+    dyn Fn(Scalar, Scalar, Scalar, Scalar) -> (Scalar, Scalar) + Send + Sync;
+
+#[cfg(not(feature = "sync"))]
+pub(crate) type ValueFn = dyn Fn(&Value) -> Value;
+#[cfg(feature = "sync")]
+pub(crate) type ValueFn = dyn Fn(&Value) -> Value + Send + Sync;
+
+#[cfg(not(feature = "sync"))]
+pub(crate) struct ScalarCell(std::cell::Cell<Scalar>);
+
+#[cfg(not(feature = "sync"))]
+impl ScalarCell {
+    pub(crate) fn new(value: Scalar) -> Self {
+        Self(std::cell::Cell::new(value))
+    }
+
+    pub(crate) fn get(&self) -> Scalar {
+        self.0.get()
+    }
+
+    pub(crate) fn set(&self, value: Scalar) {
+        self.0.set(value)
+    }
+
+    pub(crate) fn add(&self, delta: Scalar) {
+        self.0.set(self.0.get() + delta)
+    }
+}
+
+#[cfg(all(feature = "sync", not(feature = "f64")))]
+type AtomicBits = std::sync::atomic::AtomicU32;
+#[cfg(all(feature = "sync", feature = "f64"))]
+type AtomicBits = std::sync::atomic::AtomicU64;
+
+/// A scalar stored as its bit pattern so it can be updated atomically.
+#[cfg(feature = "sync")]
+pub(crate) struct ScalarCell(AtomicBits);
+
+#[cfg(feature = "sync")]
+impl ScalarCell {
+    pub(crate) fn new(value: Scalar) -> Self {
+        Self(AtomicBits::new(value.to_bits()))
+    }
+
+    pub(crate) fn get(&self) -> Scalar {
+        Scalar::from_bits(self.0.load(std::sync::atomic::Ordering::Relaxed))
+    }
+
+    pub(crate) fn set(&self, value: Scalar) {
+        self.0
+            .store(value.to_bits(), std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Adds `delta` as a single atomic update, so grads accumulated from
+    /// several threads at once are not lost.
+    pub(crate) fn add(&self, delta: Scalar) {
+        let _ = self.0.fetch_update(
+            std::sync::atomic::Ordering::Relaxed,
+            std::sync::atomic::Ordering::Relaxed,
+            |bits| Some((Scalar::from_bits(bits) + delta).to_bits()),
+        );
+    }
+}
+
+impl Debug for ScalarCell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.get(), f)
+    }
+}
+
+#[cfg(not(feature = "sync"))]
+pub(crate) struct Lock<T>(std::cell::RefCell<T>);
+#[cfg(feature = "sync")]
+pub(crate) struct Lock<T>(std::sync::RwLock<T>);
+
+impl<T> Lock<T> {
+    #[cfg(not(feature = "sync"))]
+    pub(crate) fn new(value: T) -> Self {
+        Self(std::cell::RefCell::new(value))
+    }
+
+    #[cfg(not(feature = "sync"))]
+    pub(crate) fn read(&self) -> impl Deref<Target = T> + '_ {
+        self.0.borrow()
+    }
+
+    #[cfg(not(feature = "sync"))]
+    pub(crate) fn write(&self) -> impl DerefMut<Target = T> + '_ {
+        self.0.borrow_mut()
+    }
+
+    #[cfg(feature = "sync")]
+    pub(crate) fn new(value: T) -> Self {
+        Self(std::sync::RwLock::new(value))
+    }
+
+    #[cfg(feature = "sync")]
+    pub(crate) fn read(&self) -> impl Deref<Target = T> + '_ {
+        self.0.read().unwrap()
+    }
+
+    #[cfg(feature = "sync")]
+    pub(crate) fn write(&self) -> impl DerefMut<Target = T> + '_ {
+        self.0.write().unwrap()
+    }
+}
@@ -0,0 +1,633 @@
+//! Containers of [`Value`]s with elementwise arithmetic.
+//!
+//! Everything here is built out of the scalar ops in [`engine`](crate::engine),
+//! so gradients flow through without any extra bookkeeping.
+
+use std::ops::{Add, Div, Index, Mul, Sub};
+
+use crate::engine::{Scalar, Value};
+
+/// A one-dimensional sequence of [`Value`]s.
+#[derive(Debug, Clone)]
+pub struct Vector(Vec<Value>);
+
+impl Vector {
+    pub fn new(values: Vec<Value>) -> Self {
+        Self(values)
+    }
+
+    /// Creates a vector of fresh leaves holding `data`.
+    pub fn from_data(data: &[Scalar]) -> Self {
+        Self(data.iter().map(|x| Value::new(*x)).collect())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.0
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        self.0
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Value> {
+        self.0.iter()
+    }
+
+    /// Copies out the data of every element.
+    pub fn data(&self) -> Vec<Scalar> {
+        self.0.iter().map(Value::get_data).collect()
+    }
+
+    pub fn map(&self, f: impl Fn(&Value) -> Value) -> Vector {
+        Self(self.0.iter().map(f).collect())
+    }
+
+    /// Combines two vectors of the same length element by element.
+    pub fn zip_map(
+        &self,
+        other: &Vector,
+        f: impl Fn(&Value, &Value) -> Value,
+    ) -> Vector {
+        assert_eq!(
+            self.len(),
+            other.len(),
+            "Vector length mismatch: {} vs {}",
+            self.len(),
+            other.len()
+        );
+        Self(
+            self.0
+                .iter()
+                .zip(other.0.iter())
+                .map(|(a, b)| f(a, b))
+                .collect(),
+        )
+    }
+
+    /// Sums the elements; an empty vector sums to a constant zero.
+    pub fn sum(&self) -> Value {
+        match self.0.split_first() {
+            Some((first, rest)) => {
+                rest.iter().fold(first.clone(), |acc, v| acc + v)
+            }
+            None => Value::constant(0.0),
+        }
+    }
+
+    /// Panics if the vector is empty.
+    pub fn mean(&self) -> Value {
+        assert!(!self.is_empty(), "mean of an empty Vector");
+        &self.sum() / self.len() as Scalar
+    }
+
+    pub fn dot(&self, other: &Vector) -> Value {
+        (self * other).sum()
+    }
+
+    /// The Euclidean norm.
+    pub fn norm(&self) -> Value {
+        self.dot(self).sqrt()
+    }
+
+    /// The running sums of the elements; see [`cumsum`].
+    pub fn cumsum(&self) -> Vector {
+        Self(cumsum(&self.0))
+    }
+}
+
+impl From<Vec<Value>> for Vector {
+    fn from(values: Vec<Value>) -> Self {
+        Self(values)
+    }
+}
+
+impl From<&[Scalar]> for Vector {
+    fn from(data: &[Scalar]) -> Self {
+        Self::from_data(data)
+    }
+}
+
+impl Index<usize> for Vector {
+    type Output = Value;
+
+    fn index(&self, index: usize) -> &Value {
+        &self.0[index]
+    }
+}
+
+impl<'a> IntoIterator for &'a Vector {
+    type Item = &'a Value;
+    type IntoIter = std::slice::Iter<'a, Value>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// Implements an elementwise binary op for `Vector` against another
+/// `Vector`, a `Value` or a `Scalar`, by reference and by value.
+macro_rules! elementwise {
+    ($trait:ident, $method:ident, $op:tt) => {
+        impl $trait<&Vector> for &Vector {
+            type Output = Vector;
+
+            fn $method(self, rhs: &Vector) -> Vector {
+                self.zip_map(rhs, |a, b| a $op b)
+            }
+        }
+
+        impl $trait<Vector> for Vector {
+            type Output = Vector;
+
+            fn $method(self, rhs: Vector) -> Vector {
+                &self $op &rhs
+            }
+        }
+
+        impl $trait<&Value> for &Vector {
+            type Output = Vector;
+
+            fn $method(self, rhs: &Value) -> Vector {
+                self.map(|a| a $op rhs)
+            }
+        }
+
+        impl $trait<Value> for Vector {
+            type Output = Vector;
+
+            fn $method(self, rhs: Value) -> Vector {
+                &self $op &rhs
+            }
+        }
+
+        impl $trait<Scalar> for &Vector {
+            type Output = Vector;
+
+            fn $method(self, rhs: Scalar) -> Vector {
+                self $op &Value::constant(rhs)
+            }
+        }
+
+        impl $trait<Scalar> for Vector {
+            type Output = Vector;
+
+            fn $method(self, rhs: Scalar) -> Vector {
+                &self $op rhs
+            }
+        }
+    };
+}
+
+elementwise!(Add, add, +);
+elementwise!(Sub, sub, -);
+elementwise!(Mul, mul, *);
+elementwise!(Div, div, /);
+
+/// The prefix sums of `xs`: element `i` is `xs[0] + ... + xs[i]`, so on
+/// `backward` each input collects the grads of its own position and every
+/// later one.
+///
+/// The sums are built as a parallel scan rather than a chain of adds: about
+/// `2n` nodes, but only `O(log n)` deep, so long sequences don't make for
+/// deep graphs. The first element is `xs[0]` itself.
+pub fn cumsum(xs: &[Value]) -> Vec<Value> {
+    if xs.len() <= 1 {
+        return xs.to_vec();
+    }
+    // Sums of adjacent pairs, whose prefix sums are every other output
+    let pairs: Vec<_> = xs.chunks_exact(2).map(|p| &p[0] + &p[1]).collect();
+    let evens = cumsum(&pairs);
+    (0..xs.len())
+        .map(|i| match i {
+            0 => xs[0].clone(),
+            i if i % 2 == 1 => evens[i / 2].clone(),
+            i => &evens[i / 2 - 1] + &xs[i],
+        })
+        .collect()
+}
+
+/// A row-major two-dimensional grid of [`Value`]s.
+#[derive(Debug, Clone)]
+pub struct Matrix {
+    data: Vec<Value>,
+    rows: usize,
+    cols: usize,
+}
+
+impl Matrix {
+    /// Builds a `rows` x `cols` matrix from `f(row, col)`.
+    pub fn from_fn(
+        rows: usize,
+        cols: usize,
+        mut f: impl FnMut(usize, usize) -> Value,
+    ) -> Self {
+        let data = (0..rows)
+            .flat_map(|r| (0..cols).map(move |c| (r, c)))
+            .map(|(r, c)| f(r, c))
+            .collect();
+        Self { data, rows, cols }
+    }
+
+    /// Creates a matrix of fresh leaves from row-major `data`.
+    pub fn from_flat(data: &[Scalar], rows: usize, cols: usize) -> Self {
+        assert_eq!(
+            data.len(),
+            rows * cols,
+            "{} values cannot fill a {}x{} Matrix",
+            data.len(),
+            rows,
+            cols
+        );
+        Self::from_fn(rows, cols, |r, c| Value::new(data[r * cols + c]))
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// The elements in row-major order.
+    pub fn values(&self) -> &[Value] {
+        &self.data
+    }
+
+    /// Copies out the data of every element in row-major order.
+    pub fn data(&self) -> Vec<Scalar> {
+        self.data.iter().map(Value::get_data).collect()
+    }
+
+    pub fn row(&self, r: usize) -> Vector {
+        Vector::new(self.data[r * self.cols..(r + 1) * self.cols].to_vec())
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        Self::from_fn(self.cols, self.rows, |r, c| self[(c, r)].clone())
+    }
+
+    pub fn map(&self, f: impl Fn(&Value) -> Value) -> Matrix {
+        Matrix {
+            data: self.data.iter().map(f).collect(),
+            rows: self.rows,
+            cols: self.cols,
+        }
+    }
+
+    /// Combines two matrices of the same shape element by element.
+    pub fn zip_map(
+        &self,
+        other: &Matrix,
+        f: impl Fn(&Value, &Value) -> Value,
+    ) -> Matrix {
+        assert_eq!(
+            (self.rows, self.cols),
+            (other.rows, other.cols),
+            "Matrix shape mismatch: {}x{} vs {}x{}",
+            self.rows,
+            self.cols,
+            other.rows,
+            other.cols
+        );
+        Matrix {
+            data: self
+                .data
+                .iter()
+                .zip(&other.data)
+                .map(|(a, b)| f(a, b))
+                .collect(),
+            rows: self.rows,
+            cols: self.cols,
+        }
+    }
+
+    /// Combines each row with `v`, which must have one element per column.
+    pub fn zip_map_rows(
+        &self,
+        v: &Vector,
+        f: impl Fn(&Value, &Value) -> Value,
+    ) -> Matrix {
+        assert_eq!(
+            self.cols,
+            v.len(),
+            "cannot broadcast a Vector of length {} over the rows of a {}x{} \
+             Matrix",
+            v.len(),
+            self.rows,
+            self.cols
+        );
+        Self::from_fn(self.rows, self.cols, |r, c| f(&self[(r, c)], &v[c]))
+    }
+
+    pub fn matmul(&self, rhs: &Matrix) -> Matrix {
+        assert_eq!(
+            self.cols, rhs.rows,
+            "Matrix shape mismatch: {}x{} matmul {}x{}",
+            self.rows, self.cols, rhs.rows, rhs.cols
+        );
+        let rhs = rhs.transpose();
+        Self::from_fn(self.rows, rhs.rows, |r, c| self.row(r).dot(&rhs.row(c)))
+    }
+
+    pub fn matvec(&self, x: &Vector) -> Vector {
+        assert_eq!(
+            self.cols,
+            x.len(),
+            "Matrix shape mismatch: {}x{} matvec {}",
+            self.rows,
+            self.cols,
+            x.len()
+        );
+        Vector::new((0..self.rows).map(|r| self.row(r).dot(x)).collect())
+    }
+}
+
+impl Index<(usize, usize)> for Matrix {
+    type Output = Value;
+
+    fn index(&self, (r, c): (usize, usize)) -> &Value {
+        assert!(
+            r < self.rows && c < self.cols,
+            "index ({}, {}) out of bounds for a {}x{} Matrix",
+            r,
+            c,
+            self.rows,
+            self.cols
+        );
+        &self.data[r * self.cols + c]
+    }
+}
+
+// A `Value` or scalar broadcasts over every element and a `Vector` over
+// every row; shared operands accumulate the grads of every position.
+macro_rules! matrix_elementwise {
+    ($trait:ident, $method:ident, $op:tt) => {
+        impl $trait<&Matrix> for &Matrix {
+            type Output = Matrix;
+
+            fn $method(self, rhs: &Matrix) -> Matrix {
+                self.zip_map(rhs, |a, b| a $op b)
+            }
+        }
+
+        impl $trait<Matrix> for Matrix {
+            type Output = Matrix;
+
+            fn $method(self, rhs: Matrix) -> Matrix {
+                &self $op &rhs
+            }
+        }
+
+        impl $trait<&Vector> for &Matrix {
+            type Output = Matrix;
+
+            fn $method(self, rhs: &Vector) -> Matrix {
+                self.zip_map_rows(rhs, |a, b| a $op b)
+            }
+        }
+
+        impl $trait<Vector> for Matrix {
+            type Output = Matrix;
+
+            fn $method(self, rhs: Vector) -> Matrix {
+                &self $op &rhs
+            }
+        }
+
+        impl $trait<&Value> for &Matrix {
+            type Output = Matrix;
+
+            fn $method(self, rhs: &Value) -> Matrix {
+                self.map(|a| a $op rhs)
+            }
+        }
+
+        impl $trait<Value> for Matrix {
+            type Output = Matrix;
+
+            fn $method(self, rhs: Value) -> Matrix {
+                &self $op &rhs
+            }
+        }
+
+        impl $trait<Scalar> for &Matrix {
+            type Output = Matrix;
+
+            fn $method(self, rhs: Scalar) -> Matrix {
+                self $op &Value::constant(rhs)
+            }
+        }
+
+        impl $trait<Scalar> for Matrix {
+            type Output = Matrix;
+
+            fn $method(self, rhs: Scalar) -> Matrix {
+                &self $op rhs
+            }
+        }
+    };
+}
+
+matrix_elementwise!(Add, add, +);
+matrix_elementwise!(Sub, sub, -);
+matrix_elementwise!(Mul, mul, *);
+matrix_elementwise!(Div, div, /);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{assert_all_grads_close, assert_value_close};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_elementwise_grads() {
+        let x = Vector::from_data(&[1.0, 2.0, 3.0]);
+        let y = Vector::from_data(&[4.0, 5.0, 6.0]);
+        let out = (&(&x * &y) + &(&x / &y)) - y.clone();
+        out.sum().backward();
+        let expected =
+            |x: Scalar, y: Scalar| (y + 1.0 / y, x - x / (y * y) - 1.0);
+        for i in 0..3 {
+            let (dx, dy) = expected(x[i].get_data(), y[i].get_data());
+            assert_all_grads_close(&[(&x[i], dx), (&y[i], dy)], 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_scalar_broadcast() {
+        let x = Vector::from_data(&[1.0, -2.0, 3.0]);
+        let s = &Value::new(2.0);
+        let out = (&(&x * s) + 1.0).sum();
+        assert_value_close(&out, 7.0, 0.0);
+        out.backward();
+        assert_all_grads_close(&[(s, 2.0), (&x[0], 2.0), (&x[1], 2.0)], 0.0);
+        assert_eq!((&x - 1.0).data(), vec![0.0, -3.0, 2.0]);
+        assert_eq!((&x / 2.0).data(), vec![0.5, -1.0, 1.5]);
+    }
+
+    #[test]
+    fn test_reductions() {
+        let x = Vector::from_data(&[3.0, 4.0]);
+        let y = Vector::from_data(&[1.0, -1.0]);
+        assert_value_close(&x.sum(), 7.0, 0.0);
+        assert_value_close(&x.mean(), 3.5, 0.0);
+        assert_value_close(&x.dot(&y), -1.0, 0.0);
+        let norm = x.norm();
+        assert_value_close(&norm, 5.0, 1e-6);
+        norm.backward();
+        assert_all_grads_close(&[(&x[0], 0.6), (&x[1], 0.8)], 1e-6);
+        assert_value_close(&Vector::new(vec![]).sum(), 0.0, 0.0);
+    }
+
+    #[test]
+    fn test_cumsum() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for n in [2, 5, 8, 13] {
+            let xs: Vec<_> = (0..n)
+                .map(|_| Value::new(rng.gen_range(-1.0..1.0)))
+                .collect();
+            let upstream: Vec<Scalar> =
+                (0..n).map(|_| rng.gen_range(-1.0..1.0)).collect();
+            let sums = cumsum(&xs);
+            let mut running = 0.0;
+            for (x, s) in xs.iter().zip(&sums) {
+                running += x.get_data();
+                assert_value_close(s, running, 1e-5);
+            }
+            let weighted = sums.iter().zip(&upstream).map(|(s, &g)| s * g);
+            Vector::new(weighted.collect()).sum().backward();
+            for (j, x) in xs.iter().enumerate() {
+                let suffix: Scalar = upstream[j..].iter().sum();
+                assert!((x.get_grad() - suffix).abs() < 1e-5, "grad {}", j);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cumsum_edge_cases() {
+        assert!(cumsum(&[]).is_empty());
+        let x = Value::new(2.0);
+        let sums = cumsum(std::slice::from_ref(&x));
+        assert!(sums.len() == 1 && sums[0] == x);
+        sums[0].backward();
+        assert_eq!(x.get_grad(), 1.0);
+
+        // A chain of adds this long would overflow the stack on backward
+        let xs = Vector::from_data(&vec![1.0; 200_000]);
+        let sums = xs.cumsum();
+        assert_eq!(sums[199_999].get_data(), 200_000.0);
+        sums[199_999].backward();
+        assert!(xs.iter().all(|x| x.get_grad() == 1.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Vector length mismatch: 2 vs 3")]
+    fn test_length_mismatch() {
+        let _ = Vector::from_data(&[1.0, 2.0]) + Vector::from_data(&[1.0; 3]);
+    }
+
+    #[test]
+    fn test_matrix_construction() {
+        let m = Matrix::from_flat(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 2, 3);
+        assert_eq!((m.rows(), m.cols()), (2, 3));
+        assert_eq!(m[(1, 0)].get_data(), 4.0);
+        assert_eq!(m.row(1).data(), vec![4.0, 5.0, 6.0]);
+        let t = m.transpose();
+        assert_eq!((t.rows(), t.cols()), (3, 2));
+        assert_eq!(t.data(), vec![1.0, 4.0, 2.0, 5.0, 3.0, 6.0]);
+        assert!(t[(2, 1)] == m[(1, 2)]);
+        let relu = Matrix::from_flat(&[-1.0, 2.0], 1, 2).map(Value::relu);
+        assert_eq!(relu.data(), vec![0.0, 2.0]);
+    }
+
+    #[test]
+    fn test_matmul() {
+        let a = Matrix::from_flat(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 2, 3);
+        let b = Matrix::from_flat(&[7.0, 8.0, 9.0, 10.0, 11.0, 12.0], 3, 2);
+        let c = a.matmul(&b);
+        assert_eq!((c.rows(), c.cols()), (2, 2));
+        assert_eq!(c.data(), vec![58.0, 64.0, 139.0, 154.0]);
+        Vector::new(c.values().to_vec()).sum().backward();
+        // d(sum(AB))/dA[i][k] = sum_j B[k][j]
+        assert_eq!(a[(0, 1)].get_grad(), 19.0);
+        // d(sum(AB))/dB[k][j] = sum_i A[i][k]
+        assert_eq!(b[(2, 0)].get_grad(), 9.0);
+
+        let x = Vector::from_data(&[1.0, 0.0, -1.0]);
+        assert_eq!(a.matvec(&x).data(), vec![-2.0, -2.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Matrix shape mismatch: 2x3 matmul 2x3")]
+    fn test_matmul_mismatch() {
+        let a = Matrix::from_flat(&[0.0; 6], 2, 3);
+        a.matmul(&a);
+    }
+
+    #[test]
+    #[should_panic(expected = "Matrix shape mismatch: 2x3 matvec 2")]
+    fn test_matvec_mismatch() {
+        let a = Matrix::from_flat(&[0.0; 6], 2, 3);
+        a.matvec(&Vector::from_data(&[0.0; 2]));
+    }
+
+    #[test]
+    fn test_matrix_bias_broadcast() {
+        let x = Matrix::from_flat(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 2, 3);
+        let b = Vector::from_data(&[10.0, 20.0, 30.0]);
+        let y = &x + &b;
+        assert_eq!(y.data(), vec![11.0, 22.0, 33.0, 14.0, 25.0, 36.0]);
+        // weight each output so the upstream grad is not uniform
+        let w = Matrix::from_flat(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 2, 3);
+        Vector::new((&y * &w).values().to_vec()).sum().backward();
+        // column sums of the output grad
+        let grads: Vec<Scalar> = b.iter().map(Value::get_grad).collect();
+        assert_eq!(grads, vec![5.0, 7.0, 9.0]);
+        assert_eq!(x[(1, 2)].get_grad(), 6.0);
+    }
+
+    #[test]
+    fn test_matrix_scalar_broadcast() {
+        let x = Matrix::from_flat(
+            &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0],
+            3,
+            3,
+        );
+        let k = Value::new(2.0);
+        let y = &(&x * &k) - 1.0;
+        assert_eq!(
+            y.data(),
+            vec![1.0, 3.0, 5.0, 7.0, 9.0, 11.0, 13.0, 15.0, 17.0]
+        );
+        Vector::new(y.values().to_vec()).sum().backward();
+        assert_eq!(k.get_grad(), 45.0);
+        assert!(x.values().iter().all(|v| v.get_grad() == 2.0));
+        assert_eq!((&x / 2.0)[(2, 2)].get_data(), 4.5);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "cannot broadcast a Vector of length 2 over the rows of a 2x3"
+    )]
+    fn test_matrix_broadcast_mismatch() {
+        let x = Matrix::from_flat(&[0.0; 6], 2, 3);
+        let _ = x + Vector::from_data(&[0.0; 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Matrix shape mismatch: 2x3 vs 3x2")]
+    fn test_matrix_shape_mismatch() {
+        let x = Matrix::from_flat(&[0.0; 6], 2, 3);
+        let _ = &x * &x.transpose();
+    }
+}
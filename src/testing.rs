@@ -0,0 +1,251 @@
+//! Assertions for writing gradient tests against [`Value`]s.
+
+use crate::engine::{Scalar, Value};
+
+fn is_close(actual: Scalar, expected: Scalar, tol: Scalar) -> bool {
+    actual == expected || (actual - expected).abs() <= tol
+}
+
+/// Asserts that `v`'s data is within `tol` of `expected`.
+#[track_caller]
+pub fn assert_value_close(v: &Value, expected: Scalar, tol: Scalar) {
+    let actual = v.get_data();
+    if !is_close(actual, expected, tol) {
+        panic!(
+            "data of {}: expected {} (tol {}), got {}",
+            v, expected, tol, actual
+        );
+    }
+}
+
+/// Asserts that `v`'s grad is within `tol` of `expected`.
+#[track_caller]
+pub fn assert_grad_close(v: &Value, expected: Scalar, tol: Scalar) {
+    let actual = v.get_grad();
+    if !is_close(actual, expected, tol) {
+        panic!(
+            "grad of {}: expected {} (tol {}), got {}",
+            v, expected, tol, actual
+        );
+    }
+}
+
+/// Asserts every `(value, expected grad)` pair at once, reporting all the
+/// mismatches by their index in `pairs`.
+#[track_caller]
+pub fn assert_all_grads_close(pairs: &[(&Value, Scalar)], tol: Scalar) {
+    let mismatches: Vec<String> = pairs
+        .iter()
+        .enumerate()
+        .filter(|(_, (v, expected))| !is_close(v.get_grad(), *expected, tol))
+        .map(|(i, (v, expected))| {
+            format!(
+                "  #{} {}: expected grad {}, got {}",
+                i,
+                v,
+                expected,
+                v.get_grad()
+            )
+        })
+        .collect();
+    if !mismatches.is_empty() {
+        panic!(
+            "{} of {} grads differ by more than {}:\n{}",
+            mismatches.len(),
+            pairs.len(),
+            tol,
+            mismatches.join("\n")
+        );
+    }
+}
+
+/// Panics listing every param whose backpropagated grad is further than
+/// `tol` from its `numeric` estimate. Grads larger than one are compared
+/// relative to their size.
+#[track_caller]
+fn report_mismatches(params: &[Value], numeric: &[Scalar], tol: Scalar) {
+    let mismatches: Vec<String> = params
+        .iter()
+        .zip(numeric)
+        .enumerate()
+        .filter(|(_, (p, &numeric))| {
+            let scale = numeric.abs().max(1.0);
+            !is_close(p.get_grad(), numeric, tol * scale)
+        })
+        .map(|(i, (p, numeric))| {
+            format!("  #{} {}: numeric grad {}", i, p, numeric)
+        })
+        .collect();
+    if !mismatches.is_empty() {
+        panic!(
+            "{} of {} grads disagree with finite differences:\n{}",
+            mismatches.len(),
+            params.len(),
+            mismatches.join("\n")
+        );
+    }
+}
+
+/// Checks the grads backpropagated through `f` against central finite
+/// differences, nudging each of `params` by `eps` in turn.
+///
+/// `f` is handed `params` and must rebuild its graph from them on every
+/// call; a closure that already holds the same leaves can ignore them.
+/// Grads larger than one are compared relative to their size.
+#[track_caller]
+pub fn assert_grads_match_numeric(
+    params: &[Value],
+    f: impl Fn(&[Value]) -> Value,
+    eps: Scalar,
+    tol: Scalar,
+) {
+    for p in params {
+        p.set_grad(0.0);
+    }
+    f(params).backward();
+    let numeric: Vec<Scalar> = params
+        .iter()
+        .map(|p| {
+            let x = p.get_data();
+            p.set_data(x + eps);
+            let plus = f(params).get_data();
+            p.set_data(x - eps);
+            let minus = f(params).get_data();
+            p.set_data(x);
+            (plus - minus) / (2.0 * eps)
+        })
+        .collect();
+    report_mismatches(params, &numeric, tol);
+}
+
+/// Central finite-difference estimates of the gradient of `f` at `x`,
+/// nudging each coordinate by `eps` on its own copy of `x`. The
+/// coordinates are independent, so they're spread over rayon's thread
+/// pool.
+#[cfg(feature = "parallel")]
+pub fn numeric_grads_par(
+    f: impl Fn(&[Scalar]) -> Scalar + Sync,
+    x: &[Scalar],
+    eps: Scalar,
+) -> Vec<Scalar> {
+    use rayon::prelude::*;
+    (0..x.len())
+        .into_par_iter()
+        .map(|i| {
+            let mut nudged = x.to_vec();
+            nudged[i] = x[i] + eps;
+            let plus = f(&nudged);
+            nudged[i] = x[i] - eps;
+            let minus = f(&nudged);
+            (plus - minus) / (2.0 * eps)
+        })
+        .collect()
+}
+
+/// [`assert_grads_match_numeric`] with the finite differences computed in
+/// parallel by [`numeric_grads_par`].
+///
+/// `f` builds its graph from the leaves it is given, which are `params`
+/// themselves for the backpropagated grads and fresh copies for each
+/// nudge, so that no thread touches another's graph.
+#[cfg(feature = "parallel")]
+#[track_caller]
+pub fn assert_grads_match_numeric_par(
+    params: &[Value],
+    f: impl Fn(&[Value]) -> Value + Sync,
+    eps: Scalar,
+    tol: Scalar,
+) {
+    for p in params {
+        p.set_grad(0.0);
+    }
+    f(params).backward();
+    let data: Vec<_> = params.iter().map(Value::get_data).collect();
+    let numeric = numeric_grads_par(
+        |x| {
+            let leaves: Vec<_> = x.iter().map(|&x| Value::new(x)).collect();
+            f(&leaves).get_data()
+        },
+        &data,
+        eps,
+    );
+    report_mismatches(params, &numeric, tol);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_close() {
+        let a = &Value::new(2.0);
+        let b = a * a;
+        b.backward();
+        assert_value_close(&b, 4.001, 0.01);
+        assert_grad_close(a, 4.0, 0.0);
+        assert_all_grads_close(&[(a, 4.0), (&b, 1.0)], 1e-6);
+    }
+
+    #[test]
+    #[should_panic(expected = "grad of Value { data: 2.0, grad: 4.0 }")]
+    fn test_grad_message() {
+        let a = &Value::new(2.0);
+        (a * a).backward();
+        assert_grad_close(a, 3.0, 0.1);
+    }
+
+    #[test]
+    #[should_panic(expected = "1 of 2 grads differ by more than 0.001:\n  #1")]
+    fn test_all_grads_message() {
+        let a = &Value::new(2.0);
+        let b = &Value::new(3.0);
+        (a * b).backward();
+        assert_all_grads_close(&[(a, 3.0), (b, 3.0)], 1e-3);
+    }
+
+    #[test]
+    fn test_grads_match_numeric() {
+        let params = [Value::new(0.5), Value::new(-1.5)];
+        let f = |p: &[Value]| (&p[0] * &p[1]).tanh() + p[0].pow(3.0);
+        assert_grads_match_numeric(&params, f, 1e-3, 1e-2);
+    }
+
+    #[test]
+    #[should_panic(expected = "1 of 1 grads disagree with finite differences")]
+    fn test_grads_match_numeric_message() {
+        // the custom op claims a grad of zero
+        let f = |p: &[Value]| {
+            Value::custom_unary(&p[0], |x| x * x, |_, _, _| 0.0, "bad")
+        };
+        assert_grads_match_numeric(&[Value::new(2.0)], f, 1e-3, 1e-2);
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn test_grads_match_numeric_par() {
+        let params: Vec<_> = (0..32)
+            .map(|i| Value::new(i as Scalar / 16.0 - 1.0))
+            .collect();
+        let f = |p: &[Value]| {
+            p.windows(2).fold(Value::constant(0.0), |acc, w| {
+                acc + (&w[0] * &w[1]).tanh()
+            })
+        };
+        assert_grads_match_numeric_par(&params, f, 1e-3, 1e-2);
+
+        let numeric =
+            numeric_grads_par(|x| x[0] * x[0] + 3.0 * x[1], &[2.0, 5.0], 1e-3);
+        assert!((numeric[0] - 4.0).abs() < 1e-2);
+        assert!((numeric[1] - 3.0).abs() < 1e-2);
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    #[should_panic(expected = "1 of 1 grads disagree with finite differences")]
+    fn test_grads_match_numeric_par_message() {
+        let f = |p: &[Value]| {
+            Value::custom_unary(&p[0], |x| x * x, |_, _, _| 0.0, "bad")
+        };
+        assert_grads_match_numeric_par(&[Value::new(2.0)], f, 1e-3, 1e-2);
+    }
+}
@@ -0,0 +1,1854 @@
+//! A ready-made training loop, so examples don't each write their own.
+
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display};
+use std::io::{self, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+use crate::clock::Stopwatch;
+use crate::data::{k_fold, Batch, DataLoader, Dataset};
+use crate::engine::{Scalar, Value};
+use crate::losses::{l1_penalty, Loss};
+use crate::metrics::ConfusionMatrix;
+use crate::nn::{argmax, Activation, Module};
+use crate::optim::{ClipMode, GradAccumulator, Optimizer, Scheduler};
+
+/// What happened during [`fit`], epoch by epoch.
+#[derive(Clone, Default, PartialEq)]
+pub struct History {
+    /// The epoch after which [`EarlyStopping`] ended training, if it did.
+    pub stopped_epoch: Option<usize>,
+    /// The epoch with the best value of the metric [`EarlyStopping`]
+    /// monitors, and that value.
+    pub best_monitored: Option<(usize, Scalar)>,
+    /// The validation samples' confusion matrix after each epoch; empty
+    /// unless [`FitConfig::confusion_matrix`] asked for it.
+    pub val_confusion: Vec<ConfusionMatrix>,
+    /// Every value recorded during training: `train_loss`, `val_loss`,
+    /// `lr` and `epoch_seconds` from [`fit`] itself, and whatever
+    /// callbacks add. `epoch_seconds` is missing on
+    /// `wasm32-unknown-unknown`, which has no clock.
+    pub recorder: Recorder,
+}
+
+impl History {
+    /// How many epochs ran.
+    pub fn epochs(&self) -> usize {
+        self.recorder.values("train_loss").count()
+    }
+
+    /// The mean loss over each epoch's training samples, as the weights
+    /// were updated during the epoch.
+    pub fn train_loss(&self) -> Vec<Scalar> {
+        self.recorder.values("train_loss").map(|(_, v)| v).collect()
+    }
+
+    /// The mean loss over the validation samples after each epoch; empty
+    /// without a validation loader.
+    pub fn val_loss(&self) -> Vec<Scalar> {
+        self.recorder.values("val_loss").map(|(_, v)| v).collect()
+    }
+
+    /// The epoch with the best recorded value of `name`, the lowest or the
+    /// highest depending on `mode`, and the value. NaNs are skipped and
+    /// ties go to the earliest epoch.
+    pub fn best(&self, name: &str, mode: Mode) -> Option<(usize, Scalar)> {
+        self.recorder
+            .values(name)
+            .filter(|(_, value)| !value.is_nan())
+            .fold(None, |best, (epoch, value)| match best {
+                Some((_, b)) if !mode.improves(value, b) => best,
+                _ => Some((epoch, value)),
+            })
+    }
+
+    /// Writes every record to `path` as CSV; see [`History::write_csv`].
+    #[cfg(feature = "fs")]
+    pub fn to_csv(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        self.write_csv(io::BufWriter::new(file))
+    }
+
+    /// Writes every record as CSV, one row per record with columns
+    /// `epoch,step,metric,value`. `step` is empty for records that cover a
+    /// whole epoch.
+    pub fn write_csv(&self, mut file: impl Write) -> io::Result<()> {
+        writeln!(file, "epoch,step,metric,value")?;
+        for record in self.recorder.records() {
+            let step = record.step.map(|s| s.to_string()).unwrap_or_default();
+            let name = if record.name.contains(&[',', '"', '\n'][..]) {
+                format!("\"{}\"", record.name.replace('"', "\"\""))
+            } else {
+                record.name.clone()
+            };
+            writeln!(
+                file,
+                "{},{},{},{}",
+                record.epoch, step, name, record.value
+            )?;
+        }
+        file.flush()
+    }
+}
+
+impl Display for History {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "History of {} epochs", self.epochs())?;
+        if let Some((_, loss)) = self.recorder.values("train_loss").last() {
+            write!(f, ", train loss {}", loss)?;
+        }
+        if let Some((_, loss)) = self.recorder.values("val_loss").last() {
+            write!(f, ", val loss {}", loss)?;
+        }
+        if let Some(epoch) = self.stopped_epoch {
+            write!(f, ", stopped early after epoch {}", epoch)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for History {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// One value recorded during training.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub epoch: usize,
+    /// The batch within the epoch, for values recorded per batch.
+    pub step: Option<usize>,
+    pub name: String,
+    pub value: Scalar,
+}
+
+/// Collects the [`Record`]s of a training run, in the order they were
+/// made; see [`History::recorder`].
+#[derive(Clone, Default, PartialEq)]
+pub struct Recorder {
+    records: Vec<Record>,
+}
+
+impl Recorder {
+    pub fn record(
+        &mut self,
+        epoch: usize,
+        step: Option<usize>,
+        name: &str,
+        value: Scalar,
+    ) {
+        self.records.push(Record {
+            epoch,
+            step,
+            name: name.to_string(),
+            value,
+        });
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// The (epoch, value) pairs recorded under `name`.
+    pub fn values<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = (usize, Scalar)> + 'a {
+        self.records
+            .iter()
+            .filter(move |r| r.name == name)
+            .map(|r| (r.epoch, r.value))
+    }
+}
+
+impl Display for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Recorder of {} records", self.records.len())
+    }
+}
+
+impl Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// What a [`Callback`] gets to see at the end of an epoch.
+pub struct EpochContext<'a> {
+    /// Counting from 0.
+    pub epoch: usize,
+    /// How many epochs [`fit`] was asked for.
+    pub epochs: usize,
+    pub model: &'a dyn Module,
+    /// The mean loss over the epoch's training samples.
+    pub train_loss: Scalar,
+    /// The loss on the validation loader, if there is one.
+    pub val_loss: Option<Scalar>,
+    /// The validation samples' confusion matrix, if
+    /// [`FitConfig::confusion_matrix`] asked for it.
+    pub val_confusion: Option<ConfusionMatrix>,
+    /// The lr the epoch trained with.
+    pub lr: Scalar,
+    /// How long the epoch took, validation included; `None` where there
+    /// is no clock.
+    pub seconds: Option<Scalar>,
+    /// Including this epoch's losses, which [`fit`] records before any
+    /// configured callback runs.
+    pub history: &'a mut History,
+    stop: bool,
+}
+
+impl EpochContext<'_> {
+    /// Ends training after this epoch.
+    pub fn stop(&mut self) {
+        self.stop = true;
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stop
+    }
+}
+
+/// What a [`Callback`] gets to see at the end of a batch, after any
+/// optimizer step it triggered.
+pub struct BatchContext<'a> {
+    /// Counting from 0.
+    pub epoch: usize,
+    /// How many epochs [`fit`] was asked for.
+    pub epochs: usize,
+    /// The batch within the epoch, counting from 0.
+    pub batch: usize,
+    /// How many batches an epoch has.
+    pub num_batches: usize,
+    /// How many samples the batch held.
+    pub batch_size: usize,
+    /// The batch's mean loss.
+    pub loss: Scalar,
+    /// The mean loss over the epoch's samples so far.
+    pub running_loss: Scalar,
+    pub lr: Scalar,
+    pub model: &'a dyn Module,
+}
+
+/// Hooks into [`fit`]'s loop.
+pub trait Callback {
+    fn on_epoch_end(&mut self, ctx: &mut EpochContext<'_>);
+
+    /// Does nothing by default.
+    fn on_batch_end(&mut self, _ctx: &BatchContext<'_>) {}
+}
+
+/// Fills in [`fit`]'s [`History`]: the first callback of every run.
+struct HistoryRecorder;
+
+impl Callback for HistoryRecorder {
+    fn on_epoch_end(&mut self, ctx: &mut EpochContext<'_>) {
+        let (epoch, history) = (ctx.epoch, &mut *ctx.history);
+        history
+            .recorder
+            .record(epoch, None, "train_loss", ctx.train_loss);
+        if let Some(val_loss) = ctx.val_loss {
+            history.recorder.record(epoch, None, "val_loss", val_loss);
+        }
+        if let Some(confusion) = &ctx.val_confusion {
+            history.val_confusion.push(confusion.clone());
+        }
+        history.recorder.record(epoch, None, "lr", ctx.lr);
+        if let Some(seconds) = ctx.seconds {
+            history
+                .recorder
+                .record(epoch, None, "epoch_seconds", seconds);
+        }
+    }
+}
+
+/// How many of the latest batches [`ProgressReporter`] times its estimates
+/// over.
+const PROGRESS_WINDOW: usize = 20;
+
+/// Prints how [`fit`] is getting on: every `interval` batches the epoch,
+/// batch, running loss, throughput and estimated time left, and a summary
+/// at the end of every epoch. Throughput and the estimate come from the
+/// wall-clock time of the last few batches, and are left out where there
+/// is no clock.
+///
+/// ```
+/// # use smolgrad::train::{FitConfig, ProgressReporter};
+/// let mut out = Vec::new();
+/// let reporter = ProgressReporter::with_writer(&mut out).interval(50);
+/// let config = FitConfig::default().callback(reporter);
+/// ```
+pub struct ProgressReporter<'a> {
+    out: Box<dyn Write + 'a>,
+    interval: usize,
+    quiet: bool,
+    last_batch: Stopwatch,
+    /// (seconds, samples) of recent batches, oldest first.
+    recent: VecDeque<(Scalar, usize)>,
+}
+
+impl ProgressReporter<'static> {
+    /// Prints to stdout every 10 batches.
+    pub fn new() -> Self {
+        ProgressReporter::with_writer(io::stdout())
+    }
+}
+
+impl Default for ProgressReporter<'static> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> ProgressReporter<'a> {
+    /// Like `new`, writing to `out` instead. Write errors are ignored, so
+    /// a broken pipe can't stop training.
+    pub fn with_writer(out: impl Write + 'a) -> Self {
+        Self {
+            out: Box::new(out),
+            interval: 10,
+            quiet: false,
+            last_batch: Stopwatch::start(),
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Prints a batch line every `interval` batches, and after an epoch's
+    /// last batch; 0 prints none.
+    pub fn interval(self, interval: usize) -> Self {
+        Self { interval, ..self }
+    }
+
+    /// Prints only the summary line of each epoch.
+    pub fn quiet(self, quiet: bool) -> Self {
+        Self { quiet, ..self }
+    }
+
+    /// Samples per second and seconds per batch over the recent batches.
+    fn rates(&self) -> Option<(Scalar, Scalar)> {
+        let seconds: Scalar = self.recent.iter().map(|&(s, _)| s).sum();
+        let samples: usize = self.recent.iter().map(|&(_, n)| n).sum();
+        if seconds <= 0.0 {
+            return None;
+        }
+        let per_batch = seconds / self.recent.len() as Scalar;
+        Some((samples as Scalar / seconds, per_batch))
+    }
+}
+
+/// `seconds` as `1h02m03s`, `2m03s` or `3s`.
+fn format_duration(seconds: Scalar) -> String {
+    let total = seconds.round() as u64;
+    let (h, m, s) = (total / 3600, total / 60 % 60, total % 60);
+    if h > 0 {
+        format!("{}h{:02}m{:02}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m{:02}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+impl Callback for ProgressReporter<'_> {
+    fn on_batch_end(&mut self, ctx: &BatchContext<'_>) {
+        if let Some(elapsed) = self.last_batch.elapsed() {
+            let seconds = elapsed.as_secs_f64() as Scalar;
+            self.recent.push_back((seconds, ctx.batch_size));
+            if self.recent.len() > PROGRESS_WINDOW {
+                self.recent.pop_front();
+            }
+        }
+        self.last_batch = Stopwatch::start();
+        let last = ctx.batch + 1 == ctx.num_batches;
+        if self.quiet
+            || self.interval == 0
+            || !((ctx.batch + 1).is_multiple_of(self.interval) || last)
+        {
+            return;
+        }
+        let mut line = format!(
+            "epoch {}/{}, batch {}/{}, loss {:.4}",
+            ctx.epoch + 1,
+            ctx.epochs,
+            ctx.batch + 1,
+            ctx.num_batches,
+            ctx.running_loss
+        );
+        if let Some((samples_per_sec, per_batch)) = self.rates() {
+            let epochs_left = ctx.epochs - ctx.epoch - 1;
+            let batches_left =
+                ctx.num_batches - ctx.batch - 1 + epochs_left * ctx.num_batches;
+            line += &format!(
+                ", {:.1} samples/s, eta {}",
+                samples_per_sec,
+                format_duration(per_batch * batches_left as Scalar)
+            );
+        }
+        let _ = writeln!(self.out, "{}", line);
+    }
+
+    fn on_epoch_end(&mut self, ctx: &mut EpochContext<'_>) {
+        let mut line = format!(
+            "epoch {}/{}: train loss {:.4}",
+            ctx.epoch + 1,
+            ctx.epochs,
+            ctx.train_loss
+        );
+        if let Some(val_loss) = ctx.val_loss {
+            line += &format!(", val loss {:.4}", val_loss);
+        }
+        if let Some(seconds) = ctx.seconds {
+            line += &format!(", {:.2}s", seconds);
+        }
+        let _ = writeln!(self.out, "{}", line);
+        let _ = self.out.flush();
+    }
+}
+
+/// The quantity [`EarlyStopping`] watches; lower is better.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Monitor {
+    #[default]
+    ValLoss,
+    TrainLoss,
+}
+
+impl Monitor {
+    /// The name [`fit`] records the quantity under.
+    pub fn name(self) -> &'static str {
+        match self {
+            Monitor::ValLoss => "val_loss",
+            Monitor::TrainLoss => "train_loss",
+        }
+    }
+}
+
+/// Whether lower values of a metric are better, as for a loss, or higher
+/// ones, as for an accuracy; see [`History::best`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Min,
+    Max,
+}
+
+impl Mode {
+    /// Whether `value` is strictly better than `best`.
+    fn improves(self, value: Scalar, best: Scalar) -> bool {
+        match self {
+            Mode::Min => value < best,
+            Mode::Max => value > best,
+        }
+    }
+}
+
+/// Stops [`fit`] once the monitored loss hasn't improved for `patience`
+/// epochs in a row, and optionally puts back the weights from the best
+/// epoch when it does. Improving means dropping by more than `min_delta`
+/// below the best value so far; NaN never improves.
+///
+/// ```
+/// # use smolgrad::train::{EarlyStopping, FitConfig, Monitor};
+/// let stopping = EarlyStopping::new(5)
+///     .monitor(Monitor::TrainLoss)
+///     .min_delta(1e-4)
+///     .restore_best_weights(true);
+/// let config = FitConfig::default().callback(stopping);
+/// ```
+#[derive(Debug, Clone)]
+pub struct EarlyStopping {
+    monitor: Monitor,
+    patience: usize,
+    min_delta: Scalar,
+    restore_best_weights: bool,
+    best: Option<(usize, Scalar)>,
+    best_weights: Vec<Scalar>,
+    wait: usize,
+}
+
+impl EarlyStopping {
+    /// Monitors the validation loss with no minimum delta, and leaves the
+    /// weights from the last epoch in place.
+    pub fn new(patience: usize) -> Self {
+        Self {
+            monitor: Monitor::ValLoss,
+            patience,
+            min_delta: 0.0,
+            restore_best_weights: false,
+            best: None,
+            best_weights: vec![],
+            wait: 0,
+        }
+    }
+
+    pub fn monitor(self, monitor: Monitor) -> Self {
+        Self { monitor, ..self }
+    }
+
+    pub fn min_delta(self, min_delta: Scalar) -> Self {
+        Self { min_delta, ..self }
+    }
+
+    /// On stopping, sets the model's parameters back to the best epoch's,
+    /// which means copying them out at every improvement.
+    pub fn restore_best_weights(self, restore_best_weights: bool) -> Self {
+        Self {
+            restore_best_weights,
+            ..self
+        }
+    }
+}
+
+impl Callback for EarlyStopping {
+    /// # Panics
+    ///
+    /// If monitoring the validation loss without a validation loader.
+    fn on_epoch_end(&mut self, ctx: &mut EpochContext<'_>) {
+        let value = ctx
+            .history
+            .recorder
+            .values(self.monitor.name())
+            .find(|&(epoch, _)| epoch == ctx.epoch)
+            .map(|(_, value)| value)
+            .unwrap_or_else(|| {
+                panic!(
+                    "EarlyStopping has no {:?} for epoch {}",
+                    self.monitor, ctx.epoch
+                )
+            });
+        if self
+            .best
+            .is_none_or(|(_, best)| value < best - self.min_delta)
+        {
+            self.best = Some((ctx.epoch, value));
+            self.wait = 0;
+            if self.restore_best_weights {
+                self.best_weights = ctx.model.get_parameters_flat();
+            }
+        } else {
+            self.wait += 1;
+        }
+        ctx.history.best_monitored = self.best;
+        if self.wait > 0 && self.wait >= self.patience {
+            ctx.stop();
+            ctx.history.stopped_epoch = Some(ctx.epoch);
+            if self.restore_best_weights {
+                ctx.model
+                    .set_parameters_flat(&self.best_weights)
+                    .expect("the model's parameters changed during fit");
+            }
+        }
+    }
+}
+
+/// Options for [`fit`]. The default steps on every batch and trains
+/// without clipping, penalties, validation, a schedule or callbacks.
+#[derive(Default)]
+pub struct FitConfig<'a> {
+    clip: Option<ClipMode>,
+    l1: Scalar,
+    accumulation_steps: usize,
+    validation: Option<DataLoader<&'a dyn Dataset>>,
+    confusion_classes: Option<usize>,
+    scheduler: Option<Box<dyn Scheduler + 'a>>,
+    callbacks: Vec<Box<dyn Callback + 'a>>,
+}
+
+impl<'a> FitConfig<'a> {
+    /// Clips the gradients between `backward` and every optimizer step.
+    pub fn clip(self, clip: ClipMode) -> Self {
+        Self {
+            clip: Some(clip),
+            ..self
+        }
+    }
+
+    /// Adds an [`l1_penalty`] on the model's parameters to every batch's
+    /// loss. It's left out of the recorded losses.
+    pub fn l1(self, l1: Scalar) -> Self {
+        Self { l1, ..self }
+    }
+
+    /// Accumulates gradients over `steps` batches per optimizer step with a
+    /// [`GradAccumulator`], stepping on whatever is left at the end of an
+    /// epoch.
+    ///
+    /// # Panics
+    ///
+    /// If `steps` is 0.
+    pub fn accumulation_steps(self, steps: usize) -> Self {
+        assert!(steps > 0, "accumulation needs at least one step");
+        Self {
+            accumulation_steps: steps,
+            ..self
+        }
+    }
+
+    /// Evaluates the model on `validation` after every epoch.
+    pub fn validation(self, validation: DataLoader<&'a dyn Dataset>) -> Self {
+        Self {
+            validation: Some(validation),
+            ..self
+        }
+    }
+
+    /// Also counts a [`ConfusionMatrix`] of `num_classes` classes over the
+    /// validation samples after every epoch, in the same pass as the
+    /// validation loss. A sample's predicted class is its largest output and
+    /// its true class its largest target value, as with one-hot targets.
+    /// Does nothing without [validation](FitConfig::validation).
+    pub fn confusion_matrix(self, num_classes: usize) -> Self {
+        Self {
+            confusion_classes: Some(num_classes),
+            ..self
+        }
+    }
+
+    /// Sets the optimizer's lr from `scheduler`, stepped once per epoch.
+    pub fn scheduler(self, scheduler: impl Scheduler + 'a) -> Self {
+        Self {
+            scheduler: Some(Box::new(scheduler)),
+            ..self
+        }
+    }
+
+    /// Adds a callback, run after those added before it.
+    pub fn callback(mut self, callback: impl Callback + 'a) -> Self {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+}
+
+/// The mean loss of `model` over the samples in `batch`.
+fn batch_loss(
+    model: &(impl Module + ?Sized),
+    loss: &(impl Loss + ?Sized),
+    batch: &Batch,
+) -> Value {
+    loss.batch_loss(&model.forward_batch_scalars(&batch.xs), &batch.ys)
+}
+
+/// The mean loss of `model` over one epoch of `loader`, without touching
+/// any gradients. NaN if the loader yields no samples.
+pub fn evaluate<D: Dataset>(
+    model: &(impl Module + ?Sized),
+    loss: &(impl Loss + ?Sized),
+    loader: &mut DataLoader<D>,
+) -> Scalar {
+    validate(model, loss, loader, None)
+}
+
+/// [`evaluate`], also counting each sample's predicted and true class, the
+/// largest of its outputs and of its target, into `confusion`.
+fn validate<D: Dataset>(
+    model: &(impl Module + ?Sized),
+    loss: &(impl Loss + ?Sized),
+    loader: &mut DataLoader<D>,
+    mut confusion: Option<&mut ConfusionMatrix>,
+) -> Scalar {
+    let (mut total, mut count) = (0.0, 0);
+    for batch in loader {
+        let preds = model.forward_batch_scalars(&batch.xs);
+        let batch_loss = loss.batch_loss(&preds, &batch.ys).get_data();
+        total += batch_loss * batch.len() as Scalar;
+        count += batch.len();
+        if let Some(confusion) = confusion.as_deref_mut() {
+            let class = |p: &Vec<Value>| {
+                argmax(&p.iter().map(Value::get_data).collect::<Vec<_>>())
+            };
+            let preds: Vec<_> = preds.iter().map(class).collect();
+            let targets: Vec<_> = batch.ys.iter().map(|y| argmax(y)).collect();
+            confusion.update(&preds, &targets);
+        }
+    }
+    total / count as Scalar
+}
+
+/// Trains `model` for `epochs` passes over `loader`: for every batch,
+/// forward, loss, `zero_grad`, `backward` and `step`, or with
+/// [accumulation](FitConfig::accumulation_steps) a step every few batches.
+///
+/// A batch's loss is the mean of `loss` over its samples. Each epoch's
+/// losses, lr and duration go into the returned [`History`]. The model
+/// runs each batch at once through [`Module::forward_batch`], and is left
+/// in whatever training mode it's in, for validation too. The configured
+/// callbacks see the end of every batch and epoch, in the order they were
+/// added.
+///
+/// ```
+/// # use smolgrad::data::{DataLoader, InMemoryDataset};
+/// # use smolgrad::losses::Mse;
+/// # use smolgrad::nn::{Activation, Module, MLP};
+/// # use smolgrad::optim::{Adam, ClipMode};
+/// # use smolgrad::train::{fit, FitConfig};
+/// let xs = vec![vec![0.0], vec![1.0]];
+/// let ys = vec![vec![1.0], vec![-1.0]];
+/// let data = InMemoryDataset::new(xs, ys).unwrap();
+/// let model = MLP::new(1, &[4, 1], Activation::Tanh);
+/// let mut opt = Adam::new(model.parameters(), 0.05);
+/// let mut loader = DataLoader::new(&data, 2);
+/// let config = FitConfig::default().clip(ClipMode::Norm(1.0));
+/// let history = fit(&model, &mut opt, &Mse, &mut loader, 20, config);
+/// assert_eq!(history.epochs(), 20);
+/// ```
+pub fn fit<M, O, L, D>(
+    model: &M,
+    optimizer: &mut O,
+    loss: &L,
+    loader: &mut DataLoader<D>,
+    epochs: usize,
+    mut config: FitConfig<'_>,
+) -> History
+where
+    M: Module + ?Sized,
+    O: Optimizer,
+    L: Loss + ?Sized,
+    D: Dataset,
+{
+    let params = model.parameters();
+    let mut history = History::default();
+    let mut accumulator =
+        GradAccumulator::new(config.accumulation_steps.max(1));
+    let clip = config.clip;
+    let step = |optimizer: &mut O, accumulator: &mut GradAccumulator| {
+        accumulator.finish(optimizer);
+        if let Some(clip) = clip {
+            clip.apply(&params);
+        }
+        optimizer.step();
+    };
+    if let Some(scheduler) = &mut config.scheduler {
+        scheduler.init(optimizer);
+    }
+    let num_batches = loader.num_batches();
+    config.callbacks.insert(0, Box::new(HistoryRecorder));
+    for epoch in 0..epochs {
+        let timer = Stopwatch::start();
+        let lr = optimizer.lr();
+        let (mut total, mut count) = (0.0, 0);
+        for (i, batch) in (&mut *loader).into_iter().enumerate() {
+            let batch_loss = batch_loss(model, loss, &batch);
+            let objective = if config.l1 != 0.0 {
+                l1_penalty(&params, config.l1) + &batch_loss
+            } else {
+                batch_loss.clone()
+            };
+            if accumulator.accumulate(optimizer, &objective, batch.len()) {
+                step(optimizer, &mut accumulator);
+            }
+            total += batch_loss.get_data() * batch.len() as Scalar;
+            count += batch.len();
+
+            let ctx = BatchContext {
+                epoch,
+                epochs,
+                batch: i,
+                num_batches,
+                batch_size: batch.len(),
+                loss: batch_loss.get_data(),
+                running_loss: total / count as Scalar,
+                lr: optimizer.lr(),
+                model: &model,
+            };
+            for callback in &mut config.callbacks {
+                callback.on_batch_end(&ctx);
+            }
+        }
+        if accumulator.pending() > 0 {
+            step(optimizer, &mut accumulator);
+        }
+        let (val_loss, val_confusion) = match &mut config.validation {
+            Some(validation) => {
+                let mut confusion =
+                    config.confusion_classes.map(ConfusionMatrix::new);
+                let val_loss =
+                    validate(model, loss, validation, confusion.as_mut());
+                (Some(val_loss), confusion)
+            }
+            None => (None, None),
+        };
+        if let Some(scheduler) = &mut config.scheduler {
+            scheduler.step(optimizer);
+        }
+
+        let mut ctx = EpochContext {
+            epoch,
+            epochs,
+            model: &model,
+            train_loss: total / count as Scalar,
+            val_loss,
+            val_confusion,
+            lr,
+            seconds: timer.elapsed().map(|e| e.as_secs_f64() as Scalar),
+            history: &mut history,
+            stop: false,
+        };
+        for callback in &mut config.callbacks {
+            callback.on_epoch_end(&mut ctx);
+        }
+        if ctx.stop {
+            break;
+        }
+    }
+    history
+}
+
+/// The sweep [`lr_find`] runs: `num_steps` optimizer steps with the lr
+/// growing exponentially from `start_lr` to `end_lr`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LrFindConfig {
+    pub start_lr: Scalar,
+    pub end_lr: Scalar,
+    pub num_steps: usize,
+}
+
+impl Default for LrFindConfig {
+    /// 100 steps from 1e-6 to 10.
+    fn default() -> Self {
+        Self {
+            start_lr: 1e-6,
+            end_lr: 10.0,
+            num_steps: 100,
+        }
+    }
+}
+
+/// The loss curve recorded by [`lr_find`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LrFindResult {
+    /// The lr of each step taken.
+    pub lrs: Vec<Scalar>,
+    /// The smoothed loss at each step.
+    pub losses: Vec<Scalar>,
+    /// Whether the sweep stopped early because the loss blew up.
+    pub diverged: bool,
+}
+
+impl LrFindResult {
+    /// The lr where the smoothed loss fell fastest against the log of the
+    /// lr, a reasonable lr to train with. `None` if fewer than three steps
+    /// were recorded.
+    pub fn suggestion(&self) -> Option<Scalar> {
+        // The lrs are evenly spaced in log space, so the steepest slope is
+        // where the loss drops most across neighbouring steps
+        let slopes = (1..self.losses.len().saturating_sub(1))
+            .map(|i| (i, self.losses[i + 1] - self.losses[i - 1]));
+        let steepest = slopes.min_by(|a, b| a.1.total_cmp(&b.1))?;
+        Some(self.lrs[steepest.0])
+    }
+}
+
+/// The decay of the exponential moving average that smooths the losses,
+/// which averages over roughly the last ten steps.
+const LR_FIND_SMOOTHING: Scalar = 0.9;
+/// The sweep stops once the smoothed loss grows past this many times the
+/// lowest seen.
+const LR_FIND_DIVERGENCE: Scalar = 4.0;
+
+/// Runs the learning rate range test: trains `model` for
+/// [`num_steps`](LrFindConfig) batches of `loader`, going round it as many
+/// times as that takes, with the lr ramped exponentially between the
+/// config's bounds, and records the loss at every step.
+///
+/// The optimizer comes from `optimizer`, called with the model's
+/// parameters, and is dropped afterwards. The losses are smoothed with an
+/// exponential moving average, and the sweep stops early once the
+/// smoothed loss is four times its lowest or isn't finite. Afterwards the
+/// model's parameters and their grads are put back exactly as they were,
+/// so the sweep leaves no trace on the model.
+///
+/// # Panics
+///
+/// If `start_lr` isn't positive or `loader` yields no batches.
+pub fn lr_find<M, O, L, D>(
+    model: &M,
+    optimizer: impl FnOnce(Vec<Value>) -> O,
+    loss: &L,
+    loader: &mut DataLoader<D>,
+    config: LrFindConfig,
+) -> LrFindResult
+where
+    M: Module + ?Sized,
+    O: Optimizer,
+    L: Loss + ?Sized,
+    D: Dataset,
+{
+    assert!(config.start_lr > 0.0, "lr_find needs a positive start_lr");
+    let params = model.parameters();
+    let data: Vec<_> = params.iter().map(Value::get_data).collect();
+    let grads: Vec<_> = params.iter().map(Value::get_grad).collect();
+    let mut optimizer = optimizer(params.clone());
+    let steps = config.num_steps;
+    let growth = if steps > 1 {
+        (config.end_lr / config.start_lr).powf(1.0 / (steps - 1) as Scalar)
+    } else {
+        1.0
+    };
+    let mut result = LrFindResult {
+        lrs: vec![],
+        losses: vec![],
+        diverged: false,
+    };
+    let (mut average, mut best) = (0.0, Scalar::INFINITY);
+    'sweep: while result.lrs.len() < steps {
+        let mut empty = true;
+        for batch in loader.iter() {
+            empty = false;
+            let step = result.lrs.len();
+            let lr = config.start_lr * growth.powi(step as i32);
+            optimizer.set_lr(lr);
+            let batch_loss = batch_loss(model, loss, &batch);
+            optimizer.zero_grad();
+            batch_loss.backward();
+            optimizer.step();
+            average = LR_FIND_SMOOTHING * average
+                + (1.0 - LR_FIND_SMOOTHING) * batch_loss.get_data();
+            let smoothed =
+                average / (1.0 - LR_FIND_SMOOTHING.powi(step as i32 + 1));
+            if !smoothed.is_finite() || smoothed > LR_FIND_DIVERGENCE * best {
+                result.diverged = true;
+                break 'sweep;
+            }
+            best = best.min(smoothed);
+            result.lrs.push(lr);
+            result.losses.push(smoothed);
+            if result.lrs.len() == steps {
+                break;
+            }
+        }
+        assert!(!empty, "lr_find needs a loader that yields batches");
+    }
+    for ((p, x), g) in params.iter().zip(data).zip(grads) {
+        p.set_data(x);
+        p.set_grad(g);
+    }
+    result
+}
+
+/// A candidate value for a hyperparameter in a [`ParamGrid`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamValue {
+    Scalar(Scalar),
+    Usize(usize),
+    /// Layer sizes, as [`MLP::new`](crate::nn::MLP::new) takes.
+    Sizes(Vec<usize>),
+    Activation(Activation),
+}
+
+impl From<Scalar> for ParamValue {
+    fn from(x: Scalar) -> Self {
+        ParamValue::Scalar(x)
+    }
+}
+
+impl From<usize> for ParamValue {
+    fn from(n: usize) -> Self {
+        ParamValue::Usize(n)
+    }
+}
+
+impl From<Vec<usize>> for ParamValue {
+    fn from(sizes: Vec<usize>) -> Self {
+        ParamValue::Sizes(sizes)
+    }
+}
+
+impl From<&[usize]> for ParamValue {
+    fn from(sizes: &[usize]) -> Self {
+        ParamValue::Sizes(sizes.to_vec())
+    }
+}
+
+impl From<Activation> for ParamValue {
+    fn from(activation: Activation) -> Self {
+        ParamValue::Activation(activation)
+    }
+}
+
+impl Display for ParamValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParamValue::Scalar(x) => write!(f, "{}", x),
+            ParamValue::Usize(n) => write!(f, "{}", n),
+            ParamValue::Sizes(sizes) => write!(f, "{:?}", sizes),
+            ParamValue::Activation(activation) => write!(f, "{}", activation),
+        }
+    }
+}
+
+/// Named lists of candidate hyperparameter values, searched exhaustively
+/// by [`grid_search`] and [`grid_search_cv`].
+///
+/// ```
+/// # use smolgrad::nn::Activation;
+/// # use smolgrad::train::ParamGrid;
+/// let grid = ParamGrid::new()
+///     .add("lr", [0.1, 0.01])
+///     .add("sizes", [vec![8, 1], vec![16, 16, 1]])
+///     .add("activation", [Activation::ReLU, Activation::Tanh]);
+/// assert_eq!(grid.len(), 8);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParamGrid {
+    params: Vec<(String, Vec<ParamValue>)>,
+}
+
+impl ParamGrid {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a hyperparameter to search over `values`.
+    ///
+    /// # Panics
+    ///
+    /// If the grid already has a parameter called `name`.
+    pub fn add<V: Into<ParamValue>>(
+        mut self,
+        name: &str,
+        values: impl IntoIterator<Item = V>,
+    ) -> Self {
+        assert!(
+            self.params.iter().all(|(n, _)| n != name),
+            "parameter {} is already in the grid",
+            name
+        );
+        let values = values.into_iter().map(Into::into).collect();
+        self.params.push((name.to_string(), values));
+        self
+    }
+
+    /// How many configurations the grid holds.
+    pub fn len(&self) -> usize {
+        self.params.iter().map(|(_, values)| values.len()).product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every combination of values, with the parameter added first varying
+    /// slowest, as in nested loops in the order the parameters were added.
+    pub fn param_sets(&self) -> Vec<ParamSet> {
+        (0..self.len())
+            .map(|index| {
+                // Decode index as a mixed-radix number, last digit fastest
+                let mut rest = index;
+                let mut values: Vec<_> = self
+                    .params
+                    .iter()
+                    .rev()
+                    .map(|(name, values)| {
+                        let value = values[rest % values.len()].clone();
+                        rest /= values.len();
+                        (name.clone(), value)
+                    })
+                    .collect();
+                values.reverse();
+                ParamSet {
+                    values,
+                    index,
+                    fold: None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// One configuration from a [`ParamGrid`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamSet {
+    values: Vec<(String, ParamValue)>,
+    index: usize,
+    fold: Option<usize>,
+}
+
+impl ParamSet {
+    pub fn get(&self, name: &str) -> Option<&ParamValue> {
+        let mut values = self.values.iter();
+        values.find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    /// The names and values, in the order the grid's parameters were added.
+    pub fn values(&self) -> &[(String, ParamValue)] {
+        &self.values
+    }
+
+    fn expect(&self, name: &str) -> &ParamValue {
+        self.get(name)
+            .unwrap_or_else(|| panic!("no parameter {} in {}", name, self))
+    }
+
+    /// # Panics
+    ///
+    /// If there is no parameter `name` or it isn't a scalar, as are the
+    /// other typed getters.
+    pub fn scalar(&self, name: &str) -> Scalar {
+        match self.expect(name) {
+            &ParamValue::Scalar(x) => x,
+            other => panic!("parameter {} is {}, not a scalar", name, other),
+        }
+    }
+
+    pub fn usize(&self, name: &str) -> usize {
+        match self.expect(name) {
+            &ParamValue::Usize(n) => n,
+            other => panic!("parameter {} is {}, not a usize", name, other),
+        }
+    }
+
+    pub fn sizes(&self, name: &str) -> &[usize] {
+        match self.expect(name) {
+            ParamValue::Sizes(sizes) => sizes,
+            other => panic!("parameter {} is {}, not sizes", name, other),
+        }
+    }
+
+    pub fn activation(&self, name: &str) -> &Activation {
+        match self.expect(name) {
+            ParamValue::Activation(activation) => activation,
+            other => {
+                panic!("parameter {} is {}, not an activation", name, other)
+            }
+        }
+    }
+
+    /// The position of the configuration in the grid, the same from run to
+    /// run, to seed its model and data loader with.
+    pub fn seed(&self) -> u64 {
+        self.index as u64
+    }
+
+    /// Under cross-validation, which of the folds from [`k_fold`] to
+    /// validate on. Split the data with the same seed for every
+    /// configuration so they are compared on the same folds.
+    pub fn fold(&self) -> Option<usize> {
+        self.fold
+    }
+}
+
+impl Display for ParamSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let values = self.values.iter().map(|(n, v)| format!("{}={}", n, v));
+        f.write_str(&values.collect::<Vec<_>>().join(", "))
+    }
+}
+
+/// The scores of every configuration tried by [`grid_search`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GridSearchResult {
+    /// Each configuration with its score, best (lowest) first. Ties keep
+    /// grid order and NaN scores come last.
+    pub results: Vec<(ParamSet, Scalar)>,
+}
+
+impl GridSearchResult {
+    /// The configuration with the lowest score; `None` for an empty grid.
+    pub fn best(&self) -> Option<&ParamSet> {
+        self.results.first().map(|(set, _)| set)
+    }
+
+    pub fn best_score(&self) -> Option<Scalar> {
+        self.results.first().map(|&(_, score)| score)
+    }
+}
+
+/// Scores every configuration of `grid` with `build_and_train`, which
+/// should build a model from the [`ParamSet`], train it and return a
+/// validation loss or anything else where lower is better (negate an
+/// accuracy, say). Configurations are tried in [grid
+/// order](ParamGrid::param_sets), one after another.
+///
+/// With `k_folds`, each configuration is scored `k` times, once with each
+/// [`fold`](ParamSet::fold) set, and gets the mean.
+///
+/// # Panics
+///
+/// If `k_folds` is `Some(k)` with `k < 2`.
+pub fn grid_search(
+    grid: ParamGrid,
+    mut build_and_train: impl FnMut(&ParamSet) -> Scalar,
+    k_folds: Option<usize>,
+) -> GridSearchResult {
+    assert!(
+        k_folds.is_none_or(|k| k >= 2),
+        "cross-validation needs at least 2 folds, got {:?}",
+        k_folds
+    );
+    let mut results: Vec<_> = grid
+        .param_sets()
+        .into_iter()
+        .map(|set| {
+            let score = match k_folds {
+                None => build_and_train(&set),
+                Some(k) => {
+                    let folds = (0..k).map(|fold| {
+                        build_and_train(&ParamSet {
+                            fold: Some(fold),
+                            ..set.clone()
+                        })
+                    });
+                    folds.sum::<Scalar>() / k as Scalar
+                }
+            };
+            (set, score)
+        })
+        .collect();
+    results.sort_by(|(_, a), (_, b)| {
+        a.is_nan().cmp(&b.is_nan()).then(a.total_cmp(b))
+    });
+    GridSearchResult { results }
+}
+
+/// [`grid_search`] with `k`-fold cross-validation done for you. `data` is
+/// split once by [`k_fold`] with seed 0, so every configuration sees the
+/// same folds. For each configuration and fold, `build` makes a model and
+/// its optimizer, [`fit`] trains it for `epochs` on the other folds in
+/// shuffled batches of `batch_size`, seeded with the configuration's
+/// [seed](ParamSet::seed), and the score is the mean [`evaluate`] loss on
+/// the held-out folds.
+///
+/// # Panics
+///
+/// Unless `k` is at least 2 and at most the number of samples.
+pub fn grid_search_cv<D, M, O>(
+    grid: ParamGrid,
+    data: &D,
+    k: usize,
+    mut build: impl FnMut(&ParamSet) -> (M, O),
+    loss: &(impl Loss + ?Sized),
+    epochs: usize,
+    batch_size: usize,
+) -> GridSearchResult
+where
+    D: Dataset + ?Sized,
+    M: Module,
+    O: Optimizer,
+{
+    let folds = k_fold(data, k, 0);
+    let build_and_train = |set: &ParamSet| {
+        let (train, val) = &folds[set.fold().expect("fold set by grid_search")];
+        let (model, mut optimizer) = build(set);
+        let mut loader = DataLoader::new(train, batch_size)
+            .shuffle(true)
+            .seed(set.seed());
+        let config = FitConfig::default();
+        fit(&model, &mut optimizer, loss, &mut loader, epochs, config);
+        evaluate(&model, loss, &mut DataLoader::new(val, batch_size))
+    };
+    grid_search(grid, build_and_train, Some(k))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::InMemoryDataset;
+    use crate::losses::{CrossEntropy, Mse};
+    use crate::nn::{Activation, MLP};
+    use crate::optim::Sgd;
+
+    fn line(n: usize) -> InMemoryDataset {
+        let xs: Vec<_> =
+            (0..n).map(|i| vec![i as Scalar / n as Scalar]).collect();
+        let ys = xs.iter().map(|x| vec![2.0 * x[0] - 1.0]).collect();
+        InMemoryDataset::new(xs, ys).unwrap()
+    }
+
+    /// Stops after `at` epochs and records the epochs it saw.
+    struct StopAt<'a> {
+        at: usize,
+        seen: &'a mut Vec<usize>,
+    }
+
+    impl Callback for StopAt<'_> {
+        fn on_epoch_end(&mut self, ctx: &mut EpochContext<'_>) {
+            self.seen.push(ctx.epoch);
+            assert_eq!(ctx.history.epochs(), ctx.epoch + 1);
+            if ctx.epoch + 1 == self.at {
+                ctx.stop();
+            }
+        }
+    }
+
+    #[test]
+    fn test_fit_with_validation() {
+        let (train, val) = (line(16), line(5));
+        let model = MLP::new_seeded(1, &[8, 1], Activation::Tanh, 2);
+        let mut opt = Sgd::new(model.parameters(), 0.1);
+        let mut loader = DataLoader::new(&train, 4).shuffle(true).seed(1);
+        let config = FitConfig::default().validation(DataLoader::new(&val, 2));
+        let history = fit(&model, &mut opt, &Mse, &mut loader, 30, config);
+        assert_eq!(history.epochs(), 30);
+        let (train_loss, val_loss) = (history.train_loss(), history.val_loss());
+        assert_eq!(val_loss.len(), 30);
+        assert!(train_loss[29] < train_loss[0] / 10.0);
+        assert!(val_loss[29] < val_loss[0] / 10.0);
+        let final_val = evaluate(&model, &Mse, &mut DataLoader::new(&val, 5));
+        assert!((final_val - val_loss[29]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_fit_confusion_matrix() {
+        let xs: Vec<_> =
+            (0..8).map(|i| vec![i as Scalar / 4.0 - 1.0]).collect();
+        let ys = xs
+            .iter()
+            .map(|x| {
+                if x[0] < 0.0 {
+                    vec![1.0, 0.0]
+                } else {
+                    vec![0.0, 1.0]
+                }
+            })
+            .collect();
+        let data = InMemoryDataset::new(xs.clone(), ys).unwrap();
+        let model = MLP::new_seeded(1, &[4, 2], Activation::Tanh, 3);
+        let mut opt = Sgd::new(model.parameters(), 0.1);
+        let mut loader = DataLoader::new(&data, 4);
+        let config = FitConfig::default()
+            .validation(DataLoader::new(&data, 3))
+            .confusion_matrix(2);
+        let history = fit(
+            &model,
+            &mut opt,
+            &CrossEntropy::new(),
+            &mut loader,
+            3,
+            config,
+        );
+        assert_eq!(history.val_confusion.len(), 3);
+        let preds: Vec<_> =
+            xs.iter().map(|x| argmax(&model.predict(x))).collect();
+        let targets = [0, 0, 0, 0, 1, 1, 1, 1];
+        let expected = ConfusionMatrix::from_preds(&preds, &targets, 2);
+        assert_eq!(history.val_confusion[2], expected);
+
+        // without validation there is nothing to count
+        let config = FitConfig::default().confusion_matrix(2);
+        let history = fit(
+            &model,
+            &mut opt,
+            &CrossEntropy::new(),
+            &mut loader,
+            1,
+            config,
+        );
+        assert!(history.val_confusion.is_empty());
+    }
+
+    #[test]
+    fn test_fit_callbacks_stop() {
+        let data = line(4);
+        let model = MLP::new(1, &[2, 1], Activation::Tanh);
+        let mut opt = Sgd::new(model.parameters(), 0.1);
+        let mut seen = vec![];
+        let config = FitConfig::default().callback(StopAt {
+            at: 3,
+            seen: &mut seen,
+        });
+        let mut loader = DataLoader::new(&data, 4);
+        let history = fit(&model, &mut opt, &Mse, &mut loader, 10, config);
+        assert_eq!(history.epochs(), 3);
+        assert!(history.val_loss().is_empty());
+        assert_eq!(seen, [0, 1, 2]);
+    }
+
+    /// Counts the batches it sees, and records the count at the end of
+    /// every epoch.
+    struct CountBatches<'a> {
+        batches: usize,
+        epochs: &'a mut Vec<(usize, usize)>,
+    }
+
+    impl Callback for CountBatches<'_> {
+        fn on_batch_end(&mut self, ctx: &BatchContext<'_>) {
+            assert_eq!((ctx.batch, ctx.num_batches), (self.batches % 3, 3));
+            assert_eq!(ctx.batch_size, if ctx.batch < 2 { 4 } else { 2 });
+            assert!(ctx.loss.is_finite() && ctx.running_loss.is_finite());
+            self.batches += 1;
+        }
+
+        fn on_epoch_end(&mut self, ctx: &mut EpochContext<'_>) {
+            assert_eq!(ctx.history.train_loss()[ctx.epoch], ctx.train_loss);
+            self.epochs.push((ctx.epoch, self.batches));
+        }
+    }
+
+    #[test]
+    fn test_fit_batch_callbacks() {
+        let data = line(10);
+        let model = MLP::new(1, &[2, 1], Activation::Tanh);
+        let mut opt = Sgd::new(model.parameters(), 0.1);
+        let mut epochs = vec![];
+        let counter = CountBatches {
+            batches: 0,
+            epochs: &mut epochs,
+        };
+        let config = FitConfig::default().callback(counter);
+        let mut loader = DataLoader::new(&data, 4);
+        fit(&model, &mut opt, &Mse, &mut loader, 4, config);
+        assert_eq!(epochs, [(0, 3), (1, 6), (2, 9), (3, 12)]);
+    }
+
+    #[test]
+    fn test_progress_reporter() {
+        let (train, val) = (line(10), line(4));
+        let model = MLP::new(1, &[2, 1], Activation::Tanh);
+        let mut opt = Sgd::new(model.parameters(), 0.1);
+        let mut out = Vec::new();
+        let reporter = ProgressReporter::with_writer(&mut out).interval(2);
+        let config = FitConfig::default()
+            .validation(DataLoader::new(&val, 4))
+            .callback(reporter);
+        let mut loader = DataLoader::new(&train, 4);
+        let history = fit(&model, &mut opt, &Mse, &mut loader, 2, config);
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        // Batches 2 and 3 (the last) of each epoch, then the summary
+        assert_eq!(lines.len(), 6, "{}", out);
+        assert!(lines[0].starts_with("epoch 1/2, batch 2/3, loss "));
+        assert!(lines[1].starts_with("epoch 1/2, batch 3/3, loss "));
+        assert!(lines[1].contains(" samples/s, eta "), "{}", lines[1]);
+        let summary = format!(
+            "epoch 2/2: train loss {:.4}, val loss {:.4}, ",
+            history.train_loss()[1],
+            history.val_loss()[1]
+        );
+        assert!(lines[5].starts_with(&summary), "{}", lines[5]);
+
+        let mut out = Vec::new();
+        let quiet = ProgressReporter::with_writer(&mut out).quiet(true);
+        let mut loader = DataLoader::new(&train, 4);
+        let config = FitConfig::default().callback(quiet);
+        fit(&model, &mut opt, &Mse, &mut loader, 3, config);
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 3);
+        assert!(out.lines().all(|l| l.contains(": train loss ")));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0.2), "0s");
+        assert_eq!(format_duration(59.6), "1m00s");
+        assert_eq!(format_duration(3723.0), "1h02m03s");
+    }
+
+    #[test]
+    fn test_fit_clip() {
+        let data = line(4);
+        let model = MLP::new_seeded(1, &[2, 1], Activation::Tanh, 0);
+        let before = model.get_parameters_flat();
+        let mut opt = Sgd::new(model.parameters(), 1.0);
+        let config = FitConfig::default().clip(ClipMode::Value(1e-3));
+        let mut loader = DataLoader::new(&data, 4);
+        fit(&model, &mut opt, &Mse, &mut loader, 1, config);
+        for (a, b) in model.get_parameters_flat().iter().zip(before) {
+            assert!((a - b).abs() <= 1e-3 + 1e-6);
+        }
+    }
+
+    /// Stands in for a validation loader, reporting a fixed sequence of
+    /// losses and nudging the weights so every epoch's are different.
+    struct Scripted(Vec<Scalar>);
+
+    impl Callback for Scripted {
+        fn on_epoch_end(&mut self, ctx: &mut EpochContext<'_>) {
+            let loss = self.0[ctx.epoch];
+            ctx.history
+                .recorder
+                .record(ctx.epoch, None, "val_loss", loss);
+            let params = ctx.model.get_parameters_flat();
+            let nudged: Vec<_> = params.iter().map(|p| p + 1.0).collect();
+            ctx.model.set_parameters_flat(&nudged).unwrap();
+        }
+    }
+
+    fn fit_scripted(
+        losses: &[Scalar],
+        stopping: EarlyStopping,
+    ) -> (History, Vec<Vec<Scalar>>) {
+        let data = line(4);
+        let model = MLP::new(1, &[2, 1], Activation::Tanh);
+        let mut opt = Sgd::new(model.parameters(), 0.0);
+        let mut weights = vec![];
+        let config = FitConfig::default()
+            .callback(Scripted(losses.to_vec()))
+            .callback(Record(&mut weights))
+            .callback(stopping);
+        let mut loader = DataLoader::new(&data, 4);
+        let history = fit(&model, &mut opt, &Mse, &mut loader, 20, config);
+        weights.push(model.get_parameters_flat());
+        (history, weights)
+    }
+
+    /// Copies out the weights at the end of every epoch.
+    struct Record<'a>(&'a mut Vec<Vec<Scalar>>);
+
+    impl Callback for Record<'_> {
+        fn on_epoch_end(&mut self, ctx: &mut EpochContext<'_>) {
+            self.0.push(ctx.model.get_parameters_flat());
+        }
+    }
+
+    #[test]
+    fn test_early_stopping() {
+        let losses = [5.0, 4.0, 3.0, 3.5, 2.9995, 3.2, 3.1, 1.0, 0.5];
+        let stopping = EarlyStopping::new(3).min_delta(0.01);
+        let (history, weights) = fit_scripted(&losses, stopping);
+        // Epoch 4 is within min_delta of epoch 2, so 3, 4 and 5 all fail
+        // to improve
+        assert_eq!(history.epochs(), 6);
+        assert_eq!(history.stopped_epoch, Some(5));
+        assert_eq!(history.best_monitored, Some((2, 3.0)));
+        assert_eq!(weights[6], weights[5]);
+
+        let (history, _) = fit_scripted(&losses, EarlyStopping::new(0));
+        assert_eq!(history.stopped_epoch, Some(3));
+        let improving: Vec<_> = (0..20).map(|i| 20.0 - i as Scalar).collect();
+        let (history, _) = fit_scripted(&improving, EarlyStopping::new(1));
+        assert_eq!(history.stopped_epoch, None);
+        assert_eq!(history.epochs(), 20);
+        assert_eq!(history.best_monitored, Some((19, 1.0)));
+    }
+
+    #[test]
+    fn test_early_stopping_restores_best_weights() {
+        let losses = [5.0, 4.0, 3.0, 3.5, 3.2, 3.1, 1.0];
+        let stopping = EarlyStopping::new(3).restore_best_weights(true);
+        let (history, weights) = fit_scripted(&losses, stopping);
+        assert_eq!(history.stopped_epoch, Some(5));
+        assert_ne!(weights[5], weights[2]);
+        assert_eq!(weights[6], weights[2]);
+        assert!(history.to_string().ends_with("stopped early after epoch 5"));
+    }
+
+    #[test]
+    #[should_panic(expected = "EarlyStopping has no ValLoss for epoch 0")]
+    fn test_early_stopping_needs_validation() {
+        let data = line(4);
+        let model = MLP::new(1, &[2, 1], Activation::Tanh);
+        let mut opt = Sgd::new(model.parameters(), 0.1);
+        let config = FitConfig::default().callback(EarlyStopping::new(2));
+        let mut loader = DataLoader::new(&data, 4);
+        fit(&model, &mut opt, &Mse, &mut loader, 5, config);
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_history_csv() {
+        let (train, val) = (line(8), line(3));
+        let model = MLP::new_seeded(1, &[4, 1], Activation::Tanh, 3);
+        let mut opt = Sgd::new(model.parameters(), 0.2);
+        let config = FitConfig::default()
+            .validation(DataLoader::new(&val, 3))
+            .scheduler(crate::optim::StepLr::new(1, 0.5));
+        let mut loader = DataLoader::new(&train, 4);
+        let history = fit(&model, &mut opt, &Mse, &mut loader, 3, config);
+        let lrs: Vec<_> = history.recorder.values("lr").collect();
+        assert_eq!(lrs, [(0, 0.2), (1, 0.1), (2, 0.05)]);
+        assert_eq!(opt.lr(), 0.025);
+
+        let path = std::env::temp_dir()
+            .join(format!("smolgrad-{}-history.csv", std::process::id()));
+        history.to_csv(&path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("epoch,step,metric,value"));
+        let rows: Vec<Vec<_>> = lines.map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows.len(), 12);
+        let epochs: Vec<usize> =
+            rows.iter().map(|r| r[0].parse().unwrap()).collect();
+        assert!(epochs.windows(2).all(|e| e[0] <= e[1]));
+        assert_eq!(epochs[11], 2);
+        for (i, row) in rows.iter().enumerate() {
+            let metrics = ["train_loss", "val_loss", "lr", "epoch_seconds"];
+            assert_eq!(row[1], "");
+            assert_eq!(row[2], metrics[i % 4]);
+            let value: Scalar = row[3].parse().unwrap();
+            if row[2] == "val_loss" {
+                assert_eq!(value, history.val_loss()[epochs[i]]);
+            }
+        }
+
+        let val_loss = history.val_loss();
+        let best = history.best("val_loss", Mode::Min).unwrap();
+        let lowest = val_loss.iter().cloned().fold(Scalar::MAX, Scalar::min);
+        assert_eq!(best.1, lowest);
+        assert_eq!(val_loss[best.0], lowest);
+        assert_eq!(history.best("accuracy", Mode::Max), None);
+    }
+
+    #[test]
+    fn test_recorder_from_callback() {
+        struct Accuracy;
+        impl Callback for Accuracy {
+            fn on_epoch_end(&mut self, ctx: &mut EpochContext<'_>) {
+                let value = [0.5, 0.9, 0.7][ctx.epoch];
+                ctx.history.recorder.record(ctx.epoch, None, "error", value);
+                ctx.history
+                    .recorder
+                    .record(ctx.epoch, Some(3), "a,\"b\"", 1.0);
+            }
+        }
+        let data = line(4);
+        let model = MLP::new(1, &[2, 1], Activation::Tanh);
+        let mut opt = Sgd::new(model.parameters(), 0.1);
+        let config = FitConfig::default().callback(Accuracy);
+        let mut loader = DataLoader::new(&data, 4);
+        let history = fit(&model, &mut opt, &Mse, &mut loader, 3, config);
+        assert_eq!(history.best("error", Mode::Min), Some((0, 0.5)));
+        assert_eq!(history.best("error", Mode::Max), Some((1, 0.9)));
+
+        let mut csv = Vec::new();
+        history.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.contains("\n0,3,\"a,\"\"b\"\"\",1\n"));
+    }
+
+    #[test]
+    fn test_lr_find() {
+        let data = line(16);
+        let model = MLP::new_seeded(1, &[1], Activation::Linear, 4);
+        let before = model.get_parameters_flat();
+        let mut loader = DataLoader::new(&data, 16);
+        let config = LrFindConfig {
+            start_lr: 1e-3,
+            end_lr: 10.0,
+            num_steps: 100,
+        };
+        let sgd = |p| Sgd::new(p, 0.0);
+        let result = lr_find(&model, sgd, &Mse, &mut loader, config);
+        assert_eq!(result.lrs.len(), result.losses.len());
+        assert!(result.lrs.len() <= 100);
+        // Plain gradient descent on this least squares problem diverges
+        // past an lr of about 0.75
+        let suggestion = result.suggestion().unwrap();
+        assert!(
+            (0.05..0.75).contains(&suggestion),
+            "suggested {}",
+            suggestion
+        );
+
+        let after = model.get_parameters_flat();
+        let bits =
+            |xs: &[Scalar]| xs.iter().map(|x| x.to_bits()).collect::<Vec<_>>();
+        assert_eq!(bits(&after), bits(&before));
+        assert!(model.parameters().iter().all(|p| p.get_grad() == 0.0));
+    }
+
+    #[test]
+    fn test_lr_find_full_sweep() {
+        let data = line(16);
+        let model = MLP::new_seeded(1, &[1], Activation::Linear, 4);
+        // 30 steps go round the 4 batches several times
+        let mut loader = DataLoader::new(&data, 4);
+        let config = LrFindConfig {
+            start_lr: 1e-4,
+            end_lr: 0.1,
+            num_steps: 30,
+        };
+        let sgd = |p| Sgd::new(p, 0.0);
+        let result = lr_find(&model, sgd, &Mse, &mut loader, config);
+        assert!(!result.diverged);
+        assert_eq!(result.losses.len(), 30);
+        assert!((result.lrs[0] - 1e-4).abs() < 1e-9);
+        assert!((result.lrs[29] - 0.1).abs() < 1e-5);
+        let mut ratios = result.lrs.windows(2).map(|w| w[1] / w[0]);
+        assert!(ratios.all(|r| (r - 1.268_961).abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_lr_find_diverges() {
+        let data = line(16);
+        let model = MLP::new_seeded(1, &[1], Activation::Linear, 4);
+        let before = model.get_parameters_flat();
+        let mut loader = DataLoader::new(&data, 4);
+        let config = LrFindConfig {
+            start_lr: 1e-3,
+            end_lr: 1e3,
+            num_steps: 60,
+        };
+        let result =
+            lr_find(&model, |p| Sgd::new(p, 0.0), &Mse, &mut loader, config);
+        assert!(result.diverged);
+        assert!(result.lrs.len() < 60);
+        assert_eq!(result.lrs.len(), result.losses.len());
+        let best = result.losses.iter().cloned().fold(Scalar::MAX, Scalar::min);
+        assert!(result.losses.iter().all(|&l| l <= 4.0 * best));
+        assert_eq!(model.get_parameters_flat(), before);
+    }
+
+    #[test]
+    fn test_grid_search() {
+        let grid = ParamGrid::new()
+            .add("lr", [0.1, 0.01])
+            .add("hidden", [4, 8, 16]);
+        assert_eq!(grid.len(), 6);
+        let mut seen = vec![];
+        let result = grid_search(
+            grid,
+            |set| {
+                seen.push((set.scalar("lr"), set.usize("hidden"), set.seed()));
+                assert_eq!(set.fold(), None);
+                // Scores that favour lr 0.01 with 8 hidden units
+                (set.scalar("lr") - 0.01).abs()
+                    + set.usize("hidden") as Scalar % 8.0
+            },
+            None,
+        );
+        assert_eq!(
+            seen,
+            [
+                (0.1, 4, 0),
+                (0.1, 8, 1),
+                (0.1, 16, 2),
+                (0.01, 4, 3),
+                (0.01, 8, 4),
+                (0.01, 16, 5)
+            ]
+        );
+        let best = result.best().unwrap();
+        assert_eq!(best.to_string(), "lr=0.01, hidden=8");
+        assert_eq!(best.seed(), 4);
+        assert_eq!(result.best_score(), Some(0.0));
+        // 16 % 8 == 0 too, so lr 0.01 with 16 ties and keeps grid order
+        assert_eq!(result.results[1].0.seed(), 5);
+        let scores: Vec<_> = result.results.iter().map(|r| r.1).collect();
+        assert!(scores.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_grid_search_nan_and_folds() {
+        let grid = ParamGrid::new().add("x", [1.0, 2.0, 3.0]);
+        let result = grid_search(
+            grid.clone(),
+            |set| {
+                let x = set.scalar("x");
+                if x == 1.0 {
+                    Scalar::NAN
+                } else {
+                    -x
+                }
+            },
+            None,
+        );
+        let order: Vec<_> = result.results.iter().map(|r| r.0.seed()).collect();
+        assert_eq!(order, [2, 1, 0]);
+
+        let mut calls = 0;
+        let result = grid_search(
+            grid,
+            |set| {
+                calls += 1;
+                set.scalar("x") * set.fold().unwrap() as Scalar
+            },
+            Some(3),
+        );
+        assert_eq!(calls, 9);
+        // The mean of x * (0, 1, 2)
+        let scores: Vec<_> = result.results.iter().map(|r| r.1).collect();
+        assert_eq!(scores, [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cross-validation needs at least 2 folds")]
+    fn test_grid_search_one_fold() {
+        let grid = ParamGrid::new().add("x", [1.0]);
+        grid_search(grid, |set| set.scalar("x"), Some(1));
+    }
+
+    #[test]
+    fn test_grid_search_k_fold() {
+        let data = line(12);
+        let grid = ParamGrid::new()
+            .add("lr", [0.0, 0.2])
+            .add("sizes", [vec![1]])
+            .add("activation", [Activation::Linear]);
+        let result = grid_search(
+            grid,
+            |set| {
+                let model = MLP::new_seeded(
+                    1,
+                    set.sizes("sizes"),
+                    set.activation("activation").clone(),
+                    set.seed(),
+                );
+                let mut opt = Sgd::new(model.parameters(), set.scalar("lr"));
+                let (train, val) = crate::data::k_fold(&data, 3, 0)
+                    .swap_remove(set.fold().unwrap());
+                let mut loader = DataLoader::new(&train, 4);
+                fit(
+                    &model,
+                    &mut opt,
+                    &Mse,
+                    &mut loader,
+                    20,
+                    FitConfig::default(),
+                );
+                evaluate(&model, &Mse, &mut DataLoader::new(&val, 4))
+            },
+            Some(3),
+        );
+        assert_eq!(result.best().unwrap().scalar("lr"), 0.2);
+    }
+
+    #[test]
+    fn test_grid_search_cv() {
+        let data = line(12);
+        let grid = ParamGrid::new().add("lr", [0.0, 0.2]);
+        let mut built = vec![];
+        let build = |set: &ParamSet| {
+            built.push((set.scalar("lr"), set.fold()));
+            let model = MLP::new_seeded(1, &[1], Activation::Linear, 0);
+            let opt = Sgd::new(model.parameters(), set.scalar("lr"));
+            (model, opt)
+        };
+        let result = grid_search_cv(grid, &data, 3, build, &Mse, 20, 4);
+        assert_eq!(built.len(), 6);
+        assert_eq!(
+            built[..3],
+            [(0.0, Some(0)), (0.0, Some(1)), (0.0, Some(2))]
+        );
+        assert_eq!(result.best().unwrap().scalar("lr"), 0.2);
+        assert!(result.results[0].1 < result.results[1].1);
+    }
+
+    #[test]
+    #[should_panic(expected = "parameter lr is 0.1, not a usize")]
+    fn test_param_set_types() {
+        let grid = ParamGrid::new().add("lr", [0.1]);
+        grid.param_sets()[0].usize("lr");
+    }
+
+    #[test]
+    fn test_accumulation_matches_big_batch() {
+        // 10 samples: windows of 8 and then a short one of 2
+        let data = line(10);
+        let trajectory = |batch_size, steps| {
+            let model = MLP::new_seeded(1, &[4, 1], Activation::Tanh, 8);
+            let mut opt = crate::optim::Adam::new(model.parameters(), 0.05);
+            let mut weights = Vec::new();
+            let config = FitConfig::default()
+                .accumulation_steps(steps)
+                .clip(ClipMode::Norm(0.5))
+                .callback(Record(&mut weights));
+            let mut loader = DataLoader::new(&data, batch_size);
+            let history = fit(&model, &mut opt, &Mse, &mut loader, 4, config);
+            (history.train_loss(), weights)
+        };
+        let (big_loss, big) = trajectory(8, 1);
+        let (micro_loss, micro) = trajectory(2, 4);
+        for (a, b) in big.iter().flatten().zip(micro.iter().flatten()) {
+            assert!((a - b).abs() < 1e-5, "{} vs {}", a, b);
+        }
+        for (a, b) in big_loss.iter().zip(&micro_loss) {
+            assert!((a - b).abs() < 1e-5);
+        }
+        // Without accumulation the small batches take a different path
+        let (_, unaccumulated) = trajectory(2, 1);
+        assert!((unaccumulated[0][0] - big[0][0]).abs() > 1e-4);
+    }
+}
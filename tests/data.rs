@@ -0,0 +1,87 @@
+#![cfg(feature = "fs")]
+
+use smolgrad::data::{
+    from_csv, from_csv_with_labels, Column, CsvError, CsvOptions, Dataset,
+};
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/csv/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+#[test]
+fn test_csv_with_header_and_labels() {
+    let options = CsvOptions {
+        target_columns: vec!["petal_width".into()],
+        skip_columns: vec![0.into()],
+        label_column: Some("species".into()),
+        ..CsvOptions::default()
+    };
+    let (data, labels) =
+        from_csv_with_labels(fixture("flowers.csv"), &options).unwrap();
+    assert_eq!(labels, ["setosa", "versicolor", "virginica"]);
+    assert_eq!(data.len(), 5);
+    assert_eq!((data.num_features(), data.num_targets()), (3, 2));
+    assert_eq!(data.get(0), (vec![5.1, 3.5, 1.4], vec![0.2, 0.0]));
+    assert_eq!(data.get(2), (vec![6.3, 3.3, 6.0], vec![2.5, 2.0]));
+    let classes: Vec<_> = (0..5).map(|i| data.get(i).1[1]).collect();
+    assert_eq!(classes, [0.0, 1.0, 2.0, 0.0, 1.0]);
+
+    // Without a label column the species can't be parsed
+    let options = CsvOptions {
+        label_column: None,
+        ..options
+    };
+    let err = from_csv(fixture("flowers.csv"), &options).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "line 2, column `species`: \"setosa\" is not a number"
+    );
+}
+
+#[test]
+fn test_csv_errors() {
+    let options = CsvOptions {
+        target_columns: vec!["y".into()],
+        ..CsvOptions::default()
+    };
+    let err = from_csv(fixture("malformed.csv"), &options).unwrap_err();
+    assert!(matches!(
+        err,
+        CsvError::Parse { line: 3, ref column, ref cell }
+            if *column == Column::from("x2") && cell == "n/a"
+    ));
+
+    let err = from_csv(fixture("ragged.csv"), &options).unwrap_err();
+    assert_eq!(err.to_string(), "line 3 has 2 cells, expected 3");
+
+    let options = CsvOptions {
+        target_columns: vec!["label".into()],
+        ..CsvOptions::default()
+    };
+    let err = from_csv(fixture("malformed.csv"), &options).unwrap_err();
+    assert_eq!(err.to_string(), "no column `label` in the file");
+
+    let err = from_csv(fixture("missing.csv"), &options).unwrap_err();
+    assert!(matches!(err, CsvError::Io(_)));
+}
+
+#[test]
+fn test_csv_delimiter_without_header() {
+    let options = CsvOptions {
+        has_header: false,
+        delimiter: ';',
+        target_columns: vec![2.into()],
+        ..CsvOptions::default()
+    };
+    let data = from_csv(fixture("semicolons.csv"), &options).unwrap();
+    assert_eq!(data.len(), 3);
+    assert_eq!(data.get(2), (vec![3.0, 4.25], vec![1.0]));
+
+    // Names need a header
+    let options = CsvOptions {
+        target_columns: vec!["x".into()],
+        ..options
+    };
+    let err = from_csv(fixture("semicolons.csv"), &options).unwrap_err();
+    assert!(matches!(err, CsvError::UnknownColumn(_)));
+}
@@ -0,0 +1,35 @@
+#![cfg(feature = "fs")]
+
+use smolgrad::checkpoint::WeightLayout;
+use smolgrad::engine::Value;
+use smolgrad::nn::{Activation, MLP};
+
+/// `tests/fixtures/tiny_mlp` holds the `state_dict` of
+///
+/// ```python
+/// nn.Sequential(nn.Linear(3, 4), nn.Tanh(), nn.Linear(4, 2))
+/// ```
+///
+/// renamed to `layer0`/`layer1`, one `np.save` per tensor. The first layer
+/// is float32 and the second float64, as NumPy saves by default. The
+/// expected outputs were computed in float64 from the stored weights.
+#[test]
+fn test_import_pytorch_fixture() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tiny_mlp");
+    let model = MLP::new(3, &[4, 2], Activation::Tanh);
+    model.import_npy(dir, WeightLayout::OutIn).unwrap();
+
+    let cases = [
+        ([0.0, 0.0, 0.0], [0.4097086, -0.4439819]),
+        ([1.0, -2.0, 0.5], [1.536291, -1.797024]),
+        ([-0.3, 0.8, 1.5], [-1.34272, 0.05346015]),
+    ];
+    for (x, expected) in cases.iter() {
+        let x: Vec<_> = x.iter().map(|&x| Value::new(x)).collect();
+        let out = model.call(&x);
+        for (y, e) in out.iter().zip(expected) {
+            let y = y.get_data();
+            assert!((y - e).abs() < 1e-5, "{} != {}", y, e);
+        }
+    }
+}
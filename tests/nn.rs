@@ -0,0 +1,28 @@
+use smolgrad::engine::Value;
+use smolgrad::nn::{Activation, Module, MLP};
+
+#[test]
+fn test_mlp_from_outside() {
+    let model = MLP::new(2, &[16, 16, 1], Activation::ReLU);
+    assert_eq!(model.sizes(), &[2, 16, 16, 1]);
+    assert_eq!(
+        model.parameters().len(),
+        2 * 16 + 16 + 16 * 16 + 16 + 16 + 1
+    );
+
+    let x = [Value::new(0.5), Value::new(-1.5)];
+    let out = model.call(&x);
+    assert_eq!(out.len(), 1);
+    let loss = (out[0].clone() + -1.0).pow(2.0);
+    loss.backward();
+    assert_eq!(loss.get_grad(), 1.0);
+
+    // the output layer is linear, so its bias always sees d(loss)/d(out)
+    let params = model.parameters();
+    let bias = params.last().unwrap();
+    let expected = 2.0 * (out[0].get_data() - 1.0);
+    assert!((bias.get_grad() - expected).abs() < 1e-5);
+
+    model.zero_grad();
+    assert!(params.iter().all(|p| p.get_grad() == 0.0));
+}
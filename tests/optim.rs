@@ -0,0 +1,38 @@
+use smolgrad::engine::{Scalar, Value};
+use smolgrad::nn::{Activation, Module, MLP};
+use smolgrad::optim::{Optimizer, Scheduler, Sgd, StepLr};
+
+/// An epoch loop with the scheduler stepped once per epoch, after the
+/// batches.
+#[test]
+fn test_scheduler_in_training_loop() {
+    let model = MLP::new_seeded(1, &[8, 1], Activation::Tanh, 4);
+    let mut opt = Sgd::new(model.parameters(), 0.1).momentum(0.5);
+    let mut scheduler = StepLr::new(10, 0.5);
+    let data: Vec<(Scalar, Scalar)> = (0..8)
+        .map(|i| i as Scalar / 8.0)
+        .map(|x| (x, 2.0 * x - 1.0))
+        .collect();
+
+    let mut losses = vec![];
+    let mut lrs = vec![];
+    for _ in 0..30 {
+        let mut epoch_loss = 0.0;
+        for &(x, y) in &data {
+            opt.zero_grad();
+            let out = model.call(&[Value::new(x)]);
+            let loss = (out[0].clone() + -y).pow(2.0);
+            loss.backward();
+            opt.step();
+            epoch_loss += loss.get_data();
+        }
+        lrs.push(opt.lr());
+        scheduler.step(&mut opt);
+        losses.push(epoch_loss);
+    }
+
+    assert_eq!(lrs[0], 0.1);
+    assert_eq!(lrs[10], 0.05);
+    assert_eq!(lrs[29], 0.025);
+    assert!(losses[29] < losses[0] / 10.0, "{:?}", losses);
+}
@@ -0,0 +1,184 @@
+use smolgrad::data::{
+    make_moons, make_spiral, DataLoader, Dataset, InMemoryDataset,
+};
+use smolgrad::engine::{Scalar, Value};
+use smolgrad::losses::{Hinge, Loss, Mse};
+use smolgrad::metrics::accuracy;
+use smolgrad::nn::{
+    prune_by_magnitude, Activation, Classifier, Module, QuantizedMlp, MLP,
+};
+use smolgrad::optim::{Adam, Optimizer, Sgd};
+use smolgrad::train::{fit, FitConfig};
+
+#[test]
+fn test_fit_xor() {
+    let xs = vec![
+        vec![0.0, 0.0],
+        vec![0.0, 1.0],
+        vec![1.0, 0.0],
+        vec![1.0, 1.0],
+    ];
+    let ys = vec![vec![-1.0], vec![1.0], vec![1.0], vec![-1.0]];
+    let data = InMemoryDataset::new(xs, ys).unwrap();
+    let model = MLP::new_seeded(2, &[8, 1], Activation::Tanh, 0);
+    let mut opt = Adam::new(model.parameters(), 0.05);
+    let mut loader = DataLoader::new(&data, 4);
+
+    let history = fit(
+        &model,
+        &mut opt,
+        &Mse,
+        &mut loader,
+        200,
+        FitConfig::default(),
+    );
+
+    assert_eq!(history.epochs(), 200);
+    let loss = history.train_loss()[199];
+    assert!(loss < 0.01, "{}", history);
+}
+
+// The moons tests train on few samples for few epochs, so that the suite
+// stays quick in debug builds.
+const MOONS: usize = 60;
+const MOONS_EPOCHS: usize = 40;
+
+/// The fraction of `data` on the side of zero its -1/1 target says.
+fn binary_accuracy(model: &dyn Module, data: &dyn Dataset) -> f64 {
+    let correct = (0..data.len())
+        .filter(|&i| {
+            let (x, y) = data.get(i);
+            let x: Vec<_> = x.into_iter().map(Value::new).collect();
+            let score = model.forward(&x)[0].get_data();
+            (score > 0.0) == (y[0] > 0.0)
+        })
+        .count();
+    correct as f64 / data.len() as f64
+}
+
+/// The micrograd demo: a 2-16-16-1 ReLU MLP with hinge loss and L2 on the
+/// moons, through the whole engine, nn and optim stack.
+#[test]
+fn test_fit_moons() {
+    let data = make_moons(MOONS, 0.1, 0);
+    let model = MLP::new_seeded(2, &[16, 16, 1], Activation::ReLU, 0);
+    // Weight decay 2 * alpha is the gradient of an L2 penalty alpha
+    let mut opt = Sgd::new(model.parameters(), 0.5).weight_decay(2e-4);
+    let mut loader = DataLoader::new(&data, MOONS);
+    let history = fit(
+        &model,
+        &mut opt,
+        &Hinge,
+        &mut loader,
+        MOONS_EPOCHS,
+        FitConfig::default(),
+    );
+
+    let accuracy = binary_accuracy(&model, &data);
+    assert!(accuracy > 0.95, "accuracy {}\n{}", accuracy, history);
+}
+
+/// Dropping the smallest 30% of the moons model's weights costs little
+/// accuracy, and fine-tuning with the mask keeps them at zero.
+#[test]
+fn test_prune_moons() {
+    let data = make_moons(MOONS, 0.1, 0);
+    let model = MLP::new_seeded(2, &[16, 16, 1], Activation::ReLU, 0);
+    let mut opt = Sgd::new(model.parameters(), 0.5).weight_decay(2e-4);
+    let mut loader = DataLoader::new(&data, MOONS);
+    let config = FitConfig::default();
+    fit(&model, &mut opt, &Hinge, &mut loader, MOONS_EPOCHS, config);
+    let dense = binary_accuracy(&model, &data);
+
+    let mask = prune_by_magnitude(&model, 0.3);
+    assert!((mask.sparsity() - 0.3).abs() < 0.01, "{}", mask.sparsity());
+    let pruned = binary_accuracy(&model, &data);
+    assert!(
+        pruned > dense - 0.05,
+        "{} pruned vs {} dense",
+        pruned,
+        dense
+    );
+
+    let params = model.parameters();
+    for _ in 0..10 {
+        for batch in loader.iter() {
+            let preds = model.forward_batch_scalars(&batch.xs);
+            opt.zero_grad();
+            Hinge.batch_loss(&preds, &batch.ys).backward();
+            opt.step();
+            mask.apply_after_step(&params);
+        }
+    }
+    for (p, &pruned) in params.iter().zip(mask.pruned()) {
+        assert!(!pruned || p.get_data() == 0.0);
+    }
+    let tuned = binary_accuracy(&model, &data);
+    assert!(tuned > dense - 0.05, "{} tuned vs {} dense", tuned, dense);
+}
+
+/// The model of [`test_fit_moons`], trained on `data`.
+fn fit_moons_model(data: &InMemoryDataset) -> MLP {
+    let model = MLP::new_seeded(2, &[16, 16, 1], Activation::ReLU, 0);
+    let mut opt = Sgd::new(model.parameters(), 0.5).weight_decay(2e-4);
+    let mut loader = DataLoader::new(data, MOONS);
+    let config = FitConfig::default();
+    fit(&model, &mut opt, &Hinge, &mut loader, MOONS_EPOCHS, config);
+    model
+}
+
+/// An int8 copy of the moons model draws nearly the same boundary, and
+/// survives being serialized.
+#[test]
+fn test_quantize_moons() {
+    let data = make_moons(MOONS, 0.1, 0);
+    let model = fit_moons_model(&data);
+
+    let compiled = model.compile_inference();
+    let quantized = compiled.quantize();
+    let grid: Vec<Vec<Scalar>> = (0..50)
+        .flat_map(|i| (0..50).map(move |j| (i, j)))
+        .map(|(i, j)| {
+            vec![-1.5 + i as Scalar * 0.08, -1.0 + j as Scalar * 0.05]
+        })
+        .collect();
+    let agree = |q: &QuantizedMlp| {
+        let same = grid.iter().filter(|x| {
+            (q.predict(x)[0] > 0.0) == (compiled.predict(x)[0] > 0.0)
+        });
+        same.count() as f64 / grid.len() as f64
+    };
+    let agreement = agree(&quantized);
+    assert!(agreement >= 0.98, "{} agreement", agreement);
+
+    let restored = QuantizedMlp::deserialize(&quantized.serialize()).unwrap();
+    assert_eq!(agree(&restored), agreement);
+}
+
+/// Three spiral arms can't be told apart by lines, but can by an MLP.
+#[test]
+fn test_spiral_needs_hidden_layers() {
+    let spiral = make_spiral(45, 3, 0.1, 0);
+    let (xs, ys): (Vec<_>, Vec<_>) =
+        (0..spiral.len()).map(|i| spiral.get(i)).unzip();
+    let classes: Vec<_> = ys.iter().map(|y| y[0] as usize).collect();
+    let one_hot = classes
+        .iter()
+        .map(|&c| (0..3).map(|k| if k == c { 1.0 } else { 0.0 }).collect())
+        .collect();
+    let data = InMemoryDataset::new(xs.clone(), one_hot).unwrap();
+
+    let train = |sizes: &[usize]| {
+        let model = MLP::new_seeded(2, sizes, Activation::ReLU, 0);
+        let mut opt = Adam::new(model.parameters(), 0.05);
+        let mut loader = DataLoader::new(&data, 45);
+        let config = FitConfig::default();
+        fit(&model, &mut opt, &Mse, &mut loader, 40, config);
+        let preds = Classifier::new(model).predict_batch(&xs);
+        accuracy(&preds, &classes)
+    };
+    let linear = train(&[3]);
+    let deep = train(&[16, 16, 3]);
+    assert!(linear < 0.6, "linear accuracy {}", linear);
+    assert!(deep > 0.9, "MLP accuracy {}", deep);
+}
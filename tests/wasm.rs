@@ -0,0 +1,47 @@
+use std::path::Path;
+use std::process::Command;
+
+/// Whether the standard library for `target` is installed.
+fn has_target(target: &str) -> bool {
+    let sysroot = Command::new("rustc")
+        .args(["--print", "sysroot"])
+        .output()
+        .ok()
+        .and_then(|out| String::from_utf8(out.stdout).ok());
+    sysroot.is_some_and(|root| {
+        Path::new(root.trim())
+            .join("lib/rustlib")
+            .join(target)
+            .exists()
+    })
+}
+
+/// Checks the crate for the browser: the core without `fs`, and the
+/// wasm-bindgen demo. Needs `rustup target add wasm32-unknown-unknown`,
+/// so it only runs with `cargo test -- --ignored`.
+#[test]
+#[ignore = "needs the wasm32-unknown-unknown target"]
+fn test_checks_on_wasm32() {
+    let target = "wasm32-unknown-unknown";
+    assert!(
+        has_target(target),
+        "{} is not installed; run `rustup target add {}`",
+        target,
+        target
+    );
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("wasm");
+    let checks: [&[&str]; 2] = [
+        &["--lib", "--no-default-features"],
+        &["--example", "wasm_moons"],
+    ];
+    for args in checks {
+        let status = Command::new(env!("CARGO"))
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .args(["check", "--quiet", "--target", target, "--target-dir"])
+            .arg(&target_dir)
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success(), "cargo check {:?} failed", args);
+    }
+}